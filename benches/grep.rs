@@ -0,0 +1,78 @@
+//! Times `grep_files` on a generated tree about the size of a large repository checkout.
+//!
+//! Compare with an earlier revision through criterion baselines, e.g.
+//! `cargo bench --bench grep -- --save-baseline before` there and
+//! `cargo bench --bench grep -- --baseline before` here, and with a single thread by setting
+//! `RAYON_NUM_THREADS=1`.
+
+use std::{fs, path::Path};
+
+use agenty::tools::grep::{GrepTool, GrepToolArgs};
+use criterion::{Criterion, criterion_group, criterion_main};
+
+const DIRS: usize = 64;
+const FILES_PER_DIR: usize = 64;
+const LINES_PER_FILE: usize = 400;
+const WORDS: &[&str] = &[
+    "let", "fn", "match", "self", "value", "result", "buffer", "parse", "config", "error",
+    "handle", "stream", "index", "token", "return", "impl", "struct", "iter", "map", "path",
+];
+
+/// Fills `root` with `DIRS * FILES_PER_DIR` source-like files, the same on every run.
+fn generate(root: &Path) {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+    for d in 0..DIRS {
+        let dir = root.join(format!("module{}", d));
+        fs::create_dir_all(&dir).unwrap();
+        for f in 0..FILES_PER_DIR {
+            let mut text = String::new();
+            for _ in 0..LINES_PER_FILE {
+                let words = 2 + next() % 10;
+                let line = (0..words)
+                    .map(|_| WORDS[next() % WORDS.len()])
+                    .collect::<Vec<_>>();
+                text.push_str("    ");
+                text.push_str(&line.join(" "));
+                text.push('\n');
+            }
+            // A rare needle so the output is printed rather than summarized.
+            if next() % 16 == 0 {
+                text.push_str("    // needle_marker here\n");
+            }
+            fs::write(dir.join(format!("file{}.rs", f)), text).unwrap();
+        }
+    }
+}
+
+fn args(pattern: &str) -> GrepToolArgs {
+    serde_json::from_value(serde_json::json!({ "path": ".", "pattern": pattern })).unwrap()
+}
+
+fn bench_grep(c: &mut Criterion) {
+    let root = tempfile::tempdir().unwrap();
+    generate(root.path());
+    let tool = GrepTool::new(root.path().to_path_buf());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("grep_files");
+    group.sample_size(20);
+    for (name, pattern) in [
+        ("rare", "needle_marker"),
+        ("frequent", r"fn \w+ result"),
+        ("none", "no_such_identifier"),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| rt.block_on(tool.grep(args(pattern))).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_grep);
+criterion_main!(benches);
@@ -1,31 +1,269 @@
-use std::time::Duration;
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::Path,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
 
 use crate::{
     error::AgentyError,
-    tool::{Tool, ToolBox},
+    memory::MemoryStore,
+    tool::{Tool, ToolBox, ToolDyn},
+    tools::{
+        memory::{MemoryReadTool, MemoryWriteTool},
+        todo::TodoTool,
+    },
 };
+use base64::{Engine, prelude::BASE64_STANDARD};
 use color_eyre::eyre::eyre;
-use itertools::Itertools;
-use log::{debug, warn};
+use either::Either;
 use openai_models::openai::types::chat::{
-    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage,
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestAssistantMessageContent,
+    ChatCompletionRequestAssistantMessageContentPart,
     ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
     ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-    CreateChatCompletionResponse, FinishReason,
+    CreateChatCompletionResponse, FinishReason, ReasoningEffort,
     ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent,
+    ChatCompletionRequestMessageContentPartImageArgs,
+    ChatCompletionRequestMessageContentPartTextArgs, ChatCompletionRequestUserMessageContent,
+    ChatCompletionRequestUserMessageContentPart, ImageDetail, ImageUrl,
 };
 use openai_models::{
     llm::{LLM, LLMSettings},
     openai::types::chat::{
-        ChatCompletionMessageToolCalls, ChatCompletionToolChoiceOption, FunctionCall,
+        ChatCompletionMessageToolCalls, FunctionCall,
     },
 };
+use regex::Regex;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, info_span, warn};
+
+/// The text of a message, with the text parts of multi-part contents concatenated.
+fn message_text(message: &ChatCompletionRequestMessage) -> String {
+    let content = serde_json::to_value(message)
+        .ok()
+        .and_then(|v| v.get("content").cloned());
+    match content {
+        Some(serde_json::Value::String(s)) => s,
+        Some(serde_json::Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect(),
+        _ => String::new(),
+    }
+}
+
+/// The ids of the tool calls of an assistant message, empty for other messages.
+fn tool_call_ids(message: &ChatCompletionRequestMessage) -> Vec<&str> {
+    match message {
+        ChatCompletionRequestMessage::Assistant(msg) => msg
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| match call {
+                ChatCompletionMessageToolCalls::Function(call) => call.id.as_str(),
+                ChatCompletionMessageToolCalls::Custom(call) => call.id.as_str(),
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The characters of all strings in `value`, keys excluded.
+fn string_chars(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::String(s) => s.chars().count(),
+        serde_json::Value::Array(values) => values.iter().map(string_chars).sum(),
+        serde_json::Value::Object(map) => map.values().map(string_chars).sum(),
+        _ => 0,
+    }
+}
 
+/// Split `content` into the text outside `<thinking>` blocks and the joined inner text of
+/// those blocks, if any.
+pub fn split_thinking(content: &str) -> (String, Option<String>) {
+    static THINKING: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)<thinking>(.*?)</thinking>").unwrap());
+    let thinking = THINKING
+        .captures_iter(content)
+        .map(|c| c[1].trim().to_string())
+        .collect::<Vec<_>>();
+    if thinking.is_empty() {
+        return (content.to_string(), None);
+    }
+    (
+        THINKING.replace_all(content, "").trim().to_string(),
+        Some(thinking.join("\n")),
+    )
+}
+
+#[derive(Clone)]
 pub struct Agent {
     pub tools: ToolBox,
     pub system: String,
     pub user: String,
     pub context: Vec<ChatCompletionRequestMessage>,
+    /// Whether the message at the same index of `context` is pinned, see
+    /// [`Agent::pin_message`]. Kept in sync by the methods changing `context`, messages added
+    /// to it directly are not pinned.
+    pinned: Vec<bool>,
+    /// Keep only the last N messages of `context` after every `run_once`.
+    ///
+    /// The system and the initial user prompt are never dropped, and neither are pinned
+    /// messages, which may keep the context above N. This can cause context discontinuity
+    /// (the model forgets what it did earlier) and should be used as a last resort when the
+    /// context can not be kept small otherwise.
+    pub sliding_window: Option<usize>,
+    /// Strip `<thinking>...</thinking>` blocks from assistant messages, see
+    /// [`Agent::set_extract_thinking`].
+    pub extract_thinking: bool,
+    /// The reasoning extracted from the last assistant message when `extract_thinking` is on.
+    pub last_thinking: Option<String>,
+    /// Record every request/response pair into `debug_log`, see [`Agent::set_debug_mode`].
+    pub debug_mode: bool,
+    pub debug_log: Vec<DebugTurn>,
+    /// Persistent memory exposed to the model through the memory tools, see
+    /// [`Agent::set_memory`].
+    pub memory: Option<Arc<dyn MemoryStore>>,
+    /// Decides whether [`Agent::run_until_text`] recovers from an error, see
+    /// [`Agent::set_error_handler`].
+    pub error_handler: Option<ErrorHandler>,
+    /// Give up with [`AgentyError::MaxTurnsExceeded`] after this many steps of a single
+    /// `run_until_*` call.
+    pub max_turns: Option<u32>,
+    /// Give up with [`AgentyError::TokenBudgetExceeded`] once `tokens_used` reaches it.
+    pub token_budget: Option<u32>,
+    /// Total tokens reported by the API over the lifetime of the agent.
+    pub tokens_used: u32,
+    /// Calls of [`Agent::run_once`] since the agent was created or its context reset.
+    pub turns: u32,
+    /// Give up with [`AgentyError::TaskTimeout`] when a single `run_until_*` call takes
+    /// longer. Checked between steps, a running step is not interrupted.
+    pub task_timeout: Option<Duration>,
+    /// Notified of every completed tool call, see [`Agent::run_until_text_with_observer`].
+    pub tool_observer: Option<ToolObserver>,
+    /// Stops the `run_until_*` calls with [`AgentyError::Cancelled`] once cancelled.
+    pub cancellation: Option<CancellationToken>,
+    /// Extend the system prompt of every request, see [`Agent::add_system_hook`].
+    pub system_hooks: Vec<SystemHook>,
+    /// Sent as `reasoning_effort` to reasoning models like o1 and o3, see
+    /// [`Agent::set_reasoning_effort`].
+    pub reasoning_effort: Option<ReasoningEffort>,
+}
+
+pub type ErrorHandler = Arc<dyn Fn(&AgentyError) -> Option<AgentAction<String>> + Send + Sync>;
+
+pub type ToolObserver = Arc<dyn Fn(ToolCallEvent) + Send + Sync>;
+
+pub type SystemHook = Arc<dyn Fn() -> Option<String> + Send + Sync>;
+
+/// A completed tool call as seen by a [`ToolObserver`].
+#[derive(Debug, Clone)]
+pub struct ToolCallEvent {
+    pub name: String,
+    /// The arguments exactly as the model sent them.
+    pub arguments: String,
+    /// What was handed back to the model, including the messages about invalid calls.
+    pub result: String,
+}
+
+/// Builds an [`Agent`], everything not set keeps its default.
+#[derive(Default)]
+pub struct AgentBuilder {
+    tools: ToolBox,
+    system: Option<String>,
+    user: String,
+    max_turns: Option<u32>,
+    token_budget: Option<u32>,
+    task_timeout: Option<Duration>,
+    sliding_window: Option<usize>,
+    reasoning_effort: Option<ReasoningEffort>,
+}
+
+impl AgentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tools(mut self, tools: ToolBox) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn system(mut self, system: String) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    pub fn user(mut self, user: String) -> Self {
+        self.user = user;
+        self
+    }
+
+    pub fn max_turns(mut self, max_turns: u32) -> Self {
+        self.max_turns = Some(max_turns);
+        self
+    }
+
+    pub fn token_budget(mut self, token_budget: u32) -> Self {
+        self.token_budget = Some(token_budget);
+        self
+    }
+
+    pub fn task_timeout(mut self, task_timeout: Duration) -> Self {
+        self.task_timeout = Some(task_timeout);
+        self
+    }
+
+    pub fn sliding_window(mut self, sliding_window: usize) -> Self {
+        self.sliding_window = Some(sliding_window);
+        self
+    }
+
+    pub fn reasoning_effort(mut self, reasoning_effort: ReasoningEffort) -> Self {
+        self.reasoning_effort = Some(reasoning_effort);
+        self
+    }
+
+    pub fn build(self) -> Agent {
+        let system = self.system.unwrap_or(
+            "You are an expert agent that calls tool to complete your task.".to_string(),
+        );
+        Agent {
+            tools: self.tools,
+            system,
+            user: self.user,
+            context: vec![],
+            pinned: vec![],
+            sliding_window: self.sliding_window,
+            extract_thinking: false,
+            last_thinking: None,
+            debug_mode: false,
+            debug_log: vec![],
+            memory: None,
+            error_handler: None,
+            max_turns: self.max_turns,
+            token_budget: self.token_budget,
+            tokens_used: 0,
+            turns: 0,
+            task_timeout: self.task_timeout,
+            tool_observer: None,
+            cancellation: None,
+            system_hooks: vec![],
+            reasoning_effort: self.reasoning_effort,
+        }
+    }
+}
+
+/// A raw request/response pair captured in debug mode.
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugTurn {
+    pub request_json: String,
+    pub response_json: String,
+    pub turn_index: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -35,12 +273,36 @@ pub enum AgentAction<T = ()> {
     Out(T),
 }
 
+/// [`LLMSettings`] for reasoning models like o1 and o3, used with
+/// [`Agent::set_reasoning_effort`].
+///
+/// The completion budget covers the hidden reasoning as well, so it is much larger than
+/// usual, and so is the timeout. The other fields are kept from `base`.
+pub fn reasoning_model_settings(mut base: LLMSettings) -> LLMSettings {
+    // Left out of the request with a reasoning effort, 1 is the only value accepted.
+    base.llm_temperature = 1.0;
+    base.llm_presence_penalty = 0.0;
+    base.llm_prompt_timeout = base.llm_prompt_timeout.max(600);
+    base.llm_max_completion_tokens = base.llm_max_completion_tokens.max(65536);
+    base
+}
+
 impl Agent {
+    /// `system` followed by what the `system_hooks` add right now.
+    fn system_prompt(&self) -> String {
+        let mut system = self.system.clone();
+        for text in self.system_hooks.iter().filter_map(|hook| hook()) {
+            system.push_str("\n\n");
+            system.push_str(&text);
+        }
+        system
+    }
+
     fn full_context(&self) -> Vec<ChatCompletionRequestMessage> {
         vec![
             ChatCompletionRequestMessage::System(
                 ChatCompletionRequestSystemMessageArgs::default()
-                    .content(self.system.clone())
+                    .content(self.system_prompt())
                     .build()
                     .unwrap(),
             ),
@@ -52,20 +314,165 @@ impl Agent {
             ),
         ]
         .into_iter()
-        .chain(self.context.clone().into_iter())
+        .chain(self.context.clone())
         .collect()
     }
 
+    #[deprecated(note = "use AgentBuilder")]
     pub fn new(tools: ToolBox, system: Option<String>, user: String) -> Self {
-        let system = system.unwrap_or(
-            "You are an expert agent that calls tool to complete your task.".to_string(),
-        );
-        Self {
+        AgentBuilder {
             tools,
             system,
             user,
-            context: vec![],
+            ..Default::default()
+        }
+        .build()
+    }
+
+    /// Let `token` cancel the agent from outside.
+    ///
+    /// Cancelling interrupts the running request or tool calls, which get `Tool call
+    /// cancelled` as their result, and the `run_until_*` call returns
+    /// [`AgentyError::Cancelled`].
+    pub fn with_cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Register `tools` in addition to the ones the agent was created with.
+    ///
+    /// Like [`Agent::add_tools`], fails with [`AgentyError::DuplicateTool`] if a name is
+    /// taken.
+    pub fn with_tools(
+        mut self,
+        tools: impl IntoIterator<Item = Box<dyn ToolDyn>>,
+    ) -> Result<Self, AgentyError> {
+        self.add_tools(tools)?;
+        Ok(self)
+    }
+
+    /// Register `tool`, failing with [`AgentyError::DuplicateTool`] if a tool with the same
+    /// name is registered already, see [`ToolBox::add_tool`].
+    ///
+    /// Calls can still be chained with `?`, e.g. `agent.add_tool(a)?.add_tool(b)?`.
+    pub fn add_tool<T: Tool + 'static>(&mut self, tool: T) -> Result<&mut Self, AgentyError> {
+        self.tools.add_tool(tool)?;
+        Ok(self)
+    }
+
+    /// Register `tools`, failing with [`AgentyError::DuplicateTool`] without registering any
+    /// of them if one of the names is taken.
+    pub fn add_tools(
+        &mut self,
+        tools: impl IntoIterator<Item = Box<dyn ToolDyn>>,
+    ) -> Result<&mut Self, AgentyError> {
+        self.tools.try_extend(tools)?;
+        Ok(self)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Check the limits before starting step `turn` of a task started at `started`.
+    fn check_limits(&self, turn: u32, started: Instant) -> Result<(), AgentyError> {
+        if self.is_cancelled() {
+            return Err(AgentyError::Cancelled);
+        }
+        if let Some(max_turns) = self.max_turns
+            && turn >= max_turns
+        {
+            return Err(AgentyError::MaxTurnsExceeded(max_turns));
+        }
+        if let Some(budget) = self.token_budget
+            && self.tokens_used >= budget
+        {
+            return Err(AgentyError::TokenBudgetExceeded(budget));
+        }
+        if let Some(timeout) = self.task_timeout
+            && started.elapsed() >= timeout
+        {
+            return Err(AgentyError::TaskTimeout(timeout));
+        }
+        Ok(())
+    }
+
+    /// Enable extracting `<thinking>...</thinking>` blocks from assistant messages.
+    ///
+    /// The blocks are removed before the message is appended to the context so the reasoning
+    /// does not consume tokens in subsequent turns, and their inner text is kept in
+    /// `last_thinking` for debugging.
+    pub fn set_extract_thinking(&mut self, enabled: bool) {
+        self.extract_thinking = enabled;
+    }
+
+    /// Ask reasoning models like o1 and o3 to think with `effort`, `None` to not send it.
+    ///
+    /// Those models reject the sampling settings, so `temperature` and `presence_penalty` of
+    /// the [`LLMSettings`] are left out of the request while this is set. Pair it with
+    /// [`reasoning_model_settings`] for the longer timeout and completion these models need.
+    pub fn set_reasoning_effort(&mut self, effort: Option<ReasoningEffort>) {
+        self.reasoning_effort = effort;
+    }
+
+    /// Capture the full request and response of every `run_once` call into `debug_log`, so
+    /// exact API calls can be reproduced when debugging agent behavior.
+    pub fn set_debug_mode(&mut self, enabled: bool) {
+        self.debug_mode = enabled;
+    }
+
+    /// Attach a persistent memory and register `memory_read` and `memory_write` so the
+    /// model can use it.
+    pub fn set_memory(&mut self, store: Box<dyn MemoryStore>) {
+        let store: Arc<dyn MemoryStore> = Arc::from(store);
+        self.tools.add_or_replace_tool(MemoryReadTool::new(store.clone()));
+        self.tools.add_or_replace_tool(MemoryWriteTool::new(store.clone()));
+        self.memory = Some(store);
+    }
+
+    /// Append the text returned by `hook` to the system prompt of every request, nothing is
+    /// appended when it returns `None`.
+    ///
+    /// Hooks run before each request, so they can show state that changes during the task.
+    pub fn add_system_hook(&mut self, hook: impl Fn() -> Option<String> + Send + Sync + 'static) {
+        self.system_hooks.push(Arc::new(hook));
+    }
+
+    /// Register `todo` and show its plan in the system prompt of every request, so the model
+    /// sees the current plan without calling the tool.
+    pub fn set_todo(&mut self, todo: TodoTool) {
+        self.tools.add_or_replace_tool(todo.clone());
+        self.add_system_hook(move || {
+            let plan = todo.render();
+            (!plan.is_empty()).then_some(plan)
+        });
+    }
+
+    /// Recover from errors in [`Agent::run_until_text`] instead of aborting the task.
+    ///
+    /// When a step fails, the loop carries on with the action returned by `handler`, or
+    /// propagates the error if it returns `None`. Returning [`AgentAction::Continue`] retries
+    /// with the context as it is, so only recover from errors that leave it consistent, like
+    /// transient network failures. Exceeded limits like `max_turns` and cancellation always
+    /// propagate.
+    pub fn set_error_handler(
+        &mut self,
+        handler: impl Fn(&AgentyError) -> Option<AgentAction<String>> + Send + Sync + 'static,
+    ) {
+        self.error_handler = Some(Arc::new(handler));
+    }
+
+    /// Write `debug_log` to `path` as JSONL, one turn per line.
+    pub fn debug_log_to_file(&self, path: &Path) -> Result<(), AgentyError> {
+        let mut fp = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for turn in &self.debug_log {
+            serde_json::to_writer(&mut fp, turn)?;
+            fp.write_all(b"\n")?;
         }
+        fp.flush()?;
+        Ok(())
     }
 
     pub async fn run_once<TC, MS, RF, T>(
@@ -85,14 +492,23 @@ impl Agent {
         MS: AsyncFnOnce(&mut Self, String, FinishReason) -> Result<AgentAction<T>, AgentyError>,
         RF: AsyncFnOnce(&mut Self, String, FinishReason) -> Result<AgentAction<T>, AgentyError>,
     {
+        self.turns += 1;
+        self.tools.reset_budget();
         let settings = settings.unwrap_or_else(|| llm.default_settings.clone());
         let mut req = CreateChatCompletionRequestArgs::default();
         req.messages(self.full_context())
             .model(llm.model.to_string())
-            .temperature(settings.llm_temperature)
-            .presence_penalty(settings.llm_presence_penalty)
             .max_completion_tokens(settings.llm_max_completion_tokens);
-        if self.tools.tools.len() > 0 {
+        match &self.reasoning_effort {
+            Some(effort) => {
+                req.reasoning_effort(effort.clone());
+            }
+            None => {
+                req.temperature(settings.llm_temperature)
+                    .presence_penalty(settings.llm_presence_penalty);
+            }
+        }
+        if !self.tools.is_empty() {
             req.tools(self.tools.openai_objects());
         }
         if let Some(choice) = settings.llm_tool_choice.as_ref() {
@@ -101,13 +517,30 @@ impl Agent {
         let req = req.build()?;
         let timeout = Duration::from_secs(settings.llm_prompt_timeout);
 
-        let mut resp: CreateChatCompletionResponse = llm
-            .complete_once_with_retry(&req, prefix, Some(timeout), Some(settings.llm_retry))
-            .await?;
+        let completion =
+            llm.complete_once_with_retry(&req, prefix, Some(timeout), Some(settings.llm_retry));
+        let mut resp: CreateChatCompletionResponse = match &self.cancellation {
+            Some(token) => tokio::select! {
+                resp = completion => resp?,
+                _ = token.cancelled() => return Err(AgentyError::Cancelled),
+            },
+            None => completion.await?,
+        };
+        if let Some(usage) = &resp.usage {
+            self.tokens_used += usage.total_tokens;
+        }
+
+        if self.debug_mode {
+            self.debug_log.push(DebugTurn {
+                request_json: serde_json::to_string_pretty(&req)?,
+                response_json: serde_json::to_string_pretty(&resp)?,
+                turn_index: self.debug_log.len() as u32,
+            });
+        }
 
         let choice = resp.choices.swap_remove(0);
 
-        if matches!(choice.finish_reason, Some(FinishReason::ToolCalls))
+        let action = if matches!(choice.finish_reason, Some(FinishReason::ToolCalls))
             || choice
                 .message
                 .tool_calls
@@ -115,7 +548,7 @@ impl Agent {
                 .map(|t| !t.is_empty())
                 .unwrap_or_default()
         {
-            self.context.push(ChatCompletionRequestMessage::Assistant(
+            self.append_context(ChatCompletionRequestMessage::Assistant(
                 ChatCompletionRequestAssistantMessageArgs::default()
                     .tool_calls(choice.message.tool_calls.clone().unwrap_or_default())
                     .build()?,
@@ -129,7 +562,7 @@ impl Agent {
                     .into_iter()
                     .map(|v| match v {
                         ChatCompletionMessageToolCalls::Custom(v) => {
-                            log::warn!(
+                            warn!(
                                 "Unexpected custom toolcall {:?}, covert to to function",
                                 &v
                             );
@@ -149,7 +582,7 @@ impl Agent {
         } else if matches!(choice.finish_reason, Some(FinishReason::ContentFilter))
             || choice.message.refusal.is_some()
         {
-            self.context.push(ChatCompletionRequestMessage::Assistant(
+            self.append_context(ChatCompletionRequestMessage::Assistant(
                 ChatCompletionRequestAssistantMessageArgs::default()
                     .refusal(choice.message.refusal.clone().unwrap_or_default())
                     .build()?,
@@ -160,39 +593,195 @@ impl Agent {
             || matches!(choice.finish_reason, Some(FinishReason::Length))
             || choice.message.content.is_some()
         {
-            self.context.push(ChatCompletionRequestMessage::Assistant(
+            let mut content = choice.message.content.unwrap_or_default();
+            if self.extract_thinking {
+                let (stripped, thinking) = split_thinking(&content);
+                content = stripped;
+                self.last_thinking = thinking;
+            }
+            self.append_context(ChatCompletionRequestMessage::Assistant(
                 ChatCompletionRequestAssistantMessageArgs::default()
-                    .content(choice.message.content.clone().unwrap_or_default())
+                    .content(content.clone())
                     .build()?,
             ));
             let finish_reason = choice.finish_reason.unwrap_or(FinishReason::Stop);
-            on_message(self, choice.message.content.unwrap_or_default(), finish_reason).await
+            on_message(self, content, finish_reason).await
         } else {
             Err(AgentyError::Other(eyre!(
                 "Not supported choice: {:?}",
                 &choice
             )))
+        };
+        self.apply_sliding_window();
+        action
+    }
+
+    /// Trim `context` to the last `sliding_window` messages, skipping pinned ones.
+    ///
+    /// Tool results left without their assistant tool call message are dropped as well
+    /// since the API rejects such orphans.
+    pub fn apply_sliding_window(&mut self) {
+        let Some(window) = self.sliding_window else {
+            return;
+        };
+        if self.context.len() > window {
+            // Drop the oldest messages that are not pinned until the rest fits.
+            let mut excess = self.context.len() - window;
+            let mut dropped = vec![false; self.context.len()];
+            for (i, pinned) in self.pinned_mask().into_iter().enumerate() {
+                if excess == 0 {
+                    break;
+                }
+                if !pinned {
+                    dropped[i] = true;
+                    excess -= 1;
+                }
+            }
+            self.retain_messages(dropped.into_iter().map(|dropped| !dropped).collect());
+            let answered = self
+                .context
+                .iter()
+                .flat_map(tool_call_ids)
+                .map(str::to_string)
+                .collect::<HashSet<_>>();
+            let keep = self
+                .context
+                .iter()
+                .map(|m| match m {
+                    ChatCompletionRequestMessage::Tool(msg) => {
+                        answered.contains(&msg.tool_call_id)
+                    }
+                    _ => true,
+                })
+                .collect();
+            self.retain_messages(keep);
         }
     }
 
+    /// Which messages of `context` are pinned, counting the tool results of a pinned
+    /// assistant message and the assistant message of a pinned tool result as pinned too,
+    /// since the API rejects either without the other.
+    fn pinned_mask(&self) -> Vec<bool> {
+        let pinned = (0..self.context.len())
+            .map(|i| self.is_pinned(i))
+            .collect::<Vec<_>>();
+        let mut calls = HashSet::new();
+        for (m, _) in self.context.iter().zip(&pinned).filter(|(_, p)| **p) {
+            match m {
+                ChatCompletionRequestMessage::Tool(msg) => {
+                    calls.insert(msg.tool_call_id.as_str());
+                }
+                m => calls.extend(tool_call_ids(m)),
+            }
+        }
+        // All calls of an assistant message need their results, not only the pinned one.
+        for m in &self.context {
+            let ids = tool_call_ids(m);
+            if ids.iter().any(|id| calls.contains(id)) {
+                calls.extend(ids);
+            }
+        }
+        self.context
+            .iter()
+            .zip(pinned)
+            .map(|(m, pinned)| {
+                pinned
+                    || match m {
+                        ChatCompletionRequestMessage::Tool(msg) => {
+                            calls.contains(msg.tool_call_id.as_str())
+                        }
+                        m => tool_call_ids(m).iter().any(|id| calls.contains(id)),
+                    }
+            })
+            .collect()
+    }
+
+    /// Make `pinned` as long as `context` again after it was changed directly.
+    fn sync_pins(&mut self) {
+        self.pinned.resize(self.context.len(), false);
+    }
+
+    /// Keep the messages of `context` whose entry in `keep` is true, along with their pins.
+    fn retain_messages(&mut self, keep: Vec<bool>) {
+        self.sync_pins();
+        let mut flags = keep.iter();
+        self.context.retain(|_| *flags.next().unwrap_or(&true));
+        let mut flags = keep.iter();
+        self.pinned.retain(|_| *flags.next().unwrap_or(&true));
+    }
+
+    /// Whether the message at `index` of `context` is pinned by [`Agent::pin_message`].
+    pub fn is_pinned(&self, index: usize) -> bool {
+        index < self.context.len() && self.pinned.get(index).copied().unwrap_or(false)
+    }
+
+    /// Keep the message at `index` of `context` when the context is shortened by the
+    /// sliding window or [`Agent::forget_before`], e.g. an instruction injected midway.
+    ///
+    /// Pins follow the message, not the index, so they survive messages being added or
+    /// removed before it.
+    pub fn pin_message(&mut self, index: usize) -> Result<(), AgentyError> {
+        if index >= self.context.len() {
+            return Err(AgentyError::ContextIndexOutOfBounds(
+                index,
+                self.context.len(),
+            ));
+        }
+        self.sync_pins();
+        self.pinned[index] = true;
+        Ok(())
+    }
+
+    /// Let the message at `index` of `context` be dropped again.
+    pub fn unpin_message(&mut self, index: usize) -> Result<(), AgentyError> {
+        if index >= self.context.len() {
+            return Err(AgentyError::ContextIndexOutOfBounds(
+                index,
+                self.context.len(),
+            ));
+        }
+        self.sync_pins();
+        self.pinned[index] = false;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(calls = toolcalls.len()))]
     pub async fn handle_toolcalls(
         &mut self,
         toolcalls: Vec<ChatCompletionMessageToolCall>,
     ) -> Result<Vec<(String, String, String)>, AgentyError> {
         let mut resps = vec![];
         for call in toolcalls {
-            match self
-                .tools
-                .invoke(call.function.name.clone(), call.function.arguments)
-                .await
-            {
+            let (name, arguments) = (call.function.name.clone(), call.function.arguments.clone());
+            let resp = match &self.cancellation {
+                Some(token) => {
+                    self.tools
+                        .invoke_cancellable(name, arguments, token.clone())
+                        .await
+                }
+                None => self.tools.invoke(name, arguments).await,
+            };
+            let result = match resp {
                 None => {
                     warn!("No such tool: {}, will try again", &call.function.name);
-                    return Err(AgentyError::NoSuchTool(call.function.name));
+                    AgentyError::NoSuchTool(call.function.name.clone()).into_user_message()
+                }
+                Some(Ok(v)) => v,
+                // Tell the model what was wrong so it can correct the call
+                Some(Err(e @ AgentyError::IncorrectToolCall(..))) => {
+                    warn!("Incorrect tool call: {}, will try again", e);
+                    e.into_user_message()
                 }
-                Some(Ok(v)) => resps.push((call.id.clone(), call.function.name.clone(), v)),
                 Some(Err(e)) => return Err(e),
+            };
+            if let Some(observer) = &self.tool_observer {
+                observer(ToolCallEvent {
+                    name: call.function.name.clone(),
+                    arguments: call.function.arguments,
+                    result: result.clone(),
+                });
             }
+            resps.push((call.id, call.function.name, result));
         }
         Ok(resps)
     }
@@ -205,24 +794,241 @@ impl Agent {
         Ok(())
     }
 
+    /// Append a user message made of `text` and the image at `url`, for vision models.
+    pub fn append_image_url(&mut self, text: String, url: String) -> Result<(), AgentyError> {
+        let parts = vec![
+            ChatCompletionRequestUserMessageContentPart::Text(
+                ChatCompletionRequestMessageContentPartTextArgs::default()
+                    .text(text)
+                    .build()?,
+            ),
+            ChatCompletionRequestUserMessageContentPart::ImageUrl(
+                ChatCompletionRequestMessageContentPartImageArgs::default()
+                    .image_url(ImageUrl {
+                        url,
+                        detail: Some(ImageDetail::Auto),
+                    })
+                    .build()?,
+            ),
+        ];
+        let user = ChatCompletionRequestUserMessageArgs::default()
+            .content(ChatCompletionRequestUserMessageContent::Array(parts))
+            .build()?;
+        self.append_context(ChatCompletionRequestMessage::User(user));
+        Ok(())
+    }
+
+    /// Like [`Agent::append_image_url`] with the image sent inline as a data URL.
+    pub fn append_image_base64(
+        &mut self,
+        text: String,
+        data: Vec<u8>,
+        mime_type: String,
+    ) -> Result<(), AgentyError> {
+        let url = format!("data:{};base64,{}", mime_type, BASE64_STANDARD.encode(data));
+        self.append_image_url(text, url)
+    }
+
     pub fn append_tool_results(&mut self, tool_results: Vec<(String, String, String)>) {
         for (tool_call_id, tool_name, result) in tool_results {
             let tool_msg = ChatCompletionRequestToolMessage {
                 content: ChatCompletionRequestToolMessageContent::Text(result),
                 tool_call_id: tool_call_id.clone(),
             };
-            self.context.push(ChatCompletionRequestMessage::Tool(tool_msg));
+            self.append_context(ChatCompletionRequestMessage::Tool(tool_msg));
             debug!("Added tool result for {}: {}", tool_name, tool_call_id);
         }
     }
 
     
     pub fn append_context(&mut self, ctx: ChatCompletionRequestMessage) {
+        self.sync_pins();
         self.context.push(ctx);
+        self.pinned.push(false);
+    }
+
+    /// Insert `ctx` before the conversation history, behind the system and user prompts.
+    pub fn prepend_context(&mut self, ctx: ChatCompletionRequestMessage) {
+        self.sync_pins();
+        self.context.insert(0, ctx);
+        self.pinned.insert(0, false);
+    }
+
+    /// Insert `ctx` at `index` of `context`, shifting the later messages back.
+    pub fn insert_context(
+        &mut self,
+        index: usize,
+        ctx: ChatCompletionRequestMessage,
+    ) -> Result<(), AgentyError> {
+        if index > self.context.len() {
+            return Err(AgentyError::ContextIndexOutOfBounds(
+                index,
+                self.context.len(),
+            ));
+        }
+        self.sync_pins();
+        self.context.insert(index, ctx);
+        self.pinned.insert(index, false);
+        Ok(())
+    }
+
+    /// A rough size of the request about to be sent: the characters of the system and user
+    /// prompts plus all strings of the `context` messages, like contents and tool arguments.
+    pub fn estimate_full_context_chars(&self) -> usize {
+        self.system_prompt().chars().count()
+            + self.user.chars().count()
+            + self
+                .context
+                .iter()
+                .filter_map(|m| serde_json::to_value(m).ok())
+                .map(|v| string_chars(&v))
+                .sum::<usize>()
+    }
+
+    /// The size of the messages about to be sent as JSON, more accurate than
+    /// [`Agent::estimate_full_context_chars`] but serializes the whole context.
+    pub fn estimate_full_context_json_bytes(&self) -> usize {
+        serde_json::to_vec(&self.full_context())
+            .map(|v| v.len())
+            .unwrap_or_default()
+    }
+
+    /// Forget the conversation so far, keeping the prompts, tools and settings.
+    pub fn reset_context(&mut self) {
+        self.context.clear();
+        self.pinned.clear();
+        self.last_thinking = None;
+        self.turns = 0;
     }
 
     pub fn revert_context(&mut self) {
+        self.sync_pins();
         self.context.pop();
+        self.pinned.pop();
+    }
+
+    /// Drop the first `n` messages of `context`, except pinned ones.
+    ///
+    /// This is irreversible, clone the agent beforehand to keep a checkpoint.
+    pub fn forget_before(&mut self, n: usize) {
+        let keep = self
+            .pinned_mask()
+            .into_iter()
+            .enumerate()
+            .map(|(i, pinned)| pinned || i >= n)
+            .collect();
+        self.retain_messages(keep);
+    }
+
+    /// Drop all messages before the first one containing `keyword`, returning how many
+    /// were dropped. Nothing is dropped if no message contains it.
+    ///
+    /// This is irreversible, clone the agent beforehand to keep a checkpoint.
+    pub fn forget_until_keyword(&mut self, keyword: &str) -> usize {
+        let Some(n) = self
+            .context
+            .iter()
+            .position(|m| message_text(m).contains(keyword))
+        else {
+            return 0;
+        };
+        let before = self.context.len();
+        self.forget_before(n);
+        before - self.context.len()
+    }
+
+    /// Whether any message of `context` contains `keyword`, e.g. to check a file was
+    /// already read before reading it again.
+    pub fn context_contains(&self, keyword: &str) -> bool {
+        self.context
+            .iter()
+            .any(|m| message_text(m).contains(keyword))
+    }
+
+    /// The indices of the `context` messages matching the regex `pattern`.
+    pub fn context_matches(&self, pattern: &str) -> Result<Vec<usize>, AgentyError> {
+        let regex = Regex::new(pattern)?;
+        Ok(self
+            .context
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| regex.is_match(&message_text(m)))
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    /// The most recent result of a call to the tool `tool_name` still in `context`.
+    pub fn find_last_tool_result(&self, tool_name: &str) -> Option<&str> {
+        // Tool messages only carry the call id, the name is on the assistant's call.
+        let call_name = |id: &str| {
+            self.context.iter().rev().find_map(|m| match m {
+                ChatCompletionRequestMessage::Assistant(msg) => {
+                    msg.tool_calls.iter().flatten().find_map(|call| match call {
+                        ChatCompletionMessageToolCalls::Function(call) if call.id == id => {
+                            Some(call.function.name.as_str())
+                        }
+                        _ => None,
+                    })
+                }
+                _ => None,
+            })
+        };
+        self.context.iter().rev().find_map(|m| match m {
+            ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
+                content: ChatCompletionRequestToolMessageContent::Text(text),
+                tool_call_id,
+            }) if call_name(tool_call_id) == Some(tool_name) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The text of the last assistant message with any, e.g. the answer that ended
+    /// [`Agent::run_until_text`].
+    pub fn get_last_assistant_message(&self) -> Option<&str> {
+        self.context.iter().rev().find_map(|m| match m {
+            ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+                content: Some(content),
+                ..
+            }) => match content {
+                ChatCompletionRequestAssistantMessageContent::Text(text) => Some(text.as_str()),
+                ChatCompletionRequestAssistantMessageContent::Array(parts) => {
+                    parts.iter().find_map(|part| match part {
+                        ChatCompletionRequestAssistantMessageContentPart::Text(part) => {
+                            Some(part.text.as_str())
+                        }
+                        _ => None,
+                    })
+                }
+            }
+            .filter(|text| !text.is_empty()),
+            _ => None,
+        })
+    }
+
+    /// The names of the tools called by the last assistant message with tool calls.
+    pub fn get_last_tool_call_names(&self) -> Vec<String> {
+        self.context
+            .iter()
+            .rev()
+            .find_map(|m| match m {
+                ChatCompletionRequestMessage::Assistant(msg) => {
+                    msg.tool_calls.as_ref().filter(|calls| !calls.is_empty())
+                }
+                _ => None,
+            })
+            .into_iter()
+            .flatten()
+            .map(|call| match call {
+                ChatCompletionMessageToolCalls::Function(call) => call.function.name.clone(),
+                ChatCompletionMessageToolCalls::Custom(call) => call.custom_tool.name.clone(),
+            })
+            .collect()
+    }
+
+    /// How often [`Agent::run_once`] was called since the agent was created or its context
+    /// reset, see `turns`.
+    pub fn turn_count(&self) -> u32 {
+        self.turns
     }
 
     pub async fn run_until_tool<T: Tool>(
@@ -231,28 +1037,40 @@ impl Agent {
         prefix: Option<&str>,
         settings: Option<LLMSettings>,
     ) -> Result<T::ARGUMENTS, AgentyError> {
+        self.run_until_tool_with_others::<T>(llm, prefix, settings)
+            .await
+            .map(|(arguments, _)| arguments)
+    }
+
+    /// Like [`Agent::run_until_tool`] but also returns the other tool calls of the turn
+    /// calling `T`, which are not invoked.
+    ///
+    /// The calls are in the context already as part of the assistant message, the caller
+    /// invoking them should append their results with [`Agent::append_tool_results`].
+    pub async fn run_until_tool_with_others<T: Tool>(
+        &mut self,
+        llm: &mut LLM,
+        prefix: Option<&str>,
+        settings: Option<LLMSettings>,
+    ) -> Result<(T::ARGUMENTS, Vec<ChatCompletionMessageToolCall>), AgentyError> {
+        let started = Instant::now();
+        let mut turn = 0;
         loop {
+            self.check_limits(turn, started)?;
+            turn += 1;
             let action = self
                 .run_once(
                     llm,
                     prefix,
                     settings.clone(),
                     async |ctx, toolcalls| {
-                        if let Some(call) = toolcalls.iter().find(|t| t.function.name == T::NAME) {
+                        if let Some(idx) = toolcalls.iter().position(|t| t.function.name == T::NAME) {
+                            let mut others = toolcalls;
+                            let call = others.remove(idx);
                             let td: T::ARGUMENTS = serde_json::from_str(&call.function.arguments)?;
-                            Ok(AgentAction::Out(td))
+                            Ok(AgentAction::Out((td, others)))
                         } else {
-                            let tool_results = match ctx.handle_toolcalls(toolcalls).await {
-                                Ok(v) => v,
-                                Err(e) => match &e {
-                                    AgentyError::NoSuchTool(_)
-                                    | AgentyError::IncorrectToolCall(_, _) => {
-                                        warn!("Error {} during tool call, retry...", e);
-                                        return Ok(AgentAction::Continue);
-                                    }
-                                    _ => return Err(e),
-                                },
-                            };
+                            let tool_results = ctx.handle_toolcalls(toolcalls).await?;
                             // Use correct tool response format
                             ctx.append_tool_results(
                                 tool_results.into_iter()
@@ -264,6 +1082,51 @@ impl Agent {
                     async |_, msg, _| Ok(AgentAction::Unexpected(msg)),
                     async |_, msg, _| Ok(AgentAction::Unexpected(msg)),
                 )
+                .instrument(info_span!("agent_turn", turn = %turn))
+                .await?;
+
+            match action {
+                AgentAction::Continue => continue,
+                AgentAction::Unexpected(s) => return Err(AgentyError::Unexpected(s)),
+                AgentAction::Out(s) => return Ok(s),
+            }
+        }
+    }
+
+    /// Like [`Agent::run_until_tool`] but a plain text answer also ends the loop.
+    ///
+    /// Returns `Either::Left` with the arguments when the model calls `T`, or
+    /// `Either::Right` with the message content when it answers with text.
+    pub async fn run_until_tool_or_text<T: Tool>(
+        &mut self,
+        llm: &mut LLM,
+        prefix: Option<&str>,
+        settings: Option<LLMSettings>,
+    ) -> Result<Either<T::ARGUMENTS, String>, AgentyError> {
+        let started = Instant::now();
+        let mut turn = 0;
+        loop {
+            self.check_limits(turn, started)?;
+            turn += 1;
+            let action = self
+                .run_once(
+                    llm,
+                    prefix,
+                    settings.clone(),
+                    async |ctx, toolcalls| {
+                        if let Some(call) = toolcalls.iter().find(|t| t.function.name == T::NAME) {
+                            let td: T::ARGUMENTS = serde_json::from_str(&call.function.arguments)?;
+                            Ok(AgentAction::Out(Either::Left(td)))
+                        } else {
+                            let tool_results = ctx.handle_toolcalls(toolcalls).await?;
+                            ctx.append_tool_results(tool_results);
+                            Ok(AgentAction::Continue)
+                        }
+                    },
+                    async |_, msg, _| Ok(AgentAction::Out(Either::Right(msg))),
+                    async |_, msg, _| Ok(AgentAction::Unexpected(msg)),
+                )
+                .instrument(info_span!("agent_turn", turn = %turn))
                 .await?;
 
             match action {
@@ -280,24 +1143,18 @@ impl Agent {
         prefix: Option<&str>,
         settings: Option<LLMSettings>,
     ) -> Result<String, AgentyError> {
+        let started = Instant::now();
+        let mut turn = 0;
         loop {
+            self.check_limits(turn, started)?;
+            turn += 1;
             let action = self
                 .run_once(
                     llm,
                     prefix,
                     settings.clone(),
                     async |ctx, toolcalls| {
-                        let tool_results = match ctx.handle_toolcalls(toolcalls).await {
-                            Ok(v) => v,
-                            Err(e) => match &e {
-                                AgentyError::NoSuchTool(_)
-                                | AgentyError::IncorrectToolCall(_, _) => {
-                                    warn!("Error {} during tool call, retry...", e);
-                                    return Ok(AgentAction::Continue);
-                                }
-                                _ => return Err(e),
-                            },
-                        };
+                        let tool_results = ctx.handle_toolcalls(toolcalls).await?;
                         // Use correct tool response format
                         ctx.append_tool_results(tool_results);
                         Ok(AgentAction::Continue)
@@ -305,7 +1162,23 @@ impl Agent {
                     async |_, msg, _| Ok(AgentAction::Out(msg)),
                     async |_, msg, _| Ok(AgentAction::Unexpected(msg)),
                 )
-                .await?;
+                .instrument(info_span!("agent_turn", turn = %turn))
+                .await;
+            let action = match action {
+                Ok(action) => action,
+                // Recovering from a limit would defeat its purpose
+                Err(e @ AgentyError::MaxTurnsExceeded(_))
+                | Err(e @ AgentyError::TokenBudgetExceeded(_))
+                | Err(e @ AgentyError::TaskTimeout(_))
+                | Err(e @ AgentyError::Cancelled) => return Err(e),
+                Err(e) => match self.error_handler.as_ref().and_then(|h| h(&e)) {
+                    Some(action) => {
+                        warn!("Recovered from error {}", e);
+                        action
+                    }
+                    None => return Err(e),
+                },
+            };
             debug!("Agent action: {:?}", &action);
             match action {
                 AgentAction::Continue => continue,
@@ -314,4 +1187,587 @@ impl Agent {
             }
         }
     }
+
+    /// Like [`Agent::run_until_text`] but `observer` is called with every tool call once it
+    /// completed, e.g. to show the progress of the task.
+    ///
+    /// The observer replaces `tool_observer` for the duration of the call and should
+    /// return quickly since the loop waits for it.
+    pub async fn run_until_text_with_observer(
+        &mut self,
+        llm: &mut LLM,
+        prefix: Option<&str>,
+        settings: Option<LLMSettings>,
+        observer: impl Fn(ToolCallEvent) + Send + Sync + 'static,
+    ) -> Result<String, AgentyError> {
+        let previous = self.tool_observer.replace(Arc::new(observer));
+        let resp = self.run_until_text(llm, prefix, settings).await;
+        self.tool_observer = previous;
+        resp
+    }
+
+    /// Run each subtask as a new user message followed by [`Agent::run_until_text`].
+    ///
+    /// The subtasks are chained: each one sees the original context plus all previous
+    /// subtasks and their results. Returns the per-subtask outputs.
+    pub async fn run_decomposed(
+        &mut self,
+        llm: &mut LLM,
+        subtasks: Vec<String>,
+        settings: Option<LLMSettings>,
+    ) -> Result<Vec<String>, AgentyError> {
+        let mut results = vec![];
+        for subtask in subtasks {
+            self.append_user(subtask)?;
+            results.push(self.run_until_text(llm, None, settings.clone()).await?);
+        }
+        Ok(results)
+    }
+
+    /// Like [`Agent::run_decomposed`] but the subtasks run concurrently and independently,
+    /// each on a copy of the current context. The context of `self` is left untouched.
+    pub async fn run_decomposed_parallel(
+        &self,
+        llm: &LLM,
+        subtasks: Vec<String>,
+        settings: Option<LLMSettings>,
+    ) -> Result<Vec<String>, AgentyError> {
+        futures::future::try_join_all(subtasks.into_iter().map(|subtask| {
+            let mut agent = self.clone();
+            let mut llm = llm.clone();
+            let settings = settings.clone();
+            async move {
+                agent.append_user(subtask)?;
+                agent.run_until_text(&mut llm, None, settings).await
+            }
+        }))
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::{
+            Request, capture_logs, completion, mock_llm, scripted_llm, serve, text, tool_calls,
+        },
+        tools::{
+            testing::{DelayTool, EchoTool},
+            todo::TodoTool,
+        },
+    };
+
+    fn texts(agent: &Agent) -> Vec<String> {
+        agent.context.iter().map(message_text).collect()
+    }
+
+    /// The JSON bodies of the requests a [`scripted_llm`] received.
+    fn bodies(requests: &Mutex<Vec<Request>>) -> Vec<serde_json::Value> {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| serde_json::from_str(&r.body).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_pinned_message_survives_sliding_window() {
+        let mut agent = AgentBuilder::new()
+            .user("task".to_string())
+            .sliding_window(3)
+            .build();
+        agent.append_user("rule: never push".to_string()).unwrap();
+        agent.pin_message(0).unwrap();
+        for i in 0..10 {
+            agent.append_user(format!("step {}", i)).unwrap();
+            agent.apply_sliding_window();
+        }
+        assert_eq!(texts(&agent), ["rule: never push", "step 8", "step 9"]);
+        assert!(agent.is_pinned(0));
+        assert!(!agent.is_pinned(1));
+    }
+
+    #[test]
+    fn test_identical_messages_are_pinned_separately() {
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        for _ in 0..4 {
+            agent.append_user("continue".to_string()).unwrap();
+        }
+        agent.pin_message(1).unwrap();
+        agent.pin_message(3).unwrap();
+        agent.unpin_message(3).unwrap();
+        assert_eq!(
+            (0..4).map(|i| agent.is_pinned(i)).collect::<Vec<_>>(),
+            [false, true, false, false]
+        );
+
+        // The pin follows the message when others are added or removed before it.
+        agent
+            .insert_context(
+                0,
+                ChatCompletionRequestMessage::User("first".to_string().into()),
+            )
+            .unwrap();
+        assert!(agent.is_pinned(2));
+        agent.forget_before(4);
+        assert_eq!(texts(&agent), ["continue", "continue"]);
+        assert!(agent.is_pinned(0));
+        assert!(!agent.is_pinned(1));
+        assert!(agent.pin_message(2).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_accessors_after_run() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = serve(move |_| {
+            let message = match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => json!({
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [
+                        {
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "todo", "arguments": r#"{"op": "add", "titles": ["read"]}"#},
+                        },
+                        {
+                            "id": "call_2",
+                            "type": "function",
+                            "function": {"name": "todo", "arguments": r#"{"op": "show"}"#},
+                        },
+                    ],
+                }),
+                _ => json!({"role": "assistant", "content": "The plan is ready"}),
+            };
+            completion(message)
+        })
+        .await;
+        let mut llm = mock_llm(&url);
+        let mut tools = ToolBox::new();
+        tools.add_tool(TodoTool::new()).unwrap();
+        let mut agent = AgentBuilder::new()
+            .tools(tools)
+            .user("Make a plan".to_string())
+            .build();
+        assert_eq!(agent.get_last_assistant_message(), None);
+        assert!(agent.get_last_tool_call_names().is_empty());
+        assert_eq!(agent.turn_count(), 0);
+
+        let out = agent.run_until_text(&mut llm, None, None).await.unwrap();
+        assert_eq!(out, "The plan is ready");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(agent.get_last_assistant_message(), Some(out.as_str()));
+        assert_eq!(agent.get_last_tool_call_names(), ["todo", "todo"]);
+        assert_eq!(agent.turn_count(), 2);
+
+        agent.reset_context();
+        assert_eq!(agent.turn_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sliding_window_bounds_context() {
+        let turns = (0..3)
+            .map(|i| tool_calls(&[("echo", json!({"message": format!("step {}", i)}))]))
+            .collect();
+        let (mut llm, _) = scripted_llm(turns).await;
+        let mut agent = AgentBuilder::new()
+            .user("task".to_string())
+            .sliding_window(3)
+            .build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        for _ in 0..3 {
+            agent
+                .run_once(
+                    &mut llm,
+                    None,
+                    None,
+                    async |ctx, calls| {
+                        let results = ctx.handle_toolcalls(calls).await?;
+                        ctx.append_tool_results(results);
+                        Ok(AgentAction::<()>::Continue)
+                    },
+                    async |_, _, _| Ok(AgentAction::Continue),
+                    async |_, _, _| Ok(AgentAction::Continue),
+                )
+                .await
+                .unwrap();
+            assert!(agent.context.len() <= 3, "{}", agent.context.len());
+        }
+        assert_eq!(texts(&agent).last().unwrap(), "step 2");
+    }
+
+    #[tokio::test]
+    async fn test_run_until_tool_or_text() {
+        let (mut llm, _) =
+            scripted_llm(vec![tool_calls(&[("echo", json!({"message": "hi"}))])]).await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        let out = agent
+            .run_until_tool_or_text::<EchoTool>(&mut llm, None, None)
+            .await
+            .unwrap();
+        assert!(matches!(out, Either::Left(args) if args.message == "hi"));
+
+        let (mut llm, _) = scripted_llm(vec![text("No tool needed")]).await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        let out = agent
+            .run_until_tool_or_text::<EchoTool>(&mut llm, None, None)
+            .await
+            .unwrap();
+        assert!(matches!(out, Either::Right(text) if text == "No tool needed"));
+    }
+
+    #[tokio::test]
+    async fn test_thinking_is_kept_out_of_the_context() {
+        let answer = "<thinking>The user wants a number.</thinking>\n42";
+        let (mut llm, _) = scripted_llm(vec![text(answer)]).await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.set_extract_thinking(true);
+        let out = agent.run_until_text(&mut llm, None, None).await.unwrap();
+        assert_eq!(out, "42");
+        assert_eq!(texts(&agent), ["42"]);
+        assert_eq!(
+            agent.last_thinking.as_deref(),
+            Some("The user wants a number.")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debug_mode_logs_every_turn() {
+        let (mut llm, _) = scripted_llm(vec![
+            tool_calls(&[("echo", json!({"message": "hi"}))]),
+            text("Done"),
+        ])
+        .await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        agent.set_debug_mode(true);
+        agent.run_until_text(&mut llm, None, None).await.unwrap();
+        assert_eq!(agent.debug_log.len(), 2);
+        assert_eq!(agent.debug_log[0].turn_index, 0);
+        assert_eq!(agent.debug_log[1].turn_index, 1);
+        assert!(agent.debug_log[0].response_json.contains("call_0"));
+        assert!(agent.debug_log[1].request_json.contains("call_0"));
+        assert!(agent.debug_log[1].response_json.contains("Done"));
+    }
+
+    #[tokio::test]
+    async fn test_decomposed_subtasks_see_earlier_results() {
+        let (mut llm, requests) = scripted_llm(vec![text("result one"), text("result two")]).await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        let results = agent
+            .run_decomposed(
+                &mut llm,
+                vec!["subtask one".to_string(), "subtask two".to_string()],
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(results, ["result one", "result two"]);
+
+        let messages = bodies(&requests)
+            .iter()
+            .map(|body| {
+                body["messages"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|m| m["content"].as_str().unwrap_or_default().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(messages[0][2..], ["subtask one"]);
+        assert_eq!(
+            messages[1][2..],
+            ["subtask one", "result one", "subtask two"]
+        );
+    }
+
+    #[test]
+    fn test_forget_before_shortens_the_context() {
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        for i in 0..5 {
+            agent.append_user(format!("step {}", i)).unwrap();
+        }
+        agent.forget_before(0);
+        assert_eq!(agent.context.len(), 5);
+        agent.forget_before(3);
+        assert_eq!(agent.context.len(), 2);
+        assert_eq!(texts(&agent), ["step 3", "step 4"]);
+        assert_eq!(agent.forget_until_keyword("step 4"), 1);
+        assert_eq!(agent.forget_until_keyword("missing"), 0);
+        assert_eq!(agent.context.len(), 1);
+        agent.forget_before(10);
+        assert!(agent.context.is_empty());
+    }
+
+    #[test]
+    fn test_builder_sets_every_value() {
+        let mut tools = ToolBox::new();
+        tools.add_tool(EchoTool::new()).unwrap();
+        let agent = AgentBuilder::new()
+            .tools(tools)
+            .system("Be brief.".to_string())
+            .user("task".to_string())
+            .max_turns(7)
+            .token_budget(1000)
+            .task_timeout(Duration::from_secs(30))
+            .sliding_window(20)
+            .reasoning_effort(ReasoningEffort::High)
+            .build();
+        assert!(agent.tools.get_tool::<EchoTool>("echo").is_some());
+        assert_eq!(agent.system, "Be brief.");
+        assert_eq!(agent.user, "task");
+        assert_eq!(agent.max_turns, Some(7));
+        assert_eq!(agent.token_budget, Some(1000));
+        assert_eq!(agent.task_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(agent.sliding_window, Some(20));
+        assert_eq!(agent.reasoning_effort, Some(ReasoningEffort::High));
+
+        let agent = AgentBuilder::new().build();
+        assert!(!agent.system.is_empty());
+        assert!(agent.tools.is_empty());
+        assert_eq!(agent.max_turns, None);
+        assert_eq!(agent.token_budget, None);
+        assert_eq!(agent.task_timeout, None);
+        assert_eq!(agent.sliding_window, None);
+        assert_eq!(agent.reasoning_effort, None);
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_every_tool_call() {
+        let (mut llm, _) = scripted_llm(vec![
+            tool_calls(&[
+                ("echo", json!({"message": "a"})),
+                ("echo", json!({"message": "b"})),
+            ]),
+            tool_calls(&[("missing", json!({}))]),
+            text("Done"),
+        ])
+        .await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        let seen = events.clone();
+        agent
+            .run_until_text_with_observer(&mut llm, None, None, move |event| {
+                seen.lock().unwrap().push(event)
+            })
+            .await
+            .unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.name.as_str(), e.result.as_str()))
+                .take(2)
+                .collect::<Vec<_>>(),
+            [("echo", "a"), ("echo", "b")]
+        );
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].name, "missing");
+        assert!(agent.tool_observer.is_none());
+    }
+
+    #[test]
+    fn test_image_messages() {
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent
+            .append_image_url(
+                "What is this?".to_string(),
+                "https://example.com/cat.png".to_string(),
+            )
+            .unwrap();
+        agent
+            .append_image_base64(
+                "And this?".to_string(),
+                b"png".to_vec(),
+                "image/png".to_string(),
+            )
+            .unwrap();
+        let json = serde_json::to_value(&agent.context).unwrap();
+        assert_eq!(
+            json[0],
+            json!({
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {
+                        "type": "image_url",
+                        "image_url": {"url": "https://example.com/cat.png", "detail": "auto"},
+                    },
+                ],
+            })
+        );
+        assert_eq!(json[1]["content"][1]["type"], "image_url");
+        assert_eq!(
+            json[1]["content"][1]["image_url"]["url"],
+            "data:image/png;base64,cG5n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_turns_are_traced() {
+        let (logs, _guard) = capture_logs();
+        let (mut llm, _) = scripted_llm(vec![
+            tool_calls(&[("echo", json!({"message": "hi"}))]),
+            text("Done"),
+        ])
+        .await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        agent.run_until_text(&mut llm, None, None).await.unwrap();
+        let logs = logs.lock().unwrap();
+        let logs = String::from_utf8_lossy(&logs);
+        for expected in [
+            "agent_turn{turn=1}: agenty::agent: new",
+            "agent_turn{turn=1}:handle_toolcalls{calls=1}: agenty::agent: new",
+            "agent_turn{turn=1}:handle_toolcalls{calls=1}:tool_invoke{tool_name=echo",
+            "agent_turn{turn=2}: agenty::agent: new",
+        ] {
+            assert!(logs.contains(expected), "{}", logs);
+        }
+    }
+
+    #[test]
+    fn test_context_estimates_grow() {
+        let mut agent = AgentBuilder::new()
+            .system("system".to_string())
+            .user("task".to_string())
+            .build();
+        let chars = agent.estimate_full_context_chars();
+        let bytes = agent.estimate_full_context_json_bytes();
+        assert_eq!(chars, "system".len() + "task".len());
+        agent.append_user("a new message".to_string()).unwrap();
+        agent.append_tool_results(vec![(
+            "call_0".to_string(),
+            "echo".to_string(),
+            "result".to_string(),
+        )]);
+        // The roles and the call id are strings of the messages as well.
+        let added = ["user", "a new message", "tool", "result", "call_0"];
+        assert_eq!(
+            agent.estimate_full_context_chars(),
+            chars + added.iter().map(|s| s.len()).sum::<usize>()
+        );
+        assert!(agent.estimate_full_context_json_bytes() > bytes);
+    }
+
+    #[tokio::test]
+    async fn test_search_a_mocked_conversation() {
+        let (mut llm, _) = scripted_llm(vec![
+            tool_calls(&[("echo", json!({"message": "config.toml: port = 8080"}))]),
+            tool_calls(&[("echo", json!({"message": "main.rs: fn main()"}))]),
+            text("The port is 8080"),
+        ])
+        .await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        agent.run_until_text(&mut llm, None, None).await.unwrap();
+
+        assert!(agent.context_contains("port = 8080"));
+        assert!(agent.context_contains("fn main()"));
+        assert!(!agent.context_contains("port = 9090"));
+        // The assistant messages, the tool results and the answer.
+        assert_eq!(agent.context_matches(r"\.(toml|rs):").unwrap(), [1, 3]);
+        assert_eq!(agent.context_matches("8080").unwrap(), [1, 4]);
+        assert!(agent.context_matches("(").is_err());
+        assert_eq!(
+            agent.find_last_tool_result("echo"),
+            Some("main.rs: fn main()")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_until_tool_with_others() {
+        // `delay` stands in for a read_file call made in the same turn as the final `echo`.
+        let (mut llm, requests) = scripted_llm(vec![tool_calls(&[
+            ("delay", json!({"message": "src/main.rs"})),
+            ("echo", json!({"message": "done"})),
+        ])])
+        .await;
+        let mut agent = AgentBuilder::new().user("task".to_string()).build();
+        agent.add_tool(EchoTool::new()).unwrap();
+        agent.add_tool(DelayTool::new(0)).unwrap();
+
+        let (args, others) = agent
+            .run_until_tool_with_others::<EchoTool>(&mut llm, None, None)
+            .await
+            .unwrap();
+        assert_eq!(args.message, "done");
+        assert_eq!(others.len(), 1);
+        assert_eq!(others[0].function.name, "delay");
+        assert_eq!(others[0].id, "call_0");
+        // Nothing was invoked, the calls are only in the assistant message.
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        let ChatCompletionRequestMessage::Assistant(assistant) = &agent.context[0] else {
+            panic!("expected the assistant message");
+        };
+        assert_eq!(assistant.tool_calls.as_ref().map(Vec::len), Some(2));
+        assert_eq!(agent.context.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_effort_is_sent() {
+        let (mut llm, requests) = scripted_llm(vec![text("a"), text("b")]).await;
+        let mut agent = AgentBuilder::new()
+            .user("task".to_string())
+            .reasoning_effort(ReasoningEffort::Low)
+            .build();
+        agent.run_until_text(&mut llm, None, None).await.unwrap();
+        agent.set_reasoning_effort(None);
+        agent.run_until_text(&mut llm, None, None).await.unwrap();
+
+        let bodies = bodies(&requests);
+        assert_eq!(bodies[0]["reasoning_effort"], "low");
+        assert!(bodies[0].get("temperature").is_none());
+        assert!(bodies[0].get("presence_penalty").is_none());
+        assert!(bodies[1].get("reasoning_effort").is_none());
+        assert!(bodies[1].get("temperature").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_added_tools_are_callable() {
+        let (mut llm, requests) = scripted_llm(vec![
+            tool_calls(&[
+                ("echo", json!({"message": "from echo"})),
+                ("delay", json!({"message": "from delay"})),
+            ]),
+            text("done"),
+        ])
+        .await;
+        let mut agent = AgentBuilder::new()
+            .user("task".to_string())
+            .build()
+            .with_tools([Box::new(DelayTool::new(0)) as Box<dyn ToolDyn>])
+            .unwrap();
+        agent.add_tool(EchoTool::new()).unwrap();
+
+        let answer = agent.run_until_text(&mut llm, None, None).await.unwrap();
+        assert_eq!(answer, "done");
+        assert_eq!(agent.find_last_tool_result("echo"), Some("from echo"));
+        assert_eq!(agent.find_last_tool_result("delay"), Some("from delay"));
+        let bodies = bodies(&requests);
+        assert_eq!(bodies[0]["tools"].as_array().unwrap().len(), 2);
+
+        assert!(matches!(
+            agent.add_tool(EchoTool::new()),
+            Err(AgentyError::DuplicateTool(name)) if name == "echo"
+        ));
+        // Nothing is registered when one of the names is taken.
+        let tools: Vec<Box<dyn ToolDyn>> = vec![Box::new(TodoTool::new()), Box::new(EchoTool)];
+        assert!(agent.add_tools(tools).is_err());
+        assert_eq!(agent.tools.len(), 2);
+    }
 }
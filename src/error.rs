@@ -1,3 +1,5 @@
+use itertools::Itertools;
+use serde_json::Value;
 use thiserror::Error;
 
 macro_rules! trivial {
@@ -18,10 +20,19 @@ macro_rules! trivial_other {
 
 #[derive(Error, Debug)]
 pub enum AgentyError {
-    #[error("incorrect tool call, schema: {0:?}, args: {1}")]
-    IncorrectToolCall(schemars::Schema, String),
+    /// The schema of the tool's arguments, the arguments the model sent and why they did not
+    /// deserialize.
+    #[error(
+        "incorrect tool call to {title}, required fields: [{required}]: {error}",
+        title = schema_title(.0),
+        required = schema_required(.0).join(", "),
+        error = short_error(.2)
+    )]
+    IncorrectToolCall(schemars::Schema, String, serde_json::Error),
     #[error("No such tool")]
     NoSuchTool(String),
+    #[error("tool {0} is already registered")]
+    DuplicateTool(String),
     #[error("unexpected llm response: {0}")]
     Unexpected(String),
     #[error("json error: {0}")]
@@ -32,10 +43,21 @@ pub enum AgentyError {
     Reqwest(#[from] reqwest::Error),
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
+    #[cfg(feature = "browser")]
     #[error("driver: {0}")]
     WebDriver(#[from] thirtyfour::error::WebDriverError),
     #[error("glob: {0}")]
     Glob(#[from] glob::PatternError),
+    #[error("gave up after {0} turns")]
+    MaxTurnsExceeded(u32),
+    #[error("token budget of {0} tokens exhausted")]
+    TokenBudgetExceeded(u32),
+    #[error("task did not finish within {0:?}")]
+    TaskTimeout(std::time::Duration),
+    #[error("cancelled")]
+    Cancelled,
+    #[error("context index {0} out of bounds, the context has {1} messages")]
+    ContextIndexOutOfBounds(usize, usize),
     #[error("smtlib parse error: {0}")]
     SMTPARSE(String),
     #[error("z3 expression error: {0}")]
@@ -44,6 +66,61 @@ pub enum AgentyError {
     Other(color_eyre::Report),
 }
 
+fn schema_title(schema: &schemars::Schema) -> &str {
+    schema
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown tool")
+}
+
+fn schema_required(schema: &schemars::Schema) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// The serde message names the offending field and position, but may quote long arguments
+/// back.
+fn short_error(error: &serde_json::Error) -> String {
+    error.to_string().chars().take(100).collect()
+}
+
+impl AgentyError {
+    /// A short description of the error that can be handed back to the model.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn into_user_message(&self) -> String {
+        match self {
+            Self::IncorrectToolCall(schema, args, error) => {
+                let title = schema_title(schema);
+                let problem = match serde_json::from_str::<Value>(args) {
+                    Ok(Value::Object(obj)) => {
+                        let missing = schema_required(schema)
+                            .into_iter()
+                            .filter(|f| !obj.contains_key(*f))
+                            .map(|f| format!("'{}'", f))
+                            .collect_vec();
+                        if missing.is_empty() {
+                            format!("arguments do not match the schema of {}", title)
+                        } else {
+                            format!("missing field {} in {}", missing.join(", "), title)
+                        }
+                    }
+                    Ok(_) => format!("arguments of {} must be a JSON object", title),
+                    Err(_) => format!("arguments of {} are not valid JSON", title),
+                };
+                format!("Tool call failed: {} ({})", problem, short_error(error))
+                    .chars()
+                    .take(200)
+                    .collect()
+            }
+            Self::NoSuchTool(name) => format!("Tool call failed: there is no tool named {}", name),
+            _ => format!("Tool call failed: {}", self).chars().take(200).collect(),
+        }
+    }
+}
+
 trivial!(
     openai_models::openai::error::OpenAIError,
     AgentyError::Prompt
@@ -51,3 +128,5 @@ trivial!(
 trivial_other!(color_eyre::Report);
 trivial_other!(walkdir::Error);
 trivial_other!(tokio::task::JoinError);
+trivial_other!(ignore::Error);
+trivial_other!(regex::Error);
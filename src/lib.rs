@@ -1,4 +1,10 @@
 pub mod agent;
 pub mod error;
+pub mod memory;
+pub mod pool;
+pub mod sandbox;
+#[cfg(test)]
+mod test_util;
+pub mod tokens;
 pub mod tool;
 pub mod tools;
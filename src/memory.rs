@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::Mutex,
+};
+
+use crate::{error::AgentyError, tools::file::atomic_write};
+
+/// Key-value storage the agent can use to remember things across sessions.
+pub trait MemoryStore: Debug + Send + Sync {
+    fn save<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentyError>> + Send + 'a>>;
+
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>, AgentyError>> + Send + 'a>>;
+}
+
+/// Stores every key as a file under `dir`, surviving process restarts.
+#[derive(Debug, Clone)]
+pub struct FileMemoryStore {
+    pub dir: PathBuf,
+}
+
+impl FileMemoryStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Keys are arbitrary strings, so escape everything that is not safe in a file name.
+    fn key_path(&self, key: &str) -> PathBuf {
+        let mut name = String::new();
+        for b in key.bytes() {
+            if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+                name.push(b as char);
+            } else {
+                name.push_str(&format!("%{:02X}", b));
+            }
+        }
+        self.dir.join(format!("{}.txt", name))
+    }
+}
+
+impl MemoryStore for FileMemoryStore {
+    fn save<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentyError>> + Send + 'a>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            atomic_write(&self.key_path(key), value).await?;
+            Ok(())
+        })
+    }
+
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>, AgentyError>> + Send + 'a>> {
+        Box::pin(async move {
+            match tokio::fs::read_to_string(self.key_path(key)).await {
+                Ok(value) => Ok(Some(value)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+/// Keeps everything in memory, mostly useful for tests and short-lived agents.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemoryStore for InMemoryStore {
+    fn save<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentyError>> + Send + 'a>> {
+        self.entries
+            .lock()
+            .expect("poisoned")
+            .insert(key.to_string(), value.to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>, AgentyError>> + Send + 'a>> {
+        let value = self.entries.lock().expect("poisoned").get(key).cloned();
+        Box::pin(async { Ok(value) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = dir.path().join("memory");
+        let store = FileMemoryStore::new(memory.clone());
+        let entries = [
+            ("plan", "1. read\n2. fix\n"),
+            ("user/name", "Ada"),
+            ("user%2Fname", "not the same key"),
+            ("../escape", "stays inside"),
+            ("ünïcode key", "ünïcode value"),
+        ];
+        for (key, value) in entries {
+            store.save(key, value).await.unwrap();
+        }
+        store.save("plan", "done\n").await.unwrap();
+
+        // A new store on the same directory, as after a restart.
+        let store = FileMemoryStore::new(memory.clone());
+        assert_eq!(store.load("plan").await.unwrap().as_deref(), Some("done\n"));
+        for (key, value) in &entries[1..] {
+            assert_eq!(store.load(key).await.unwrap().as_deref(), Some(*value));
+        }
+        assert_eq!(store.load("missing").await.unwrap(), None);
+        assert_eq!(std::fs::read_dir(&memory).unwrap().count(), entries.len());
+    }
+}
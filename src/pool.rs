@@ -0,0 +1,195 @@
+use std::sync::{Arc, Mutex};
+
+use openai_models::llm::LLM;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::agent::Agent;
+
+type AgentFactory = Arc<dyn Fn() -> Agent + Send + Sync>;
+
+/// Agents paired with their [`LLM`] clients, reused across requests so the clients keep
+/// their connections.
+///
+/// Only the clients are reused, every request gets a fresh agent so no tool state, prompt
+/// change or cancellation leaks into the next one.
+///
+/// Clones share the same agents.
+#[derive(Clone)]
+pub struct AgentPool {
+    pool: Arc<Mutex<Vec<(Agent, LLM)>>>,
+    available: Arc<Semaphore>,
+    agent_factory: AgentFactory,
+}
+
+impl AgentPool {
+    /// `size` agents made by `agent_factory`, each with a client made by `llm_factory`.
+    ///
+    /// Build the tools inside `agent_factory`, cloning a template agent would share the
+    /// state of tools like the scratchpad between requests.
+    pub fn new(
+        size: usize,
+        agent_factory: impl Fn() -> Agent + Send + Sync + 'static,
+        llm_factory: impl Fn() -> LLM,
+    ) -> Self {
+        let pool = (0..size)
+            .map(|_| (agent_factory(), llm_factory()))
+            .collect();
+        Self {
+            pool: Arc::new(Mutex::new(pool)),
+            available: Arc::new(Semaphore::new(size)),
+            agent_factory: Arc::new(agent_factory),
+        }
+    }
+
+    /// Borrow an agent, waiting until one is returned if all are in use.
+    pub async fn acquire(&self) -> PoolGuard {
+        let permit = self
+            .available
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore closed?!");
+        let pair = self
+            .pool
+            .lock()
+            .expect("poisoned")
+            .pop()
+            .expect("permit without an agent?!");
+        PoolGuard {
+            pair: Some(pair),
+            pool: self.pool.clone(),
+            agent_factory: self.agent_factory.clone(),
+            _permit: permit,
+        }
+    }
+
+    /// Agents not in use right now.
+    pub fn available(&self) -> usize {
+        self.available.available_permits()
+    }
+}
+
+/// An agent borrowed from an [`AgentPool`]. On drop its client goes back to the pool with a
+/// new agent so the next request starts afresh.
+pub struct PoolGuard {
+    pair: Option<(Agent, LLM)>,
+    pool: Arc<Mutex<Vec<(Agent, LLM)>>>,
+    agent_factory: AgentFactory,
+    // Released after the agent is back in the pool, as fields drop after `drop`.
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PoolGuard {
+    pub fn agent(&mut self) -> &mut Agent {
+        self.parts().0
+    }
+
+    pub fn llm(&mut self) -> &mut LLM {
+        self.parts().1
+    }
+
+    /// Both at once, as the `run_until_*` calls need them.
+    pub fn parts(&mut self) -> (&mut Agent, &mut LLM) {
+        let (agent, llm) = self.pair.as_mut().expect("guard already dropped?!");
+        (agent, llm)
+    }
+}
+
+impl Drop for PoolGuard {
+    fn drop(&mut self) {
+        if let Some((_, llm)) = self.pair.take() {
+            let agent = (self.agent_factory)();
+            self.pool.lock().expect("poisoned").push((agent, llm));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        agent::AgentBuilder,
+        error::AgentyError,
+        test_util::{completion, mock_llm, serve, text},
+        tools::testing::EchoTool,
+    };
+
+    use super::*;
+
+    fn pool(size: usize) -> AgentPool {
+        AgentPool::new(
+            size,
+            || AgentBuilder::new().user("task".to_string()).build(),
+            || mock_llm("http://127.0.0.1:9"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_acquire_blocks_until_release() {
+        let pool = pool(2);
+        let first = pool.acquire().await;
+        let _second = pool.acquire().await;
+        assert_eq!(pool.available(), 0);
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await.agent().user.clone() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        let user = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("acquire still blocked after a release")
+            .unwrap();
+        assert_eq!(user, "task");
+    }
+
+    #[tokio::test]
+    async fn test_agent_is_reset_on_release() {
+        let pool = pool(1);
+        {
+            let mut guard = pool.acquire().await;
+            let agent = guard.agent();
+            agent.append_user("hello".to_string()).unwrap();
+            agent.tokens_used = 1000;
+            agent.turns = 3;
+            agent.user = "changed".to_string();
+            agent.add_tool(EchoTool::new()).unwrap();
+        }
+        let mut guard = pool.acquire().await;
+        let agent = guard.agent();
+        assert!(agent.context.is_empty());
+        assert_eq!(agent.tokens_used, 0);
+        assert_eq!(agent.turns, 0);
+        assert!(agent.debug_log.is_empty());
+        assert_eq!(agent.user, "task");
+        assert!(agent.tools.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_agent_is_not_reused() {
+        let url = serve(|_| completion(text("done"))).await;
+        let pool = AgentPool::new(
+            1,
+            || AgentBuilder::new().user("task".to_string()).build(),
+            move || mock_llm(&url),
+        );
+        {
+            let mut guard = pool.acquire().await;
+            let (agent, llm) = guard.parts();
+            let token = CancellationToken::new();
+            agent.cancellation = Some(token.clone());
+            token.cancel();
+            let result = agent.run_until_text(llm, None, None).await;
+            assert!(matches!(result, Err(AgentyError::Cancelled)));
+        }
+        let mut guard = pool.acquire().await;
+        let (agent, llm) = guard.parts();
+        assert_eq!(agent.run_until_text(llm, None, None).await.unwrap(), "done");
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use ignore::{WalkBuilder, overrides::OverrideBuilder};
+use similar::TextDiff;
+use tempfile::TempDir;
+
+use crate::{error::AgentyError, tools::file::atomic_write};
+
+#[derive(Debug, Clone)]
+pub struct SandboxOptions {
+    /// Extra gitignore-style globs to leave out of the copy, on top of `.gitignore` files.
+    pub excludes: Vec<String>,
+    /// Hard-link files instead of copying them where the filesystem allows.
+    ///
+    /// Only safe if everything writing into the sandbox replaces files (like
+    /// [`atomic_write`]) instead of modifying them in place.
+    pub hard_link: bool,
+}
+
+impl Default for SandboxOptions {
+    fn default() -> Self {
+        Self {
+            excludes: vec![".git/".to_string()],
+            hard_link: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxChange {
+    Added(PathBuf),
+    Modified(PathBuf),
+    Deleted(PathBuf),
+}
+
+impl SandboxChange {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Added(p) | Self::Modified(p) | Self::Deleted(p) => p,
+        }
+    }
+}
+
+/// A throwaway copy of a workspace.
+///
+/// Point the tools' `cwd` at [`Sandbox::path`], let the agent do whatever it wants there, then
+/// review [`Sandbox::diff`] and materialize the result with [`Sandbox::apply`]. The copy is
+/// removed when the sandbox is dropped.
+#[derive(Debug)]
+pub struct Sandbox {
+    pub original: PathBuf,
+    pub options: SandboxOptions,
+    dir: TempDir,
+}
+
+impl Sandbox {
+    pub async fn copy_of(root: PathBuf) -> Result<Self, AgentyError> {
+        Self::copy_of_with(root, SandboxOptions::default()).await
+    }
+
+    pub async fn copy_of_with(root: PathBuf, options: SandboxOptions) -> Result<Self, AgentyError> {
+        let root = root.canonicalize()?;
+        let dir = TempDir::new()?;
+        let sandbox = Self {
+            original: root,
+            options,
+            dir,
+        };
+        let files = sandbox.walk(&sandbox.original).await?;
+        let (src, dst, hard_link) = (
+            sandbox.original.clone(),
+            sandbox.path().to_path_buf(),
+            sandbox.options.hard_link,
+        );
+        tokio::task::spawn_blocking(move || -> Result<(), AgentyError> {
+            for rel in files {
+                let to = dst.join(&rel);
+                if let Some(parent) = to.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let from = src.join(&rel);
+                if !hard_link || std::fs::hard_link(&from, &to).is_err() {
+                    std::fs::copy(&from, &to)?;
+                }
+            }
+            Ok(())
+        })
+        .await??;
+        Ok(sandbox)
+    }
+
+    /// The root of the copy, to be used as the tools' `cwd`.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Relative paths of all files below `root` honoring `.gitignore` and the excludes.
+    async fn walk(&self, root: &Path) -> Result<BTreeSet<PathBuf>, AgentyError> {
+        let mut overrides = OverrideBuilder::new(root);
+        for exclude in &self.options.excludes {
+            overrides.add(&format!("!{}", exclude))?;
+        }
+        let overrides = overrides.build()?;
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut files = BTreeSet::new();
+            for ent in WalkBuilder::new(&root)
+                .hidden(false)
+                .require_git(false)
+                .overrides(overrides)
+                .build()
+            {
+                let ent = ent?;
+                if ent.file_type().is_some_and(|t| t.is_file()) {
+                    files.insert(
+                        ent.path()
+                            .strip_prefix(&root)
+                            .expect("walked outside root?!")
+                            .to_path_buf(),
+                    );
+                }
+            }
+            Ok(files)
+        })
+        .await?
+    }
+
+    /// Files added, modified or deleted in the sandbox compared to the original tree.
+    pub async fn changes(&self) -> Result<Vec<SandboxChange>, AgentyError> {
+        let before = self.walk(&self.original).await?;
+        let after = self.walk(self.path()).await?;
+        let mut changes = vec![];
+        for rel in before.union(&after) {
+            match (before.contains(rel), after.contains(rel)) {
+                (true, false) => changes.push(SandboxChange::Deleted(rel.clone())),
+                (false, true) => changes.push(SandboxChange::Added(rel.clone())),
+                _ => {
+                    let a = tokio::fs::read(self.original.join(rel)).await?;
+                    let b = tokio::fs::read(self.path().join(rel)).await?;
+                    if a != b {
+                        changes.push(SandboxChange::Modified(rel.clone()));
+                    }
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// A unified diff of all modifications versus the original tree.
+    pub async fn diff(&self) -> Result<String, AgentyError> {
+        let mut resp = String::new();
+        for change in self.changes().await? {
+            let rel = change.path();
+            let old = match &change {
+                SandboxChange::Added(_) => vec![],
+                _ => tokio::fs::read(self.original.join(rel)).await?,
+            };
+            let new = match &change {
+                SandboxChange::Deleted(_) => vec![],
+                _ => tokio::fs::read(self.path().join(rel)).await?,
+            };
+            let old_name = match &change {
+                SandboxChange::Added(_) => "/dev/null".to_string(),
+                _ => format!("a/{}", rel.display()),
+            };
+            let new_name = match &change {
+                SandboxChange::Deleted(_) => "/dev/null".to_string(),
+                _ => format!("b/{}", rel.display()),
+            };
+            match (String::from_utf8(old), String::from_utf8(new)) {
+                (Ok(old), Ok(new)) => {
+                    resp.push_str(
+                        &TextDiff::from_lines(&old, &new)
+                            .unified_diff()
+                            .header(&old_name, &new_name)
+                            .to_string(),
+                    );
+                }
+                _ => resp.push_str(&format!(
+                    "Binary files {} and {} differ\n",
+                    old_name, new_name
+                )),
+            }
+        }
+        Ok(resp)
+    }
+
+    /// Copy all changes back to the original tree, returning what was applied.
+    pub async fn apply(&self) -> Result<Vec<SandboxChange>, AgentyError> {
+        let changes = self.changes().await?;
+        for change in &changes {
+            let target = self.original.join(change.path());
+            match change {
+                SandboxChange::Deleted(_) => tokio::fs::remove_file(&target).await?,
+                _ => {
+                    if let Some(parent) = target.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    let content = tokio::fs::read(self.path().join(change.path())).await?;
+                    atomic_write(&target, content).await?;
+                }
+            }
+        }
+        Ok(changes)
+    }
+}
@@ -0,0 +1,188 @@
+//! Helpers shared by the unit tests: a tiny HTTP server and [`LLM`] clients pointed at it.
+
+use std::sync::{Arc, Mutex};
+
+use clap::{Args, Command, FromArgMatches};
+use openai_models::llm::{LLM, OpenAISetup};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// A request received by [`serve`].
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// The path with the query string.
+    pub path: String,
+    pub body: String,
+}
+
+/// What [`serve`] answers: status, content type and body.
+pub type Response = (u16, &'static str, String);
+
+/// Serve HTTP/1.1 on a random local port with `handler`, returning the base URL.
+///
+/// Every connection is closed after one response, which is all the tests need.
+pub async fn serve(handler: impl Fn(Request) -> Response + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![];
+                let mut chunk = [0u8; 4096];
+                let header_end = loop {
+                    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                };
+                let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+                let length = head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                while buf.len() < header_end + length {
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+                let request = Request {
+                    method: request_line.next().unwrap_or_default().to_string(),
+                    path: request_line.next().unwrap_or_default().to_string(),
+                    body: String::from_utf8_lossy(&buf[header_end..header_end + length])
+                        .to_string(),
+                };
+                let (status, content_type, body) = handler(request);
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    url
+}
+
+/// A chat completion answering with the assistant `message`, e.g.
+/// `json!({"role": "assistant", "content": "Done"})`.
+pub fn completion(message: serde_json::Value) -> Response {
+    let finish_reason = if message.get("tool_calls").is_some() {
+        "tool_calls"
+    } else {
+        "stop"
+    };
+    let body = serde_json::json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "gpt-4o",
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": finish_reason,
+        }],
+        "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+    });
+    (200, "application/json", body.to_string())
+}
+
+/// An assistant message answering with `text`, for [`completion`].
+pub fn text(text: &str) -> serde_json::Value {
+    serde_json::json!({"role": "assistant", "content": text})
+}
+
+/// An assistant message calling the tools `calls` given as `(name, arguments)`, for
+/// [`completion`]. The calls get the ids `call_0`, `call_1` and so on.
+pub fn tool_calls(calls: &[(&str, serde_json::Value)]) -> serde_json::Value {
+    let calls = calls
+        .iter()
+        .enumerate()
+        .map(|(i, (name, arguments))| {
+            serde_json::json!({
+                "id": format!("call_{}", i),
+                "type": "function",
+                "function": {"name": name, "arguments": arguments.to_string()},
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({"role": "assistant", "content": null, "tool_calls": calls})
+}
+
+/// An [`LLM`] answering its requests in turn with the assistant `messages`, and the
+/// requests it received so far. Requests after the last message fail with status 500.
+pub async fn scripted_llm(messages: Vec<serde_json::Value>) -> (LLM, Arc<Mutex<Vec<Request>>>) {
+    let requests = Arc::new(Mutex::new(vec![]));
+    let received = requests.clone();
+    let url = serve(move |request| {
+        let mut received = received.lock().unwrap();
+        received.push(request);
+        match messages.get(received.len() - 1) {
+            Some(message) => completion(message.clone()),
+            None => (500, "text/plain", "no more answers".to_string()),
+        }
+    })
+    .await;
+    (mock_llm(&url), requests)
+}
+
+/// An [`LLM`] sending its requests to `url`, e.g. one returned by [`serve`].
+pub fn mock_llm(url: &str) -> LLM {
+    // Parsed like a command line so the settings get their defaults whatever fields the
+    // version of openai-models has. `--llm-retry` counts the attempts, 1 means no retries.
+    let matches = OpenAISetup::augment_args(Command::new("test"))
+        .try_get_matches_from([
+            "test",
+            "--openai-url",
+            url,
+            "--openai-key",
+            "test",
+            "--model",
+            "gpt-4o",
+            "--llm-retry",
+            "1",
+        ])
+        .unwrap();
+    OpenAISetup::from_arg_matches(&matches).unwrap().to_llm()
+}
+
+/// Collect what is logged through `tracing` on this thread as plain text until the guard is
+/// dropped, including a `new` event for every span entered.
+pub fn capture_logs() -> (Arc<Mutex<Vec<u8>>>, tracing::subscriber::DefaultGuard) {
+    struct Writer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Writer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let logs = Arc::new(Mutex::new(vec![]));
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::NEW)
+        .with_writer(move || Writer(writer.clone()))
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
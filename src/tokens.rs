@@ -0,0 +1,69 @@
+use std::fmt::Debug;
+
+/// Counts the tokens of text the way a model would, to keep text within a token budget.
+pub trait TokenCounter: Debug + Send + Sync {
+    /// The tokens of `text` for `model`, the default of the counter if `None`.
+    fn count(&self, text: &str, model: Option<&str>) -> usize;
+
+    /// How the tokens are counted, shown with the counts.
+    fn name(&self) -> &'static str;
+}
+
+/// Estimates about 4 characters of ASCII text per token, and a token per character of
+/// other scripts.
+///
+/// Real tokenizers vary by model, the estimate errs on the high side for most texts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicTokenCounter;
+
+impl TokenCounter for HeuristicTokenCounter {
+    fn count(&self, text: &str, _model: Option<&str>) -> usize {
+        let ascii = text.bytes().filter(|b| b.is_ascii()).count();
+        let other = text.chars().filter(|c| !c.is_ascii()).count();
+        ascii.div_ceil(4) + other
+    }
+
+    fn name(&self) -> &'static str {
+        "heuristic estimate"
+    }
+}
+
+/// Counts with the tokenizers of OpenAI models, `o200k_base` for unknown models.
+#[cfg(feature = "tiktoken")]
+#[derive(Debug, Clone, Default)]
+pub struct TiktokenCounter {
+    /// The model to count for when none is given.
+    pub default_model: Option<String>,
+}
+
+#[cfg(feature = "tiktoken")]
+impl TiktokenCounter {
+    pub fn new(default_model: Option<String>) -> Self {
+        Self { default_model }
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl TokenCounter for TiktokenCounter {
+    fn count(&self, text: &str, model: Option<&str>) -> usize {
+        use tiktoken_rs::tokenizer::{Tokenizer, get_tokenizer};
+
+        let tokenizer = model
+            .or(self.default_model.as_deref())
+            .and_then(get_tokenizer)
+            .unwrap_or(Tokenizer::O200kBase);
+        // The singletons are built once, building a tokenizer takes a while.
+        let bpe = match tokenizer {
+            Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+            Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+            Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+            Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+            Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+        };
+        bpe.encode_with_special_tokens(text).len()
+    }
+
+    fn name(&self) -> &'static str {
+        "tiktoken"
+    }
+}
@@ -1,16 +1,24 @@
 use std::future::Future;
 use std::pin::Pin;
-use std::{collections::HashMap, fmt::Debug};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
 
 use dyn_clone::DynClone;
-use log::debug;
 use openai_models::openai::types::chat::{ChatCompletionTool, ChatCompletionTools, FunctionObject};
 use schemars::schema_for;
 use serde::de::DeserializeOwned;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, Level, debug, event, info_span};
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::error::AgentyError;
+use crate::{error::AgentyError, tools::file::truncate_at_line_boundary};
 
-pub trait ToolDyn: DynClone + Debug + std::any::Any {
+pub trait ToolDyn: DynClone + Debug + std::any::Any + Send + Sync {
     fn name(&self) -> String;
     fn to_openai_obejct(&self) -> ChatCompletionTool;
     fn call(
@@ -50,9 +58,10 @@ pub trait Tool: Send + Sync + DynClone + Debug {
         async move {
             match serde_json::from_str::<Self::ARGUMENTS>(&arguments) {
                 Ok(args) => self.invoke(args).await,
-                Err(_) => Err(AgentyError::IncorrectToolCall(
+                Err(e) => Err(AgentyError::IncorrectToolCall(
                     schema_for!(Self::ARGUMENTS),
                     arguments,
+                    e,
                 )),
             }
         }
@@ -80,9 +89,168 @@ impl<T: Tool + DynClone + 'static> ToolDyn for T {
     }
 }
 
+/// A tool constructed by its factory on the first call, see [`ToolBox::add_lazy_tool`].
+///
+/// The schema is derived from `T` alone, so the tool can be offered to the model before it
+/// exists. Clones share the same instance.
+pub struct LazyTool<T> {
+    name: String,
+    factory: Arc<dyn Fn() -> T + Send + Sync>,
+    tool: Arc<OnceLock<T>>,
+}
+
+impl<T: Tool> LazyTool<T> {
+    pub fn new(name: &str, factory: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.to_string(),
+            factory: Arc::new(factory),
+            tool: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// The tool, constructing it if this is the first access.
+    pub fn get(&self) -> &T {
+        self.tool.get_or_init(|| (self.factory)())
+    }
+
+    /// The tool for changing it, constructing it if needed, `None` while clones share it.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.get();
+        Arc::get_mut(&mut self.tool)?.get_mut()
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.tool.get().is_some()
+    }
+}
+
+impl<T> Clone for LazyTool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            factory: self.factory.clone(),
+            tool: self.tool.clone(),
+        }
+    }
+}
+
+impl<T: Debug> Debug for LazyTool<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyTool")
+            .field("name", &self.name)
+            .field("tool", &self.tool.get())
+            .finish()
+    }
+}
+
+impl<T: Tool + 'static> ToolDyn for LazyTool<T> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn call(
+        &self,
+        arguments: String,
+    ) -> Pin<Box<dyn Future<Output = Result<String, AgentyError>> + Send + '_>> {
+        Box::pin(self.get().call(arguments))
+    }
+
+    fn to_openai_obejct(&self) -> ChatCompletionTool {
+        ChatCompletionTool {
+            function: FunctionObject {
+                name: self.name.clone(),
+                description: T::DESCRIPTION.map(|e| e.to_string()),
+                parameters: Some(
+                    serde_json::to_value(schema_for!(T::ARGUMENTS))
+                        .expect("Fail to generate schema?!"),
+                ),
+                strict: Some(T::STRICT),
+            },
+        }
+    }
+}
+
+/// How many characters of tool output a single agent step may add to the context.
+///
+/// Clones share the same counter, so one budget can be handed to several toolboxes. The
+/// agent resets it before every step, see [`ToolBox::reset_budget`].
+#[derive(Debug, Clone)]
+pub struct OutputBudget {
+    pub limit: usize,
+    remaining: Arc<AtomicUsize>,
+}
+
+impl OutputBudget {
+    pub const EXHAUSTED: &str = "output budget for this step exhausted, ask for less data";
+
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            remaining: Arc::new(AtomicUsize::new(limit)),
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.remaining.store(self.limit, Ordering::Relaxed);
+    }
+
+    /// Take `output` out of the budget, cutting it at a line boundary if it does not fit.
+    pub fn charge(&self, output: String) -> String {
+        let chars = output.chars().count();
+        let mut taken = 0;
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                taken = remaining.min(chars);
+                Some(remaining - taken)
+            });
+        if taken == chars {
+            output
+        } else if taken == 0 {
+            Self::EXHAUSTED.to_string()
+        } else {
+            let end = output
+                .char_indices()
+                .nth(taken)
+                .map_or(output.len(), |(i, _)| i);
+            format!(
+                "{}\n(output cut to fit the output budget of this step, ask for less data)",
+                truncate_at_line_boundary(&output, end).trim_end_matches('\n')
+            )
+        }
+    }
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct ToolBox {
     pub tools: HashMap<String, Box<dyn ToolDyn>>,
+    /// Shared by all tools in the box, unlimited if `None`.
+    pub budget: Option<OutputBudget>,
+    /// Bounds the tool calls running at the same time across all clones of the box.
+    pub limiter: Option<Arc<Semaphore>>,
+    inflight: Arc<AtomicUsize>,
+    /// The names of the tools registered by [`ToolBox::add_tool_group`] per group.
+    groups: HashMap<String, Vec<String>>,
+}
+
+/// Counts a running tool call for [`ToolBox::inflight`], also when the call is cancelled.
+struct InflightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InflightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl ToolBox {
@@ -90,6 +258,29 @@ impl ToolBox {
         Self::default()
     }
 
+    pub fn with_budget(mut self, budget: OutputBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Let at most `max_concurrent` tool calls run at the same time, others wait for a slot.
+    pub fn with_concurrency_limit(mut self, max_concurrent: usize) -> Self {
+        self.limiter = Some(Arc::new(Semaphore::new(max_concurrent)));
+        self
+    }
+
+    /// Number of tool calls currently running, not counting the ones waiting for a slot.
+    pub fn inflight(&self) -> usize {
+        self.inflight.load(Ordering::Relaxed)
+    }
+
+    /// Called by the agent before each step so every step gets a fresh budget.
+    pub fn reset_budget(&self) {
+        if let Some(budget) = &self.budget {
+            budget.reset();
+        }
+    }
+
     pub fn openai_objects(&self) -> Vec<ChatCompletionTools> {
         self.tools
             .iter()
@@ -97,24 +288,529 @@ impl ToolBox {
             .collect()
     }
 
-    pub fn add_tool<T: Tool + 'static>(&mut self, tool: T) {
-        self.add_dyn_tool(Box::new(tool) as _);
+    /// The same definitions as [`ToolBox::openai_objects`] in the format of the Anthropic
+    /// messages API, with the schema under `input_schema`.
+    pub fn anthropic_tools(&self) -> Vec<serde_json::Value> {
+        self.tools
+            .values()
+            .map(|t| {
+                let function = t.to_openai_obejct().function;
+                let schema = function
+                    .parameters
+                    .unwrap_or_else(|| serde_json::json!({"type": "object"}));
+                let mut tool = serde_json::json!({
+                    "name": function.name,
+                    "input_schema": schema,
+                });
+                if let Some(description) = function.description {
+                    tool["description"] = description.into();
+                }
+                tool
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn ToolDyn> {
+        self.into_iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut dyn ToolDyn> {
+        self.tools.values_mut().map(|t| t.as_mut() as &mut dyn ToolDyn)
+    }
+
+    /// The tool registered as `name`, if it is a `T`.
+    ///
+    /// A tool added by [`ToolBox::add_lazy_tool`] is constructed if it was not called yet.
+    pub fn get_tool<T: Tool + 'static>(&self, name: &str) -> Option<&T> {
+        let tool = self.tools.get(name)?.as_ref() as &dyn std::any::Any;
+        tool.downcast_ref::<T>()
+            .or_else(|| tool.downcast_ref::<LazyTool<T>>().map(LazyTool::get))
+    }
+
+    /// The tool registered as `name`, if it is a `T`.
+    ///
+    /// Like [`ToolBox::get_tool`] for lazy tools, which are only returned while no clone of
+    /// the box shares them.
+    pub fn get_tool_mut<T: Tool + 'static>(&mut self, name: &str) -> Option<&mut T> {
+        let tool = self.tools.get_mut(name)?.as_mut() as &mut dyn std::any::Any;
+        if tool.is::<T>() {
+            return tool.downcast_mut::<T>();
+        }
+        tool.downcast_mut::<LazyTool<T>>()?.get_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Register `tool`, failing with [`AgentyError::DuplicateTool`] if a tool with the same
+    /// name is registered already.
+    ///
+    /// # Migration
+    ///
+    /// This used to replace the existing tool silently. Propagate the error with
+    /// `toolbox.add_tool(tool)?`, or call [`ToolBox::add_or_replace_tool`] where replacing
+    /// is intended.
+    pub fn add_tool<T: Tool + 'static>(&mut self, tool: T) -> Result<(), AgentyError> {
+        self.try_add_tool(tool)
+    }
+
+    /// The same as [`ToolBox::add_tool`].
+    pub fn try_add_tool<T: Tool + 'static>(&mut self, tool: T) -> Result<(), AgentyError> {
+        self.add_dyn_tool(Box::new(tool) as _)
+    }
+
+    /// Register `tool`, replacing a registered tool with the same name.
+    pub fn add_or_replace_tool<T: Tool + 'static>(&mut self, tool: T) {
+        self.add_or_replace_dyn_tool(Box::new(tool) as _);
+    }
+
+    /// Register a tool that is only constructed by `factory` when it is first invoked.
+    pub fn add_lazy_tool<T: Tool + 'static>(
+        &mut self,
+        name: &str,
+        factory: impl Fn() -> T + Send + Sync + 'static,
+    ) -> Result<(), AgentyError> {
+        self.add_dyn_tool(Box::new(LazyTool::new(name, factory)) as _)
+    }
+
+    pub fn add_dyn_tool(&mut self, tool: Box<dyn ToolDyn>) -> Result<(), AgentyError> {
+        let name = tool.name();
+        if self.tools.contains_key(&name) {
+            return Err(AgentyError::DuplicateTool(name));
+        }
+        self.tools.insert(name, tool);
+        Ok(())
     }
 
-    pub fn add_dyn_tool(&mut self, tool: Box<dyn ToolDyn>) {
+    pub fn add_or_replace_dyn_tool(&mut self, tool: Box<dyn ToolDyn>) {
         self.tools.insert(tool.name(), tool);
     }
 
+    /// Register `tools` as the group `group_name` so they can be removed together later,
+    /// adding to the group if it exists.
+    ///
+    /// Fails with [`AgentyError::DuplicateTool`] without registering any of them if one of
+    /// the names is taken.
+    pub fn add_tool_group(
+        &mut self,
+        group_name: &str,
+        tools: impl IntoIterator<Item = Box<dyn ToolDyn>>,
+    ) -> Result<(), AgentyError> {
+        let tools = tools.into_iter().collect::<Vec<_>>();
+        self.check_new_names(&tools)?;
+        let group = self.groups.entry(group_name.to_string()).or_default();
+        for tool in tools {
+            group.push(tool.name());
+            self.tools.insert(tool.name(), tool);
+        }
+        Ok(())
+    }
+
+    /// Register `tools`, failing with [`AgentyError::DuplicateTool`] without registering any
+    /// of them if one of the names is taken. Unlike [`Extend`], which replaces.
+    pub fn try_extend(
+        &mut self,
+        tools: impl IntoIterator<Item = Box<dyn ToolDyn>>,
+    ) -> Result<(), AgentyError> {
+        let tools = tools.into_iter().collect::<Vec<_>>();
+        self.check_new_names(&tools)?;
+        for tool in tools {
+            self.tools.insert(tool.name(), tool);
+        }
+        Ok(())
+    }
+
+    fn check_new_names(&self, tools: &[Box<dyn ToolDyn>]) -> Result<(), AgentyError> {
+        let mut names = HashSet::new();
+        for tool in tools {
+            let name = tool.name();
+            if self.tools.contains_key(&name) || !names.insert(name.clone()) {
+                return Err(AgentyError::DuplicateTool(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Unregister all tools of the group `group_name` and return them, empty if there is no
+    /// such group.
+    ///
+    /// Tools of the group that were replaced by [`ToolBox::add_or_replace_tool`] since are
+    /// removed as well.
+    pub fn remove_group(&mut self, group_name: &str) -> Vec<Box<dyn ToolDyn>> {
+        self.groups
+            .remove(group_name)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|name| self.tools.remove(name))
+            .collect()
+    }
+
+    /// Take all tools out of the box, sorted by name, e.g. to split them into several boxes.
+    /// The groups are forgotten as well.
+    pub fn drain_tools(&mut self) -> Vec<Box<dyn ToolDyn>> {
+        self.groups.clear();
+        let mut tools = self.tools.drain().collect::<Vec<_>>();
+        tools.sort_by(|a, b| a.0.cmp(&b.0));
+        tools.into_iter().map(|(_, tool)| tool).collect()
+    }
+
+    /// All tools of the box sorted by name, see [`ToolBox::drain_tools`].
+    pub fn into_vec(mut self) -> Vec<Box<dyn ToolDyn>> {
+        self.drain_tools()
+    }
+
+    /// The names of the groups registered by [`ToolBox::add_tool_group`], sorted.
+    pub fn group_names(&self) -> Vec<String> {
+        let mut names = self.groups.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
     pub async fn invoke(
         &self,
         tool_name: String,
         arguments: String,
     ) -> Option<Result<String, AgentyError>> {
         if let Some(tool) = self.tools.get(&tool_name) {
-            debug!("Invoking tool {} with arguments {}", &tool_name, &arguments);
-            Some(tool.call(arguments).await)
+            let _permit = match &self.limiter {
+                Some(limiter) => Some(limiter.acquire().await.expect("semaphore closed?!")),
+                None => None,
+            };
+            let _inflight = InflightGuard::new(&self.inflight);
+            debug!("Invoking tool {}", &tool_name);
+            // The hash tells calls with the same arguments apart without logging them.
+            let span = info_span!(
+                "tool_invoke",
+                tool_name = %tool_name,
+                args_hash = %format!("{:016x}", xxh3_64(arguments.as_bytes()))
+            );
+            let resp = tool.call(arguments).instrument(span.clone()).await;
+            span.in_scope(|| match &resp {
+                Ok(output) => event!(Level::INFO, result_len = output.len(), "tool call done"),
+                Err(e) => event!(Level::WARN, error = %e, "tool call failed"),
+            });
+            Some(match &self.budget {
+                Some(budget) => resp.map(|output| budget.charge(output)),
+                None => resp,
+            })
         } else {
             None
         }
     }
+
+    /// Like [`ToolBox::invoke`] but gives up on the call once `token` is cancelled.
+    ///
+    /// The running call is dropped at its next await point and `Tool call cancelled` is
+    /// returned in place of its result. Work a tool already moved to a blocking thread still
+    /// runs to completion.
+    pub async fn invoke_cancellable(
+        &self,
+        tool_name: String,
+        arguments: String,
+        token: CancellationToken,
+    ) -> Option<Result<String, AgentyError>> {
+        tokio::select! {
+            resp = self.invoke(tool_name, arguments) => resp,
+            _ = token.cancelled() => Some(Ok("Tool call cancelled".to_string())),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a ToolBox {
+    type Item = &'a dyn ToolDyn;
+    type IntoIter = std::iter::Map<
+        std::collections::hash_map::Values<'a, String, Box<dyn ToolDyn>>,
+        fn(&'a Box<dyn ToolDyn>) -> &'a dyn ToolDyn,
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tools.values().map(|t| t.as_ref())
+    }
+}
+
+impl FromIterator<Box<dyn ToolDyn>> for ToolBox {
+    fn from_iter<I: IntoIterator<Item = Box<dyn ToolDyn>>>(iter: I) -> Self {
+        let mut toolbox = Self::new();
+        toolbox.extend(iter);
+        toolbox
+    }
+}
+
+/// Like extending a map, a later tool replaces an earlier one with the same name.
+impl Extend<Box<dyn ToolDyn>> for ToolBox {
+    fn extend<I: IntoIterator<Item = Box<dyn ToolDyn>>>(&mut self, iter: I) {
+        for tool in iter {
+            self.add_or_replace_dyn_tool(tool);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::capture_logs,
+        tools::testing::{DelayTool, EchoTool},
+    };
+
+    fn toolbox() -> ToolBox {
+        let mut toolbox = ToolBox::new();
+        toolbox.add_tool(EchoTool::new()).unwrap();
+        toolbox.add_tool(DelayTool::new(50)).unwrap();
+        toolbox
+    }
+
+    async fn call(toolbox: &ToolBox, name: &str, message: &str) -> Option<String> {
+        let arguments = serde_json::json!({ "message": message }).to_string();
+        let resp = toolbox.invoke(name.to_string(), arguments).await?;
+        Some(resp.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_testing_tools() {
+        let toolbox = toolbox();
+        assert_eq!(call(&toolbox, "echo", "hi").await.unwrap(), "hi");
+        let started = Instant::now();
+        assert_eq!(call(&toolbox, "delay", "later").await.unwrap(), "later");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(call(&toolbox, "missing", "hi").await.is_none());
+    }
+
+    #[test]
+    fn test_iter_visits_every_tool() {
+        let mut toolbox = toolbox();
+        assert_eq!(toolbox.iter().count(), toolbox.len());
+        assert_eq!((&toolbox).into_iter().count(), 2);
+        assert_eq!(toolbox.iter_mut().count(), 2);
+        let mut names = toolbox.iter().map(|t| t.name()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["delay", "echo"]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_into_toolbox() {
+        let tools: Vec<Box<dyn ToolDyn>> =
+            vec![Box::new(EchoTool::new()), Box::new(DelayTool::new(0))];
+        let toolbox = tools.into_iter().collect::<ToolBox>();
+        assert_eq!(toolbox.len(), 2);
+        assert_eq!(call(&toolbox, "echo", "a").await.unwrap(), "a");
+        assert_eq!(call(&toolbox, "delay", "b").await.unwrap(), "b");
+
+        // A later tool replaces an earlier one with the same name.
+        let mut toolbox = toolbox;
+        toolbox.extend([Box::new(EchoTool::new()) as Box<dyn ToolDyn>]);
+        assert_eq!(toolbox.len(), 2);
+    }
+
+    #[test]
+    fn test_budget_counts_chars() {
+        let budget = OutputBudget::new(10);
+        // 8 characters but 16 bytes.
+        assert_eq!(budget.charge("äöüß\näöü".to_string()), "äöüß\näöü");
+        assert_eq!(budget.remaining(), 2);
+        assert_eq!(
+            budget.charge("ab\ncd".to_string()),
+            "ab\n(output cut to fit the output budget of this step, ask for less data)"
+        );
+        assert_eq!(budget.charge("more".to_string()), OutputBudget::EXHAUSTED);
+        budget.reset();
+        assert_eq!(budget.remaining(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_lazy_tool_is_built_once() {
+        let built = Arc::new(AtomicUsize::new(0));
+        let counter = built.clone();
+        let mut toolbox = ToolBox::new();
+        toolbox
+            .add_lazy_tool("echo", move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                EchoTool::new()
+            })
+            .unwrap();
+        assert_eq!(toolbox.openai_objects().len(), 1);
+        assert_eq!(built.load(Ordering::SeqCst), 0);
+
+        let clone = toolbox.clone();
+        assert_eq!(call(&toolbox, "echo", "a").await.unwrap(), "a");
+        assert_eq!(call(&toolbox, "echo", "b").await.unwrap(), "b");
+        assert_eq!(call(&clone, "echo", "c").await.unwrap(), "c");
+        assert_eq!(built.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_serializes_calls() {
+        let mut toolbox = ToolBox::new().with_concurrency_limit(1);
+        toolbox.add_tool(DelayTool::new(100)).unwrap();
+        let started = Instant::now();
+        let (a, b) = tokio::join!(call(&toolbox, "delay", "a"), call(&toolbox, "delay", "b"));
+        assert_eq!((a.unwrap(), b.unwrap()), ("a".to_string(), "b".to_string()));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(toolbox.inflight(), 0);
+    }
+
+    #[test]
+    fn test_anthropic_tools_keep_the_schema() {
+        let toolbox = toolbox();
+        let openai = toolbox
+            .iter()
+            .map(|t| (t.name(), t.to_openai_obejct().function))
+            .collect::<HashMap<_, _>>();
+        let anthropic = toolbox.anthropic_tools();
+        assert_eq!(anthropic.len(), 2);
+        for tool in anthropic {
+            let function = &openai[tool["name"].as_str().unwrap()];
+            assert_eq!(Some(&tool["input_schema"]), function.parameters.as_ref());
+            assert_eq!(
+                tool["description"].as_str(),
+                function.description.as_deref()
+            );
+            assert!(tool.get("parameters").is_none());
+        }
+    }
+
+    #[test]
+    fn test_duplicate_names_are_rejected() {
+        let mut toolbox = toolbox();
+        assert!(matches!(
+            toolbox.add_tool(EchoTool::new()),
+            Err(AgentyError::DuplicateTool(name)) if name == "echo"
+        ));
+        let lazy = toolbox.add_lazy_tool("delay", || DelayTool::new(0));
+        assert!(lazy.is_err());
+        assert_eq!(toolbox.len(), 2);
+        toolbox.add_or_replace_tool(EchoTool::new());
+        assert_eq!(toolbox.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelling_a_slow_call() {
+        let mut toolbox = ToolBox::new();
+        toolbox.add_tool(DelayTool::new(10_000)).unwrap();
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        let arguments = serde_json::json!({ "message": "late" }).to_string();
+        let resp = toolbox
+            .invoke_cancellable("delay".to_string(), arguments, token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp, "Tool call cancelled");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(toolbox.inflight(), 0);
+    }
+
+    #[test]
+    fn test_get_tool_by_type() {
+        let mut toolbox = toolbox();
+        toolbox.add_lazy_tool("lazy_echo", EchoTool::new).unwrap();
+        assert!(toolbox.get_tool::<EchoTool>("echo").is_some());
+        assert_eq!(toolbox.get_tool::<DelayTool>("delay").unwrap().delay_ms, 50);
+        assert!(toolbox.get_tool::<DelayTool>("echo").is_none());
+        assert!(toolbox.get_tool::<EchoTool>("missing").is_none());
+
+        assert!(toolbox.get_tool::<DelayTool>("lazy_echo").is_none());
+        assert!(toolbox.get_tool::<EchoTool>("lazy_echo").is_some());
+
+        toolbox.get_tool_mut::<DelayTool>("delay").unwrap().delay_ms = 0;
+        assert_eq!(toolbox.get_tool::<DelayTool>("delay").unwrap().delay_ms, 0);
+        assert!(toolbox.get_tool_mut::<EchoTool>("lazy_echo").is_some());
+        let shared = toolbox.clone();
+        assert!(toolbox.get_tool_mut::<EchoTool>("lazy_echo").is_none());
+        drop(shared);
+        assert!(toolbox.get_tool_mut::<EchoTool>("lazy_echo").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_invoke_is_traced_without_arguments() {
+        let (logs, _guard) = capture_logs();
+        let toolbox = toolbox();
+        call(&toolbox, "echo", "secret argument").await.unwrap();
+        let logs = logs.lock().unwrap();
+        let logs = String::from_utf8_lossy(&logs);
+        for expected in [
+            "Invoking tool echo",
+            "tool_invoke{tool_name=echo args_hash=",
+            "tool call done result_len=15",
+        ] {
+            assert!(logs.contains(expected), "{}", logs);
+        }
+        assert!(!logs.contains("secret argument"), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn test_remove_group() {
+        let mut toolbox = ToolBox::new();
+        toolbox.add_tool(DelayTool::new(0)).unwrap();
+        let group: Vec<Box<dyn ToolDyn>> = vec![Box::new(EchoTool::new())];
+        toolbox.add_tool_group("testing", group).unwrap();
+        assert_eq!(toolbox.group_names(), ["testing"]);
+        assert_eq!(toolbox.openai_objects().len(), 2);
+
+        let removed = toolbox.remove_group("testing");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].name(), "echo");
+        let names = toolbox
+            .openai_objects()
+            .into_iter()
+            .map(|t| match t {
+                ChatCompletionTools::Function(t) => t.function.name,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["delay"]);
+        assert!(call(&toolbox, "echo", "hi").await.is_none());
+        assert!(toolbox.group_names().is_empty());
+        assert!(toolbox.remove_group("testing").is_empty());
+    }
+
+    #[test]
+    fn test_into_vec_keeps_every_tool() {
+        let boxed = toolbox();
+        let len = boxed.len();
+        let tools = boxed.into_vec();
+        assert_eq!(tools.len(), len);
+        assert_eq!(
+            tools.iter().map(|t| t.name()).collect::<Vec<_>>(),
+            ["delay", "echo"]
+        );
+
+        let mut toolbox = toolbox();
+        assert_eq!(toolbox.drain_tools().len(), 2);
+        assert!(toolbox.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_incorrect_call_message_is_short() {
+        let toolbox = toolbox();
+        let long = "x".repeat(1000);
+        let cases = [
+            (json!({ "mesage": 1 }).to_string(), "missing field 'message"),
+            (json!({ "message": 1 }).to_string(), "integer `1`"),
+            (json!(long).to_string(), "must be a JSON object"),
+            (format!("{{\"message\": \"{}", long), "are not valid JSON"),
+        ];
+        for (arguments, expected) in cases {
+            let resp = toolbox.invoke("echo".to_string(), arguments).await.unwrap();
+            let err = resp.unwrap_err();
+            assert!(!err.to_string().contains(&long), "{}", err);
+            let message = err.into_user_message();
+            assert!(message.chars().count() < 200, "{}", message);
+            assert!(message.contains(expected), "{}", message);
+        }
+    }
 }
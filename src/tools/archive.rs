@@ -0,0 +1,638 @@
+use std::{
+    fs::File,
+    future::Future,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{
+    file::{atomic_write, render_file_bytes, sanitize_join_relative_path},
+    journal::FsJournal,
+};
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveOp {
+    List,
+    Read,
+    ExtractTo,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ArchiveToolArgs {
+    pub archive_path: PathBuf,
+    pub op: ArchiveOp,
+    /// Entry name inside the archive, required by `read`. For `extract_to`, extracts only this
+    /// entry if given, otherwise the whole archive.
+    pub entry: Option<String>,
+    /// Maximum bytes returned by `read`, 8192 by default.
+    pub max_bytes: Option<usize>,
+    /// Destination directory for `extract_to`.
+    pub destination: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn open_zip(path: &Path) -> Result<zip::ZipArchive<File>, String> {
+    zip::ZipArchive::new(File::open(path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+}
+
+impl ArchiveKind {
+    fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveTool {
+    pub cwd: PathBuf,
+    /// Ceiling on the total decompressed bytes a single `extract_to` may write.
+    pub max_decompressed_size: u64,
+    pub max_list_entries: usize,
+}
+
+impl ArchiveTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_decompressed_size: 256 * 1024 * 1024,
+            max_list_entries: 1000,
+        }
+    }
+
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: u64) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    fn open_tar(path: &Path, kind: ArchiveKind) -> std::io::Result<tar::Archive<Box<dyn Read>>> {
+        let fp = BufReader::new(File::open(path)?);
+        let reader: Box<dyn Read> = if kind == ArchiveKind::TarGz {
+            Box::new(flate2::read::GzDecoder::new(fp))
+        } else {
+            Box::new(fp)
+        };
+        Ok(tar::Archive::new(reader))
+    }
+
+    fn list(&self, path: &Path, kind: ArchiveKind) -> Result<String, String> {
+        let mut lns = vec![];
+        let mut total = 0usize;
+        if kind == ArchiveKind::Zip {
+            let mut zip = open_zip(path)?;
+            total = zip.len();
+            for i in 0..zip.len().min(self.max_list_entries) {
+                let ent = zip.by_index(i).map_err(|e| e.to_string())?;
+                lns.push(format!(
+                    "{}\t{}\t{}",
+                    ent.name().map_err(|e| e.to_string())?,
+                    ent.size(),
+                    ent.compressed_size()
+                ));
+            }
+        } else {
+            let mut tar = Self::open_tar(path, kind).map_err(|e| e.to_string())?;
+            for ent in tar.entries().map_err(|e| e.to_string())? {
+                let ent = ent.map_err(|e| e.to_string())?;
+                total += 1;
+                if lns.len() < self.max_list_entries {
+                    lns.push(format!(
+                        "{}\t{}\t-",
+                        ent.path().map_err(|e| e.to_string())?.to_string_lossy(),
+                        ent.header().size().map_err(|e| e.to_string())?
+                    ));
+                }
+            }
+        }
+        let mut resp = format!("name\tsize\tcompressed_size\n{}", lns.into_iter().join("\n"));
+        if total > self.max_list_entries {
+            resp.push_str(&format!(
+                "\n({} more entries not shown)",
+                total - self.max_list_entries
+            ));
+        }
+        Ok(resp)
+    }
+
+    fn read(
+        &self,
+        path: &Path,
+        kind: ArchiveKind,
+        entry: &str,
+        max_bytes: usize,
+    ) -> Result<String, String> {
+        let mut buf = vec![];
+        if kind == ArchiveKind::Zip {
+            let mut zip = open_zip(path)?;
+            let ent = zip
+                .by_name(entry)
+                .map_err(|e| format!("Fail to find entry {} due to {}", entry, e))?;
+            ent.take(max_bytes as u64)
+                .read_to_end(&mut buf)
+                .map_err(|e| e.to_string())?;
+        } else {
+            let mut tar = Self::open_tar(path, kind).map_err(|e| e.to_string())?;
+            let mut found = false;
+            for ent in tar.entries().map_err(|e| e.to_string())? {
+                let ent = ent.map_err(|e| e.to_string())?;
+                if ent.path().map_err(|e| e.to_string())?.to_string_lossy() == entry {
+                    ent.take(max_bytes as u64)
+                        .read_to_end(&mut buf)
+                        .map_err(|e| e.to_string())?;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Err(format!("No entry named {} in the archive", entry));
+            }
+        }
+        Ok(render_file_bytes(buf))
+    }
+
+    /// Copy a single entry below `destination`, refusing names escaping it (zip-slip) and
+    /// stopping once `budget` decompressed bytes are used up.
+    fn extract_entry(
+        destination: &Path,
+        name: &str,
+        reader: impl Read,
+        budget: &mut u64,
+    ) -> Result<(), String> {
+        let target = sanitize_join_relative_path(destination, Path::new(name))
+            .map_err(|e| format!("Refuse to extract unsafe entry name: {}", e))?;
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut out = File::create(&target).map_err(|e| e.to_string())?;
+        let written = std::io::copy(&mut reader.take(*budget + 1), &mut out)
+            .map_err(|e| e.to_string())?;
+        if written > *budget {
+            drop(out);
+            let _ = std::fs::remove_file(&target);
+            return Err(format!(
+                "Extraction aborted at {}: decompressed size exceeds the limit",
+                name
+            ));
+        }
+        *budget -= written;
+        Ok(())
+    }
+
+    fn extract_to(
+        &self,
+        path: &Path,
+        kind: ArchiveKind,
+        entry: Option<&str>,
+        destination: &Path,
+    ) -> Result<String, String> {
+        let mut budget = self.max_decompressed_size;
+        let mut extracted = 0usize;
+        let mut skipped = vec![];
+        if kind == ArchiveKind::Zip {
+            let mut zip = open_zip(path)?;
+            for i in 0..zip.len() {
+                let ent = zip.by_index(i).map_err(|e| e.to_string())?;
+                let name = ent.name().map_err(|e| e.to_string())?.to_string();
+                if entry.is_some_and(|e| e != name) || ent.is_dir() {
+                    continue;
+                }
+                if ent.is_symlink() {
+                    skipped.push(name);
+                    continue;
+                }
+                if ent.size() > budget {
+                    return Err(format!(
+                        "Extraction aborted at {}: decompressed size exceeds the limit",
+                        name
+                    ));
+                }
+                Self::extract_entry(destination, &name, ent, &mut budget)?;
+                extracted += 1;
+            }
+        } else {
+            let mut tar = Self::open_tar(path, kind).map_err(|e| e.to_string())?;
+            for ent in tar.entries().map_err(|e| e.to_string())? {
+                let ent = ent.map_err(|e| e.to_string())?;
+                let name = ent
+                    .path()
+                    .map_err(|e| e.to_string())?
+                    .to_string_lossy()
+                    .to_string();
+                if entry.is_some_and(|e| e != name) || ent.header().entry_type().is_dir() {
+                    continue;
+                }
+                if !ent.header().entry_type().is_file() {
+                    skipped.push(name);
+                    continue;
+                }
+                Self::extract_entry(destination, &name, ent, &mut budget)?;
+                extracted += 1;
+            }
+        }
+        if extracted == 0 && let Some(entry) = entry {
+            return Err(format!("No entry named {} in the archive", entry));
+        }
+        let mut resp = format!(
+            "Extracted {} entries ({} bytes)",
+            extracted,
+            self.max_decompressed_size - budget
+        );
+        if !skipped.is_empty() {
+            resp.push_str(&format!(
+                "\nSkipped non-regular entries: {}",
+                skipped.into_iter().join(", ")
+            ));
+        }
+        Ok(resp)
+    }
+
+    pub async fn archive(&self, arguments: ArchiveToolArgs) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &arguments.archive_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        if !target_path.is_file() {
+            return Ok(format!("{:?} is not a file", &arguments.archive_path));
+        }
+        let Some(kind) = ArchiveKind::detect(&target_path) else {
+            return Ok(format!(
+                "{:?} is not a supported archive, only .zip, .tar and .tar.gz are supported",
+                &arguments.archive_path
+            ));
+        };
+        let destination = match (arguments.op, &arguments.destination) {
+            (ArchiveOp::ExtractTo, None) => {
+                return Ok("`destination` is required for extract_to".to_string());
+            }
+            (ArchiveOp::ExtractTo, Some(dest)) => {
+                match sanitize_join_relative_path(&self.cwd, dest) {
+                    Ok(p) => Some(p),
+                    Err(e) => return Ok(e),
+                }
+            }
+            _ => None,
+        };
+        if matches!(arguments.op, ArchiveOp::Read) && arguments.entry.is_none() {
+            return Ok("`entry` is required for read".to_string());
+        }
+
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let resp = match arguments.op {
+                ArchiveOp::List => this.list(&target_path, kind),
+                ArchiveOp::Read => this.read(
+                    &target_path,
+                    kind,
+                    arguments.entry.as_deref().unwrap_or_default(),
+                    arguments.max_bytes.unwrap_or(8192),
+                ),
+                ArchiveOp::ExtractTo => this.extract_to(
+                    &target_path,
+                    kind,
+                    arguments.entry.as_deref(),
+                    destination.as_deref().unwrap_or(&this.cwd),
+                ),
+            };
+            Ok(resp.unwrap_or_else(|e| e))
+        })
+        .await?
+    }
+}
+
+impl Tool for ArchiveTool {
+    type ARGUMENTS = ArchiveToolArgs;
+    const NAME: &str = "inspect_archive";
+    const DESCRIPTION: Option<&str> = Some(
+        "Inspect a .zip, .tar or .tar.gz archive at `archive_path` without extracting it. `op` is 'list' to list entries with sizes, 'read' to read the single `entry` (up to `max_bytes`, hexdump for binary entries) or 'extract_to' to extract `entry` (or everything) into the `destination` directory. All paths should be relative paths and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.archive(arguments)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ZipListArgs {
+    pub archive_path: PathBuf,
+}
+
+/// Lists the entries of ZIP archives with their sizes and compression.
+#[derive(Debug, Clone)]
+pub struct ZipListTool {
+    pub cwd: PathBuf,
+    pub max_list_entries: usize,
+}
+
+impl ZipListTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_list_entries: 1000,
+        }
+    }
+
+    fn list(path: &Path, max_list_entries: usize) -> Result<String, String> {
+        let mut zip = open_zip(path)?;
+        let mut lns = vec![];
+        for i in 0..zip.len().min(max_list_entries) {
+            let ent = zip.by_index(i).map_err(|e| e.to_string())?;
+            let ratio = if ent.size() == 0 {
+                "-".to_string()
+            } else {
+                format!(
+                    "{:.1}%",
+                    ent.compressed_size() as f64 * 100.0 / ent.size() as f64
+                )
+            };
+            lns.push(format!(
+                "{}\t{}\t{}\t{}",
+                ent.name().map_err(|e| e.to_string())?,
+                ent.size(),
+                ent.compressed_size(),
+                ratio
+            ));
+        }
+        let mut resp = format!(
+            "name\tsize\tcompressed_size\tratio\n{}",
+            lns.into_iter().join("\n")
+        );
+        if zip.len() > max_list_entries {
+            resp.push_str(&format!(
+                "\n({} more entries not shown)",
+                zip.len() - max_list_entries
+            ));
+        }
+        Ok(resp)
+    }
+
+    pub async fn list_zip(&self, archive_path: PathBuf) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &archive_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        if !target_path.is_file() {
+            return Ok(format!("{:?} is not a file", &archive_path));
+        }
+        let max_list_entries = self.max_list_entries;
+        Ok(tokio::task::spawn_blocking(move || {
+            Self::list(&target_path, max_list_entries)
+                .unwrap_or_else(|e| format!("Fail to list {:?} due to {}", &archive_path, e))
+        })
+        .await?)
+    }
+}
+
+impl Tool for ZipListTool {
+    type ARGUMENTS = ZipListArgs;
+    const NAME: &str = "list_zip";
+    const DESCRIPTION: Option<&str> = Some(
+        "List the entries of the ZIP archive at `archive_path` with their size, compressed size and compression ratio (compressed size of the size). The path should be a relative path and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.list_zip(arguments.archive_path)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ZipExtractArgs {
+    pub archive_path: PathBuf,
+    /// The entry name as listed by `list_zip`.
+    pub entry_path: String,
+    /// The file to write the entry to.
+    pub output_path: PathBuf,
+}
+
+/// Extracts single entries of ZIP archives into the workspace.
+#[derive(Debug, Clone)]
+pub struct ZipExtractTool {
+    pub cwd: PathBuf,
+    /// Ceiling on the decompressed bytes of the entry.
+    pub max_decompressed_size: u64,
+    pub journal: Option<FsJournal>,
+}
+
+impl ZipExtractTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_decompressed_size: 256 * 1024 * 1024,
+            journal: None,
+        }
+    }
+
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: u64) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    pub fn with_journal(mut self, journal: FsJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// The decompressed content of `entry`, written by the caller.
+    fn extract(path: &Path, entry: &str, max_decompressed_size: u64) -> Result<Vec<u8>, String> {
+        let mut zip = open_zip(path)?;
+        let ent = zip
+            .by_name(entry)
+            .map_err(|e| format!("Fail to find entry {} due to {}", entry, e))?;
+        if ent.is_dir() {
+            return Err(format!("{} is a directory", entry));
+        }
+        if ent.is_symlink() {
+            return Err(format!("{} is a symlink and will not be extracted", entry));
+        }
+        if ent.size() > max_decompressed_size {
+            return Err(format!(
+                "{} is {} bytes decompressed, more than the limit of {} bytes",
+                entry,
+                ent.size(),
+                max_decompressed_size
+            ));
+        }
+        // The declared size may lie, count what is actually read.
+        let mut buf = vec![];
+        ent.take(max_decompressed_size + 1)
+            .read_to_end(&mut buf)
+            .map_err(|e| e.to_string())?;
+        if buf.len() as u64 > max_decompressed_size {
+            return Err(format!(
+                "Extraction aborted: {} exceeds the limit of {} bytes decompressed",
+                entry, max_decompressed_size
+            ));
+        }
+        Ok(buf)
+    }
+
+    pub async fn extract_zip(&self, arguments: ZipExtractArgs) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &arguments.archive_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        let output = match sanitize_join_relative_path(&self.cwd, &arguments.output_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        if !target_path.is_file() {
+            return Ok(format!("{:?} is not a file", &arguments.archive_path));
+        }
+        if output.is_dir() {
+            return Ok(format!(
+                "{:?} is a directory, `output_path` is the file to write",
+                &arguments.output_path
+            ));
+        }
+        let max_decompressed_size = self.max_decompressed_size;
+        let entry = arguments.entry_path.clone();
+        let content = match tokio::task::spawn_blocking(move || {
+            Self::extract(&target_path, &entry, max_decompressed_size)
+        })
+        .await?
+        {
+            Ok(content) => content,
+            Err(e) => return Ok(e),
+        };
+        if let Some(parent) = output.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if let Some(journal) = &self.journal {
+            journal.record(&output).await?;
+        }
+        atomic_write(&output, &content).await?;
+        Ok(format!(
+            "Extracted {} to {:?} ({} bytes)",
+            &arguments.entry_path,
+            &arguments.output_path,
+            content.len()
+        ))
+    }
+}
+
+impl Tool for ZipExtractTool {
+    type ARGUMENTS = ZipExtractArgs;
+    const NAME: &str = "extract_zip";
+    const DESCRIPTION: Option<&str> = Some(
+        "Extract the single entry `entry_path` of the ZIP archive at `archive_path` to the file `output_path`, overwriting it. Use `list_zip` to find the entry names. All paths should be relative paths and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.extract_zip(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    /// A ZIP archive of `files` with deflate compression.
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, content) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn workspace() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let readme = b"# agenty\n".repeat(100);
+        let archive = zip_of(&[("README.md", &readme), ("src/empty.rs", b"")]);
+        std::fs::write(dir.path().join("project.zip"), archive).unwrap();
+        dir
+    }
+
+    fn extract_args(entry_path: &str, output_path: &str) -> ZipExtractArgs {
+        ZipExtractArgs {
+            archive_path: PathBuf::from("project.zip"),
+            entry_path: entry_path.to_string(),
+            output_path: PathBuf::from(output_path),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_zip() {
+        let dir = workspace();
+        let resp = ZipListTool::new(dir.path().to_path_buf())
+            .list_zip(PathBuf::from("project.zip"))
+            .await
+            .unwrap();
+        let lines = resp.lines().collect_vec();
+        assert_eq!(lines[0], "name\tsize\tcompressed_size\tratio");
+        assert!(lines[1].starts_with("README.md\t900\t"), "{}", resp);
+        assert!(lines[1].ends_with('%'), "{}", resp);
+        assert_eq!(lines[2], "src/empty.rs\t0\t2\t-");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_extract_zip() {
+        let dir = workspace();
+        let journal = FsJournal::new(dir.path().join(".journal")).await.unwrap();
+        let tool = ZipExtractTool::new(dir.path().to_path_buf()).with_journal(journal.clone());
+        std::fs::write(dir.path().join("README.md"), "old\n").unwrap();
+
+        let resp = tool
+            .extract_zip(extract_args("README.md", "README.md"))
+            .await
+            .unwrap();
+        assert_eq!(resp, "Extracted README.md to \"README.md\" (900 bytes)");
+        let readme = std::fs::read(dir.path().join("README.md")).unwrap();
+        assert_eq!(readme, b"# agenty\n".repeat(100));
+        let resp = tool
+            .extract_zip(extract_args("src/empty.rs", "out/empty.rs"))
+            .await
+            .unwrap();
+        assert_eq!(resp, "Extracted src/empty.rs to \"out/empty.rs\" (0 bytes)");
+        assert_eq!(std::fs::read(dir.path().join("out/empty.rs")).unwrap(), b"");
+
+        // Both writes went through the journal.
+        journal.undo_all().await.unwrap();
+        let readme = std::fs::read_to_string(dir.path().join("README.md")).unwrap();
+        assert_eq!(readme, "old\n");
+        assert!(!dir.path().join("out/empty.rs").exists());
+
+        let resp = tool
+            .with_max_decompressed_size(100)
+            .extract_zip(extract_args("README.md", "README.md"))
+            .await
+            .unwrap();
+        assert!(resp.ends_with("more than the limit of 100 bytes"));
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fmt::Debug, future::Future, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+/// Puts a question to the human behind the agent and resolves to the reply, however the host
+/// talks to them: a terminal prompt, a web socket or a chat message.
+pub type UserCallback =
+    Arc<dyn Fn(String) -> BoxFuture<'static, Result<String, AgentyError>> + Send + Sync>;
+
+/// Puts `action` to the user through `callback` and resolves to whether they approved it by
+/// replying yes. Tools doing something risky gate it on this.
+pub async fn ask_approval(callback: &UserCallback, action: &str) -> Result<bool, AgentyError> {
+    let reply = callback(format!("{}\nApprove? [y/N]", action)).await?;
+    Ok(matches!(reply.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AskUserArgs {
+    pub question: String,
+    /// Answers to offer, the user may still reply something else.
+    pub choices: Option<Vec<String>>,
+}
+
+/// Lets the model ask the user instead of guessing when the task is ambiguous.
+#[derive(Clone)]
+pub struct AskUserTool {
+    pub callback: UserCallback,
+    /// Carry on without an answer after this long, so unattended runs do not hang.
+    pub timeout: Option<Duration>,
+}
+
+impl Debug for AskUserTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AskUserTool")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AskUserTool {
+    pub fn new(
+        callback: impl Fn(String) -> BoxFuture<'static, Result<String, AgentyError>>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        Self {
+            callback: Arc::new(callback),
+            timeout: None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub async fn ask_user(
+        &self,
+        question: String,
+        choices: Option<Vec<String>>,
+    ) -> Result<String, AgentyError> {
+        let choices = choices.unwrap_or_default();
+        let mut prompt = question;
+        for (i, choice) in choices.iter().enumerate() {
+            prompt.push_str(&format!("\n{}. {}", i + 1, choice));
+        }
+        let reply = (self.callback)(prompt);
+        let reply = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, reply).await {
+                Ok(reply) => reply?,
+                Err(_) => {
+                    return Ok(format!(
+                        "The user did not respond within {}s, carry on with your best judgement",
+                        timeout.as_secs_f32()
+                    ));
+                }
+            },
+            None => reply.await?,
+        };
+        // Picking a choice by its number is the natural thing to do at a prompt.
+        let reply = match reply.trim().parse::<usize>() {
+            Ok(n) if (1..=choices.len()).contains(&n) => choices[n - 1].clone(),
+            _ => reply,
+        };
+        Ok(format!("The user replied: {}", reply))
+    }
+}
+
+impl Tool for AskUserTool {
+    type ARGUMENTS = AskUserArgs;
+    const NAME: &str = "ask_user";
+    const DESCRIPTION: Option<&str> = Some(
+        "Ask the user a `question` and wait for the reply. Only ask when the task is ambiguous and guessing could go wrong, and offer the likely answers in `choices` where possible. The user may not respond, then carry on with your best judgement.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.ask_user(arguments.question, arguments.choices)
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use thirtyfour::{
+    error::{WebDriverErrorInner, no_such_element},
+    prelude::*,
+};
+use tokio::sync::Mutex;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::truncate_at_line_boundary;
+
+pub const DEFAULT_WEBDRIVER_URL: &str = "http://localhost:9515";
+pub const DEFAULT_MAX_PAGE_TEXT: usize = 16384;
+pub const DEFAULT_MAX_ELEMENTS: usize = 20;
+/// Attributes reported for the elements found by a selector.
+const ELEMENT_ATTRIBUTES: &[&str] = &["id", "class", "name", "href", "src", "type", "value"];
+/// Directory under the working directory screenshots are saved to.
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrowserAction {
+    Goto,
+    CurrentText,
+    Find,
+    Back,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct BrowserArgs {
+    pub action: BrowserAction,
+    /// The page to open with `goto`.
+    pub url: Option<String>,
+    /// The CSS selector to `find`.
+    pub selector: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct BrowserClickArgs {
+    pub selector: String,
+    /// Which of the elements matching `selector` to click, counting from 0, 0 by default.
+    pub nth: Option<usize>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct BrowserTypeArgs {
+    pub selector: String,
+    pub text: String,
+    /// Clear the field before typing, false by default.
+    pub clear_first: Option<bool>,
+    /// Press enter after typing, false by default.
+    pub submit: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct BrowserScreenshotArgs {
+    /// Only capture the first element matching this CSS selector instead of the page.
+    pub selector: Option<String>,
+}
+
+/// Drives a browser through a WebDriver server like chromedriver.
+///
+/// The session is only created on the first call, so a missing server is reported to the
+/// model then instead of failing the setup. Clones share the session.
+#[derive(Debug, Clone)]
+pub struct BrowserTool {
+    pub server_url: String,
+    pub headless: bool,
+    /// The page text returned by `current_text` is cut after this many bytes.
+    pub max_text: usize,
+    pub max_elements: usize,
+    session: Arc<Mutex<Option<WebDriver>>>,
+}
+
+impl BrowserTool {
+    pub fn new(server_url: String) -> Self {
+        Self {
+            server_url,
+            headless: true,
+            max_text: DEFAULT_MAX_PAGE_TEXT,
+            max_elements: DEFAULT_MAX_ELEMENTS,
+            session: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_headless(mut self, headless: bool) -> Self {
+        self.headless = headless;
+        self
+    }
+
+    /// Close the browser, the next call opens a new one.
+    pub async fn quit(&self) -> Result<(), AgentyError> {
+        if let Some(driver) = self.session.lock().await.take() {
+            driver.quit().await?;
+        }
+        Ok(())
+    }
+
+    async fn driver(&self) -> WebDriverResult<WebDriver> {
+        let mut session = self.session.lock().await;
+        if let Some(driver) = session.as_ref() {
+            return Ok(driver.clone());
+        }
+        let mut caps = DesiredCapabilities::chrome();
+        if self.headless {
+            caps.set_headless()?;
+        }
+        let driver = WebDriver::new(&self.server_url, caps).await?;
+        *session = Some(driver.clone());
+        Ok(driver)
+    }
+
+    async fn describe_element(element: &WebElement) -> WebDriverResult<String> {
+        let mut desc = format!("<{}", element.tag_name().await?);
+        for name in ELEMENT_ATTRIBUTES {
+            if let Some(value) = element.attr(*name).await? {
+                desc.push_str(&format!(" {}={:?}", name, value));
+            }
+        }
+        let text = element.text().await?.split_whitespace().join(" ");
+        let mut short = text.chars().take(200).collect::<String>();
+        if short.len() < text.len() {
+            short.push_str("...");
+        }
+        desc.push_str(&format!("> {}", short));
+        Ok(desc)
+    }
+
+    async fn run_action(&self, arguments: BrowserArgs) -> WebDriverResult<String> {
+        let driver = self.driver().await?;
+        match arguments.action {
+            BrowserAction::Goto => {
+                let Some(url) = arguments.url else {
+                    return Ok("`url` is required to goto".to_string());
+                };
+                driver.goto(url).await?;
+                Ok(format!(
+                    "Opened {}, titled {:?}",
+                    driver.current_url().await?,
+                    driver.title().await?
+                ))
+            }
+            BrowserAction::Back => {
+                driver.back().await?;
+                Ok(format!("Back at {}", driver.current_url().await?))
+            }
+            BrowserAction::CurrentText => {
+                let ret = driver
+                    .execute("return document.body ? document.body.innerText : '';", vec![])
+                    .await?;
+                let text = ret.json().as_str().unwrap_or_default().to_string();
+                let cut = truncate_at_line_boundary(&text, self.max_text);
+                let mut resp = format!("{}\n\n{}", driver.current_url().await?, cut);
+                if cut.len() < text.len() {
+                    resp.push_str(&format!(
+                        "\n(text truncated, {} of {} bytes shown)",
+                        cut.len(),
+                        text.len()
+                    ));
+                }
+                Ok(resp)
+            }
+            BrowserAction::Find => {
+                let Some(selector) = arguments.selector else {
+                    return Ok("`selector` is required to find".to_string());
+                };
+                let elements = driver.find_all(By::Css(selector.clone())).await?;
+                if elements.is_empty() {
+                    return Ok(format!("No element matches {:?}", selector));
+                }
+                let mut lns = vec![format!(
+                    "{} elements match {:?}:",
+                    elements.len(),
+                    selector
+                )];
+                for element in elements.iter().take(self.max_elements) {
+                    lns.push(Self::describe_element(element).await?);
+                }
+                if elements.len() > self.max_elements {
+                    lns.push(format!(
+                        "... and {} more, use a more specific selector",
+                        elements.len() - self.max_elements
+                    ));
+                }
+                Ok(lns.join("\n"))
+            }
+        }
+    }
+
+    /// The `nth` element matching `selector`.
+    async fn find_nth(
+        driver: &WebDriver,
+        selector: &str,
+        nth: usize,
+    ) -> WebDriverResult<WebElement> {
+        let elements = driver.find_all(By::Css(selector.to_string())).await?;
+        let count = elements.len();
+        elements.into_iter().nth(nth).ok_or_else(|| {
+            no_such_element(format!(
+                "{} elements match {:?}, there is no element {}",
+                count, selector, nth
+            ))
+        })
+    }
+
+    /// The error for the model, with the page the browser is at when an element could not be
+    /// used since the page is likely not what the model expects then.
+    async fn report_error(&self, e: WebDriverError) -> String {
+        let mut resp = format!("Browser error: {}", e);
+        if matches!(
+            e.as_inner(),
+            WebDriverErrorInner::NoSuchElement(_)
+                | WebDriverErrorInner::StaleElementReference(_)
+                | WebDriverErrorInner::ElementNotInteractable(_)
+        ) && let Some(driver) = self.session.lock().await.clone()
+            && let (Ok(url), Ok(title)) = (driver.current_url().await, driver.title().await)
+        {
+            resp.push_str(&format!("\nThe browser is at {}, titled {:?}", url, title));
+        }
+        resp
+    }
+
+    /// Run `action`, failing with the error for the model.
+    async fn attempt<T>(
+        &self,
+        action: impl Future<Output = WebDriverResult<T>>,
+    ) -> Result<T, String> {
+        match action.await {
+            Ok(ret) => Ok(ret),
+            // Also covers a driver that is not running, the model may be able to carry on
+            // without the browser.
+            Err(e) => Err(self.report_error(e).await),
+        }
+    }
+
+    async fn handle(
+        &self,
+        action: impl Future<Output = WebDriverResult<String>>,
+    ) -> Result<String, AgentyError> {
+        Ok(self.attempt(action).await.unwrap_or_else(|e| e))
+    }
+
+    pub async fn browse(&self, arguments: BrowserArgs) -> Result<String, AgentyError> {
+        self.handle(self.run_action(arguments)).await
+    }
+
+    pub async fn click(&self, arguments: BrowserClickArgs) -> Result<String, AgentyError> {
+        self.handle(async {
+            let driver = self.driver().await?;
+            let nth = arguments.nth.unwrap_or_default();
+            let element = Self::find_nth(&driver, &arguments.selector, nth).await?;
+            element.click().await?;
+            Ok(format!(
+                "Clicked element {} of {:?}, the browser is at {}, titled {:?}",
+                nth,
+                &arguments.selector,
+                driver.current_url().await?,
+                driver.title().await?
+            ))
+        })
+        .await
+    }
+
+    pub async fn type_text(&self, arguments: BrowserTypeArgs) -> Result<String, AgentyError> {
+        self.handle(async {
+            let driver = self.driver().await?;
+            let element = Self::find_nth(&driver, &arguments.selector, 0).await?;
+            if arguments.clear_first.unwrap_or_default() {
+                element.clear().await?;
+            }
+            element.send_keys(arguments.text.as_str()).await?;
+            if arguments.submit.unwrap_or_default() {
+                element.send_keys(Key::Enter).await?;
+                return Ok(format!(
+                    "Typed into {:?} and submitted, the browser is at {}, titled {:?}",
+                    &arguments.selector,
+                    driver.current_url().await?,
+                    driver.title().await?
+                ));
+            }
+            Ok(format!("Typed into {:?}", &arguments.selector))
+        })
+        .await
+    }
+
+    /// Save a PNG screenshot of the page or of the element matching `selector` under
+    /// `cwd`/[`SCREENSHOT_DIR`].
+    pub async fn screenshot(
+        &self,
+        cwd: &Path,
+        selector: Option<String>,
+    ) -> Result<String, AgentyError> {
+        let png = self
+            .attempt(async {
+                let driver = self.driver().await?;
+                match &selector {
+                    Some(selector) => {
+                        Self::find_nth(&driver, selector, 0)
+                            .await?
+                            .screenshot_as_png()
+                            .await
+                    }
+                    None => driver.screenshot_as_png().await,
+                }
+            })
+            .await;
+        let png = match png {
+            Ok(png) => png,
+            Err(e) => return Ok(e),
+        };
+        let dir = cwd.join(SCREENSHOT_DIR);
+        tokio::fs::create_dir_all(&dir).await?;
+        let (file, path) = tempfile::Builder::new()
+            .prefix("screenshot-")
+            .suffix(".png")
+            .tempfile_in(&dir)?
+            .keep()
+            .map_err(|e| e.error)?;
+        drop(file);
+        tokio::fs::write(&path, &png).await?;
+        let rel = path.strip_prefix(cwd).unwrap_or(&path);
+        Ok(format!(
+            "Saved a screenshot of {} to {:?}, {} bytes",
+            selector
+                .map(|s| format!("{:?}", s))
+                .unwrap_or_else(|| "the page".to_string()),
+            rel,
+            png.len()
+        ))
+    }
+}
+
+impl Tool for BrowserTool {
+    type ARGUMENTS = BrowserArgs;
+    const NAME: &str = "browser";
+    const DESCRIPTION: Option<&str> = Some(
+        "Control a web browser. `action` is one of 'goto' to open `url`, 'current_text' to get the visible text of the current page, 'find' to list the elements matching the CSS `selector` with their text and attributes, and 'back' to go back to the previous page. Long page texts are truncated.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.browse(arguments)
+    }
+}
+
+/// Clicks elements in the browser of a [`BrowserTool`].
+#[derive(Debug, Clone)]
+pub struct BrowserClickTool {
+    pub browser: BrowserTool,
+}
+
+impl BrowserClickTool {
+    /// Share the session of `browser`.
+    pub fn new(browser: BrowserTool) -> Self {
+        Self { browser }
+    }
+}
+
+impl Tool for BrowserClickTool {
+    type ARGUMENTS = BrowserClickArgs;
+    const NAME: &str = "browser_click";
+    const DESCRIPTION: Option<&str> = Some(
+        "Click the element matching the CSS `selector` in the browser, the `nth` one if several match. Returns the page the browser is at afterwards.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.browser.click(arguments)
+    }
+}
+
+/// Types into elements in the browser of a [`BrowserTool`].
+#[derive(Debug, Clone)]
+pub struct BrowserTypeTool {
+    pub browser: BrowserTool,
+}
+
+impl BrowserTypeTool {
+    /// Share the session of `browser`.
+    pub fn new(browser: BrowserTool) -> Self {
+        Self { browser }
+    }
+}
+
+impl Tool for BrowserTypeTool {
+    type ARGUMENTS = BrowserTypeArgs;
+    const NAME: &str = "browser_type";
+    const DESCRIPTION: Option<&str> = Some(
+        "Type `text` into the first element matching the CSS `selector` in the browser. Set `clear_first` to replace the current value and `submit` to press enter afterwards, like to send a search.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.browser.type_text(arguments)
+    }
+}
+
+/// Takes screenshots in the browser of a [`BrowserTool`], saved under `cwd`.
+#[derive(Debug, Clone)]
+pub struct BrowserScreenshotTool {
+    pub browser: BrowserTool,
+    pub cwd: PathBuf,
+}
+
+impl BrowserScreenshotTool {
+    /// Share the session of `browser`.
+    pub fn new(browser: BrowserTool, cwd: PathBuf) -> Self {
+        Self { browser, cwd }
+    }
+}
+
+impl Tool for BrowserScreenshotTool {
+    type ARGUMENTS = BrowserScreenshotArgs;
+    const NAME: &str = "browser_screenshot";
+    const DESCRIPTION: Option<&str> = Some(
+        "Take a PNG screenshot of the current page in the browser, or only of the first element matching the CSS `selector`. The screenshot is saved to a file in the working directory and its path is returned.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.browser.screenshot(&self.cwd, arguments.selector)
+    }
+}
@@ -0,0 +1,335 @@
+use std::{collections::HashSet, future::Future, path::PathBuf, process::Stdio, time::Duration};
+
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+};
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{file::truncate_at_line_boundary, shell::kill};
+
+pub const DEFAULT_CARGO_TIMEOUT: Duration = Duration::from_secs(600);
+pub const MAX_CARGO_DIAGNOSTICS: usize = 50;
+/// Bytes of the output of every failing test.
+const MAX_TEST_STDOUT: usize = 2048;
+/// Bytes of plain output kept, the test harness output of large suites is long.
+const MAX_PLAIN_OUTPUT: usize = 1024 * 1024;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CargoCommand {
+    Check,
+    Test,
+    Clippy,
+    Build,
+}
+
+impl CargoCommand {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Check => "check",
+            Self::Test => "test",
+            Self::Clippy => "clippy",
+            Self::Build => "build",
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CargoArgs {
+    pub command: CargoCommand,
+    /// Only this package of the workspace.
+    pub package: Option<String>,
+    /// Only run the tests whose names contain this, for `test`.
+    pub test_filter: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    level: String,
+    message: String,
+    #[serde(default)]
+    spans: Vec<CompilerSpan>,
+}
+
+#[derive(Deserialize)]
+struct CompilerSpan {
+    file_name: String,
+    line_start: usize,
+    is_primary: bool,
+}
+
+/// The compiler diagnostics as `file:line: level: message`, without duplicates and the
+/// summaries like `aborting due to 2 previous errors`.
+fn diagnostic_line(message: &CompilerMessage) -> Option<String> {
+    if message.level != "error" && message.level != "warning" {
+        return None;
+    }
+    let text = message.message.lines().next().unwrap_or_default();
+    match message.spans.iter().find(|s| s.is_primary) {
+        Some(span) => Some(format!(
+            "{}:{}: {}: {}",
+            span.file_name, span.line_start, message.level, text
+        )),
+        None if text.starts_with("aborting due to")
+            || (text.starts_with('`') && text.contains(" generated ")) =>
+        {
+            None
+        }
+        None => Some(format!("{}: {}", message.level, text)),
+    }
+}
+
+#[derive(Default)]
+struct TestSummary {
+    binaries: usize,
+    passed: usize,
+    failed: usize,
+    ignored: usize,
+    failures: Vec<String>,
+}
+
+/// Sum up the `test result:` lines of all test binaries and collect the failing tests.
+fn summarize_tests(output: &str) -> TestSummary {
+    let mut summary = TestSummary::default();
+    for line in output.lines() {
+        if let Some(result) = line.strip_prefix("test result: ") {
+            summary.binaries += 1;
+            for part in result.split(';') {
+                let mut words = part.split_whitespace().rev();
+                let (Some(what), Some(count)) = (words.next(), words.next()) else {
+                    continue;
+                };
+                let Ok(count) = count.parse::<usize>() else {
+                    continue;
+                };
+                match what {
+                    "passed" => summary.passed += count,
+                    "failed" => summary.failed += count,
+                    "ignored" => summary.ignored += count,
+                    _ => {}
+                }
+            }
+        } else if let Some(name) = line
+            .strip_prefix("test ")
+            .and_then(|l| l.strip_suffix(" ... FAILED"))
+        {
+            summary.failures.push(name.to_string());
+        }
+    }
+    summary
+}
+
+/// The captured output of the failing test `name`, between its `---- name stdout ----`
+/// header and the next section.
+fn failure_output(output: &str, name: &str) -> Option<String> {
+    let header = format!("---- {} stdout ----\n", name);
+    let start = output.find(&header)? + header.len();
+    let rest = &output[start..];
+    let end = ["\n---- ", "\nfailures:\n", "\n\nfailures:"]
+        .iter()
+        .filter_map(|m| rest.find(m))
+        .min()
+        .unwrap_or(rest.len());
+    let body = rest[..end].trim_end();
+    let cut = truncate_at_line_boundary(body, MAX_TEST_STDOUT);
+    Some(if cut.len() < body.len() {
+        format!("{}\n(truncated)", cut.trim_end())
+    } else {
+        body.to_string()
+    })
+}
+
+/// Runs cargo in a Rust workspace and reports diagnostics and test results compactly.
+#[derive(Debug, Clone)]
+pub struct CargoTool {
+    pub cwd: PathBuf,
+    pub timeout: Duration,
+    pub max_diagnostics: usize,
+}
+
+impl CargoTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            timeout: DEFAULT_CARGO_TIMEOUT,
+            max_diagnostics: MAX_CARGO_DIAGNOSTICS,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn cargo(&self, arguments: CargoArgs) -> Result<String, AgentyError> {
+        if !self.cwd.join("Cargo.toml").is_file() {
+            return Ok(
+                "There is no Cargo.toml in the working directory, it is not a Rust project"
+                    .to_string(),
+            );
+        }
+        if arguments.test_filter.is_some() && arguments.command != CargoCommand::Test {
+            return Ok("`test_filter` only applies to `test`".to_string());
+        }
+
+        let mut cmd = Command::new("cargo");
+        cmd.arg(arguments.command.as_str())
+            .arg("--message-format=json")
+            .arg("--color=never");
+        if let Some(package) = &arguments.package {
+            cmd.arg("--package").arg(package);
+        }
+        if let Some(filter) = &arguments.test_filter {
+            cmd.arg("--").arg(filter);
+        }
+        // Backtraces of failing tests would crowd out everything else.
+        cmd.current_dir(&self.cwd)
+            .env("RUST_BACKTRACE", "0")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // Lead a new process group so a timeout kills rustc and the tests as well.
+        #[cfg(unix)]
+        cmd.process_group(0);
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => return Ok(format!("Fail to run cargo due to {}", e)),
+        };
+        let pid = child.id();
+
+        // Compiler messages are JSON lines on stdout, everything else like the test harness
+        // output is plain text.
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let collect_stdout = tokio::spawn(async move {
+            let mut diagnostics = vec![];
+            let mut errors = 0;
+            let mut seen = HashSet::new();
+            let mut plain = String::new();
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match serde_json::from_str::<CargoMessage>(&line) {
+                    Ok(msg) => {
+                        if msg.reason != "compiler-message" {
+                            continue;
+                        }
+                        let Some(message) = msg.message else {
+                            continue;
+                        };
+                        if let Some(diagnostic) = diagnostic_line(&message)
+                            && seen.insert(diagnostic.clone())
+                        {
+                            errors += (message.level == "error") as usize;
+                            diagnostics.push(diagnostic);
+                        }
+                    }
+                    Err(_) if plain.len() < MAX_PLAIN_OUTPUT => {
+                        plain.push_str(&line);
+                        plain.push('\n');
+                    }
+                    Err(_) => {}
+                }
+            }
+            (diagnostics, errors, plain)
+        });
+        let collect_stderr = tokio::spawn(async move {
+            let mut plain = String::new();
+            let mut lines = BufReader::new(stderr).lines();
+            // Keep reading past the limit so cargo does not block on a full pipe.
+            while let Ok(Some(line)) = lines.next_line().await {
+                if plain.len() < MAX_PLAIN_OUTPUT {
+                    plain.push_str(&line);
+                    plain.push('\n');
+                }
+            }
+            plain
+        });
+
+        let status = match tokio::time::timeout(self.timeout, child.wait()).await {
+            Ok(status) => Some(status?),
+            Err(_) => {
+                kill(pid, &mut child).await;
+                None
+            }
+        };
+        let (diagnostics, errors, plain) = collect_stdout.await?;
+        let stderr = collect_stderr.await?;
+
+        let outcome = match status {
+            None => format!("killed after the timeout of {}s", self.timeout.as_secs()),
+            Some(status) if status.success() => "succeeded".to_string(),
+            Some(status) => match status.code() {
+                Some(code) => format!("failed with exit code {}", code),
+                None => "terminated by a signal".to_string(),
+            },
+        };
+        let mut resp = format!("cargo {}: {}", arguments.command.as_str(), outcome);
+
+        if !diagnostics.is_empty() {
+            resp.push_str(&format!(
+                "\n{} errors, {} warnings:\n{}",
+                errors,
+                diagnostics.len() - errors,
+                diagnostics.iter().take(self.max_diagnostics).join("\n")
+            ));
+            if diagnostics.len() > self.max_diagnostics {
+                resp.push_str(&format!(
+                    "\n({} more diagnostics not shown)",
+                    diagnostics.len() - self.max_diagnostics
+                ));
+            }
+        }
+
+        if arguments.command == CargoCommand::Test {
+            let summary = summarize_tests(&plain);
+            if summary.binaries > 0 {
+                resp.push_str(&format!(
+                    "\ntests: {} passed, {} failed, {} ignored",
+                    summary.passed, summary.failed, summary.ignored
+                ));
+                for name in &summary.failures {
+                    resp.push_str(&format!("\nFAILED {}", name));
+                    if let Some(output) = failure_output(&plain, name) {
+                        resp.push_str(&format!("\n{}", output));
+                    }
+                }
+            }
+        }
+
+        // Errors of cargo itself, like an unknown package, are only on stderr.
+        if status.is_some_and(|s| !s.success()) && errors == 0 {
+            let errors = stderr.lines().filter(|l| l.starts_with("error")).join("\n");
+            if !errors.is_empty() {
+                resp.push_str(&format!("\n{}", errors));
+            }
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for CargoTool {
+    type ARGUMENTS = CargoArgs;
+    const NAME: &str = "cargo";
+    const DESCRIPTION: Option<&str> = Some(
+        "Run `cargo check`, `test`, `clippy` or `build` in the Rust workspace and get the outcome, the compiler errors and warnings as `file:line: level: message` and for `test` the number of passed and failed tests with the output of the failing ones. Pass `package` to only build one package of the workspace and `test_filter` to only run the matching tests.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.cargo(arguments)
+    }
+}
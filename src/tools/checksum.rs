@@ -0,0 +1,142 @@
+use std::{future::Future, path::PathBuf};
+
+use itertools::Itertools;
+use md5::Md5;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::sanitize_join_relative_path;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Md5,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Md5 => "md5",
+            Self::Blake3 => "blake3",
+        }
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Md5(Md5),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Md5 => Self::Md5(Md5::new()),
+            ChecksumAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Md5(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            Self::Sha256(h) => h.finalize().iter().map(|b| format!("{:02x}", b)).join(""),
+            Self::Md5(h) => h.finalize().iter().map(|b| format!("{:02x}", b)).join(""),
+            Self::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ChecksumToolArgs {
+    pub path: PathBuf,
+    pub algorithm: Option<ChecksumAlgorithm>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChecksumTool {
+    pub cwd: PathBuf,
+}
+
+impl ChecksumTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self { cwd }
+    }
+
+    pub async fn checksum(
+        &self,
+        path: PathBuf,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        match tokio::fs::metadata(&target_path).await {
+            Ok(meta) => {
+                if meta.is_dir() {
+                    return Ok(format!("Path {:?} is a directory", &path));
+                }
+            }
+            Err(e) => {
+                return Ok(format!("Fail to get metadata of {:?} due to {}", &path, e));
+            }
+        };
+        let mut fp = match tokio::fs::File::open(&target_path).await {
+            Ok(fp) => fp,
+            Err(e) => return Ok(format!("Fail to open {:?} due to {}", &path, e)),
+        };
+
+        let mut hasher = Hasher::new(algorithm);
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let n = fp.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            size += n as u64;
+        }
+
+        Ok(format!(
+            "{}\t{}\t{:?}\t{} bytes",
+            algorithm.name(),
+            hasher.finalize_hex(),
+            &path,
+            size
+        ))
+    }
+}
+
+impl Tool for ChecksumTool {
+    type ARGUMENTS = ChecksumToolArgs;
+    const NAME: &str = "checksum_file";
+    const DESCRIPTION: Option<&str> = Some(
+        "Compute the checksum of the file at `path`. `algorithm` is one of 'sha256' (default), 'md5' and 'blake3'. Returns the hex digest and the file size in bytes. The path should be always relative path and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.checksum(arguments.path, arguments.algorithm.unwrap_or_default())
+    }
+}
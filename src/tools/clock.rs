@@ -0,0 +1,242 @@
+use std::{future::Future, time::Duration};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+pub const DEFAULT_MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Formats tried on timestamps without an offset, which are taken in the default zone.
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%d/%b/%Y:%H:%M:%S",
+    "%b %d %Y %H:%M:%S",
+];
+
+/// Formats tried on timestamps with an offset, beyond RFC 3339 and RFC 2822.
+const OFFSET_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    // Common log format, e.g. `10/Oct/2000:13:55:36 -0700`.
+    "%d/%b/%Y:%H:%M:%S %z",
+];
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockOp {
+    Now,
+    Parse,
+    Diff,
+    Sleep,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ClockArgs {
+    pub op: ClockOp,
+    /// The timestamp to `parse`, or the start to `diff`.
+    pub value: Option<String>,
+    /// The end to `diff`, now if not given.
+    pub other: Option<String>,
+    /// How long to `sleep`.
+    pub seconds: Option<u64>,
+}
+
+/// Spell out `secs` like `3 days 4 hours`, leaving out the units that are zero.
+pub fn human_duration(secs: u64) -> String {
+    let units = [
+        ("day", 86400),
+        ("hour", 3600),
+        ("minute", 60),
+        ("second", 1),
+    ];
+    let mut rest = secs;
+    let mut parts = vec![];
+    for (unit, size) in units {
+        let n = rest / size;
+        rest %= size;
+        if n > 0 {
+            parts.push(format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" }));
+        }
+    }
+    if parts.is_empty() {
+        "0 seconds".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Tells the model the time, which it can not know otherwise.
+#[derive(Debug, Clone)]
+pub struct ClockTool {
+    /// The zone of timestamps without an offset, also shown besides UTC.
+    pub timezone: Tz,
+    /// Longer sleeps are cut to this.
+    pub max_sleep: Duration,
+}
+
+impl Default for ClockTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockTool {
+    pub fn new() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            max_sleep: DEFAULT_MAX_SLEEP,
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_max_sleep(mut self, max_sleep: Duration) -> Self {
+        self.max_sleep = max_sleep;
+        self
+    }
+
+    fn show(&self, time: DateTime<Utc>) -> String {
+        let local = time.with_timezone(&self.timezone);
+        format!(
+            "UTC: {}\n{}: {} ({})",
+            time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.timezone,
+            local.to_rfc3339_opts(SecondsFormat::Secs, false),
+            local.format("%A")
+        )
+    }
+
+    fn in_zone(&self, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+        // The earlier one of the times repeated when the clocks go back.
+        self.timezone
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    /// Parse `value` in one of the common formats, errors are meant for the model.
+    pub fn parse(&self, value: &str) -> Result<DateTime<Utc>, String> {
+        let value = value.trim();
+        if let Ok(t) = DateTime::parse_from_rfc3339(value) {
+            return Ok(t.with_timezone(&Utc));
+        }
+        if let Ok(t) = DateTime::parse_from_rfc2822(value) {
+            return Ok(t.with_timezone(&Utc));
+        }
+        for fmt in OFFSET_FORMATS {
+            if let Ok(t) = DateTime::parse_from_str(value, fmt) {
+                return Ok(t.with_timezone(&Utc));
+            }
+        }
+        for fmt in NAIVE_FORMATS {
+            if let Ok(naive) = NaiveDateTime::parse_from_str(value, fmt) {
+                return self
+                    .in_zone(naive)
+                    .ok_or_else(|| format!("{} does not exist in {}", value, self.timezone));
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            return self
+                .in_zone(date.and_time(Default::default()))
+                .ok_or_else(|| format!("{} does not exist in {}", value, self.timezone));
+        }
+        // Unix timestamps, in milliseconds if too large for seconds of this era.
+        if let Ok(n) = value.parse::<i64>() {
+            let t = if n.abs() >= 100_000_000_000 {
+                DateTime::from_timestamp_millis(n)
+            } else {
+                DateTime::from_timestamp(n, 0)
+            };
+            return t.ok_or_else(|| format!("{} is out of range as a unix timestamp", value));
+        }
+        Err(format!(
+            "Can not parse {:?}, use RFC 3339 like 2024-01-31T12:00:00Z",
+            value
+        ))
+    }
+
+    pub async fn clock(&self, arguments: ClockArgs) -> Result<String, AgentyError> {
+        match arguments.op {
+            ClockOp::Now => Ok(self.show(Utc::now())),
+            ClockOp::Parse => {
+                let Some(value) = arguments.value else {
+                    return Ok("`value` is required to parse".to_string());
+                };
+                Ok(self.parse(&value).map_or_else(|e| e, |t| self.show(t)))
+            }
+            ClockOp::Diff => {
+                let Some(value) = arguments.value else {
+                    return Ok("`value` is required to diff".to_string());
+                };
+                let from = match self.parse(&value) {
+                    Ok(t) => t,
+                    Err(e) => return Ok(e),
+                };
+                let (to, other) = match arguments.other {
+                    Some(other) => match self.parse(&other) {
+                        Ok(t) => (t, other),
+                        Err(e) => return Ok(e),
+                    },
+                    None => (Utc::now(), "now".to_string()),
+                };
+                let secs = (to - from).num_seconds();
+                Ok(match secs.signum() {
+                    0 => format!("{} is the same time as {}", value, other),
+                    1 => format!(
+                        "{} is {} before {}",
+                        value,
+                        human_duration(secs.unsigned_abs()),
+                        other
+                    ),
+                    _ => format!(
+                        "{} is {} after {}",
+                        value,
+                        human_duration(secs.unsigned_abs()),
+                        other
+                    ),
+                })
+            }
+            ClockOp::Sleep => {
+                let Some(seconds) = arguments.seconds else {
+                    return Ok("`seconds` is required to sleep".to_string());
+                };
+                let wanted = Duration::from_secs(seconds);
+                let slept = wanted.min(self.max_sleep);
+                tokio::time::sleep(slept).await;
+                Ok(if slept < wanted {
+                    format!(
+                        "Slept for {}, the most allowed at once",
+                        human_duration(slept.as_secs())
+                    )
+                } else {
+                    format!("Slept for {}", human_duration(slept.as_secs()))
+                })
+            }
+        }
+    }
+}
+
+impl Tool for ClockTool {
+    type ARGUMENTS = ClockArgs;
+    const NAME: &str = "clock";
+    const DESCRIPTION: Option<&str> = Some(
+        "Work with the current time. `op` is 'now' to get the current time, 'parse' to normalize the timestamp `value`, 'diff' to get the time from `value` to `other` or to now, and 'sleep' to wait `seconds` before checking on something again. Timestamps may be RFC 3339, RFC 2822, log formats, dates or unix timestamps, those without an offset are taken in the local zone.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.clock(arguments)
+    }
+}
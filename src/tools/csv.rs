@@ -0,0 +1,275 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{sanitize_join_relative_path, table_row};
+
+pub const DEFAULT_CSV_ROWS: usize = 20;
+pub const MAX_CSV_ROWS: usize = 200;
+/// Wider tables are cut, the model can pick the columns it needs with `columns`.
+pub const MAX_CSV_COLUMNS: usize = 12;
+const DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvOp {
+    Preview,
+    Stats,
+    Filter,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CsvArgs {
+    pub file_path: PathBuf,
+    pub op: CsvOp,
+    /// Only these columns, by header name.
+    pub columns: Option<Vec<String>>,
+    /// The column and the text it must contain to `filter`.
+    pub where_contains: Option<(String, String)>,
+    /// Rows shown at most.
+    pub limit: Option<usize>,
+}
+
+/// Guess the delimiter from the first lines: the candidate found the same number of times
+/// on every line, the most often.
+pub fn sniff_delimiter(path: &Path) -> std::io::Result<u8> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("tsv") => return Ok(b'\t'),
+        _ => {}
+    }
+    let lines = BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|ln| !ln.trim().is_empty())
+        .take(10)
+        .collect_vec();
+    Ok(DELIMITERS
+        .into_iter()
+        .filter_map(|d| {
+            let counts = lines
+                .iter()
+                .map(|ln| ln.bytes().filter(|b| *b == d).count())
+                .collect_vec();
+            let first = *counts.first()?;
+            (first > 0 && counts.iter().all(|c| *c == first)).then_some((d, first))
+        })
+        .max_by_key(|(_, count)| *count)
+        .map_or(b',', |(d, _)| d))
+}
+
+#[derive(Default)]
+struct ColumnStats {
+    values: usize,
+    empty: usize,
+    distinct: HashSet<String>,
+    integers: usize,
+    floats: usize,
+    booleans: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ColumnStats {
+    fn add(&mut self, value: &str) {
+        let value = value.trim();
+        if value.is_empty() {
+            self.empty += 1;
+            return;
+        }
+        self.values += 1;
+        if !self.distinct.contains(value) {
+            self.distinct.insert(value.to_string());
+        }
+        if value.parse::<i64>().is_ok() {
+            self.integers += 1;
+        }
+        if let Ok(f) = value.parse::<f64>() {
+            self.floats += 1;
+            self.min = Some(self.min.map_or(f, |m| m.min(f)));
+            self.max = Some(self.max.map_or(f, |m| m.max(f)));
+        }
+        if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") {
+            self.booleans += 1;
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.values {
+            0 => "empty",
+            n if self.integers == n => "integer",
+            n if self.floats == n => "float",
+            n if self.booleans == n => "boolean",
+            _ => "text",
+        }
+    }
+
+    fn numeric(&self) -> bool {
+        self.values > 0 && self.floats == self.values
+    }
+}
+
+/// Previews, summarizes and filters delimited data files.
+#[derive(Debug, Clone)]
+pub struct CsvTool {
+    pub cwd: PathBuf,
+}
+
+impl CsvTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self { cwd }
+    }
+
+    fn run(path: &Path, arguments: CsvArgs) -> Result<String, String> {
+        let delimiter = sniff_delimiter(path)
+            .map_err(|e| format!("Fail to open {:?} due to {}", &arguments.file_path, e))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_path(path)
+            .map_err(|e| format!("Fail to open {:?} due to {}", &arguments.file_path, e))?;
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("Fail to parse the header due to {}", e))?
+            .iter()
+            .map(String::from)
+            .collect_vec();
+
+        let mut indices = match &arguments.columns {
+            Some(columns) if !columns.is_empty() => {
+                let mut indices = vec![];
+                for column in columns {
+                    match headers.iter().position(|h| h == column) {
+                        Some(i) => indices.push(i),
+                        None => {
+                            return Err(format!(
+                                "No column {:?}, the columns are: {}",
+                                column,
+                                headers.join(", ")
+                            ));
+                        }
+                    }
+                }
+                indices
+            }
+            _ => (0..headers.len()).collect(),
+        };
+        let mut note = String::new();
+        if arguments.op != CsvOp::Stats && indices.len() > MAX_CSV_COLUMNS {
+            let hidden = indices.split_off(MAX_CSV_COLUMNS);
+            note = format!(
+                "\n({} more columns not shown: {}. Pick the columns to show with `columns`)",
+                hidden.len(),
+                hidden.iter().map(|i| &headers[*i]).join(", ")
+            );
+        }
+        let condition = match arguments.where_contains {
+            Some((column, needle)) => match headers.iter().position(|h| *h == column) {
+                Some(i) => Some((i, needle)),
+                None => {
+                    return Err(format!(
+                        "No column {:?}, the columns are: {}",
+                        column,
+                        headers.join(", ")
+                    ));
+                }
+            },
+            None if arguments.op == CsvOp::Filter => {
+                return Err("`where_contains` is required to filter".to_string());
+            }
+            None => None,
+        };
+        let limit = arguments
+            .limit
+            .unwrap_or(DEFAULT_CSV_ROWS)
+            .clamp(1, MAX_CSV_ROWS);
+
+        let mut table = prettytable::Table::new();
+        table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+        let mut stats = indices.iter().map(|_| ColumnStats::default()).collect_vec();
+        let mut rows = 0;
+        let mut shown = 0;
+        for (line, record) in reader.records().enumerate() {
+            let record =
+                record.map_err(|e| format!("Fail to parse row {} due to {}", line + 1, e))?;
+            if let Some((i, needle)) = &condition
+                && !record.get(*i).unwrap_or_default().contains(needle.as_str())
+            {
+                continue;
+            }
+            rows += 1;
+            let fields = indices.iter().map(|i| record.get(*i).unwrap_or_default());
+            if arguments.op == CsvOp::Stats {
+                for (stat, field) in stats.iter_mut().zip(fields) {
+                    stat.add(field);
+                }
+            } else if shown < limit {
+                table.add_row(table_row(fields));
+                shown += 1;
+            }
+        }
+
+        if arguments.op == CsvOp::Stats {
+            table.set_titles(table_row([
+                "column", "type", "values", "empty", "distinct", "min", "max",
+            ]));
+            for (i, stat) in indices.iter().zip(&stats) {
+                let (min, max) = match (stat.numeric(), stat.min, stat.max) {
+                    (true, Some(min), Some(max)) => (min.to_string(), max.to_string()),
+                    _ => (String::new(), String::new()),
+                };
+                table.add_row(table_row([
+                    headers[*i].as_str(),
+                    stat.kind(),
+                    &stat.values.to_string(),
+                    &stat.empty.to_string(),
+                    &stat.distinct.len().to_string(),
+                    &min,
+                    &max,
+                ]));
+            }
+            return Ok(format!("{} rows\n{}", rows, table));
+        }
+
+        table.set_titles(table_row(indices.iter().map(|i| headers[*i].as_str())));
+        let summary = match &condition {
+            Some(_) => format!("{} matching rows, showing {}", rows, shown),
+            None => format!("{} rows, showing {}", rows, shown),
+        };
+        Ok(format!("{}\n{}{}", summary, table, note.trim_start()))
+    }
+
+    pub async fn csv(&self, arguments: CsvArgs) -> Result<String, AgentyError> {
+        let path = match sanitize_join_relative_path(&self.cwd, &arguments.file_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        Ok(
+            tokio::task::spawn_blocking(move || Self::run(&path, arguments).unwrap_or_else(|e| e))
+                .await?,
+        )
+    }
+}
+
+impl Tool for CsvTool {
+    type ARGUMENTS = CsvArgs;
+    const NAME: &str = "csv";
+    const DESCRIPTION: Option<&str> = Some(
+        "Inspect a CSV, TSV or similar delimited file at `file_path`, the delimiter is detected and the first row is the header. `op` is 'preview' to show the first `limit` rows as a table, 'stats' to get the type, number of values, distinct values and the range of numbers of every column, and 'filter' to show the rows whose column contains a text, given as `where_contains`: [column, text]. Pass `columns` to only include those columns, wide tables are cut otherwise.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.csv(arguments)
+    }
+}
@@ -0,0 +1,114 @@
+use std::{future::Future, path::PathBuf};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use similar::TextDiff;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{sanitize_join_relative_path, truncate_at_line_boundary};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DiffFilesToolArgs {
+    pub path_a: PathBuf,
+    pub path_b: PathBuf,
+    pub context_lines: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffFilesTool {
+    pub cwd: PathBuf,
+    pub max_file_size: u64,
+    pub max_output: usize,
+}
+
+impl DiffFilesTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_file_size: 1024 * 1024,
+            max_output: 16384,
+        }
+    }
+
+    async fn read_text(&self, path: &PathBuf) -> Result<String, String> {
+        let target_path = sanitize_join_relative_path(&self.cwd, path)?;
+        let meta = tokio::fs::metadata(&target_path)
+            .await
+            .map_err(|e| format!("Fail to get metadata of {:?} due to {}", path, e))?;
+        if meta.is_dir() {
+            return Err(format!("Path {:?} is a directory", path));
+        }
+        if meta.len() > self.max_file_size {
+            return Err(format!(
+                "{:?} is too large to diff ({} bytes, limit {} bytes)",
+                path,
+                meta.len(),
+                self.max_file_size
+            ));
+        }
+        let buf = tokio::fs::read(&target_path)
+            .await
+            .map_err(|e| format!("Fail to read {:?} due to {}", path, e))?;
+        if buf.contains(&0) {
+            return Err(format!("{:?} is a binary file", path));
+        }
+        String::from_utf8(buf).map_err(|_| format!("{:?} is not a valid UTF-8 text file", path))
+    }
+
+    pub async fn diff_files(
+        &self,
+        path_a: PathBuf,
+        path_b: PathBuf,
+        context_lines: usize,
+    ) -> Result<String, AgentyError> {
+        let a = match self.read_text(&path_a).await {
+            Ok(a) => a,
+            Err(e) => return Ok(e),
+        };
+        let b = match self.read_text(&path_b).await {
+            Ok(b) => b,
+            Err(e) => return Ok(e),
+        };
+        if a == b {
+            return Ok(format!("{:?} and {:?}: files are identical", &path_a, &path_b));
+        }
+
+        let diff = TextDiff::from_lines(&a, &b);
+        let resp = diff
+            .unified_diff()
+            .context_radius(context_lines)
+            .header(&path_a.to_string_lossy(), &path_b.to_string_lossy())
+            .to_string();
+        let cut = truncate_at_line_boundary(&resp, self.max_output);
+        if cut.len() < resp.len() {
+            Ok(format!(
+                "{}(diff truncated, {} of {} bytes shown)",
+                cut,
+                cut.len(),
+                resp.len()
+            ))
+        } else {
+            Ok(resp)
+        }
+    }
+}
+
+impl Tool for DiffFilesTool {
+    type ARGUMENTS = DiffFilesToolArgs;
+    const NAME: &str = "diff_files";
+    const DESCRIPTION: Option<&str> = Some(
+        "Produce a unified diff between two text files `path_a` and `path_b` with `context_lines` (default 3) lines of context. Binary files and very large files are refused. The paths should be always relative path and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.diff_files(
+            arguments.path_a,
+            arguments.path_b,
+            arguments.context_lines.unwrap_or(3),
+        )
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+use itertools::Itertools;
+use log::warn;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{human_size, sanitize_join_relative_path};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DirectorySizeArgs {
+    pub path: PathBuf,
+    /// How many directory levels below `path` are reported, 1 by default.
+    pub depth: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct DirStat {
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectorySizeTool {
+    pub cwd: PathBuf,
+    pub top_n: usize,
+    pub max_depth: usize,
+    pub max_entries: usize,
+}
+
+impl DirectorySizeTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            top_n: 30,
+            max_depth: 8,
+            max_entries: 200_000,
+        }
+    }
+
+    #[cfg(unix)]
+    fn inode(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt;
+        (meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+    }
+
+    #[cfg(not(unix))]
+    fn inode(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+        None
+    }
+
+    pub fn directory_size(&self, path: PathBuf, depth: usize) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        if !target_path.is_dir() {
+            return Ok(format!("{:?} is not a directory", &path));
+        }
+        let depth = depth.min(self.max_depth);
+
+        let mut stats: HashMap<PathBuf, DirStat> = HashMap::new();
+        let mut seen_inodes = HashSet::new();
+        let mut total = DirStat::default();
+        let mut entries = 0usize;
+        let mut capped = false;
+        for ent in WalkBuilder::new(&target_path)
+            .hidden(false)
+            .filter_entry(|e| e.file_name() != ".git")
+            .build()
+        {
+            let ent = match ent {
+                Ok(ent) => ent,
+                Err(e) => {
+                    warn!("Fail to walk due to {}", e);
+                    continue;
+                }
+            };
+            entries += 1;
+            if entries > self.max_entries {
+                capped = true;
+                break;
+            }
+            if !ent.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let meta = match ent.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    warn!("Fail to get metadata of {:?} due to {}", ent.path(), e);
+                    continue;
+                }
+            };
+            if let Some(inode) = Self::inode(&meta)
+                && !seen_inodes.insert(inode)
+            {
+                continue;
+            }
+            let rel = ent
+                .path()
+                .strip_prefix(&target_path)
+                .unwrap_or(ent.path())
+                .parent()
+                .unwrap_or(Path::new(""));
+            total.bytes += meta.len();
+            total.files += 1;
+            let mut dir = PathBuf::new();
+            for comp in rel.components().take(depth) {
+                dir.push(comp);
+                let stat = stats.entry(dir.clone()).or_default();
+                stat.bytes += meta.len();
+                stat.files += 1;
+            }
+        }
+
+        let lns = stats
+            .into_iter()
+            .sorted_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)))
+            .take(self.top_n)
+            .map(|(dir, stat)| {
+                format!(
+                    "{}\t{}\t{}",
+                    human_size(stat.bytes),
+                    stat.files,
+                    path.join(dir).display()
+                )
+            })
+            .join("\n");
+        let mut resp = format!(
+            "size\tfiles\tdirectory\n{}\ntotal {} in {} files under {:?}",
+            lns,
+            human_size(total.bytes),
+            total.files,
+            &path
+        );
+        if capped {
+            resp.push_str(&format!(
+                "\n(stopped after {} entries, the numbers are incomplete)",
+                self.max_entries
+            ));
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for DirectorySizeTool {
+    type ARGUMENTS = DirectorySizeArgs;
+    const NAME: &str = "directory_size";
+    const DESCRIPTION: Option<&str> = Some(
+        "Summarize disk usage under the directory `path` like `du`: the largest subdirectories up to `depth` levels deep with their sizes and file counts, plus the totals. Files ignored by .gitignore are skipped. The path should be always relative path and '.' is allowed while '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        let this = self.clone();
+        async move {
+            tokio::task::spawn_blocking(move || {
+                this.directory_size(arguments.path, arguments.depth.unwrap_or(1))
+            })
+            .await
+            .expect("fail to join")
+        }
+    }
+}
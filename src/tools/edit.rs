@@ -0,0 +1,616 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+use itertools::Itertools;
+use log::warn;
+use regex::{NoExpand, Regex, RegexBuilder};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use similar::TextDiff;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{
+    file::{FileGlobs, atomic_write, sanitize_join_relative_path, truncate_at_line_boundary},
+    grep::DEFAULT_MAX_FILESIZE,
+    journal::FsJournal,
+    staging::STAGING_DIR,
+};
+
+/// Number the lines `[start, end)` of `lines` like `cat -n` does.
+pub fn numbered_excerpt(lines: &[&str], start: usize, end: usize) -> String {
+    lines[start..end.min(lines.len())]
+        .iter()
+        .enumerate()
+        .map(|(i, ln)| format!("{:>6}\t{}", start + i + 1, ln))
+        .join("\n")
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InsertPosition {
+    Before,
+    After,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct InsertAtLineArgs {
+    pub file_path: PathBuf,
+    /// 1-based line number the content is inserted relative to.
+    pub line: usize,
+    pub content: String,
+    pub position: InsertPosition,
+}
+
+#[derive(Debug, Clone)]
+pub struct InsertAtLineTool {
+    pub cwd: PathBuf,
+    pub journal: Option<FsJournal>,
+}
+
+impl InsertAtLineTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self { cwd, journal: None }
+    }
+
+    pub fn with_journal(mut self, journal: FsJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub async fn insert_at_line(&self, arguments: InsertAtLineArgs) -> Result<String, AgentyError> {
+        let file_path = arguments.file_path;
+        let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        let original = match tokio::fs::read(&target_path).await {
+            Ok(buf) => match String::from_utf8(buf) {
+                Ok(s) => s,
+                Err(_) => return Ok(format!("{:?} is not a UTF-8 text file", &file_path)),
+            },
+            Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+        };
+
+        let newline = if original.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut lines = original.lines().collect_vec();
+        let line_count = lines.len();
+        if arguments.line == 0 || arguments.line > line_count.max(1) {
+            return Ok(format!(
+                "Line {} is out of range, {:?} has {} lines",
+                arguments.line, &file_path, line_count
+            ));
+        }
+
+        let index = match arguments.position {
+            InsertPosition::Before => arguments.line - 1,
+            InsertPosition::After => arguments.line.min(line_count),
+        };
+        let new_lines = arguments.content.lines().collect_vec();
+        let inserted = new_lines.len();
+        lines.splice(index..index, new_lines);
+
+        let mut updated = lines.iter().join(newline);
+        if original.ends_with('\n') || original.is_empty() {
+            updated.push_str(newline);
+        }
+
+        if let Some(journal) = &self.journal {
+            journal.record(&target_path).await?;
+        }
+        atomic_write(&target_path, &updated).await?;
+
+        Ok(format!(
+            "Inserted {} lines into {:?}, the file now has {} lines:\n{}",
+            inserted,
+            &file_path,
+            lines.len(),
+            numbered_excerpt(&lines, index.saturating_sub(3), index + inserted + 3)
+        ))
+    }
+}
+
+impl Tool for InsertAtLineTool {
+    type ARGUMENTS = InsertAtLineArgs;
+    const NAME: &str = "insert_at_line";
+    const DESCRIPTION: Option<&str> = Some(
+        "Insert `content` into the file `file_path` before or after the given 1-based `line` (`position` is 'before' or 'after'). The line endings of the file are preserved and a numbered excerpt around the insertion is returned for verification. The path should be always relative path and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.insert_at_line(arguments)
+    }
+}
+
+/// A parsed `s/pattern/replacement/flags` expression.
+#[derive(Debug, Clone)]
+pub struct SedExpression {
+    pub regex: Regex,
+    /// Replacement in `regex` crate syntax (`${1}`), converted from sed syntax (`\1`, `&`).
+    pub replacement: String,
+    pub global: bool,
+}
+
+impl SedExpression {
+    /// Parse a sed substitution. Any delimiter is accepted (`s|a|b|`) and can be escaped
+    /// with a backslash inside the pattern and the replacement.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let mut chars = expression.chars();
+        if chars.next() != Some('s') {
+            return Err(format!("{} does not start with 's'", expression));
+        }
+        let delim = chars
+            .next()
+            .ok_or_else(|| format!("{} misses the delimiter", expression))?;
+
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            if escaped {
+                if c != delim {
+                    parts.last_mut().unwrap().push('\\');
+                }
+                parts.last_mut().unwrap().push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delim {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        if parts.len() != 3 {
+            return Err(format!(
+                "{} is not in the form s/pattern/replacement/flags",
+                expression
+            ));
+        }
+
+        let (mut global, mut case_insensitive) = (false, false);
+        for flag in parts[2].chars() {
+            match flag {
+                'g' => global = true,
+                'i' | 'I' => case_insensitive = true,
+                _ => return Err(format!("unknown flag '{}' in {}", flag, expression)),
+            }
+        }
+        let regex = RegexBuilder::new(&parts[0])
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| format!("invalid pattern in {}: {}", expression, e))?;
+
+        let mut replacement = String::new();
+        let mut chars = parts[1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(d) if d.is_ascii_digit() => replacement.push_str(&format!("${{{}}}", d)),
+                    Some('n') => replacement.push('\n'),
+                    Some('t') => replacement.push('\t'),
+                    Some(other) => replacement.push(other),
+                    None => replacement.push('\\'),
+                },
+                '&' => replacement.push_str("${0}"),
+                '$' => replacement.push_str("$$"),
+                c => replacement.push(c),
+            }
+        }
+
+        Ok(Self {
+            regex,
+            replacement,
+            global,
+        })
+    }
+
+    /// Apply the substitution, returning the new text and the number of substitutions.
+    pub fn apply(&self, text: &str) -> (String, usize) {
+        let limit = if self.global { 0 } else { 1 };
+        let count = match self.regex.find_iter(text).count() {
+            n if self.global => n,
+            n => n.min(1),
+        };
+        (
+            self.regex
+                .replacen(text, limit, self.replacement.as_str())
+                .to_string(),
+            count,
+        )
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SedReplaceArgs {
+    pub file_path: PathBuf,
+    /// Expressions in the form `s/pattern/replacement/flags`, applied in order.
+    pub expressions: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SedReplaceTool {
+    pub cwd: PathBuf,
+    pub journal: Option<FsJournal>,
+}
+
+impl SedReplaceTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self { cwd, journal: None }
+    }
+
+    pub fn with_journal(mut self, journal: FsJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub async fn sed_replace(
+        &self,
+        file_path: PathBuf,
+        expressions: Vec<String>,
+    ) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        let mut parsed = vec![];
+        for expression in &expressions {
+            match SedExpression::parse(expression) {
+                Ok(e) => parsed.push(e),
+                Err(e) => return Ok(format!("Fail to parse expression: {}", e)),
+            }
+        }
+        let mut content = match tokio::fs::read(&target_path).await {
+            Ok(buf) => match String::from_utf8(buf) {
+                Ok(s) => s,
+                Err(_) => return Ok(format!("{:?} is not a UTF-8 text file", &file_path)),
+            },
+            Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+        };
+
+        let mut total = 0;
+        for expression in &parsed {
+            let (updated, count) = expression.apply(&content);
+            content = updated;
+            total += count;
+        }
+        if total > 0 {
+            if let Some(journal) = &self.journal {
+                journal.record(&target_path).await?;
+            }
+            atomic_write(&target_path, &content).await?;
+        }
+
+        Ok(format!(
+            "applied {} expressions, {} total substitutions",
+            parsed.len(),
+            total
+        ))
+    }
+}
+
+impl Tool for SedReplaceTool {
+    type ARGUMENTS = SedReplaceArgs;
+    const NAME: &str = "sed_replace";
+    const DESCRIPTION: Option<&str> = Some(
+        "Apply a list of sed-like substitutions `s/pattern/replacement/flags` to the file `file_path` in order. The pattern is a regex, use \\1 or & in the replacement to refer to captured groups. Flags: 'g' replaces all occurrences instead of the first one, 'i' matches case-insensitively. The path should be always relative path and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.sed_replace(arguments.file_path, arguments.expressions)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ReplaceInFilesArgs {
+    /// A directory to search recursively or a single file.
+    pub directory: PathBuf,
+    pub pattern: String,
+    /// Can refer to capture groups with `$1` or `${name}` unless `literal` is set.
+    pub replacement: String,
+    /// Treat `pattern` and `replacement` as literal strings instead of a regex.
+    pub literal: Option<bool>,
+    /// Only touch files matching any of these glob patterns, e.g. `*.rs` or `src/**/*.rs`.
+    pub include_globs: Option<Vec<String>>,
+    /// Only report what would change, true by default.
+    pub dry_run: Option<bool>,
+}
+
+/// The files under `target` that [`ReplaceInFilesTool`] may rewrite.
+///
+/// Hidden and gitignored files are left out, and so are `.git`, the staging directory and
+/// the journal directory `skip_dir` whatever the ignore files say, as rewriting those
+/// corrupts the repository or the undo log.
+fn replace_candidates(
+    target: &Path,
+    globs: Option<&FileGlobs>,
+    skip_dir: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    if target.is_file() {
+        return vec![target.to_path_buf()];
+    }
+    let skip_dir = skip_dir.and_then(|dir| dir.canonicalize().ok());
+    let mut candidates = vec![];
+    for result in WalkBuilder::new(target)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |ent| {
+            if !ent.file_type().is_some_and(|t| t.is_dir()) {
+                return true;
+            }
+            let name = ent.file_name();
+            if name == ".git" || name == STAGING_DIR {
+                return false;
+            }
+            // Only canonicalize the directories that may be the journal.
+            !skip_dir.as_ref().is_some_and(|skip| {
+                skip.file_name() == Some(name)
+                    && ent.path().canonicalize().ok().as_ref() == Some(skip)
+            })
+        })
+        .build()
+    {
+        let ent = match result {
+            Ok(ent) => ent,
+            Err(err) => {
+                warn!("Fail to walk due to {}", err);
+                continue;
+            }
+        };
+        if !ent.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let rel = ent.path().strip_prefix(target).unwrap_or(ent.path());
+        if let Some(globs) = globs
+            && !globs.is_match(rel)
+        {
+            continue;
+        }
+        candidates.push(ent.into_path());
+    }
+    candidates
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplaceInFilesTool {
+    pub cwd: PathBuf,
+    pub journal: Option<FsJournal>,
+    /// Larger files, usually generated or minified, are left alone.
+    pub max_filesize: u64,
+    /// The diff in the response is cut after this many bytes.
+    pub max_diff: usize,
+}
+
+impl ReplaceInFilesTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            journal: None,
+            max_filesize: DEFAULT_MAX_FILESIZE,
+            max_diff: 16384,
+        }
+    }
+
+    pub fn with_journal(mut self, journal: FsJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub async fn replace_in_files(
+        &self,
+        arguments: ReplaceInFilesArgs,
+    ) -> Result<String, AgentyError> {
+        let literal = arguments.literal.unwrap_or(false);
+        let dry_run = arguments.dry_run.unwrap_or(true);
+        let target_path = match sanitize_join_relative_path(&self.cwd, &arguments.directory) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        if !target_path.exists() {
+            return Ok(format!("{:?} does not exist", &arguments.directory));
+        }
+        if arguments
+            .directory
+            .components()
+            .any(|c| c.as_os_str() == ".git" || c.as_os_str() == STAGING_DIR)
+        {
+            return Ok(format!(
+                "{:?} is internal to git or the staging, replacing in it is not allowed",
+                &arguments.directory
+            ));
+        }
+        let globs = match arguments
+            .include_globs
+            .map(|f| FileGlobs::new(&f, false))
+            .transpose()
+        {
+            Ok(globs) => globs,
+            Err(e) => return Ok(e),
+        };
+        let regex = if literal {
+            regex::escape(&arguments.pattern)
+        } else {
+            arguments.pattern.clone()
+        };
+        let regex = match Regex::new(&regex) {
+            Ok(r) => r,
+            Err(e) => return Ok(format!("regex {} error with {}", &arguments.pattern, e)),
+        };
+
+        let skip_dir = self.journal.as_ref().map(|j| j.dir().to_path_buf());
+        let candidates = tokio::task::spawn_blocking(move || {
+            replace_candidates(&target_path, globs.as_ref(), skip_dir)
+        })
+        .await?;
+
+        // Compute every replacement before writing anything, so a failure to read one file
+        // does not leave the others half done.
+        let mut changes = vec![];
+        for path in candidates {
+            let Ok(meta) = tokio::fs::metadata(&path).await else {
+                continue;
+            };
+            if meta.len() > self.max_filesize {
+                continue;
+            }
+            let Ok(original) = tokio::fs::read(&path).await else {
+                continue;
+            };
+            if original.contains(&0) {
+                continue;
+            }
+            let Ok(original) = String::from_utf8(original) else {
+                continue;
+            };
+            let count = regex.find_iter(&original).count();
+            if count == 0 {
+                continue;
+            }
+            let updated = if literal {
+                regex.replace_all(&original, NoExpand(&arguments.replacement))
+            } else {
+                regex.replace_all(&original, arguments.replacement.as_str())
+            }
+            .to_string();
+            if updated != original {
+                changes.push((path, original, updated, count));
+            }
+        }
+        if changes.is_empty() {
+            return Ok(format!("No matches of {} found", &arguments.pattern));
+        }
+
+        let mut summary = vec![];
+        let mut diff = String::new();
+        for (path, original, updated, count) in &changes {
+            let rel = path.strip_prefix(&self.cwd).unwrap_or(path);
+            summary.push(format!("{}: {} replacements", rel.display(), count));
+            diff.push_str(
+                &TextDiff::from_lines(original, updated)
+                    .unified_diff()
+                    .header(&rel.to_string_lossy(), &rel.to_string_lossy())
+                    .to_string(),
+            );
+        }
+        let total: usize = changes.iter().map(|c| c.3).sum();
+
+        if !dry_run {
+            for (path, _, updated, _) in &changes {
+                if let Some(journal) = &self.journal {
+                    journal.record(path).await?;
+                }
+                atomic_write(path, updated).await?;
+            }
+        }
+
+        let cut = truncate_at_line_boundary(&diff, self.max_diff);
+        let diff = if cut.len() < diff.len() {
+            format!("{}(diff truncated, {} of {} bytes shown)", cut, cut.len(), diff.len())
+        } else {
+            diff
+        };
+        Ok(format!(
+            "{} {} replacements in {} files{}:\n{}\n{}",
+            if dry_run { "Would make" } else { "Made" },
+            total,
+            changes.len(),
+            if dry_run {
+                ", nothing written, call again with `dry_run` false to apply"
+            } else {
+                ""
+            },
+            summary.join("\n"),
+            diff
+        ))
+    }
+}
+
+impl Tool for ReplaceInFilesTool {
+    type ARGUMENTS = ReplaceInFilesArgs;
+    const NAME: &str = "replace_in_files";
+    const DESCRIPTION: Option<&str> = Some(
+        "Replace every match of the regex `pattern` with `replacement` in all files under `directory`, which can also be a single file. Use $1 or ${name} in the replacement to refer to captured groups, or set `literal` to true to replace a plain string. Pass glob patterns in `include_globs` to only touch matching files, e.g. '*.rs'. Binary, very large, hidden and gitignored files are skipped. By default `dry_run` is true and only the number of replacements per file and a unified diff are returned without changing anything, review them and call again with `dry_run` false to write the files. The path should be always relative path and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.replace_in_files(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replace_skips_internal_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (path, content) in [
+            ("src/lib.rs", "// branch main\n"),
+            (".git/HEAD", "ref: refs/heads/main\n"),
+            (".agenty_staging/stage-1/src/lib.rs", "main\n"),
+            ("target/debug/build.log", "main\n"),
+            (".gitignore", "target/\n"),
+        ] {
+            std::fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+            std::fs::write(root.join(path), content).unwrap();
+        }
+        let journal = FsJournal::new(root.join("journal")).await.unwrap();
+        std::fs::write(root.join("journal/notes.txt"), "main\n").unwrap();
+
+        let resp = ReplaceInFilesTool::new(root.to_path_buf())
+            .with_journal(journal)
+            .replace_in_files(ReplaceInFilesArgs {
+                directory: PathBuf::from("."),
+                pattern: "main".to_string(),
+                replacement: "trunk".to_string(),
+                literal: Some(true),
+                include_globs: None,
+                dry_run: Some(false),
+            })
+            .await
+            .unwrap();
+        assert!(resp.starts_with("Made 1 replacements in 1 files"), "{}", resp);
+        let read = |path: &str| std::fs::read_to_string(root.join(path)).unwrap();
+        assert_eq!(read("src/lib.rs"), "// branch trunk\n");
+        assert_eq!(read(".git/HEAD"), "ref: refs/heads/main\n");
+        assert_eq!(read(".agenty_staging/stage-1/src/lib.rs"), "main\n");
+        assert_eq!(read("target/debug/build.log"), "main\n");
+        assert_eq!(read("journal/notes.txt"), "main\n");
+    }
+
+    #[tokio::test]
+    async fn test_sed_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "foo foo baz\nbaz foo\n").unwrap();
+        let resp = SedReplaceTool::new(dir.path().to_path_buf())
+            .sed_replace(
+                PathBuf::from("notes.txt"),
+                vec!["s/foo/bar/g".to_string(), "s/baz/qux/".to_string()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp, "applied 2 expressions, 4 total substitutions");
+        // Without `g` only the first occurrence in the whole file is replaced.
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "bar bar qux\nbaz bar\n");
+
+        let resp = SedReplaceTool::new(dir.path().to_path_buf())
+            .sed_replace(PathBuf::from("notes.txt"), vec!["s/foo".to_string()])
+            .await
+            .unwrap();
+        assert!(resp.starts_with("Fail to parse expression"), "{}", resp);
+    }
+}
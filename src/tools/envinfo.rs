@@ -0,0 +1,121 @@
+use std::{future::Future, path::PathBuf};
+
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvInfoOp {
+    GetEnv,
+    Platform,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct EnvInfoArgs {
+    pub op: EnvInfoOp,
+    /// The variable for `get_env`, all allowed variables if not given.
+    pub name: Option<String>,
+}
+
+/// The name of this machine, `None` if it can not be told.
+fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if ret != 0 {
+            return None;
+        }
+        // Truncated names may lack the terminating NUL.
+        let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        Some(String::from_utf8_lossy(&buf[..len]).to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok()
+    }
+}
+
+/// Answers questions about environment variables and the platform the agent runs on.
+///
+/// Only the variables of the allowlist are ever read, as the environment usually holds
+/// secrets like API keys. The allowlist is required by [`EnvInfoTool::new`] so it can not be
+/// forgotten, pass an empty one to only offer `platform`.
+#[derive(Debug, Clone)]
+pub struct EnvInfoTool {
+    /// Reported as the working directory.
+    pub cwd: PathBuf,
+    /// The names of the variables the model may read, matched exactly.
+    pub allowed_vars: Vec<String>,
+}
+
+impl EnvInfoTool {
+    pub fn new(cwd: PathBuf, allowed_vars: Vec<String>) -> Self {
+        Self { cwd, allowed_vars }
+    }
+
+    fn get_env(&self, name: &str) -> String {
+        if !self.allowed_vars.iter().any(|v| v == name) {
+            return format!(
+                "{} is not allowed, the allowed variables are: {}",
+                name,
+                if self.allowed_vars.is_empty() {
+                    "none".to_string()
+                } else {
+                    self.allowed_vars.join(", ")
+                }
+            );
+        }
+        match std::env::var_os(name) {
+            Some(value) => format!("{}={}", name, value.to_string_lossy()),
+            None => format!("{} is not set", name),
+        }
+    }
+
+    fn platform(&self) -> String {
+        let cpus = std::thread::available_parallelism()
+            .map_or_else(|_| "unknown".to_string(), |n| n.to_string());
+        format!(
+            "os: {} ({})\narch: {}\nhostname: {}\ncpus: {}\ncwd: {}",
+            std::env::consts::OS,
+            std::env::consts::FAMILY,
+            std::env::consts::ARCH,
+            hostname().unwrap_or_else(|| "unknown".to_string()),
+            cpus,
+            self.cwd.display()
+        )
+    }
+
+    pub async fn env_info(&self, arguments: EnvInfoArgs) -> Result<String, AgentyError> {
+        Ok(match (arguments.op, arguments.name) {
+            (EnvInfoOp::GetEnv, Some(name)) => self.get_env(&name),
+            (EnvInfoOp::GetEnv, None) if self.allowed_vars.is_empty() => {
+                "No environment variables are allowed".to_string()
+            }
+            (EnvInfoOp::GetEnv, None) => self
+                .allowed_vars
+                .iter()
+                .map(|name| self.get_env(name))
+                .join("\n"),
+            (EnvInfoOp::Platform, _) => self.platform(),
+        })
+    }
+}
+
+impl Tool for EnvInfoTool {
+    type ARGUMENTS = EnvInfoArgs;
+    const NAME: &str = "env_info";
+    const DESCRIPTION: Option<&str> = Some(
+        "Get facts about the environment: `op` 'get_env' returns the value of the environment variable `name`, or of all variables you may read without `name`, and tells apart variables that are not set from those you are not allowed to read. 'platform' returns the operating system, CPU architecture, hostname, number of CPUs and the working directory.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.env_info(arguments)
+    }
+}
@@ -1,18 +1,36 @@
 use std::{
+    collections::BTreeSet,
     future::Future,
     path::{Component, Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
 };
 
-use color_eyre::eyre::{OptionExt, eyre};
-use hxd::AsHexd;
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use hxd::{
+    AsHexd,
+    options::{GroupSize, Grouping, HexdOptions, HexdOptionsBuilder, Spacing},
+};
 use itertools::Itertools;
+use log::warn;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
 use tokio::io::AsyncReadExt;
 use tokio_stream::{StreamExt, wrappers::ReadDirStream};
 
 use crate::{error::AgentyError, tool::Tool};
 
+use super::{
+    journal::FsJournal,
+    lang::{detect_language, top_level_items},
+    staging::{STAGING_DIR, create_stage, stage_dir},
+};
+
 pub fn sanitize_join_relative_path(cwd: &Path, rpath: &Path) -> Result<PathBuf, String> {
     if rpath.is_absolute() {
         return Err(format!("{:?} is an absolute path", rpath));
@@ -24,31 +42,348 @@ pub fn sanitize_join_relative_path(cwd: &Path, rpath: &Path) -> Result<PathBuf,
     Ok(cwd.join(rpath))
 }
 
+/// Cut `s` to at most `max` bytes, ending at the last complete line that fits.
+/// Falls back to the last char boundary when not even a single line fits.
+pub fn truncate_at_line_boundary(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    match s[..end].rfind('\n') {
+        Some(idx) => &s[..idx + 1],
+        None => &s[..end],
+    }
+}
+
+/// Format a byte count like `9.8 KiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Write `content` to a temporary file next to `path` and rename it over `path`, so readers
+/// never observe a partially written file.
+pub async fn atomic_write(path: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let fname = path
+        .file_name()
+        .map(|t| t.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(
+        ".{}.agenty-tmp-{}-{}",
+        fname,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    if let Err(e) = tokio::fs::write(&tmp, content).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    if let Err(e) = tokio::fs::rename(&tmp, path).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Whether `buf` looks like text: no NUL bytes and only a few control characters.
+pub fn looks_textual(buf: &[u8]) -> bool {
+    if buf.contains(&0) {
+        return false;
+    }
+    let controls = buf
+        .iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c')) || b == 0x7f)
+        .count();
+    controls * 100 <= buf.len()
+}
+
+/// Number of lines in `buf`, counting a last line without a trailing newline.
+fn bytecount_lines(buf: &[u8]) -> usize {
+    let newlines = buf.iter().filter(|&&b| b == b'\n').count();
+    if buf.last().is_some_and(|&b| b != b'\n') {
+        newlines + 1
+    } else {
+        newlines
+    }
+}
+
+/// How binary files are hexdumped, the defaults match the plain `hxd` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexFormat {
+    /// Bytes per line.
+    pub columns: u8,
+    /// Bytes printed without a space between them.
+    pub group_size: u8,
+    /// Show the bytes as ASCII on the right.
+    pub ascii: bool,
+}
+
+impl Default for HexFormat {
+    fn default() -> Self {
+        Self {
+            columns: 16,
+            group_size: 2,
+            ascii: true,
+        }
+    }
+}
+
+impl HexFormat {
+    /// The `hxd` options, errors are meant for the model.
+    pub fn options(&self) -> Result<HexdOptions, String> {
+        let group_size = match self.group_size {
+            1 => GroupSize::Byte,
+            2 => GroupSize::Short,
+            4 => GroupSize::Int,
+            8 => GroupSize::Long,
+            16 => GroupSize::ULong,
+            n => {
+                return Err(format!(
+                    "`hex_group_size` must be 1, 2, 4, 8 or 16, not {}",
+                    n
+                ));
+            }
+        };
+        let columns = self.columns as usize;
+        if columns == 0 || !columns.is_multiple_of(self.group_size as usize) {
+            return Err(format!(
+                "`hex_columns` must be a positive multiple of `hex_group_size` {}, not {}",
+                self.group_size, columns
+            ));
+        }
+        let grouping = match group_size {
+            GroupSize::Byte => Grouping::Ungrouped {
+                byte_count: columns,
+                spacing: Spacing::Normal,
+            },
+            _ => Grouping::Grouped {
+                group_size,
+                num_groups: columns / self.group_size as usize,
+                byte_spacing: Spacing::None,
+                group_spacing: Spacing::Normal,
+            },
+        };
+        Ok(HexdOptions {
+            grouping,
+            show_ascii: self.ascii,
+            ..HexdOptions::default()
+        })
+    }
+}
+
+/// Render raw file contents for the model.
+///
+/// Binary data, see [`looks_textual`], is hexdumped even if it is valid UTF-8. UTF-8 text is
+/// returned as-is (tolerating a character cut by a previous truncation) and other text is
+/// decoded with the detected legacy encoding and prefixed with `[decoded as ENCODING]`.
+pub fn render_file_bytes(buf: Vec<u8>) -> String {
+    render_file_bytes_with(buf, HexdOptions::default())
+}
+
+/// [`render_file_bytes`] hexdumping with `hex`, see [`HexFormat::options`].
+pub fn render_file_bytes_with(buf: Vec<u8>, hex: HexdOptions) -> String {
+    if !looks_textual(&buf) {
+        return buf.hexd().with_options(hex).dump_to::<String>();
+    }
+    let e = match String::from_utf8(buf) {
+        Ok(s) => return s,
+        Err(e) => e,
+    };
+    let valid_up_to = e.utf8_error().valid_up_to();
+    if e.utf8_error().error_len().is_none() {
+        // Only an incomplete character at the very end, most likely our own cutoff.
+        let mut buf = e.into_bytes();
+        buf.truncate(valid_up_to);
+        return String::from_utf8(buf).expect("validated above");
+    }
+    let buf = e.into_bytes();
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(&buf, true);
+    let encoding = detector.guess(None, Utf8Detection::Deny);
+    let (decoded, _, _) = encoding.decode(&buf);
+    format!("[decoded as {}]\n{}", encoding.name().to_uppercase(), decoded)
+}
+
+/// Decode `buf` as UTF-8 replacing invalid bytes with U+FFFD if less than `threshold` of its
+/// bytes are invalid, unless it is valid UTF-8 already.
+///
+/// Binary data and text in legacy encodings are left to [`render_file_bytes`]. A legacy
+/// encoding like Latin-1 has lone high bytes wherever UTF-8 would have multi-byte
+/// characters, so `buf` counts as legacy if it has at least [`MIN_LEGACY_SEQUENCES`] invalid
+/// sequences and more of them than valid multi-byte characters. A few stray bytes in ASCII
+/// text are replaced.
+pub fn lossy_utf8_below(buf: &[u8], threshold: f32) -> Option<String> {
+    match std::str::from_utf8(buf) {
+        Ok(_) => return None,
+        // Only an incomplete character at the very end, see `render_file_bytes`.
+        Err(e) if e.error_len().is_none() => return None,
+        Err(_) => {}
+    }
+    if !looks_textual(buf) {
+        return None;
+    }
+    let (mut invalid, mut sequences, mut multibyte) = (0, 0, 0);
+    for chunk in buf.utf8_chunks() {
+        if !chunk.invalid().is_empty() {
+            invalid += chunk.invalid().len();
+            sequences += 1;
+        }
+        multibyte += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+    }
+    if (invalid as f32) / (buf.len() as f32) >= threshold {
+        return None;
+    }
+    if sequences >= MIN_LEGACY_SEQUENCES && sequences > multibyte {
+        return None;
+    }
+    Some(format!(
+        "[{} invalid UTF-8 bytes replaced with U+FFFD]\n{}",
+        invalid,
+        String::from_utf8_lossy(buf)
+    ))
+}
+
+/// Invalid UTF-8 sequences a file needs at least to be taken for a legacy encoding, fewer are
+/// encoding artifacts in otherwise UTF-8 text, see [`lossy_utf8_below`].
+pub const MIN_LEGACY_SEQUENCES: usize = 4;
+
 #[derive(Deserialize, JsonSchema, Default)]
 pub struct ReadFileToolArgs {
     pub file_path: PathBuf,
+    /// For large files, return the first lines and the top-level item signatures instead of
+    /// the raw head of the file.
+    pub preview: Option<bool>,
+    /// Render CSV and TSV files as an aligned table, on by default for those files.
+    pub csv_pretty_print: Option<bool>,
+    /// Add the modification time to the header, to pass as `expected_mtime` when writing.
+    pub include_metadata: Option<bool>,
+    /// Bytes per line of the hexdump of binary files, 16 by default.
+    pub hex_columns: Option<u8>,
+    /// Bytes per group of the hexdump of binary files: 1, 2, 4, 8 or 16, 2 by default.
+    pub hex_group_size: Option<u8>,
+    /// Show the ASCII column of the hexdump of binary files, on by default.
+    pub hex_ascii: Option<bool>,
+}
+
+const MAX_TABLE_ROWS: usize = 50;
+const MAX_TABLE_CELL_CHARS: usize = 40;
+
+/// A table row of `fields` on a single line each, long fields cut.
+pub fn table_row<'a>(fields: impl IntoIterator<Item = &'a str>) -> prettytable::Row {
+    prettytable::Row::new(
+        fields
+            .into_iter()
+            .map(|field| {
+                let mut field = field.replace(['\r', '\n'], " ");
+                if field.chars().count() > MAX_TABLE_CELL_CHARS {
+                    field = field.chars().take(MAX_TABLE_CELL_CHARS - 1).collect();
+                    field.push('…');
+                }
+                prettytable::Cell::new(&field)
+            })
+            .collect(),
+    )
 }
 
+/// Render delimited data as an aligned table of the header and the first rows, `None` if it
+/// can not be parsed.
+pub fn render_table(buf: &[u8], delimiter: u8) -> Option<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(buf);
+    let mut records = reader.records();
+    let cells = |record: csv::StringRecord| table_row(&record);
+    let mut table = prettytable::Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(cells(records.next()?.ok()?));
+    for record in records.by_ref().take(MAX_TABLE_ROWS) {
+        table.add_row(cells(record.ok()?));
+    }
+    let more = records.count();
+    let mut resp = table.to_string();
+    if more > 0 {
+        resp.push_str(&format!("({} more rows)", more));
+    }
+    Some(resp)
+}
+
+pub const DEFAULT_BINARY_THRESHOLD: f32 = 0.1;
+
 #[derive(Debug, Clone)]
 pub struct ReadFileTool {
     pub cwd: PathBuf,
+    /// Files with less than this fraction of invalid UTF-8 bytes are returned as text with
+    /// the invalid bytes replaced, [`DEFAULT_BINARY_THRESHOLD`] if `None`.
+    pub binary_threshold: Option<f32>,
 }
 
 impl ReadFileTool {
     pub fn new(cwd: PathBuf) -> Self {
-        Self { cwd }
+        Self {
+            cwd,
+            binary_threshold: None,
+        }
+    }
+
+    pub fn with_binary_threshold(mut self, binary_threshold: f32) -> Self {
+        self.binary_threshold = Some(binary_threshold);
+        self
     }
 
     pub async fn read_file(&self, file_path: PathBuf) -> Result<String, AgentyError> {
+        self.read_file_with(ReadFileToolArgs {
+            file_path,
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn read_file_with(&self, arguments: ReadFileToolArgs) -> Result<String, AgentyError> {
+        let ReadFileToolArgs {
+            file_path,
+            preview,
+            csv_pretty_print,
+            include_metadata,
+            hex_columns,
+            hex_group_size,
+            hex_ascii,
+        } = arguments;
+        let preview = preview.unwrap_or_default();
+        let default_hex = HexFormat::default();
+        let hex = HexFormat {
+            columns: hex_columns.unwrap_or(default_hex.columns),
+            group_size: hex_group_size.unwrap_or(default_hex.group_size),
+            ascii: hex_ascii.unwrap_or(default_hex.ascii),
+        };
+        let hex = match hex.options() {
+            Ok(hex) => hex,
+            Err(e) => return Ok(e),
+        };
         let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
             Ok(p) => p,
             Err(e) => return Ok(e),
         };
-        match tokio::fs::metadata(&target_path).await {
+        let mtime = match tokio::fs::metadata(&target_path).await {
             Ok(meta) => {
                 if meta.is_dir() {
                     return Ok(format!("Path {:?} is a directory", &target_path));
                 }
+                meta.modified().ok()
             }
             Err(e) => {
                 return Ok(format!(
@@ -65,17 +400,76 @@ impl ReadFileTool {
         let mut buf = vec![];
         fp.read_to_end(&mut buf).await?;
 
-        let buf = if buf.len() >= 8192 {
-            // too long and cutoff
-            buf[0..8192].to_vec()
+        let size = buf.len();
+        let textual = looks_textual(&buf);
+        let language = detect_language(&file_path);
+        let mut header = if textual {
+            let lines = bytecount_lines(&buf);
+            format!(
+                "# {} — {}, {} lines, {}",
+                file_path.display(),
+                language.unwrap_or("Text"),
+                lines,
+                human_size(size as u64)
+            )
         } else {
-            buf
+            format!("# {} — binary, {}", file_path.display(), human_size(size as u64))
         };
+        if include_metadata.unwrap_or_default()
+            && let Some(mtime) = mtime
+        {
+            header.push_str(&format!("\n# mtime: {}", serde_json::to_string(&mtime)?));
+        }
 
-        match String::from_utf8(buf) {
-            Ok(s) => Ok(s),
-            Err(e) => Ok(e.into_bytes().hexd().dump_to::<String>()),
+        let delimiter = match language {
+            Some("CSV") => Some(b','),
+            Some("TSV") => Some(b'\t'),
+            _ => None,
+        };
+        if let Some(delimiter) = delimiter
+            && textual
+            && csv_pretty_print.unwrap_or(true)
+            && let Some(table) = render_table(&buf, delimiter)
+        {
+            return Ok(format!("{} (as table)\n{}", header, table));
+        }
+
+        if size >= 8192 {
+            if preview && textual {
+                let text = String::from_utf8_lossy(&buf);
+                let items = language
+                    .map(|l| top_level_items(l, &text))
+                    .unwrap_or_default();
+                let mut resp = format!(
+                    "{} (preview)\n{}\n...",
+                    header,
+                    text.lines().take(50).join("\n")
+                );
+                if !items.is_empty() {
+                    resp.push_str("\n\nTop-level items:\n");
+                    resp.push_str(
+                        &items
+                            .into_iter()
+                            .take(200)
+                            .map(|(ln, sig)| format!("{}: {}", ln, sig))
+                            .join("\n"),
+                    );
+                }
+                return Ok(resp);
+            }
+            // too long and cutoff
+            buf.truncate(8192);
+            header.push_str(" (showing the first 8192 bytes)");
         }
+
+        let threshold = self.binary_threshold.unwrap_or(DEFAULT_BINARY_THRESHOLD);
+        // Files with NULs or many control bytes are hexdumped even if they are valid UTF-8.
+        let text = textual.then(|| lossy_utf8_below(&buf, threshold)).flatten();
+        let body = match text {
+            Some(text) => text,
+            None => render_file_bytes_with(buf, hex),
+        };
+        Ok(format!("{}\n{}", header, body))
     }
 }
 
@@ -83,28 +477,78 @@ impl Tool for ReadFileTool {
     type ARGUMENTS = ReadFileToolArgs;
     const NAME: &str = "read_file";
     const DESCRIPTION: Option<&str> = Some(
-        "Read file contents of the path `file_path`. The result will be hexdump if the file is a binary file.",
+        "Read file contents of the path `file_path`. The result starts with a header line with the detected language, line count and size. Large files are cut off; pass `preview: true` to get the first 50 lines plus the top-level item signatures of a large file instead. A few invalid bytes in otherwise UTF-8 text are replaced with U+FFFD, other text in legacy encodings is decoded and prefixed with the detected encoding. CSV and TSV files are rendered as a table of the first rows unless `csv_pretty_print` is false. Set `include_metadata` to true to get the modification time of the file in the header as well. The result will be hexdump if the file is a binary file, `hex_columns`, `hex_group_size` and `hex_ascii` only apply to binary files and set the bytes per line, the bytes per group and whether to show the ASCII column.",
     );
 
     fn invoke(
         &self,
         arguments: Self::ARGUMENTS,
     ) -> impl Future<Output = Result<String, AgentyError>> + Send {
-        self.read_file(arguments.file_path)
+        self.read_file_with(arguments)
     }
 }
 
-pub fn list_files(cwd: &Path, fpaths: Vec<PathBuf>) -> Result<Vec<String>, AgentyError> {
-    let mut lns = vec![];
-    let cwd = cwd.canonicalize()?;
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Alphabetical.
+    #[default]
+    Name,
+    /// Largest first.
+    Size,
+    /// Most recently modified first.
+    Modified,
+}
+
+/// A file as listed by [`list_entries`].
+#[derive(Debug, Clone, Serialize)]
+pub struct FileEntry {
+    /// Relative to the listing `cwd`.
+    pub path: PathBuf,
+    /// `file`, `directory` or `symlink`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub size: u64,
+}
+
+/// The entries of `fpaths`, in their order unless `sort_by` is given.
+pub fn list_entries(
+    cwd: &Path,
+    fpaths: Vec<PathBuf>,
+    sort_by: Option<SortBy>,
+) -> Result<Vec<FileEntry>, AgentyError> {
+    let mut entries = vec![];
     for fp in fpaths {
         let meta = fp.metadata()?;
-        let ln = format!(
-            "{:?}\t{}\t{}",
-            fp.canonicalize()?
-                .strip_prefix(&cwd)
-                .expect(&format!("{:?} not relative to {:?}?!", &fp, cwd)),
-            if meta.is_dir() {
+        let modified = meta.modified().unwrap_or(std::time::UNIX_EPOCH);
+        entries.push((fp, meta, modified));
+    }
+    match sort_by {
+        Some(SortBy::Name) => entries.sort_by(|a, b| a.0.cmp(&b.0)),
+        Some(SortBy::Size) => {
+            entries.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)))
+        }
+        Some(SortBy::Modified) => {
+            entries.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)))
+        }
+        None => {}
+    }
+
+    let mut listed = vec![];
+    let canonical_cwd = cwd.canonicalize()?;
+    for (fp, meta, _) in entries {
+        // Paths reached through a symlink resolve outside of `cwd`, keep them as walked.
+        let rel = match fp.strip_prefix(cwd) {
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => fp
+                .canonicalize()?
+                .strip_prefix(&canonical_cwd)
+                .unwrap_or_else(|_| panic!("{:?} not relative to {:?}?!", &fp, cwd))
+                .to_path_buf(),
+        };
+        listed.push(FileEntry {
+            path: rel,
+            kind: if meta.is_dir() {
                 "directory"
             } else if meta.is_file() {
                 "file"
@@ -113,28 +557,73 @@ pub fn list_files(cwd: &Path, fpaths: Vec<PathBuf>) -> Result<Vec<String>, Agent
             } else {
                 ""
             },
-            meta.len()
-        );
-        lns.push(ln);
+            size: meta.len(),
+        });
     }
-    Ok(lns)
+    Ok(listed)
+}
+
+/// One line per file, in the order of `fpaths` unless `sort_by` is given.
+pub fn list_files(
+    cwd: &Path,
+    fpaths: Vec<PathBuf>,
+    sort_by: Option<SortBy>,
+) -> Result<Vec<String>, AgentyError> {
+    Ok(list_entries(cwd, fpaths, sort_by)?
+        .into_iter()
+        .map(|e| format!("{:?}\t{}\t{}", e.path, e.kind, e.size))
+        .collect())
 }
 
 #[derive(Deserialize, JsonSchema)]
 pub struct ListDirectoryToolArgs {
     pub relative_path: PathBuf,
+    /// `name` by default.
+    pub sort_by: Option<SortBy>,
+    /// List entries starting with a dot like `.git`, false by default.
+    pub show_hidden: Option<bool>,
+}
+
+/// Whether the name of `path` starts with a dot.
+pub fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
 }
 
 #[derive(Debug, Clone)]
 pub struct ListDirectoryTool {
     pub cwd: PathBuf,
+    /// Whether hidden entries are listed when the model does not say.
+    pub show_hidden: bool,
 }
 
 impl ListDirectoryTool {
     pub fn new_root(path: PathBuf) -> Self {
-        Self { cwd: path }
+        Self {
+            cwd: path,
+            show_hidden: false,
+        }
+    }
+
+    /// Like [`ListDirectoryTool::new_root`] but hidden entries are listed by default.
+    pub fn new_show_hidden(path: PathBuf) -> Self {
+        Self {
+            cwd: path,
+            show_hidden: true,
+        }
     }
+
     pub async fn list_directory(&self, relative_path: PathBuf) -> Result<String, AgentyError> {
+        self.list_directory_sorted(relative_path, SortBy::default(), self.show_hidden)
+            .await
+    }
+
+    pub async fn list_directory_sorted(
+        &self,
+        relative_path: PathBuf,
+        sort_by: SortBy,
+        show_hidden: bool,
+    ) -> Result<String, AgentyError> {
         let target_path = match sanitize_join_relative_path(&self.cwd, &relative_path) {
             Ok(p) => p,
             Err(e) => return Ok(e),
@@ -147,9 +636,11 @@ impl ListDirectoryTool {
         let mut items = vec![];
         while let Some(ent) = st.next().await {
             let ent = ent?;
-            items.push(ent.path());
+            if show_hidden || !is_hidden(&ent.path()) {
+                items.push(ent.path());
+            }
         }
-        let lns = list_files(&self.cwd, items)?;
+        let lns = list_files(&self.cwd, items, Some(sort_by))?;
         Ok(format!(
             "The contents of folder {:?} is:\nname\ttype\tsize\n{}",
             &relative_path,
@@ -162,14 +653,18 @@ impl Tool for ListDirectoryTool {
     type ARGUMENTS = ListDirectoryToolArgs;
     const NAME: &str = "list_dir";
     const DESCRIPTION: Option<&str> = Some(
-        "List a given directory entries. '.' is allowed to list entries of the root directory but '..' is not allowed to avoid path traversal. Absolute path is not allowed and you shall always use relative path to the root directory.",
+        "List a given directory entries. '.' is allowed to list entries of the root directory but '..' is not allowed to avoid path traversal. Absolute path is not allowed and you shall always use relative path to the root directory. Entries are sorted by `sort_by`: 'name' (default), 'size' for the largest first or 'modified' for the most recently modified first. Entries starting with a dot like '.git' are only listed with `show_hidden` set to true.",
     );
 
     fn invoke(
         &self,
         arguments: Self::ARGUMENTS,
     ) -> impl Future<Output = Result<String, AgentyError>> + Send {
-        self.list_directory(arguments.relative_path)
+        self.list_directory_sorted(
+            arguments.relative_path,
+            arguments.sort_by.unwrap_or_default(),
+            arguments.show_hidden.unwrap_or(self.show_hidden),
+        )
     }
 }
 
@@ -177,25 +672,104 @@ impl Tool for ListDirectoryTool {
 pub struct FindFileArgs {
     pub directory: PathBuf,
     pub file_name_pattern: String,
+    /// More patterns, a file matching any of the patterns is returned.
+    pub file_name_patterns: Option<Vec<String>>,
+    /// Match case-insensitively, true by default.
+    pub case_insensitive: Option<bool>,
+    /// Descend into symlinked directories, false by default.
+    pub follow_symlinks: Option<bool>,
+    /// `text` by default.
+    pub output_format: Option<FindFileFormat>,
+    /// Also search files and directories starting with a dot, false by default.
+    pub show_hidden: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FindFileFormat {
+    #[default]
+    Text,
+    /// An array of `{"path", "type", "size"}` objects.
+    Json,
 }
 
+/// Glob patterns selecting files below a directory.
+///
+/// Patterns with a separator are matched against the path relative to the directory,
+/// others against the file name only.
+#[derive(Debug, Clone)]
+pub struct FileGlobs {
+    name_set: GlobSet,
+    path_set: GlobSet,
+}
+
+impl FileGlobs {
+    pub fn new(patterns: &[String], case_insensitive: bool) -> Result<Self, String> {
+        let mut name_set = GlobSetBuilder::new();
+        let mut path_set = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern)
+                .case_insensitive(case_insensitive)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("Fail to compile the glob pattern due to {}", e))?;
+            if pattern.contains('/') {
+                path_set.add(glob);
+            } else {
+                name_set.add(glob);
+            }
+        }
+        match (name_set.build(), path_set.build()) {
+            (Ok(name_set), Ok(path_set)) => Ok(Self { name_set, path_set }),
+            (Err(e), _) | (_, Err(e)) => {
+                Err(format!("Fail to compile the glob pattern due to {}", e))
+            }
+        }
+    }
+
+    /// Whether `rel`, relative to the searched directory, matches any of the patterns.
+    pub fn is_match(&self, rel: &Path) -> bool {
+        rel.file_name()
+            .is_some_and(|name| self.name_set.is_match(name))
+            || self.path_set.is_match(rel)
+    }
+}
+
+/// Files returned at most by [`FindFileTool`].
+pub const MAX_FIND_RESULTS: usize = 200;
+
 #[derive(Debug, Clone)]
 pub struct FindFileTool {
     pub cwd: PathBuf,
+    /// Whether hidden files and directories are searched when the model does not say.
+    pub show_hidden: bool,
 }
 
 impl FindFileTool {
     pub fn new(path: PathBuf) -> Self {
-        Self { cwd: path }
+        Self {
+            cwd: path,
+            show_hidden: false,
+        }
+    }
+
+    pub fn with_show_hidden(mut self, show_hidden: bool) -> Self {
+        self.show_hidden = show_hidden;
+        self
     }
+
     pub fn find_file(
         cwd: PathBuf,
         directory: PathBuf,
-        pattern: String,
+        patterns: Vec<String>,
+        case_insensitive: bool,
+        follow_symlinks: bool,
+        format: FindFileFormat,
+        show_hidden: bool,
     ) -> Result<String, AgentyError> {
-        let re = match glob::Pattern::new(&pattern) {
-            Ok(re) => re,
-            Err(e) => return Ok(format!("Fail to compile the glob pattern due to {}", e)),
+        let globs = match FileGlobs::new(&patterns, case_insensitive) {
+            Ok(globs) => globs,
+            Err(e) => return Ok(e),
         };
 
         let target_path = match sanitize_join_relative_path(&cwd, &directory) {
@@ -206,24 +780,52 @@ impl FindFileTool {
             return Ok(format!("{:?} is not a directory", &target_path));
         }
 
-        let mut items = vec![];
-        for ent in walkdir::WalkDir::new(&target_path) {
-            let ent = ent?;
-            let fname = ent
-                .file_name()
-                .to_str()
-                .ok_or_eyre(eyre!("non-utf8 fname ignored {:?}", &ent))?;
-            if re.matches(&fname) {
-                items.push(ent.path().to_path_buf());
+        let mut items = BTreeSet::new();
+        // The searched directory itself may be hidden, only what is below it is skipped.
+        // Staged files are not written yet, so they are skipped even when hidden ones are not.
+        let walk = walkdir::WalkDir::new(&target_path)
+            .follow_links(follow_symlinks)
+            .into_iter()
+            .filter_entry(|ent| {
+                ent.depth() == 0
+                    || (ent.file_name() != STAGING_DIR && (show_hidden || !is_hidden(ent.path())))
+            });
+        for ent in walk {
+            // Symlink loops and unreadable directories should not fail the whole search
+            let ent = match ent {
+                Ok(ent) => ent,
+                Err(e) => {
+                    warn!("Fail to walk due to {}", e);
+                    continue;
+                }
+            };
+            let rel = ent
+                .path()
+                .strip_prefix(&target_path)
+                .expect("walked outside target?!");
+            if !rel.as_os_str().is_empty() && globs.is_match(rel) {
+                items.insert(ent.path().to_path_buf());
             }
         }
-        let lns = list_files(&cwd, items)?;
-        Ok(format!(
+        let found = items.len();
+        let items = items.into_iter().take(MAX_FIND_RESULTS).collect();
+        if format == FindFileFormat::Json {
+            return Ok(serde_json::to_string(&list_entries(&cwd, items, None)?)?);
+        }
+        let lns = list_files(&cwd, items, None)?;
+        let mut resp = format!(
             "The files found under directory {:?} with given pattern {} are:\n{}",
             &directory,
-            &pattern,
+            patterns.iter().join(", "),
             lns.into_iter().join("\n")
-        ))
+        );
+        if found > MAX_FIND_RESULTS {
+            resp.push_str(&format!(
+                "\n... and {} more, use a more specific pattern",
+                found - MAX_FIND_RESULTS
+            ));
+        }
+        Ok(resp)
     }
 }
 
@@ -231,7 +833,7 @@ impl Tool for FindFileTool {
     type ARGUMENTS = FindFileArgs;
     const NAME: &str = "find_file";
     const DESCRIPTION: Option<&str> = Some(
-        "Find files with names having the given glob pattern under the given directory. For example, use '*.c' to find all C source files. For directory, note '.' is allowed to list entries of the root directory but '..' is not allowed to avoid path traversal. Absolute path is not allowed and you shall always use relative path to the root directory.",
+        "Find files with names having the given glob pattern under the given directory. For example, use '*.c' to find all C source files. If the pattern contains a '/', it is matched against the path relative to the given directory instead of the file name, e.g. 'src/**/*.rs' finds Rust files at any depth under src and 'tests/*_integration.rs' only directly under tests. More patterns can be given in `file_name_patterns` and files matching any of them are returned. Matching is case-insensitive unless `case_insensitive` is false. Symlinked directories are only entered with `follow_symlinks` set to true, and files and directories starting with a dot only with `show_hidden` set to true. At most 200 files are returned, as text or with `output_format` 'json' as an array of objects with `path`, `type` and `size`. For directory, note '.' is allowed to list entries of the root directory but '..' is not allowed to avoid path traversal. Absolute path is not allowed and you shall always use relative path to the root directory.",
     );
 
     fn invoke(
@@ -239,9 +841,22 @@ impl Tool for FindFileTool {
         arguments: Self::ARGUMENTS,
     ) -> impl Future<Output = Result<String, AgentyError>> + Send {
         let cwd = self.cwd.clone();
+        let show_hidden = self.show_hidden;
         async move {
             tokio::task::spawn_blocking(move || {
-                Self::find_file(cwd, arguments.directory, arguments.file_name_pattern)
+                let patterns = std::iter::once(arguments.file_name_pattern)
+                    .chain(arguments.file_name_patterns.unwrap_or_default())
+                    .unique()
+                    .collect();
+                Self::find_file(
+                    cwd,
+                    arguments.directory,
+                    patterns,
+                    arguments.case_insensitive.unwrap_or(true),
+                    arguments.follow_symlinks.unwrap_or(false),
+                    arguments.output_format.unwrap_or_default(),
+                    arguments.show_hidden.unwrap_or(show_hidden),
+                )
             })
             .await
             .expect("fail to join")
@@ -249,39 +864,179 @@ impl Tool for FindFileTool {
     }
 }
 
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    /// Write the line endings exactly as given.
+    #[default]
+    Preserve,
+}
+
+impl LineEnding {
+    /// Convert all line endings of `content` to this style.
+    pub fn normalize(&self, content: &str) -> String {
+        match self {
+            Self::Preserve => content.to_string(),
+            Self::Lf => content.replace("\r\n", "\n"),
+            Self::CrLf => content.replace("\r\n", "\n").replace('\n', "\r\n"),
+        }
+    }
+}
+
 #[derive(Deserialize, JsonSchema)]
 pub struct WriteFileArgs {
     pub file_path: PathBuf,
     pub content: String,
+    /// Normalize line endings to `Lf` or `CrLf` before writing, `Preserve` by default.
+    pub line_ending: Option<LineEnding>,
+    /// Remove the UTF-8 byte order mark at the start of the content.
+    pub strip_bom: Option<bool>,
+    /// The mtime reported by `read_file`, the write is refused if the file changed since.
+    pub expected_mtime: Option<SystemTime>,
+    /// Only return the unified diff the write would make, false by default.
+    pub dry_run: Option<bool>,
 }
 
+pub const WRITE_CONFLICT: &str = "conflict: file modified since last read";
+
 #[derive(Debug, Clone)]
 pub struct WriteFileTool {
     pub cwd: PathBuf,
+    pub journal: Option<FsJournal>,
+    /// The id of the open stage in staged mode, shared by all clones.
+    pub stage: Option<Arc<Mutex<Option<String>>>>,
 }
 
 impl WriteFileTool {
     pub fn new(cwd: PathBuf) -> Self {
-        Self { cwd }
+        Self {
+            cwd,
+            journal: None,
+            stage: None,
+        }
+    }
+
+    pub fn with_journal(mut self, journal: FsJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Write into a stage under [`STAGING_DIR`] instead of the destination.
+    ///
+    /// Writes go to the same stage until the model commits it with
+    /// [`super::staging::CommitStagingTool`] or discards it with
+    /// [`super::staging::RollbackStagingTool`], so register those as well. Other tools keep
+    /// seeing the original files until the commit.
+    pub fn staged(mut self) -> Self {
+        self.stage = Some(Arc::new(Mutex::new(None)));
+        self
+    }
+
+    /// The open stage, opening a new one if the last was committed or rolled back.
+    fn current_stage(&self, stage: &Mutex<Option<String>>) -> std::io::Result<String> {
+        let mut stage = stage.lock().expect("poisoned");
+        if let Some(id) = stage.as_ref()
+            && stage_dir(&self.cwd, id).is_ok()
+        {
+            return Ok(id.clone());
+        }
+        let id = create_stage(&self.cwd)?;
+        *stage = Some(id.clone());
+        Ok(id)
     }
 
     pub async fn write_file(&self, file_path: PathBuf, content: String) -> Result<String, AgentyError> {
+        self.write_file_with(file_path, content, LineEnding::Preserve, false, None, false)
+            .await
+    }
+
+    pub async fn write_file_with(
+        &self,
+        file_path: PathBuf,
+        content: String,
+        line_ending: LineEnding,
+        strip_bom: bool,
+        expected_mtime: Option<SystemTime>,
+        dry_run: bool,
+    ) -> Result<String, AgentyError> {
+        let content = if strip_bom {
+            content.trim_start_matches('\u{feff}').to_string()
+        } else {
+            content
+        };
+        let content = line_ending.normalize(&content);
+
         let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
             Ok(p) => p,
             Err(e) => return Ok(e),
         };
 
-        if let Ok(meta) = tokio::fs::metadata(&target_path).await {
-            if meta.is_dir() {
-                return Ok(format!("Path {:?} is a directory, cannot write to it", &file_path));
+        if let Ok(meta) = tokio::fs::metadata(&target_path).await
+            && meta.is_dir()
+        {
+            return Ok(format!("Path {:?} is a directory, cannot write to it", &file_path));
+        }
+
+        // Optimistic concurrency: another agent may have written the file since the model
+        // read it. A file deleted since then counts as modified as well.
+        if let Some(expected) = expected_mtime {
+            let mtime = tokio::fs::metadata(&target_path)
+                .await
+                .and_then(|meta| meta.modified())
+                .ok();
+            if mtime != Some(expected) {
+                return Ok(WRITE_CONFLICT.to_string());
+            }
+        }
+
+        // Compared to the file itself, also in staged mode.
+        if dry_run {
+            let original = match tokio::fs::read(&target_path).await {
+                Ok(buf) => String::from_utf8_lossy(&buf).to_string(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(format!("(new file)\n{}", content));
+                }
+                Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+            };
+            if original == content {
+                return Ok(format!("{:?} already has this content", &file_path));
+            }
+            let name = file_path.to_string_lossy();
+            return Ok(TextDiff::from_lines(&original, &content)
+                .unified_diff()
+                .header(&name, &name)
+                .to_string());
+        }
+
+        if let Some(stage) = &self.stage {
+            let stage_id = self.current_stage(stage)?;
+            let rel = target_path
+                .strip_prefix(&self.cwd)
+                .expect("sanitized path outside of cwd?!");
+            let staged_path = self
+                .cwd
+                .join(STAGING_DIR)
+                .join(&stage_id)
+                .join(rel);
+            if let Some(parent) = staged_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
             }
+            atomic_write(&staged_path, content).await?;
+            return Ok(format!(
+                "Staged file {:?} in stage {}, call commit_staging with this stage_id to write all staged files or rollback_staging to discard them",
+                &file_path, &stage_id
+            ));
         }
 
         if let Some(parent) = target_path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        tokio::fs::write(&target_path, content).await?;
+        if let Some(journal) = &self.journal {
+            journal.record(&target_path).await?;
+        }
+        atomic_write(&target_path, content).await?;
 
         Ok(format!("Successfully wrote to file {:?}", &file_path))
     }
@@ -291,14 +1046,316 @@ impl Tool for WriteFileTool {
     type ARGUMENTS = WriteFileArgs;
     const NAME: &str = "write_file";
     const DESCRIPTION: Option<&str> = Some(
-        "Write content to the file at the given path. The file will be created if it doesn't exist, or overwritten if it does. Parent directories will be created automatically. The path should be always relative path and '.' is allowed while '..' is not allowed.",
+        "Write content to the file at the given path. The file will be created if it doesn't exist, or overwritten if it does. Parent directories will be created automatically. Set `line_ending` to 'Lf' or 'CrLf' to normalize line endings and `strip_bom` to remove byte order marks. Pass the mtime from `read_file` with `include_metadata` as `expected_mtime` to only write if nobody changed the file since, otherwise read it again and retry. Set `dry_run` to true to only get the unified diff the write would make without writing, to check it first. The path should be always relative path and '.' is allowed while '..' is not allowed.",
     );
 
     fn invoke(
         &self,
         arguments: Self::ARGUMENTS,
     ) -> impl Future<Output = Result<String, AgentyError>> + Send {
-        self.write_file(arguments.file_path, arguments.content)
+        self.write_file_with(
+            arguments.file_path,
+            arguments.content,
+            arguments.line_ending.unwrap_or_default(),
+            arguments.strip_bom.unwrap_or_default(),
+            arguments.expected_mtime,
+            arguments.dry_run.unwrap_or_default(),
+        )
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(dir: &tempfile::TempDir, name: &str, content: &[u8]) -> String {
+        std::fs::write(dir.path().join(name), content).unwrap();
+        ReadFileTool::new(dir.path().to_path_buf())
+            .read_file(PathBuf::from(name))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_one_bad_byte_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = "café → ok\n".repeat(80).into_bytes();
+        content.truncate(1000);
+        content.insert(500, 0xff);
+        let resp = read(&dir, "app.log", &content).await;
+        assert!(resp.contains("[1 invalid UTF-8 bytes replaced with U+FFFD]"));
+        assert!(resp.contains('\u{fffd}'));
+        assert!(resp.contains("café → ok"));
+    }
+
+    #[tokio::test]
+    async fn test_one_bad_byte_in_ascii_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = "GET /index.html 200\n".repeat(50).into_bytes();
+        assert_eq!(content.len(), 1000);
+        content.insert(500, 0xe9);
+        let resp = read(&dir, "access.log", &content).await;
+        assert!(resp.contains("[1 invalid UTF-8 bytes replaced with U+FFFD]"));
+        assert!(resp.contains('\u{fffd}'));
+        assert!(!resp.contains("[decoded as"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_bad_bytes_above_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = "café → ok\n".repeat(10).into_bytes();
+        content.extend([0xff; 30]);
+        std::fs::write(dir.path().join("app.log"), &content).unwrap();
+        let resp = ReadFileTool::new(dir.path().to_path_buf())
+            .with_binary_threshold(0.01)
+            .read_file(PathBuf::from("app.log"))
+            .await
+            .unwrap();
+        assert!(!resp.contains("U+FFFD"));
+    }
+
+    #[tokio::test]
+    async fn test_latin1_is_not_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = b"// Cette fonction est appel\xe9e avant le d\xe9marrage.\n".repeat(20);
+        content.extend(b"fn main() {}\n".repeat(50));
+        let resp = read(&dir, "main.rs", &content).await;
+        assert!(resp.contains("[decoded as WINDOWS-1252]"));
+        assert!(resp.contains("appelée avant le démarrage"));
+        assert!(!resp.contains('\u{fffd}'));
+    }
+
+    #[tokio::test]
+    async fn test_gbk_is_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "// 读取配置文件并初始化日志系统\nint main() { return 0; }\n".repeat(20);
+        let (content, _, _) = encoding_rs::GBK.encode(&text);
+        let resp = read(&dir, "main.c", &content).await;
+        assert!(resp.contains("[decoded as GBK]"), "{}", resp);
+        assert!(resp.contains("读取配置文件并初始化日志系统"));
+    }
+
+    #[tokio::test]
+    async fn test_shift_jis_is_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "設定ファイルを読み込んでログを初期化します。\n".repeat(20);
+        let (content, _, _) = encoding_rs::SHIFT_JIS.encode(&text);
+        let resp = read(&dir, "notes.txt", &content).await;
+        assert!(resp.contains("[decoded as SHIFT_JIS]"), "{}", resp);
+        assert!(resp.contains("設定ファイルを読み込んでログを初期化します。"));
+    }
+
+    #[tokio::test]
+    async fn test_nul_heavy_binary_is_hexdumped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = b"SQLite format 3\0".to_vec();
+        content.extend([0u8; 200]);
+        content.extend("é".as_bytes().repeat(10));
+        content.push(0xff);
+        content.extend([0u8; 200]);
+        let resp = read(&dir, "data.db", &content).await;
+        assert!(!resp.contains("U+FFFD"));
+        assert!(resp.contains("5351 4C69 7465"));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_does_not_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let main = root.join("main.rs");
+        let old = "fn main() {\n    println!(\"hi\");\n}\n";
+        std::fs::write(&main, old).unwrap();
+        let mtime = std::fs::metadata(&main).unwrap().modified().unwrap();
+        let journal = FsJournal::new(root.join("journal")).await.unwrap();
+        let tool = WriteFileTool::new(root.to_path_buf())
+            .with_journal(journal.clone())
+            .staged();
+        let dry_run = |path: &str, content: &str| {
+            tool.write_file_with(
+                PathBuf::from(path),
+                content.to_string(),
+                LineEnding::Preserve,
+                false,
+                None,
+                true,
+            )
+        };
+
+        let resp = dry_run("main.rs", "fn main() {\n    println!(\"bye\");\n}\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            resp,
+            "--- main.rs\n+++ main.rs\n@@ -1,3 +1,3 @@\n fn main() {\n-    println!(\"hi\");\n+    println!(\"bye\");\n }\n"
+        );
+        let resp = dry_run("src/new.rs", "pub fn new() {}\n").await.unwrap();
+        assert_eq!(resp, "(new file)\npub fn new() {}\n");
+
+        assert_eq!(std::fs::read_to_string(&main).unwrap(), old);
+        assert_eq!(std::fs::metadata(&main).unwrap().modified().unwrap(), mtime);
+        assert!(!root.join("src").exists());
+        assert!(!root.join(STAGING_DIR).exists());
+        assert!(journal.changes().await.is_empty());
+    }
+
+    #[test]
+    fn test_path_globs_match_relative_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        for name in ["src/a.rs", "src/nested/b.rs", "other/c.rs"] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        let resp = FindFileTool::find_file(
+            root.to_path_buf(),
+            PathBuf::from("."),
+            vec!["src/**/*.rs".to_string()],
+            true,
+            false,
+            FindFileFormat::Text,
+            false,
+        )
+        .unwrap();
+        assert!(resp.contains("\"src/a.rs\""), "{}", resp);
+        assert!(resp.contains("\"src/nested/b.rs\""), "{}", resp);
+        assert!(!resp.contains("c.rs"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_line_endings_are_normalized() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = WriteFileTool::new(dir.path().to_path_buf());
+        let mixed = "\u{feff}one\r\ntwo\nthree\r\n";
+        for (line_ending, expected) in [
+            (LineEnding::Lf, "one\ntwo\nthree\n"),
+            (LineEnding::CrLf, "one\r\ntwo\r\nthree\r\n"),
+        ] {
+            tool.write_file_with(
+                PathBuf::from("out.txt"),
+                mixed.to_string(),
+                line_ending,
+                true,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+            let written = std::fs::read_to_string(dir.path().join("out.txt")).unwrap();
+            assert_eq!(written, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_csv_is_rendered_as_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = b"name,age,city\nAlice,30,Paris\nBob,4,Rome\n";
+        let resp = read(&dir, "users.csv", csv).await;
+        let table = "\
++-------+-----+-------+
+| name  | age | city  |
++-------+-----+-------+
+| Alice | 30  | Paris |
+| Bob   | 4   | Rome  |
++-------+-----+-------+
+";
+        assert!(resp.ends_with(table), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_sorted_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "c.txt", "b.txt"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let resp = ListDirectoryTool::new_root(dir.path().to_path_buf())
+            .list_directory_sorted(PathBuf::from("."), SortBy::Name, false)
+            .await
+            .unwrap();
+        let names: Vec<_> = resp.lines().skip(2).map(|ln| &ln[..7]).collect();
+        assert_eq!(names, ["\"a.txt\"", "\"b.txt\"", "\"c.txt\""]);
+    }
+
+    #[test]
+    fn test_find_file_json_output() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "").unwrap();
+        let resp = FindFileTool::find_file(
+            dir.path().to_path_buf(),
+            PathBuf::from("."),
+            vec!["*.rs".to_string()],
+            true,
+            false,
+            FindFileFormat::Json,
+            false,
+        )
+        .unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&resp).unwrap();
+        assert_eq!(
+            entries,
+            [serde_json::json!({"path": "a.rs", "type": "file", "size": 10})]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hidden_entries_are_not_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "target\n").unwrap();
+        std::fs::write(dir.path().join("main.rs"), "").unwrap();
+        let tool = ListDirectoryTool::new_root(dir.path().to_path_buf());
+        let list = |hidden| tool.list_directory_sorted(PathBuf::from("."), SortBy::Name, hidden);
+
+        let resp = list(false).await.unwrap();
+        assert!(resp.contains("\"main.rs\""), "{}", resp);
+        assert!(!resp.contains(".git"), "{}", resp);
+        let resp = list(true).await.unwrap();
+        assert!(resp.contains("\".git\"\tdirectory"), "{}", resp);
+        assert!(resp.contains("\".gitignore\"\tfile"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_stale_mtime_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        std::fs::write(&path, "old\n").unwrap();
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let stale = mtime - std::time::Duration::from_secs(60);
+        let tool = WriteFileTool::new(dir.path().to_path_buf());
+        let write = |expected_mtime| {
+            tool.write_file_with(
+                PathBuf::from("notes.md"),
+                "new\n".to_string(),
+                LineEnding::Preserve,
+                false,
+                Some(expected_mtime),
+                false,
+            )
+        };
+
+        assert_eq!(write(stale).await.unwrap(), WRITE_CONFLICT);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+        assert_ne!(write(mtime).await.unwrap(), WRITE_CONFLICT);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+    }
+
+    #[tokio::test]
+    async fn test_hex_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data.bin"), (0u8..64).collect::<Vec<_>>()).unwrap();
+        let resp = ReadFileTool::new(dir.path().to_path_buf())
+            .read_file_with(ReadFileToolArgs {
+                file_path: PathBuf::from("data.bin"),
+                hex_columns: Some(8),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let rows: Vec<_> = resp.lines().skip(1).collect();
+        assert_eq!(rows.len(), 8, "{}", resp);
+        assert_eq!(rows[0], "00000000: 0001 0203 0405 0607 |........|");
+        assert_eq!(rows[7], "00000038: 3839 3A3B 3C3D 3E3F |89:;<=>?|");
+    }
+}
@@ -0,0 +1,603 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    future::Future,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{
+    human_size, looks_textual, sanitize_join_relative_path, truncate_at_line_boundary,
+};
+
+pub const DEFAULT_MAX_GIT_OUTPUT: usize = 16384;
+pub const DEFAULT_LOG_COUNT: usize = 20;
+pub const MAX_LOG_COUNT: usize = 200;
+pub const MAX_BLAME_LINES: usize = 200;
+
+/// Run git with `args` in `cwd`, returning its stdout. Errors are meant for the model.
+async fn git<I, S>(cwd: &Path, args: I) -> Result<Vec<u8>, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("git")
+        // Never take locks a concurrent git of the user would trip over.
+        .args(["--no-optional-locks", "-c", "core.quotepath=off"])
+        .args(args)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Fail to run git due to {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// The path of `cwd` relative to the root of its repository, like `src/`, errors if `cwd` is
+/// not in a repository.
+async fn repo_prefix(cwd: &Path) -> Result<String, String> {
+    match git(cwd, ["rev-parse", "--show-prefix"]).await {
+        Ok(prefix) => Ok(String::from_utf8_lossy(&prefix).trim().to_string()),
+        Err(_) => Err(format!(
+            "{:?} is not inside a git repository, git tools can not be used",
+            cwd
+        )),
+    }
+}
+
+/// Revisions are passed as arguments, so they must not be mistaken for options.
+fn check_rev(rev: &str) -> Result<(), String> {
+    if rev.is_empty() || rev.starts_with('-') {
+        return Err(format!("{:?} is not a valid revision", rev));
+    }
+    Ok(())
+}
+
+/// `path` checked to stay within `cwd`, still relative so git resolves it from `cwd`.
+fn checked_path(cwd: &Path, path: &Path) -> Result<PathBuf, String> {
+    sanitize_join_relative_path(cwd, path)?;
+    Ok(path.to_path_buf())
+}
+
+fn cap_output(resp: String, max: usize) -> String {
+    let cut = truncate_at_line_boundary(&resp, max);
+    if cut.len() < resp.len() {
+        format!(
+            "{}\n(output truncated, {} of {} bytes shown)",
+            cut.trim_end(),
+            cut.len(),
+            resp.len()
+        )
+    } else {
+        resp
+    }
+}
+
+/// `secs` since the epoch as a UTC date like `2024-05-17`.
+fn short_date(secs: i64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = secs.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The meaning of a status letter of `git status --porcelain`.
+fn status_name(code: char) -> &'static str {
+    match code {
+        'M' => "modified",
+        'T' => "type changed",
+        'A' => "added",
+        'D' => "deleted",
+        'R' => "renamed",
+        'C' => "copied",
+        'U' => "unmerged",
+        '?' => "untracked",
+        '!' => "ignored",
+        _ => "",
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GitStatusTool {
+    pub cwd: PathBuf,
+    pub max_output: usize,
+}
+
+impl GitStatusTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_GIT_OUTPUT,
+        }
+    }
+
+    pub async fn git_status(&self) -> Result<String, AgentyError> {
+        let prefix = match repo_prefix(&self.cwd).await {
+            Ok(prefix) => prefix,
+            Err(e) => return Ok(e),
+        };
+        let out = match git(&self.cwd, ["status", "--porcelain=v1", "-z", "--branch"]).await {
+            Ok(out) => String::from_utf8_lossy(&out).to_string(),
+            Err(e) => return Ok(e),
+        };
+
+        let mut branch = String::new();
+        let mut table = prettytable::Table::new();
+        table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+        table.set_titles(prettytable::row!["path", "staged", "unstaged"]);
+        // Porcelain paths are relative to the repository root, show them relative to `cwd`
+        // like the paths the other tools take.
+        let rel = |p: &str| p.strip_prefix(&prefix).unwrap_or(p).to_string();
+        let mut entries = out.split('\0').filter(|e| !e.is_empty());
+        while let Some(entry) = entries.next() {
+            if let Some(header) = entry.strip_prefix("## ") {
+                branch = header.to_string();
+                continue;
+            }
+            let mut codes = entry.chars();
+            let (Some(x), Some(y)) = (codes.next(), codes.next()) else {
+                continue;
+            };
+            let mut path = rel(entry.get(3..).unwrap_or_default());
+            // With -z the source of a rename or copy follows as its own entry.
+            if matches!(x, 'R' | 'C')
+                && let Some(from) = entries.next()
+            {
+                path = format!("{} -> {}", rel(from), path);
+            }
+            let (staged, unstaged) = if x == '?' || x == '!' {
+                ("", status_name(x))
+            } else {
+                (status_name(x), status_name(y))
+            };
+            table.add_row(prettytable::row![path, staged, unstaged]);
+        }
+
+        let mut resp = format!("On branch {}", branch);
+        if !prefix.is_empty() {
+            resp.push_str(&format!(
+                ", paths relative to {:?} in the repository",
+                prefix
+            ));
+        }
+        if table.is_empty() {
+            resp.push_str("\nNothing to commit, the working tree is clean");
+        } else {
+            resp.push('\n');
+            resp.push_str(&table.to_string());
+        }
+        Ok(cap_output(resp, self.max_output))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GitStatusArgs {}
+
+impl Tool for GitStatusTool {
+    type ARGUMENTS = GitStatusArgs;
+    const NAME: &str = "git_status";
+    const DESCRIPTION: Option<&str> = Some(
+        "Show the current branch of the git repository and a table of the changed and untracked files, with what is staged and what is not.",
+    );
+
+    fn invoke(
+        &self,
+        _arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.git_status()
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GitLogArgs {
+    /// Commits returned at most, 20 by default.
+    pub max_count: Option<usize>,
+    /// Only commits touching this file or directory.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitLogTool {
+    pub cwd: PathBuf,
+    pub max_output: usize,
+}
+
+impl GitLogTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_GIT_OUTPUT,
+        }
+    }
+
+    pub async fn git_log(
+        &self,
+        max_count: Option<usize>,
+        path: Option<PathBuf>,
+    ) -> Result<String, AgentyError> {
+        if let Err(e) = repo_prefix(&self.cwd).await {
+            return Ok(e);
+        }
+        let max_count = max_count.unwrap_or(DEFAULT_LOG_COUNT).min(MAX_LOG_COUNT);
+        let mut args = vec![
+            "log".into(),
+            format!("--max-count={}", max_count),
+            "--date=short".into(),
+            "--format=%h %ad %an: %s".into(),
+            "--".into(),
+        ];
+        if let Some(path) = &path {
+            match checked_path(&self.cwd, path) {
+                Ok(path) => args.push(path.to_string_lossy().to_string()),
+                Err(e) => return Ok(e),
+            }
+        }
+        let out = match git(&self.cwd, args).await {
+            Ok(out) => String::from_utf8_lossy(&out).to_string(),
+            Err(e) => return Ok(e),
+        };
+        if out.trim().is_empty() {
+            return Ok("No commits found".to_string());
+        }
+        Ok(cap_output(out, self.max_output))
+    }
+}
+
+impl Tool for GitLogTool {
+    type ARGUMENTS = GitLogArgs;
+    const NAME: &str = "git_log";
+    const DESCRIPTION: Option<&str> = Some(
+        "List the latest commits of the git repository, newest first, with their short hash, date, author and subject. Give `path` to only list the commits touching a file or directory, and `max_count` to get more or fewer commits.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.git_log(arguments.max_count, arguments.path)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GitDiffArgs {
+    /// Like `HEAD~3`, `main..feature` or a single commit to diff against, the working tree
+    /// is diffed against the index if not given.
+    pub rev_range: Option<String>,
+    /// Only diff this file or directory.
+    pub path: Option<PathBuf>,
+    /// Diff the staged changes instead, false by default.
+    pub staged: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitDiffTool {
+    pub cwd: PathBuf,
+    pub max_output: usize,
+}
+
+impl GitDiffTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_GIT_OUTPUT,
+        }
+    }
+
+    pub async fn git_diff(&self, arguments: GitDiffArgs) -> Result<String, AgentyError> {
+        if let Err(e) = repo_prefix(&self.cwd).await {
+            return Ok(e);
+        }
+        // Binary files are only reported as differing without `--binary`, and the stat up
+        // front gives the overview even when the patch is truncated.
+        let mut args = vec![
+            "diff".to_string(),
+            "--no-color".into(),
+            "--no-ext-diff".into(),
+            "--no-textconv".into(),
+            "--stat".into(),
+            "--patch".into(),
+        ];
+        if arguments.staged.unwrap_or(false) {
+            args.push("--cached".into());
+        }
+        if let Some(rev_range) = arguments.rev_range {
+            if let Err(e) = check_rev(&rev_range) {
+                return Ok(e);
+            }
+            args.push(rev_range);
+        }
+        args.push("--".into());
+        if let Some(path) = &arguments.path {
+            match checked_path(&self.cwd, path) {
+                Ok(path) => args.push(path.to_string_lossy().to_string()),
+                Err(e) => return Ok(e),
+            }
+        }
+        let out = match git(&self.cwd, args).await {
+            Ok(out) => String::from_utf8_lossy(&out).to_string(),
+            Err(e) => return Ok(e),
+        };
+        if out.trim().is_empty() {
+            return Ok("No differences".to_string());
+        }
+        Ok(cap_output(out, self.max_output))
+    }
+}
+
+impl Tool for GitDiffTool {
+    type ARGUMENTS = GitDiffArgs;
+    const NAME: &str = "git_diff";
+    const DESCRIPTION: Option<&str> = Some(
+        "Show the changes in the git repository as a unified diff preceded by a summary per file. By default the unstaged changes of the working tree are shown, set `staged` to true for the staged ones or give a `rev_range` like 'HEAD~3' or 'main..feature'. `path` limits the diff to a file or directory. Binary files are only reported as changed and long diffs are truncated.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.git_diff(arguments)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GitShowArgs {
+    /// A commit, branch or tag.
+    pub rev: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitShowTool {
+    pub cwd: PathBuf,
+    pub max_output: usize,
+}
+
+impl GitShowTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_GIT_OUTPUT,
+        }
+    }
+
+    pub async fn git_show(&self, rev: String, path: PathBuf) -> Result<String, AgentyError> {
+        if let Err(e) = repo_prefix(&self.cwd).await {
+            return Ok(e);
+        }
+        if let Err(e) = check_rev(&rev) {
+            return Ok(e);
+        }
+        let path = match checked_path(&self.cwd, &path) {
+            Ok(path) => path,
+            Err(e) => return Ok(e),
+        };
+        // `./` resolves the path from `cwd` instead of the repository root.
+        let object = format!("{}:./{}", rev, path.display());
+        let out = match git(&self.cwd, ["show", "--no-textconv", &object]).await {
+            Ok(out) => out,
+            Err(e) => return Ok(e),
+        };
+        if !looks_textual(&out) {
+            return Ok(format!(
+                "{:?} at {} is a binary file of {}",
+                &path,
+                &rev,
+                human_size(out.len() as u64)
+            ));
+        }
+        Ok(cap_output(
+            format!(
+                "{:?} at {}:\n{}",
+                &path,
+                &rev,
+                String::from_utf8_lossy(&out)
+            ),
+            self.max_output,
+        ))
+    }
+}
+
+impl Tool for GitShowTool {
+    type ARGUMENTS = GitShowArgs;
+    const NAME: &str = "git_show";
+    const DESCRIPTION: Option<&str> = Some(
+        "Show the content of the file at `path` as it was at the revision `rev` of the git repository, e.g. 'HEAD~1' or a commit hash. Binary files are only reported by their size and long files are truncated.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.git_show(arguments.rev, arguments.path)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GitBlameArgs {
+    pub file_path: PathBuf,
+    /// The first line to blame, counting from 1, 1 by default.
+    pub start_line: Option<usize>,
+    /// The last line to blame, inclusive.
+    pub end_line: Option<usize>,
+}
+
+/// What `git blame --porcelain` tells about a commit, only given for its first line.
+#[derive(Default)]
+struct BlameCommit {
+    author: String,
+    date: String,
+    boundary: bool,
+    filename: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitBlameTool {
+    pub cwd: PathBuf,
+    pub max_output: usize,
+}
+
+impl GitBlameTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_GIT_OUTPUT,
+        }
+    }
+
+    pub async fn git_blame(&self, arguments: GitBlameArgs) -> Result<String, AgentyError> {
+        let prefix = match repo_prefix(&self.cwd).await {
+            Ok(prefix) => prefix,
+            Err(e) => return Ok(e),
+        };
+        let path = match checked_path(&self.cwd, &arguments.file_path) {
+            Ok(path) => path,
+            Err(e) => return Ok(e),
+        };
+        let tracked = git(
+            &self.cwd,
+            [
+                OsStr::new("ls-files"),
+                OsStr::new("--error-unmatch"),
+                OsStr::new("--"),
+                path.as_os_str(),
+            ],
+        )
+        .await;
+        if tracked.is_err() {
+            return Ok(format!("{:?} is not tracked by git", &path));
+        }
+        let lines = match tokio::fs::read(self.cwd.join(&path)).await {
+            Ok(content) => {
+                content.split(|b| *b == b'\n').count() - usize::from(content.ends_with(b"\n"))
+            }
+            Err(e) => return Ok(format!("Fail to read {:?} due to {}", &path, e)),
+        };
+
+        let start = arguments.start_line.unwrap_or(1).max(1);
+        if start > lines {
+            return Ok(format!("{:?} has only {} lines", &path, lines));
+        }
+        let mut end = arguments.end_line.unwrap_or(usize::MAX).min(lines);
+        if end < start {
+            return Ok(format!("end_line {} is before start_line {}", end, start));
+        }
+        let mut note = String::new();
+        if end - start + 1 > MAX_BLAME_LINES {
+            end = start + MAX_BLAME_LINES - 1;
+            note = format!(
+                "\n(only lines {} to {} shown, blame at most {} lines per call)",
+                start, end, MAX_BLAME_LINES
+            );
+        }
+
+        let out = match git(
+            &self.cwd,
+            [
+                OsStr::new("blame"),
+                OsStr::new("--porcelain"),
+                OsStr::new("-L"),
+                OsStr::new(&format!("{},{}", start, end)),
+                OsStr::new("--"),
+                path.as_os_str(),
+            ],
+        )
+        .await
+        {
+            Ok(out) => String::from_utf8_lossy(&out).to_string(),
+            Err(e) => return Ok(e),
+        };
+
+        let repo_path = Path::new(&prefix).join(&path);
+        let mut commits: HashMap<String, BlameCommit> = HashMap::new();
+        let mut current: Option<(String, usize)> = None;
+        let mut lns = vec![];
+        for ln in out.lines() {
+            // The content of a line ends its entry.
+            if let Some(content) = ln.strip_prefix('\t') {
+                let Some((sha, line_no)) = current.take() else {
+                    continue;
+                };
+                let commit = commits.entry(sha.clone()).or_default();
+                let hash = if sha.bytes().all(|b| b == b'0') {
+                    "uncommitted".to_string()
+                } else if commit.boundary {
+                    format!("^{}", &sha[..7])
+                } else {
+                    sha[..8].to_string()
+                };
+                let mut ln = format!("{:>5} {} {} {}", line_no, hash, commit.author, commit.date);
+                if !commit.filename.is_empty() && Path::new(&commit.filename) != repo_path {
+                    ln.push_str(&format!(" (as {})", commit.filename));
+                }
+                ln.push_str(&format!(" | {}", content));
+                lns.push(ln);
+                continue;
+            }
+            let (key, value) = ln.split_once(' ').unwrap_or((ln, ""));
+            if key.len() == 40 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+                let line_no = value
+                    .split(' ')
+                    .nth(1)
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_default();
+                commits.entry(key.to_string()).or_default();
+                current = Some((key.to_string(), line_no));
+                continue;
+            }
+            let Some((sha, _)) = &current else {
+                continue;
+            };
+            let commit = commits.entry(sha.clone()).or_default();
+            match key {
+                "author" => commit.author = value.to_string(),
+                "author-time" => commit.date = short_date(value.parse().unwrap_or_default()),
+                "boundary" => commit.boundary = true,
+                "filename" => commit.filename = value.to_string(),
+                _ => {}
+            }
+        }
+        let mut resp = format!(
+            "{:?}, lines {} to {}:\n{}",
+            &path,
+            start,
+            end,
+            lns.join("\n")
+        );
+        resp.push_str(&note);
+        Ok(cap_output(resp, self.max_output))
+    }
+}
+
+impl Tool for GitBlameTool {
+    type ARGUMENTS = GitBlameArgs;
+    const NAME: &str = "git_blame";
+    const DESCRIPTION: Option<&str> = Some(
+        "Show who last changed each line of `file_path` in the git repository and when, as the line number, short commit hash, author, date and content. Limit the lines with `start_line` and `end_line`, at most 200 lines are blamed per call. Lines from a commit where the file had another name are marked with that name, and lines not committed yet as uncommitted.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.git_blame(arguments)
+    }
+}
@@ -1,84 +1,1064 @@
-use std::path::PathBuf;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
+use color_eyre::eyre::eyre;
 use grep::{
+    matcher::Matcher,
     printer::StandardBuilder,
-    regex::RegexMatcher,
-    searcher::{BinaryDetection, SearcherBuilder},
+    regex::{RegexMatcher, RegexMatcherBuilder},
+    searcher::{
+        BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkContextKind,
+        SinkFinish, SinkMatch,
+    },
 };
+use itertools::Itertools;
 use log::warn;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
 use crate::{error::AgentyError, tool::Tool};
 
-use super::file::sanitize_join_relative_path;
+use super::{
+    file::{FileGlobs, human_size, sanitize_join_relative_path, truncate_at_line_boundary},
+    staging::STAGING_DIR,
+};
+
+pub const DEFAULT_MAX_OUTPUT_CHARS: usize = 16384;
+pub const DEFAULT_SUMMARY_THRESHOLD: usize = 200;
+pub const DEFAULT_MAX_MATCHES_PER_FILE: usize = 20;
+pub const DEFAULT_MAX_TOTAL_MATCHES: usize = 200;
+pub const DEFAULT_MAX_MULTILINE_FILE_SIZE: u64 = 8 * 1024 * 1024;
+pub const DEFAULT_MAX_FILESIZE: u64 = 4 * 1024 * 1024;
+pub const DEFAULT_CONTEXT_LINES: usize = 2;
+/// Files listed at most in the summary of a query with too many matches.
+const MAX_SUMMARY_FILES: usize = 50;
+
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GrepFormat {
+    Text,
+    Json,
+}
+
+/// How the matching lines of the `text` format are printed.
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumberFormat {
+    /// The grep printer, long lines are omitted.
+    #[default]
+    Default,
+    /// `file:line:col:text` per line with the path relative to `cwd`.
+    Colon,
+    /// A JSON object with `file`, `line`, `col` and `text` per line.
+    Json,
+}
 
 #[derive(JsonSchema, Deserialize)]
 pub struct GrepToolArgs {
-    pub directory: PathBuf,
+    /// A directory to search recursively or a single file.
+    #[serde(alias = "directory")]
+    pub path: PathBuf,
+    /// More files or directories to search in the same call.
+    pub paths: Option<Vec<PathBuf>>,
     pub pattern: String,
+    /// Treat `pattern` as a literal string instead of a regex.
+    pub fixed_string: Option<bool>,
+    /// Only search files matching any of these glob patterns, e.g. `*.rs` or `src/**/*.rs`.
+    pub files: Option<Vec<String>>,
+    /// Let the pattern match across lines, `.` then also matches a newline.
+    pub multiline: Option<bool>,
+    /// Return the lines that do not match instead.
+    pub invert: Option<bool>,
+    /// Return only the number of matching lines per file.
+    pub count_only: Option<bool>,
+    /// Return only the files with matches, each with its number of matches.
+    pub files_with_matches: Option<bool>,
+    /// Return only the paths of the files with matches, one per line.
+    pub unique_files: Option<bool>,
+    /// Descend into symlinked directories, false by default.
+    pub follow_symlinks: Option<bool>,
+    /// Maximum characters of matches returned, 16384 by default.
+    pub max_output_chars: Option<usize>,
+    /// `text` by default, `json` returns the matches as a JSON object.
+    pub format: Option<GrepFormat>,
+    /// `default`, `colon` or `json`, how the lines of the `text` format are printed.
+    pub line_number_format: Option<LineNumberFormat>,
+}
+
+/// A single match as returned by [`GrepTool::grep_structured`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrepMatch {
+    /// Relative to the `cwd` of the tool.
+    pub path: PathBuf,
+    /// 1-based, of the first line for multiline matches.
+    pub line_number: u64,
+    /// 1-based byte offset of the match in its line, `None` for inverted searches.
+    pub column: Option<u64>,
+    /// The matched line or lines without the trailing newline.
+    pub text: String,
+    pub context_before: Vec<String>,
+    /// Lines following this match and preceding the next one are only attached here.
+    pub context_after: Vec<String>,
+}
+
+/// An in-memory writer the printer owns while the searching code can still inspect it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collects the matches of a single file as [`GrepMatch`]es.
+struct MatchSink<'a> {
+    matcher: &'a RegexMatcher,
+    path: &'a Path,
+    matches: Vec<GrepMatch>,
+    before: Vec<String>,
+    binary: bool,
+}
+
+impl<'a> MatchSink<'a> {
+    fn new(matcher: &'a RegexMatcher, path: &'a Path) -> Self {
+        Self {
+            matcher,
+            path,
+            matches: vec![],
+            before: vec![],
+            binary: false,
+        }
+    }
+}
+
+fn sink_line(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(['\n', '\r'])
+        .to_string()
+}
+
+impl Sink for MatchSink<'_> {
+    type Error = std::io::Error;
+
+    fn matched(&mut self, searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
+        let column = if searcher.invert_match() {
+            None
+        } else {
+            self.matcher
+                .find(mat.bytes())
+                .ok()
+                .flatten()
+                .map(|m| m.start() as u64 + 1)
+        };
+        self.matches.push(GrepMatch {
+            path: self.path.to_path_buf(),
+            line_number: mat.line_number().unwrap_or_default(),
+            column,
+            text: sink_line(mat.bytes()),
+            context_before: std::mem::take(&mut self.before),
+            context_after: vec![],
+        });
+        Ok(true)
+    }
+
+    fn context(
+        &mut self,
+        _searcher: &Searcher,
+        context: &SinkContext<'_>,
+    ) -> Result<bool, Self::Error> {
+        let line = sink_line(context.bytes());
+        match context.kind() {
+            SinkContextKind::Before => self.before.push(line),
+            SinkContextKind::After => {
+                if let Some(last) = self.matches.last_mut() {
+                    last.context_after.push(line);
+                }
+            }
+            SinkContextKind::Other => {}
+        }
+        Ok(true)
+    }
+
+    fn binary_data(&mut self, _searcher: &Searcher, _offset: u64) -> Result<bool, Self::Error> {
+        self.binary = true;
+        Ok(false)
+    }
+}
+
+/// Prints every matched line as `file:line:col:text` or as a JSON object of its own.
+///
+/// The lines of a multiline match are printed one by one, all but the first with column 1,
+/// as are inverted matches.
+struct LineSink<'a> {
+    matcher: &'a RegexMatcher,
+    path: String,
+    format: LineNumberFormat,
+    buf: SharedBuffer,
+}
+
+impl Sink for LineSink<'_> {
+    type Error = std::io::Error;
+
+    fn matched(&mut self, searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
+        let first_column = if searcher.invert_match() {
+            1
+        } else {
+            self.matcher
+                .find(mat.bytes())
+                .ok()
+                .flatten()
+                .map_or(1, |m| m.start() as u64 + 1)
+        };
+        let first_line = mat.line_number().unwrap_or_default();
+        let mut buf = self.buf.0.borrow_mut();
+        for (i, line) in mat.lines().enumerate() {
+            let line_number = first_line + i as u64;
+            let column = if i == 0 { first_column } else { 1 };
+            let text = sink_line(line);
+            match self.format {
+                LineNumberFormat::Json => {
+                    let record = serde_json::json!({
+                        "file": self.path,
+                        "line": line_number,
+                        "col": column,
+                        "text": text,
+                    });
+                    writeln!(buf, "{}", record)?;
+                }
+                _ => writeln!(buf, "{}:{}:{}:{}", self.path, line_number, column, text)?,
+            }
+        }
+        Ok(true)
+    }
+
+    fn binary_data(&mut self, _searcher: &Searcher, _offset: u64) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
+
+fn build_matcher(regex: &str, multiline: bool) -> Result<RegexMatcher, grep::regex::Error> {
+    if multiline {
+        RegexMatcherBuilder::new()
+            .multi_line(true)
+            .dot_matches_new_line(true)
+            .build(regex)
+    } else {
+        RegexMatcher::new_line_matcher(regex)
+    }
+}
+
+/// The matcher of `pattern`, escaped first with `fixed_string`.
+///
+/// Models often forget to escape a `(` or `[`, so with `lenient` an invalid regex is searched
+/// literally rather than failing. The note tells the model when that happened.
+fn build_pattern_matcher(
+    pattern: &str,
+    fixed_string: bool,
+    multiline: bool,
+    lenient: bool,
+) -> Result<(RegexMatcher, &'static str), String> {
+    let regex = if fixed_string {
+        regex::escape(pattern)
+    } else {
+        pattern.to_string()
+    };
+    match build_matcher(&regex, multiline) {
+        Ok(v) => Ok((v, "")),
+        Err(e) => match build_matcher(&regex::escape(pattern), multiline) {
+            Ok(v) if lenient && !fixed_string => Ok((
+                v,
+                "(pattern was not valid regex; searched literally instead)\n",
+            )),
+            _ => Err(format!("regex {} error with {}", pattern, e)),
+        },
+    }
+}
+
+/// Every file to search, files in `targets` as is and directories walked and filtered
+/// by `globs`.
+///
+/// The files of a directory are sorted by their relative path, whatever order the
+/// filesystem lists them in, so the same query always gives the same output. Files
+/// staged under [`STAGING_DIR`] are not written yet and are left out.
+pub(super) fn search_candidates(
+    targets: &[PathBuf],
+    globs: Option<&FileGlobs>,
+    follow_symlinks: bool,
+) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    for target_path in targets {
+        if target_path.is_file() {
+            candidates.push(target_path.clone());
+            continue;
+        }
+        for result in WalkDir::new(target_path)
+            .follow_links(follow_symlinks)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|ent| ent.depth() == 0 || ent.file_name() != STAGING_DIR)
+        {
+            let dent = match result {
+                Ok(dent) => dent,
+                Err(err) => {
+                    warn!("Fail to walk due to {}", err);
+                    continue;
+                }
+            };
+            if !dent.file_type().is_file() {
+                continue;
+            }
+            let rel = dent.path().strip_prefix(target_path).unwrap_or(dent.path());
+            if let Some(globs) = globs
+                && !globs.is_match(rel)
+            {
+                continue;
+            }
+            candidates.push(dent.into_path());
+        }
+    }
+    candidates.into_iter().unique().collect()
+}
+
+/// Append `note` on a line of its own.
+fn push_note(resp: &mut String, note: &str) {
+    if note.is_empty() {
+        return;
+    }
+    if !resp.is_empty() && !resp.ends_with('\n') {
+        resp.push('\n');
+    }
+    resp.push_str(note);
+}
+
+/// Cut `s` to at most `max_chars` characters, ending at the last complete line that fits.
+fn truncate_chars(s: &str, max_chars: usize) -> &str {
+    let end = s.char_indices().nth(max_chars).map_or(s.len(), |(i, _)| i);
+    truncate_at_line_boundary(s, end)
+}
+
+/// `body` and the `notes` after it, each note on a line of its own, in at most `max_chars`
+/// characters.
+///
+/// The notes say what was searched or left out, so `body` is cut at a line boundary to
+/// make room for them and for `marker`, which tells the model it was cut.
+fn fit_output(body: &str, marker: &str, notes: &[&str], max_chars: usize) -> String {
+    let mut resp = body.to_string();
+    for note in notes {
+        push_note(&mut resp, note);
+    }
+    if resp.chars().count() <= max_chars {
+        return resp;
+    }
+    let notes = std::iter::once(marker)
+        .chain(notes.iter().copied())
+        .filter(|note| !note.is_empty())
+        .join("\n");
+    let room = max_chars.saturating_sub(notes.chars().count() + 1);
+    let mut resp = truncate_chars(body, room).to_string();
+    push_note(&mut resp, &notes);
+    truncate_chars(&resp, max_chars).to_string()
+}
+
+/// The match caps and the output budget shared by the files searched in parallel.
+///
+/// Files are printed in the order of the candidates whatever order they are searched in, so
+/// what they would print is summed up in that order as they complete. Once the files before
+/// some file use up a cap, the files from there on are only counted, and once more lines
+/// match than are shown without a summary, nothing is kept at all.
+struct SharedBudget {
+    max_matches: usize,
+    max_chars: usize,
+    summary_threshold: usize,
+    /// Matching lines of the files completed so far, binary files left out.
+    found: AtomicUsize,
+    /// Files from this index on print nothing.
+    cutoff: AtomicUsize,
+    printed: Mutex<PrintedPrefix>,
+}
+
+/// The matches and characters printed by the first `next` candidates.
+#[derive(Default)]
+struct PrintedPrefix {
+    next: usize,
+    /// Completed files after `next` as `(matches, chars, overflow)`.
+    pending: BTreeMap<usize, (usize, usize, bool)>,
+    matches: usize,
+    chars: usize,
+}
+
+impl SharedBudget {
+    fn new(max_matches: usize, max_chars: usize, summary_threshold: usize, print: bool) -> Self {
+        Self {
+            max_matches,
+            max_chars,
+            summary_threshold,
+            found: AtomicUsize::new(0),
+            cutoff: AtomicUsize::new(if print { usize::MAX } else { 0 }),
+            printed: Mutex::new(PrintedPrefix::default()),
+        }
+    }
+
+    fn may_print(&self, index: usize) -> bool {
+        index < self.cutoff.load(Ordering::Relaxed)
+            && self.found.load(Ordering::Relaxed) <= self.summary_threshold
+    }
+
+    fn complete(&self, index: usize, file: &FoundFile) {
+        if file.binary {
+            self.record(index, (0, 0, false));
+        } else {
+            self.found.fetch_add(file.count, Ordering::Relaxed);
+            self.record(index, (file.pieces.len(), file.chars, file.overflow));
+        }
+    }
+
+    fn record(&self, index: usize, usage: (usize, usize, bool)) {
+        // Nothing after the cutoff changes it, and past the threshold nothing is printed.
+        if self.cutoff.load(Ordering::Relaxed) != usize::MAX
+            || self.found.load(Ordering::Relaxed) > self.summary_threshold
+        {
+            return;
+        }
+        let mut printed = self.printed.lock().unwrap();
+        let mut usage = Some(usage);
+        if printed.next != index {
+            printed.pending.insert(index, usage.take().unwrap());
+        }
+        loop {
+            let next = printed.next;
+            let Some((matches, chars, overflow)) =
+                usage.take().or_else(|| printed.pending.remove(&next))
+            else {
+                break;
+            };
+            printed.next += 1;
+            printed.matches += matches;
+            printed.chars += chars;
+            if overflow || printed.matches >= self.max_matches || printed.chars > self.max_chars {
+                self.cutoff.fetch_min(printed.next, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// What the search of a single file found.
+#[derive(Default)]
+struct FoundFile {
+    /// All matching lines, shown or not.
+    count: usize,
+    binary: bool,
+    /// What the printer printed for each of the first matches.
+    pieces: Vec<String>,
+    chars: usize,
+    /// The match after `pieces` alone would overrun the budget.
+    overflow: bool,
+}
+
+/// Counts the matching lines of a file and keeps what a printer sink prints for the first
+/// `max_matches` of them, as long as the [`SharedBudget`] may still let them show. Without
+/// a printer sink the lines are only counted.
+///
+/// The buffer is taken after every match, so context printed before a match goes with it.
+struct FileSink<'a, S> {
+    inner: Option<S>,
+    buf: &'a SharedBuffer,
+    budget: &'a SharedBudget,
+    index: usize,
+    max_matches: usize,
+    found: FoundFile,
+}
+
+impl<S: Sink> Sink for FileSink<'_, S> {
+    type Error = S::Error;
+
+    fn matched(&mut self, searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, S::Error> {
+        self.found.count += 1;
+        let Some(inner) = &mut self.inner else {
+            return Ok(true);
+        };
+        if self.found.overflow
+            || self.found.pieces.len() >= self.max_matches
+            || !self.budget.may_print(self.index)
+        {
+            return Ok(true);
+        }
+        inner.matched(searcher, mat)?;
+        let piece =
+            String::from_utf8_lossy(&std::mem::take(&mut *self.buf.0.borrow_mut())).into_owned();
+        let chars = piece.chars().count();
+        if self.found.chars + chars > self.budget.max_chars {
+            self.found.overflow = true;
+        } else {
+            self.found.chars += chars;
+            self.found.pieces.push(piece);
+        }
+        Ok(true)
+    }
+
+    fn context(
+        &mut self,
+        searcher: &Searcher,
+        context: &SinkContext<'_>,
+    ) -> Result<bool, S::Error> {
+        match &mut self.inner {
+            Some(inner) => inner.context(searcher, context),
+            None => Ok(true),
+        }
+    }
+
+    fn context_break(&mut self, searcher: &Searcher) -> Result<bool, S::Error> {
+        match &mut self.inner {
+            Some(inner) => inner.context_break(searcher),
+            None => Ok(true),
+        }
+    }
+
+    fn binary_data(&mut self, _searcher: &Searcher, _offset: u64) -> Result<bool, S::Error> {
+        self.found.binary = true;
+        Ok(false)
+    }
+
+    fn begin(&mut self, searcher: &Searcher) -> Result<bool, S::Error> {
+        match &mut self.inner {
+            Some(inner) => inner.begin(searcher),
+            None => Ok(true),
+        }
+    }
+
+    fn finish(&mut self, searcher: &Searcher, finish: &SinkFinish) -> Result<(), S::Error> {
+        match &mut self.inner {
+            Some(inner) => inner.finish(searcher, finish),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The printed matches of the files in order, within `budget` characters and at most
+/// `max_matches` matches, whether the budget ran out and how many matches of each file
+/// were left out.
+fn stitch<'a>(
+    counts: &'a [(PathBuf, usize)],
+    found: &[FoundFile],
+    max_matches: usize,
+    budget: usize,
+) -> (String, bool, Vec<(&'a PathBuf, usize)>) {
+    let mut out = String::new();
+    let mut remaining = budget;
+    let mut exhausted = false;
+    let mut matches_left = max_matches;
+    let mut omitted = vec![];
+    for ((path, count), found) in counts.iter().zip(found) {
+        let mut shown = 0;
+        for piece in &found.pieces {
+            if exhausted || matches_left == 0 {
+                break;
+            }
+            let chars = piece.chars().count();
+            if chars > remaining {
+                exhausted = true;
+                break;
+            }
+            out.push_str(piece);
+            remaining -= chars;
+            shown += 1;
+            matches_left -= 1;
+        }
+        if found.overflow && shown == found.pieces.len() && matches_left > 0 {
+            exhausted = true;
+        }
+        if shown < *count {
+            omitted.push((path, count - shown));
+        }
+    }
+    (out, exhausted, omitted)
 }
 
 #[derive(Debug, Clone)]
 pub struct GrepTool {
     pub cwd: PathBuf,
+    /// Above this many matching lines only the per-file counts are returned.
+    pub summary_threshold: usize,
+    pub max_matches_per_file: usize,
+    pub max_total_matches: usize,
+    /// Multiline search holds whole files in memory, larger files are skipped.
+    pub max_multiline_file_size: u64,
+    /// Larger files, usually generated or minified, are not searched.
+    pub max_filesize: u64,
+    /// Tell the model how many files were not searched for being too large or binary.
+    pub report_skipped: bool,
+    /// Lines of context around each match of the structured output.
+    pub context_lines: usize,
 }
 
 impl GrepTool {
     pub fn new(cwd: PathBuf) -> Self {
-        Self { cwd }
+        Self::with_summary_threshold(cwd, DEFAULT_SUMMARY_THRESHOLD)
+    }
+
+    pub fn with_summary_threshold(cwd: PathBuf, summary_threshold: usize) -> Self {
+        Self {
+            cwd,
+            summary_threshold,
+            max_matches_per_file: DEFAULT_MAX_MATCHES_PER_FILE,
+            max_total_matches: DEFAULT_MAX_TOTAL_MATCHES,
+            max_multiline_file_size: DEFAULT_MAX_MULTILINE_FILE_SIZE,
+            max_filesize: DEFAULT_MAX_FILESIZE,
+            report_skipped: true,
+            context_lines: DEFAULT_CONTEXT_LINES,
+        }
+    }
+
+    pub fn with_context_lines(mut self, context_lines: usize) -> Self {
+        self.context_lines = context_lines;
+        self
+    }
+
+    pub fn with_max_filesize(mut self, max_filesize: u64, report_skipped: bool) -> Self {
+        self.max_filesize = max_filesize;
+        self.report_skipped = report_skipped;
+        self
+    }
+
+    pub fn with_match_limits(mut self, per_file: usize, total: usize) -> Self {
+        self.max_matches_per_file = per_file;
+        self.max_total_matches = total;
+        self
+    }
+
+    /// The search paths joined to `cwd`, errors are meant for the model.
+    fn resolve_targets(
+        &self,
+        path: PathBuf,
+        paths: Option<Vec<PathBuf>>,
+    ) -> Result<Vec<PathBuf>, String> {
+        let mut targets = vec![];
+        for path in std::iter::once(path).chain(paths.unwrap_or_default()).unique() {
+            let target_path = sanitize_join_relative_path(&self.cwd, &path)?;
+            if !target_path.exists() {
+                return Err(format!("{:?} does not exist", &path));
+            }
+            targets.push(target_path);
+        }
+        Ok(targets)
+    }
+
+    /// Search like [`GrepTool::grep`] but return every match with its position and
+    /// `context_lines` lines of context, for hosts that render the matches themselves.
+    ///
+    /// `count_only`, `files_with_matches`, `unique_files`, `max_output_chars` and `format` are
+    /// ignored, and so
+    /// are the match limits. Too large and binary files are skipped as usual.
+    pub async fn grep_structured(
+        &self,
+        arguments: GrepToolArgs,
+    ) -> Result<Vec<GrepMatch>, AgentyError> {
+        match self.collect_matches(arguments, false).await? {
+            Ok((matches, _)) => Ok(matches),
+            Err(e) => Err(AgentyError::Other(eyre!(e))),
+        }
+    }
+
+    /// With `lenient` an invalid regex is searched literally and the note says so.
+    async fn collect_matches(
+        &self,
+        arguments: GrepToolArgs,
+        lenient: bool,
+    ) -> Result<Result<(Vec<GrepMatch>, &'static str), String>, AgentyError> {
+        let targets = match self.resolve_targets(arguments.path, arguments.paths) {
+            Ok(targets) => targets,
+            Err(e) => return Ok(Err(e)),
+        };
+        let globs = match arguments.files.map(|f| FileGlobs::new(&f, false)).transpose() {
+            Ok(globs) => globs,
+            Err(e) => return Ok(Err(e)),
+        };
+        let fixed_string = arguments.fixed_string.unwrap_or(false);
+        let multiline = arguments.multiline.unwrap_or(false);
+        let invert = arguments.invert.unwrap_or(false);
+        let follow_symlinks = arguments.follow_symlinks.unwrap_or(false);
+        let pattern = arguments.pattern;
+        let max_filesize = self.max_filesize;
+        let max_multiline_file_size = self.max_multiline_file_size;
+        let context_lines = self.context_lines;
+        let cwd = self.cwd.clone();
+
+        Ok(tokio::task::spawn_blocking(move || {
+            let (matcher, note) =
+                build_pattern_matcher(&pattern, fixed_string, multiline, lenient)?;
+            let mut builder = SearcherBuilder::new();
+            builder
+                .binary_detection(BinaryDetection::quit(b'\x00'))
+                .line_number(true)
+                .multi_line(multiline)
+                .invert_match(invert)
+                .before_context(context_lines)
+                .after_context(context_lines);
+
+            let matches = search_candidates(&targets, globs.as_ref(), follow_symlinks)
+                .into_par_iter()
+                .filter(|path| {
+                    path.metadata().map_or(true, |meta| {
+                        meta.len() <= max_filesize
+                            && (!multiline || meta.len() <= max_multiline_file_size)
+                    })
+                })
+                .map_init(
+                    || builder.build(),
+                    |searcher, path| {
+                        let rel = path.strip_prefix(&cwd).unwrap_or(&path);
+                        let mut sink = MatchSink::new(&matcher, rel);
+                        if let Err(e) = searcher.search_path(&matcher, &path, &mut sink) {
+                            warn!("Fail to search {:?} due to {}", &path, e);
+                        }
+                        if sink.binary { vec![] } else { sink.matches }
+                    },
+                )
+                .flatten()
+                .collect();
+            Ok((matches, note))
+        })
+        .await?)
     }
-    pub async fn grep(&self, directory: PathBuf, pattern: String) -> Result<String, AgentyError> {
-        let target_path = match sanitize_join_relative_path(&self.cwd, &directory) {
-            Ok(p) => p,
+
+    /// The `json` format of [`GrepTool::grep`], the match limits and `max_output_chars`
+    /// apply and the number of matches left out is reported as `omitted`.
+    async fn grep_json(&self, arguments: GrepToolArgs) -> Result<String, AgentyError> {
+        let max_output_chars = arguments
+            .max_output_chars
+            .unwrap_or(DEFAULT_MAX_OUTPUT_CHARS);
+        let (matches, note) = match self.collect_matches(arguments, true).await? {
+            Ok(v) => v,
             Err(e) => return Ok(e),
         };
-        if !target_path.is_dir() {
-            return Ok(format!("{:?} is not a directory", &directory));
+        let total = matches.len();
+        let mut matches = matches
+            .into_iter()
+            .chunk_by(|m| m.path.clone())
+            .into_iter()
+            .flat_map(|(_, file)| file.take(self.max_matches_per_file).collect_vec())
+            .take(self.max_total_matches)
+            .collect_vec();
+        let render = |matches: &[GrepMatch]| {
+            let mut resp = serde_json::json!({
+                "matches": matches,
+                "omitted": total - matches.len(),
+            });
+            if !note.is_empty() {
+                resp["note"] = note.trim_end().into();
+            }
+            resp.to_string()
+        };
+        let mut resp = render(&matches);
+        while resp.chars().count() > max_output_chars && !matches.is_empty() {
+            matches.pop();
+            resp = render(&matches);
+        }
+        Ok(resp)
+    }
+
+    pub async fn grep(&self, arguments: GrepToolArgs) -> Result<String, AgentyError> {
+        // Counts are compact in text already, so they are never turned into JSON.
+        if arguments.format == Some(GrepFormat::Json)
+            && arguments.count_only != Some(true)
+            && arguments.files_with_matches != Some(true)
+            && arguments.unique_files != Some(true)
+        {
+            return self.grep_json(arguments).await;
         }
+        let GrepToolArgs {
+            path,
+            paths,
+            pattern,
+            fixed_string,
+            files,
+            multiline,
+            invert,
+            count_only,
+            files_with_matches,
+            unique_files,
+            follow_symlinks,
+            max_output_chars,
+            format: _,
+            line_number_format,
+        } = arguments;
+        let line_number_format = line_number_format.unwrap_or_default();
+        let follow_symlinks = follow_symlinks.unwrap_or(false);
+        let invert = invert.unwrap_or(false);
+        let count_only = count_only.unwrap_or(false);
+        let files_with_matches = files_with_matches.unwrap_or(false);
+        let unique_files = unique_files.unwrap_or(false);
+        let fixed_string = fixed_string.unwrap_or(false);
+        let multiline = multiline.unwrap_or(false);
+        let max_multiline_file_size = self.max_multiline_file_size;
+        let max_filesize = self.max_filesize;
+        let report_skipped = self.report_skipped;
+        let max_output_chars = max_output_chars.unwrap_or(DEFAULT_MAX_OUTPUT_CHARS);
+
+        let targets = match self.resolve_targets(path, paths) {
+            Ok(targets) => targets,
+            Err(e) => return Ok(e),
+        };
+        let globs = match files.map(|f| FileGlobs::new(&f, false)).transpose() {
+            Ok(globs) => globs,
+            Err(e) => return Ok(e),
+        };
+        let cwd = self.cwd.clone();
+        let summary_threshold = self.summary_threshold;
+        let max_matches_per_file = self.max_matches_per_file;
+        let max_total_matches = self.max_total_matches;
 
         tokio::task::spawn_blocking(move || {
-            let mut buf = vec![];
-            let cur = std::io::Cursor::new(&mut buf);
-            let mut printer = StandardBuilder::new()
-                .column(true)
-                .max_columns(Some(80))
-                .build_no_color(cur);
-            let mut searcher = SearcherBuilder::new()
-                .binary_detection(BinaryDetection::quit(b'\x00'))
-                .line_number(true)
-                .build();
-            let matcher = match RegexMatcher::new_line_matcher(&pattern) {
+            let (matcher, note) = match build_pattern_matcher(&pattern, fixed_string, multiline, true)
+            {
                 Ok(v) => v,
-                Err(e) => return Ok(format!("regex {} error with {}", pattern, e)),
+                Err(e) => return Ok(e),
             };
 
-            for result in WalkDir::new(&target_path) {
-                let dent = match result {
-                    Ok(dent) => dent,
-                    Err(err) => {
-                        warn!("Fail to walk due to {}", err);
-                        continue;
+            let mut builder = SearcherBuilder::new();
+            builder
+                .binary_detection(BinaryDetection::quit(b'\x00'))
+                .line_number(true)
+                .multi_line(multiline)
+                .invert_match(invert);
+
+            // Files given explicitly are searched as is, `files` only filters walked ones.
+            let mut skipped = String::new();
+            let mut too_large = 0;
+            let candidates = search_candidates(&targets, globs.as_ref(), follow_symlinks)
+                .into_iter()
+                .filter(|path| {
+                    let Ok(meta) = path.metadata() else {
+                        return true;
+                    };
+                    if meta.len() > max_filesize {
+                        too_large += 1;
+                        return false;
                     }
-                };
-                if !dent.file_type().is_file() {
-                    continue;
+                    if multiline && meta.len() > max_multiline_file_size {
+                        skipped.push_str(&format!(
+                            "(skipped {}, {} is too large for a multiline search)\n",
+                            path.strip_prefix(&cwd).unwrap_or(path).display(),
+                            human_size(meta.len())
+                        ));
+                        return false;
+                    }
+                    true
+                })
+                .collect_vec();
+            // Searching is where the time goes on large trees, so spread it over threads. Each
+            // file is searched once, counting all of its matches and keeping what is printed
+            // for the first ones, which are put together in the order of the candidates below
+            // so the output stays deterministic. A query hitting thousands of lines still only
+            // costs a summary.
+            let budget = SharedBudget::new(
+                max_total_matches,
+                max_output_chars,
+                summary_threshold,
+                !(count_only || files_with_matches || unique_files),
+            );
+            let max_matches = max_matches_per_file.min(max_total_matches);
+            let (binaries, counts): (Vec<_>, Vec<_>) = candidates
+                .into_par_iter()
+                .enumerate()
+                .map_init(
+                    || {
+                        let buf = SharedBuffer::default();
+                        let printer = StandardBuilder::new()
+                            .column(true)
+                            .max_columns(Some(80))
+                            .build_no_color(buf.clone());
+                        (builder.build(), printer, buf)
+                    },
+                    |(searcher, printer, buf), (index, path)| {
+                        buf.0.borrow_mut().clear();
+                        // Setting up a printer for every file costs as much as the search of a
+                        // small file, so files that cannot show anything are only counted.
+                        let inner = budget.may_print(index).then(|| {
+                            let inner: Box<dyn Sink<Error = std::io::Error>> =
+                                if line_number_format == LineNumberFormat::Default {
+                                    Box::new(printer.sink_with_path(&matcher, &path))
+                                } else {
+                                    let rel = path.strip_prefix(&cwd).unwrap_or(&path);
+                                    Box::new(LineSink {
+                                        matcher: &matcher,
+                                        path: rel.display().to_string(),
+                                        format: line_number_format,
+                                        buf: buf.clone(),
+                                    })
+                                };
+                            inner
+                        });
+                        let mut sink = FileSink {
+                            inner,
+                            buf,
+                            budget: &budget,
+                            index,
+                            max_matches,
+                            found: FoundFile::default(),
+                        };
+                        if let Err(e) = searcher.search_path(&matcher, &path, &mut sink) {
+                            warn!("Fail to search {:?} due to {}", &path, e);
+                        }
+                        let found = std::mem::take(&mut sink.found);
+                        drop(sink);
+                        budget.complete(index, &found);
+                        (path, found)
+                    },
+                )
+                .filter(|(_, found)| found.binary || found.count > 0)
+                .partition(|(_, found)| found.binary);
+            let (counts, found): (Vec<_>, Vec<_>) = counts
+                .into_iter()
+                .map(|(path, found)| ((path, found.count), found))
+                .unzip();
+            let coverage = if report_skipped && (too_large > 0 || !binaries.is_empty()) {
+                format!(
+                    "(not searched: {} files larger than {}, {} binary files)",
+                    too_large,
+                    human_size(max_filesize),
+                    binaries.len()
+                )
+            } else {
+                String::new()
+            };
+
+            if unique_files {
+                if counts.is_empty() {
+                    return Ok("No files match".to_string());
                 }
-                if let Err(e) = searcher.search_path(
-                    &matcher,
-                    dent.path(),
-                    printer.sink_with_path(&matcher, dent.path()),
-                ) {
-                    warn!("Fail to search {:?} due to {}", &dent, e);
+                let resp = counts
+                    .iter()
+                    .map(|(path, _)| path.strip_prefix(&cwd).unwrap_or(path).display())
+                    .join("\n");
+                let marker = format!("(output truncated, {} files match)", counts.len());
+                return Ok(fit_output(&resp, &marker, &[], max_output_chars));
+            }
+            let total: usize = counts.iter().map(|(_, count)| count).sum();
+            // Counts are compact anyway, so neither the summary nor the match caps apply.
+            if count_only || files_with_matches {
+                let lns = counts
+                    .iter()
+                    .map(|(path, count)| {
+                        let path = path.strip_prefix(&cwd).unwrap_or(path).display();
+                        if files_with_matches {
+                            format!("{}: {} match(es)", path, count)
+                        } else {
+                            format!("{}: {}", path, count)
+                        }
+                    })
+                    .join("\n");
+                let resp = format!(
+                    "{}{}total {} in {} files\n{}",
+                    note,
+                    skipped,
+                    total,
+                    counts.len(),
+                    lns
+                );
+                return Ok(fit_output(
+                    &resp,
+                    "(output truncated)",
+                    &[&coverage],
+                    max_output_chars,
+                ));
+            }
+            if total > summary_threshold {
+                let files = counts.len();
+                let mut lns = counts
+                    .into_iter()
+                    .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+                    .map(|(path, count)| {
+                        format!(
+                            "{}: {} matches",
+                            path.strip_prefix(&cwd).unwrap_or(&path).display(),
+                            count
+                        )
+                    })
+                    .collect_vec();
+                if lns.len() > MAX_SUMMARY_FILES {
+                    let more = lns.len() - MAX_SUMMARY_FILES;
+                    lns.truncate(MAX_SUMMARY_FILES);
+                    lns.push(format!("... and {} more files", more));
                 }
+                let resp = format!(
+                    "{}{}Too many matches to show, narrow the pattern or pass `files` to search fewer files. Matches per file:\n{}\ntotal {} in {} files",
+                    note,
+                    skipped,
+                    lns.join("\n"),
+                    total,
+                    files
+                );
+                return Ok(fit_output(
+                    &resp,
+                    "(output truncated)",
+                    &[&coverage],
+                    max_output_chars,
+                ));
             }
-            let mut resp = String::from_utf8_lossy(&buf).to_string();
-            if resp.len() > 16384 {
-                // cutoff a bit...
-                resp = (&resp[0..16384]).to_string();
+
+            // What was left out is noted after the matches and counts against the limit as
+            // well, so the matches are put together again with less room until it all fits.
+            let head = format!("{}{}", note, skipped);
+            let mut budget = max_output_chars.saturating_sub(head.chars().count());
+            loop {
+                let (out, exhausted, omitted) =
+                    stitch(&counts, &found, max_total_matches, budget);
+                let mut resp = format!("{}{}", head, out);
+                if exhausted {
+                    push_note(
+                        &mut resp,
+                        &format!("(output truncated, {} chars shown)", out.chars().count()),
+                    );
+                }
+                if !omitted.is_empty() {
+                    let total: usize = omitted.iter().map(|(_, n)| n).sum();
+                    // Files are listed in at most half the limit, the matches get the rest.
+                    let mut note = format!("({} matches omitted in ", total);
+                    let mut listed = 0;
+                    for (path, n) in omitted.iter().take(MAX_SUMMARY_FILES) {
+                        let path = path.strip_prefix(&cwd).unwrap_or(path);
+                        let file = format!("{} ({})", path.display(), n);
+                        if listed > 0 && note.chars().count() + file.chars().count() + 2
+                            > max_output_chars / 2
+                        {
+                            break;
+                        }
+                        if listed > 0 {
+                            note.push_str(", ");
+                        }
+                        note.push_str(&file);
+                        listed += 1;
+                    }
+                    if omitted.len() > listed {
+                        note.push_str(&format!(" and {} more files", omitted.len() - listed));
+                    }
+                    note.push(')');
+                    push_note(&mut resp, &note);
+                }
+                push_note(&mut resp, &coverage);
+                let over = resp.chars().count().saturating_sub(max_output_chars);
+                if over == 0 || budget == 0 {
+                    return Ok(truncate_chars(&resp, max_output_chars).to_string());
+                }
+                budget = budget.saturating_sub(over);
             }
-            Ok(resp)
         })
         .await?
     }
@@ -88,13 +1068,310 @@ impl Tool for GrepTool {
     type ARGUMENTS = GrepToolArgs;
     const NAME: &str = "grep_files";
     const DESCRIPTION: Option<&str> = Some(
-        "Grep files in the given path with pattern. The path should be always relative path and '.' is allowed while '..' is not allowed. Note the pattern is in regex grammar not glob grammar.",
+        "Grep files in the given `path` with pattern, `path` can be a directory to search recursively or a single file and more of them can be given in `paths`. The paths should be always relative paths and '.' is allowed while '..' is not allowed. Note the pattern is in regex grammar not glob grammar, set `fixed_string` to true to search for the pattern as a literal string instead, e.g. 'fn main()'. A pattern that is not a valid regex is searched literally. Set `multiline` to true to let the pattern span lines, e.g. 'impl Tool for\\s+\\w+Tool \\{'. Pass glob patterns in `files` to only search matching files, e.g. '*.rs' or 'src/**/*.rs'. The output is limited to `max_output_chars` characters and a limited number of matches per file, omitted matches are counted at the end. When there are too many matches only the number of matches per file is returned. Set `count_only` to true to always get just the number of matching lines per file, or `files_with_matches` to true to only learn which files match with their counts, or `unique_files` to true to get nothing but the matching paths, one per line, and `invert` to true to get the lines not matching the pattern instead. Both can be combined. Very large files and binary files are not searched. Symlinked directories are only searched with `follow_symlinks` set to true, which can visit the same files more than once when links point into the searched tree. Set `format` to 'json' to get the matches as a JSON object with the path, line number, column, text and surrounding lines of each match, e.g. to process them further. For the `text` format, set `line_number_format` to 'colon' to get every matching line as 'file:line:col:text' or to 'json' to get it as a JSON object with `file`, `line`, `col` and `text` on a line of its own.",
     );
 
     fn invoke(
         &self,
         arguments: Self::ARGUMENTS,
     ) -> impl Future<Output = Result<String, AgentyError>> + Send {
-        self.grep(arguments.directory, arguments.pattern)
+        self.grep(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory with `files` files named `f000.txt` on, each with `lines` matching lines.
+    fn fixture(files: usize, lines: usize) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for f in 0..files {
+            let text = (0..lines)
+                .map(|l| format!("needle {} of file {}\nhay\n", l, f))
+                .collect::<String>();
+            std::fs::write(dir.path().join(format!("f{:03}.txt", f)), text).unwrap();
+        }
+        dir
+    }
+
+    fn args(value: serde_json::Value) -> GrepToolArgs {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_caps_apply_across_parallel_files() {
+        let dir = fixture(64, 3);
+        let resp = GrepTool::new(dir.path().to_path_buf())
+            .with_match_limits(2, 9)
+            .grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "line_number_format": "colon",
+            })))
+            .await
+            .unwrap();
+        let lines = resp.lines().collect_vec();
+        assert_eq!(
+            lines[..9],
+            [
+                "f000.txt:1:1:needle 0 of file 0",
+                "f000.txt:3:1:needle 1 of file 0",
+                "f001.txt:1:1:needle 0 of file 1",
+                "f001.txt:3:1:needle 1 of file 1",
+                "f002.txt:1:1:needle 0 of file 2",
+                "f002.txt:3:1:needle 1 of file 2",
+                "f003.txt:1:1:needle 0 of file 3",
+                "f003.txt:3:1:needle 1 of file 3",
+                "f004.txt:1:1:needle 0 of file 4",
+            ]
+        );
+        assert!(
+            lines[9].starts_with("(183 matches omitted in f000.txt (1), f001.txt (1),"),
+            "{}",
+            lines[9]
+        );
+        assert_eq!(lines.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_output_fits_limit_at_line_boundaries() {
+        let dir = fixture(30, 4);
+        std::fs::write(dir.path().join("big.txt"), "needle\n".repeat(100)).unwrap();
+        let line = regex::Regex::new(r"^f\d{3}\.txt:\d+:1:needle \d+ of file \d+$").unwrap();
+        for mode in ["lines", "count_only", "unique_files", "summary"] {
+            let tool = GrepTool::with_summary_threshold(
+                dir.path().to_path_buf(),
+                if mode == "summary" { 50 } else { 200 },
+            )
+            .with_match_limits(3, 60)
+            .with_max_filesize(200, true);
+            for max in [20, 45, 80, 150, 400, 1000, 3000] {
+                let mut value = serde_json::json!({
+                    "path": ".",
+                    "pattern": "needle",
+                    "max_output_chars": max,
+                    "line_number_format": "colon",
+                });
+                if mode == "count_only" || mode == "unique_files" {
+                    value[mode] = true.into();
+                }
+                let resp = tool.grep(args(value)).await.unwrap();
+                assert!(resp.chars().count() <= max, "{} {}: {}", mode, max, resp);
+                if mode == "lines" {
+                    for ln in resp.lines() {
+                        let complete = ln.starts_with('(') || line.is_match(ln);
+                        assert!(complete, "{}: {:?}", max, ln);
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notes_are_kept_within_limit() {
+        let dir = fixture(10, 3);
+        let resp = GrepTool::new(dir.path().to_path_buf())
+            .grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "max_output_chars": 200,
+                "line_number_format": "colon",
+            })))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp,
+            "f000.txt:1:1:needle 0 of file 0\n\
+             (output truncated, 32 chars shown)\n\
+             (29 matches omitted in f000.txt (2), f001.txt (3), f002.txt (3), f003.txt (3), \
+             f004.txt (3) and 5 more files)"
+        );
+        assert!(resp.chars().count() <= 200);
+    }
+
+    #[tokio::test]
+    async fn test_same_query_gives_same_output() {
+        let dir = tempfile::tempdir().unwrap();
+        // Created out of order so the directory listing is not sorted already.
+        for i in (0..200).rev() {
+            let path = dir.path().join(format!("d{}/s{}/f{}.txt", i % 7, i % 3, i));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let text = "hay\nneedle\n".repeat(1 + i % 4);
+            std::fs::write(path, text).unwrap();
+        }
+        let tool = GrepTool::with_summary_threshold(dir.path().to_path_buf(), 10_000)
+            .with_match_limits(3, 400);
+        let queries = [
+            serde_json::json!({ "path": ".", "pattern": "needle" }),
+            serde_json::json!({ "path": ".", "pattern": "needle", "line_number_format": "colon" }),
+            serde_json::json!({ "path": ".", "pattern": "needle", "max_output_chars": 3000 }),
+            serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "format": "json",
+                "max_output_chars": 1_000_000,
+            }),
+            serde_json::json!({ "path": ".", "pattern": "needle", "count_only": true }),
+            serde_json::json!({ "path": ".", "pattern": "needle", "unique_files": true }),
+        ];
+        for query in queries {
+            let first = tool.grep(args(query.clone())).await.unwrap();
+            for _ in 0..5 {
+                assert_eq!(tool.grep(args(query.clone())).await.unwrap(), first);
+            }
+            let structured = tool.grep_structured(args(query.clone())).await.unwrap();
+            let paths = structured.iter().map(|m| m.path.clone()).collect_vec();
+            assert!(paths.is_sorted(), "{:?}", paths);
+        }
+
+        let files = tool
+            .grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "unique_files": true,
+                "max_output_chars": 100_000,
+            })))
+            .await
+            .unwrap();
+        let files = files.lines().collect_vec();
+        assert_eq!(files.len(), 200);
+        assert!(files.is_sorted(), "{:?}", files);
+    }
+
+    #[tokio::test]
+    async fn test_fixed_string() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("main.rs"),
+            "fn main() {}\nfn main_loop() {}\n",
+        )
+        .unwrap();
+        let tool = GrepTool::new(dir.path().to_path_buf());
+        let grep = |fixed_string: bool| {
+            tool.grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "fn main()",
+                "fixed_string": fixed_string,
+                "line_number_format": "colon",
+            })))
+        };
+        assert_eq!(grep(true).await.unwrap(), "main.rs:1:1:fn main() {}\n");
+        // `()` is an empty group, so the regex matches any line starting with `fn main`.
+        assert_eq!(
+            grep(false).await.unwrap(),
+            "main.rs:1:1:fn main() {}\nmain.rs:2:1:fn main_loop() {}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_special_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("calc.py"),
+            "total = price * (1 + tax)\nitems[0] = a+b\nprint(total)\n",
+        )
+        .unwrap();
+        let tool = GrepTool::new(dir.path().to_path_buf());
+        for (pattern, line) in [
+            ("* (1 + tax)", "calc.py:1:15:total = price * (1 + tax)\n"),
+            ("items[0] = a+b", "calc.py:2:1:items[0] = a+b\n"),
+            ("$^.?|\\", ""),
+        ] {
+            let resp = tool
+                .grep(args(serde_json::json!({
+                    "path": ".",
+                    "pattern": pattern,
+                    "fixed_string": true,
+                    "line_number_format": "colon",
+                })))
+                .await
+                .unwrap();
+            assert_eq!(resp, line, "{}", pattern);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_regex_is_searched_literally() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("calc.py"), "print(total\nprint(x)\n").unwrap();
+        let tool = GrepTool::new(dir.path().to_path_buf());
+        let resp = tool
+            .grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "print(total",
+                "line_number_format": "colon",
+            })))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp,
+            "(pattern was not valid regex; searched literally instead)\n\
+             calc.py:1:1:print(total\n"
+        );
+
+        let resp = tool
+            .grep_structured(args(serde_json::json!({
+                "path": ".",
+                "pattern": "print(total",
+            })))
+            .await;
+        let err = resp.unwrap_err().to_string();
+        assert!(err.starts_with("regex print(total error with"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("lib.rs"), "needle\n").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("linked")).unwrap();
+        let tool = GrepTool::new(dir.path().to_path_buf());
+        let grep = |follow_symlinks: bool| {
+            tool.grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "follow_symlinks": follow_symlinks,
+                "line_number_format": "colon",
+            })))
+        };
+        assert_eq!(grep(true).await.unwrap(), "linked/lib.rs:1:1:needle\n");
+        assert!(!grep(false).await.unwrap().contains("lib.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_files_with_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "needle\nhay\nneedle\nneedle\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "needle\n").unwrap();
+        std::fs::write(dir.path().join("c.txt"), "hay\n").unwrap();
+        let resp = GrepTool::new(dir.path().to_path_buf())
+            .grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "files_with_matches": true,
+            })))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp,
+            "total 4 in 2 files\na.txt: 3 match(es)\nb.txt: 1 match(es)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unique_files() {
+        let dir = fixture(3, 4);
+        std::fs::write(dir.path().join("hay.txt"), "hay\n").unwrap();
+        let resp = GrepTool::new(dir.path().to_path_buf())
+            .grep(args(serde_json::json!({
+                "path": ".",
+                "pattern": "needle",
+                "unique_files": true,
+            })))
+            .await
+            .unwrap();
+        assert_eq!(resp, "f000.txt\nf001.txt\nf002.txt");
     }
 }
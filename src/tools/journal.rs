@@ -0,0 +1,165 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::sync::Mutex;
+
+use crate::error::AgentyError;
+
+use super::file::atomic_write;
+
+/// A single mutation recorded by [`FsJournal`].
+#[derive(Debug, Clone)]
+pub struct FsChange {
+    pub id: u64,
+    pub path: PathBuf,
+    /// Copy of the original contents, `None` if the file did not exist before.
+    pub backup: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct JournalInner {
+    next_id: u64,
+    changes: Vec<FsChange>,
+}
+
+/// Undo log shared by the tools that mutate files.
+///
+/// Every mutation first saves the original file into the journal directory so the host
+/// application can roll back. The journal is meant for the host only and is never exposed
+/// to the model.
+#[derive(Debug, Clone)]
+pub struct FsJournal {
+    dir: PathBuf,
+    inner: Arc<Mutex<JournalInner>>,
+}
+
+impl FsJournal {
+    pub async fn new(dir: PathBuf) -> Result<Self, AgentyError> {
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self {
+            dir,
+            inner: Arc::new(Mutex::new(JournalInner {
+                next_id: 0,
+                changes: vec![],
+            })),
+        })
+    }
+
+    /// The directory holding the backups.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Record the current state of `path` before it gets mutated, returning the change id.
+    pub async fn record(&self, path: &Path) -> Result<u64, AgentyError> {
+        let mut inner = self.inner.lock().await;
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let backup = if tokio::fs::try_exists(path).await? {
+            // A new file rather than one named after the id, which starts at 0 in every
+            // journal and would clash with the backups of others sharing the directory.
+            let (file, backup) = tempfile::Builder::new()
+                .prefix(&format!("{:08}-", id))
+                .tempfile_in(&self.dir)?
+                .keep()
+                .map_err(|e| e.error)?;
+            drop(file);
+            tokio::fs::copy(path, &backup).await?;
+            Some(backup)
+        } else {
+            None
+        };
+        inner.changes.push(FsChange {
+            id,
+            path: path.to_path_buf(),
+            backup,
+        });
+        Ok(id)
+    }
+
+    async fn restore(change: &FsChange) -> Result<(), AgentyError> {
+        match &change.backup {
+            Some(backup) => {
+                let content = tokio::fs::read(backup).await?;
+                atomic_write(&change.path, content).await?;
+                tokio::fs::remove_file(backup).await?;
+            }
+            None => match tokio::fs::remove_file(&change.path).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            },
+        }
+        Ok(())
+    }
+
+    /// Revert the most recent change, returning it if there was any.
+    pub async fn undo_last(&self) -> Result<Option<FsChange>, AgentyError> {
+        let mut inner = self.inner.lock().await;
+        let Some(change) = inner.changes.pop() else {
+            return Ok(None);
+        };
+        if let Err(e) = Self::restore(&change).await {
+            inner.changes.push(change);
+            return Err(e);
+        }
+        Ok(Some(change))
+    }
+
+    /// Revert the change `id` alone, returning it if it could still be undone.
+    ///
+    /// Later changes of the same path are overwritten, so they should be undone first.
+    pub async fn undo(&self, id: u64) -> Result<Option<FsChange>, AgentyError> {
+        let mut inner = self.inner.lock().await;
+        let Some(index) = inner.changes.iter().position(|c| c.id == id) else {
+            return Ok(None);
+        };
+        Self::restore(&inner.changes[index]).await?;
+        Ok(Some(inner.changes.remove(index)))
+    }
+
+    /// Revert all changes, newest first, returning them in the order they were undone.
+    pub async fn undo_all(&self) -> Result<Vec<FsChange>, AgentyError> {
+        let mut undone = vec![];
+        while let Some(change) = self.undo_last().await? {
+            undone.push(change);
+        }
+        Ok(undone)
+    }
+
+    /// All changes that can still be undone, oldest first.
+    pub async fn changes(&self) -> Vec<FsChange> {
+        self.inner.lock().await.changes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_journals_sharing_a_dir_keep_their_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = dir.path().join("backups");
+        let first = FsJournal::new(backups.clone()).await.unwrap();
+        let second = FsJournal::new(backups).await.unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+
+        // Both journals hand out id 0 first.
+        assert_eq!(first.record(&a).await.unwrap(), 0);
+        assert_eq!(second.record(&b).await.unwrap(), 0);
+        std::fs::write(&a, "changed a").unwrap();
+        std::fs::write(&b, "changed b").unwrap();
+
+        assert!(first.undo_last().await.unwrap().is_some());
+        assert!(second.undo_last().await.unwrap().is_some());
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "b");
+        assert_eq!(std::fs::read_dir(first.dir()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,654 @@
+use std::{cmp::Ordering, future::Future, path::PathBuf};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{sanitize_join_relative_path, truncate_at_line_boundary};
+
+pub const DEFAULT_MAX_JSON_OUTPUT: usize = 16384;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct JsonQueryArgs {
+    /// The JSON document inline, either this or `file_path`.
+    pub json: Option<String>,
+    /// A JSON file to query, either this or `json`.
+    pub file_path: Option<PathBuf>,
+    pub query: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Field(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>),
+    Iterate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+/// Longer operators first, so `<=` is not taken for `<`.
+const CMP_OPS: [(&str, CmpOp); 6] = [
+    ("==", CmpOp::Eq),
+    ("!=", CmpOp::Ne),
+    ("<=", CmpOp::Le),
+    (">=", CmpOp::Ge),
+    ("<", CmpOp::Lt),
+    (">", CmpOp::Gt),
+];
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Truthy(Filter),
+    Compare(Filter, CmpOp, Filter),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    /// The steps with the text they were parsed from, to point at the failing one.
+    Path(Vec<(Step, String)>),
+    Literal(Value),
+    Keys,
+    Length,
+    Select(Box<Condition>),
+}
+
+/// The byte offsets in `s` outside of strings and brackets, where the separators of a level
+/// can be found.
+fn top_level_offsets(s: &str) -> Vec<usize> {
+    let mut offsets = vec![];
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => offsets.push(i),
+            _ => {}
+        }
+    }
+    offsets
+}
+
+fn split_top_level<'a>(s: &'a str, sep: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    for i in top_level_offsets(s) {
+        if i >= start && s[i..].starts_with(sep) {
+            parts.push(&s[start..i]);
+            start = i + sep.len();
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// The end of the string literal starting at `start`, past its closing quote.
+fn string_end(s: &str, start: usize) -> Option<usize> {
+    let mut escaped = false;
+    for (i, b) in s.bytes().enumerate().skip(start + 1) {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The end of the bracket opened at `start`, past the matching `]`.
+fn bracket_end(s: &str, start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < s.len() {
+        match s.as_bytes()[i] {
+            b'"' => {
+                i = string_end(s, i)?;
+                continue;
+            }
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn parse_string(s: &str) -> Result<String, String> {
+    serde_json::from_str(s).map_err(|e| format!("invalid string {}: {}", s, e))
+}
+
+fn parse_index(s: &str) -> Result<Option<i64>, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    s.parse()
+        .map(Some)
+        .map_err(|_| format!("{:?} is not an index", s))
+}
+
+fn parse_path(text: &str) -> Result<Vec<(Step, String)>, String> {
+    let mut steps = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        let start = i;
+        let c = text[i..].chars().next().expect("in bounds");
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+        match c {
+            '.' => {
+                i += 1;
+                let rest = &text[i..];
+                if rest.starts_with('"') {
+                    let end = string_end(text, i)
+                        .ok_or_else(|| format!("unterminated string in `{}`", &text[start..]))?;
+                    steps.push((
+                        Step::Field(parse_string(&text[i..end])?),
+                        text[start..end].to_string(),
+                    ));
+                    i = end;
+                } else {
+                    let len = rest
+                        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    if len > 0 {
+                        i += len;
+                        steps.push((
+                            Step::Field(text[start + 1..i].to_string()),
+                            text[start..i].to_string(),
+                        ));
+                    } else if !(rest.is_empty() || rest.starts_with('[')) {
+                        return Err(format!("unexpected `{}`", &text[start..]));
+                    }
+                }
+            }
+            '[' => {
+                let end = bracket_end(text, i)
+                    .ok_or_else(|| format!("unclosed `[` in `{}`", &text[start..]))?;
+                let inner = text[i + 1..end - 1].trim();
+                let step = if inner.is_empty() {
+                    Step::Iterate
+                } else if inner.starts_with('"') {
+                    Step::Field(parse_string(inner)?)
+                } else if let Some((from, to)) = inner.split_once(':') {
+                    Step::Slice(parse_index(from)?, parse_index(to)?)
+                } else {
+                    Step::Index(parse_index(inner)?.expect("not empty"))
+                };
+                steps.push((step, text[start..end].to_string()));
+                i = end;
+            }
+            _ => return Err(format!("unexpected `{}`", &text[start..])),
+        }
+    }
+    Ok(steps)
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let alternatives = split_top_level(text, " or ");
+    if alternatives.len() > 1 {
+        return Ok(Condition::Or(
+            alternatives
+                .into_iter()
+                .map(parse_condition)
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    let all = split_top_level(text, " and ");
+    if all.len() > 1 {
+        return Ok(Condition::And(
+            all.into_iter()
+                .map(parse_condition)
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    for i in top_level_offsets(text) {
+        if let Some((op_text, op)) = CMP_OPS.iter().find(|(o, _)| text[i..].starts_with(o)) {
+            let rhs = &text[i + op_text.len()..];
+            return Ok(Condition::Compare(
+                parse_filter(&text[..i])?,
+                *op,
+                parse_filter(rhs)?,
+            ));
+        }
+    }
+    Ok(Condition::Truthy(parse_filter(text)?))
+}
+
+fn parse_filter(text: &str) -> Result<Filter, String> {
+    let text = text.trim();
+    match text {
+        "" => return Err("empty filter".to_string()),
+        "keys" => return Ok(Filter::Keys),
+        "length" => return Ok(Filter::Length),
+        _ => {}
+    }
+    if let Some(inner) = text
+        .strip_prefix("select(")
+        .and_then(|t| t.strip_suffix(')'))
+    {
+        return Ok(Filter::Select(Box::new(parse_condition(inner)?)));
+    }
+    if text.starts_with(['.', '[']) {
+        return Ok(Filter::Path(parse_path(text)?));
+    }
+    serde_json::from_str(text)
+        .map(Filter::Literal)
+        .map_err(|_| format!("unknown filter `{}`", text))
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// `index` counted from the end if negative, clamped to `0..=len`.
+fn clamp_index(index: i64, len: usize) -> usize {
+    if index < 0 {
+        len.saturating_sub(index.unsigned_abs() as usize)
+    } else {
+        (index as usize).min(len)
+    }
+}
+
+fn apply_step(step: &Step, value: &Value) -> Result<Vec<Value>, String> {
+    Ok(match (step, value) {
+        (Step::Field(_) | Step::Index(_) | Step::Slice(..), Value::Null) => vec![Value::Null],
+        (Step::Field(key), Value::Object(map)) => {
+            vec![map.get(key).cloned().unwrap_or(Value::Null)]
+        }
+        (Step::Index(index), Value::Array(items)) => {
+            let i = if *index < 0 {
+                items.len().checked_sub(index.unsigned_abs() as usize)
+            } else {
+                Some(*index as usize)
+            };
+            vec![i.and_then(|i| items.get(i)).cloned().unwrap_or(Value::Null)]
+        }
+        (Step::Slice(from, to), Value::Array(items)) => {
+            let from = clamp_index(from.unwrap_or(0), items.len());
+            let to = clamp_index(to.unwrap_or(items.len() as i64), items.len());
+            vec![Value::Array(
+                items.get(from..to.max(from)).unwrap_or_default().to_vec(),
+            )]
+        }
+        (Step::Slice(from, to), Value::String(s)) => {
+            let chars = s.chars().collect::<Vec<_>>();
+            let from = clamp_index(from.unwrap_or(0), chars.len());
+            let to = clamp_index(to.unwrap_or(chars.len() as i64), chars.len());
+            vec![Value::String(chars[from..to.max(from)].iter().collect())]
+        }
+        (Step::Iterate, Value::Array(items)) => items.clone(),
+        (Step::Iterate, Value::Object(map)) => map.values().cloned().collect(),
+        (Step::Iterate, _) => return Err(format!("can not iterate over {}", type_name(value))),
+        (Step::Field(key), _) => {
+            return Err(format!("can not index {} with {:?}", type_name(value), key));
+        }
+        (_, _) => return Err(format!("can not index {} with a number", type_name(value))),
+    })
+}
+
+fn compare(lhs: &Value, op: CmpOp, rhs: &Value) -> bool {
+    let ordering = match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ if lhs == rhs => Some(Ordering::Equal),
+        _ => None,
+    };
+    match op {
+        CmpOp::Eq => ordering == Some(Ordering::Equal),
+        CmpOp::Ne => ordering != Some(Ordering::Equal),
+        CmpOp::Lt => ordering == Some(Ordering::Less),
+        CmpOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        CmpOp::Gt => ordering == Some(Ordering::Greater),
+        CmpOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+fn holds(condition: &Condition, value: &Value) -> Result<bool, String> {
+    Ok(match condition {
+        Condition::Truthy(filter) => apply(filter, value)?.iter().any(truthy),
+        Condition::Compare(lhs, op, rhs) => {
+            let first = |filter| -> Result<Value, String> {
+                Ok(apply(filter, value)?
+                    .into_iter()
+                    .next()
+                    .unwrap_or(Value::Null))
+            };
+            compare(&first(lhs)?, *op, &first(rhs)?)
+        }
+        Condition::And(all) => {
+            for c in all {
+                if !holds(c, value)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        Condition::Or(alternatives) => {
+            for c in alternatives {
+                if holds(c, value)? {
+                    return Ok(true);
+                }
+            }
+            false
+        }
+    })
+}
+
+fn apply(filter: &Filter, value: &Value) -> Result<Vec<Value>, String> {
+    match filter {
+        Filter::Path(steps) => {
+            let mut values = vec![value.clone()];
+            for (step, text) in steps {
+                let mut next = vec![];
+                for value in &values {
+                    next.extend(
+                        apply_step(step, value)
+                            .map_err(|e| format!("Error at `{}`: {}", text, e))?,
+                    );
+                }
+                values = next;
+            }
+            Ok(values)
+        }
+        Filter::Literal(literal) => Ok(vec![literal.clone()]),
+        Filter::Keys => match value {
+            Value::Object(map) => {
+                let mut keys = map.keys().cloned().collect::<Vec<_>>();
+                keys.sort();
+                Ok(vec![keys.into()])
+            }
+            Value::Array(items) => Ok(vec![(0..items.len()).collect::<Vec<_>>().into()]),
+            _ => Err(format!("Error at `keys`: {} has no keys", type_name(value))),
+        },
+        Filter::Length => match value {
+            Value::Null => Ok(vec![0.into()]),
+            Value::Array(items) => Ok(vec![items.len().into()]),
+            Value::Object(map) => Ok(vec![map.len().into()]),
+            Value::String(s) => Ok(vec![s.chars().count().into()]),
+            Value::Number(n) => Ok(vec![n.as_f64().map(f64::abs).into()]),
+            Value::Bool(_) => Err("Error at `length`: boolean has no length".to_string()),
+        },
+        Filter::Select(condition) => Ok(if holds(condition, value)? {
+            vec![value.clone()]
+        } else {
+            vec![]
+        }),
+    }
+}
+
+/// Run `query` against `value`, a practical subset of jq.
+///
+/// Supported are paths like `.foo.bar[0]`, `.["a key"]`, `.items[]` and slices like
+/// `.[1:3]`, `keys`, `length` and `select(...)` with comparisons joined by `and` or `or`,
+/// combined with `|`. Errors are meant for the model and name the failing part of the query.
+pub fn json_query(value: &Value, query: &str) -> Result<Vec<Value>, String> {
+    let query = if query.trim().is_empty() { "." } else { query };
+    let filters = split_top_level(query, "|")
+        .into_iter()
+        .map(|term| {
+            parse_filter(term).map_err(|e| format!("Can not parse `{}`: {}", term.trim(), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut values = vec![value.clone()];
+    for filter in &filters {
+        let mut next = vec![];
+        for value in &values {
+            next.extend(apply(filter, value)?);
+        }
+        values = next;
+    }
+    Ok(values)
+}
+
+/// Extracts parts of a JSON document, so large documents need not be read whole.
+#[derive(Debug, Clone)]
+pub struct JsonQueryTool {
+    pub cwd: PathBuf,
+    /// Bytes of output at most.
+    pub max_output: usize,
+}
+
+impl JsonQueryTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_JSON_OUTPUT,
+        }
+    }
+
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    pub async fn json_query(&self, arguments: JsonQueryArgs) -> Result<String, AgentyError> {
+        let content = match (arguments.json, arguments.file_path) {
+            (Some(json), None) => json,
+            (None, Some(file_path)) => {
+                let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
+                    Ok(p) => p,
+                    Err(e) => return Ok(e),
+                };
+                match tokio::fs::read_to_string(&target_path).await {
+                    Ok(content) => content,
+                    Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+                }
+            }
+            _ => return Ok("Exactly one of `json` and `file_path` is required".to_string()),
+        };
+        let value: Value = match serde_json::from_str(&content) {
+            Ok(value) => value,
+            Err(e) => return Ok(format!("Invalid JSON: {}", e)),
+        };
+        let results = match json_query(&value, &arguments.query) {
+            Ok(results) => results,
+            Err(e) => return Ok(e),
+        };
+        if results.is_empty() {
+            return Ok("No results".to_string());
+        }
+        let resp = results
+            .iter()
+            .map(serde_json::to_string_pretty)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
+        let cut = truncate_at_line_boundary(&resp, self.max_output);
+        if cut.len() < resp.len() {
+            return Ok(format!(
+                "{}\n(output truncated, {} of {} bytes shown, narrow down the query)",
+                cut.trim_end(),
+                cut.len(),
+                resp.len()
+            ));
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for JsonQueryTool {
+    type ARGUMENTS = JsonQueryArgs;
+    const NAME: &str = "json_query";
+    const DESCRIPTION: Option<&str> = Some(
+        "Extract parts of a JSON document given inline as `json` or in the file `file_path` with a jq-like `query`. Supported are paths like '.foo.bar[0]', '.[\"a key\"]', '.[-1]', slices like '.items[2:5]', iterating with '.items[]', 'keys', 'length' and 'select(.name == \"x\" and .size > 3)', chained with '|', e.g. '.items[] | select(.done == false) | .title'. Each result is pretty-printed and the output is limited, so narrow down large documents with the query.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.json_query(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn doc() -> Value {
+        json!({
+            "name": "agenty",
+            "a key": {"nested": [1, 2, 3]},
+            "items": [
+                {"title": "one", "done": true, "size": 1},
+                {"title": "two", "done": false, "size": 5},
+                {"title": "three", "done": false, "size": 9},
+            ],
+        })
+    }
+
+    fn query(q: &str) -> Result<Vec<Value>, String> {
+        json_query(&doc(), q)
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(query(".name").unwrap(), [json!("agenty")]);
+        assert_eq!(query(".items[0].title").unwrap(), [json!("one")]);
+        assert_eq!(query(".items[-1].size").unwrap(), [json!(9)]);
+        assert_eq!(query(".[\"a key\"].nested[1]").unwrap(), [json!(2)]);
+        assert_eq!(query(".\"a key\".nested[5]").unwrap(), [Value::Null]);
+        assert_eq!(query(".missing.deeper").unwrap(), [Value::Null]);
+        assert_eq!(query("").unwrap(), [doc()]);
+    }
+
+    #[test]
+    fn test_iterate_and_select() {
+        assert_eq!(
+            query(".items[].title").unwrap(),
+            [json!("one"), json!("two"), json!("three")]
+        );
+        assert_eq!(
+            query(".items[] | select(.done == false and .size > 5) | .title").unwrap(),
+            [json!("three")]
+        );
+        assert_eq!(
+            query(".items[] | select(.done or .title == \"two\") | .size").unwrap(),
+            [json!(1), json!(5)]
+        );
+        assert_eq!(
+            query(".items[] | select(.size <= 5) | .title").unwrap(),
+            [json!("one"), json!("two")]
+        );
+        assert!(query(".items[] | select(.size > 100)").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_slices() {
+        assert_eq!(query(".items[1:] | length").unwrap(), [json!(2)]);
+        assert_eq!(query(".items[:1][0].title").unwrap(), [json!("one")]);
+        assert_eq!(query(".items[-2:-1][].title").unwrap(), [json!("two")]);
+        assert_eq!(query(".items[5:9]").unwrap(), [json!([])]);
+        assert_eq!(query(".name[1:4]").unwrap(), [json!("gen")]);
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let doc = json!({
+            "größe": [{"名前": "ä", "n": 1}, {"名前": "ö", "n": 2}],
+        });
+        assert_eq!(
+            json_query(&doc, ".größe[] | .名前").unwrap(),
+            [json!("ä"), json!("ö")]
+        );
+        assert_eq!(
+            json_query(&doc, ".größe[] | select(.名前 == \"ö\") | .n").unwrap(),
+            [json!(2)]
+        );
+        assert_eq!(
+            json_query(&doc, ".größe[] | select(.n > 1 and .名前 != \"ä\") | .名前").unwrap(),
+            [json!("ö")]
+        );
+    }
+
+    #[test]
+    fn test_keys_and_length() {
+        assert_eq!(query("keys").unwrap(), [json!(["a key", "items", "name"])]);
+        assert_eq!(query(".items | keys").unwrap(), [json!([0, 1, 2])]);
+        assert_eq!(query(".items | length").unwrap(), [json!(3)]);
+        assert_eq!(query(".name | length").unwrap(), [json!(6)]);
+        assert_eq!(query(".missing | length").unwrap(), [json!(0)]);
+    }
+
+    #[test]
+    fn test_errors_name_the_failing_part() {
+        assert_eq!(
+            query(".name.first").unwrap_err(),
+            "Error at `.first`: can not index string with \"first\""
+        );
+        assert_eq!(
+            query(".items.title").unwrap_err(),
+            "Error at `.title`: can not index array with \"title\""
+        );
+        assert_eq!(
+            query(".name[0]").unwrap_err(),
+            "Error at `[0]`: can not index string with a number"
+        );
+        assert_eq!(
+            query(".items[0].size[]").unwrap_err(),
+            "Error at `[]`: can not iterate over number"
+        );
+        assert_eq!(
+            query(".items[0].done | length").unwrap_err(),
+            "Error at `length`: boolean has no length"
+        );
+        assert_eq!(
+            query(".name | keys").unwrap_err(),
+            "Error at `keys`: string has no keys"
+        );
+        assert_eq!(
+            query(".items[0 | length").unwrap_err(),
+            "Can not parse `.items[0 | length`: unclosed `[` in `[0 | length`"
+        );
+        assert_eq!(
+            query(".items | frobnicate").unwrap_err(),
+            "Can not parse `frobnicate`: unknown filter `frobnicate`"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::{path::Path, sync::LazyLock};
+
+use regex::Regex;
+
+/// Guess the language of a source file from its extension.
+pub fn detect_language(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "rs" => "Rust",
+        "py" | "pyi" => "Python",
+        "js" | "mjs" | "cjs" | "jsx" => "JavaScript",
+        "ts" | "tsx" | "mts" | "cts" => "TypeScript",
+        "go" => "Go",
+        "c" | "h" => "C",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" => "C++",
+        "java" => "Java",
+        "kt" | "kts" => "Kotlin",
+        "rb" => "Ruby",
+        "cs" => "C#",
+        "swift" => "Swift",
+        "scala" => "Scala",
+        "php" => "PHP",
+        "lua" => "Lua",
+        "ex" | "exs" => "Elixir",
+        "dart" => "Dart",
+        "sh" | "bash" | "zsh" => "Shell",
+        "md" | "markdown" => "Markdown",
+        "toml" => "TOML",
+        "json" => "JSON",
+        "yaml" | "yml" => "YAML",
+        "html" | "htm" => "HTML",
+        "css" => "CSS",
+        "sql" => "SQL",
+        "csv" => "CSV",
+        "tsv" => "TSV",
+        "txt" => "Text",
+        _ => return None,
+    })
+}
+
+static RUST_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^(pub(\([^)]*\))?\s+)?((async|const|unsafe|extern\s+"[^"]*")\s+)*(fn|struct|enum|union|trait|impl|mod|type|const|static|macro_rules!)\b"#)
+        .unwrap()
+});
+static PYTHON_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^((async\s+)?(def|class)\s|[A-Z][A-Z0-9_]*\s*(:[^=]*)?=)").unwrap()
+});
+static JS_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(export\s+)?(default\s+)?(async\s+)?(function\*?|class|interface|type|enum|const|let)\s").unwrap()
+});
+static GO_ITEM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(func|type|const|var)\s").unwrap());
+/// Methods in JavaScript and TypeScript classes, which have no keyword.
+static JS_MEMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^((public|private|protected|static|async|get|set|readonly)\s+)*#?[A-Za-z_$][\w$]*\s*(<[^>]*>)?\(").unwrap()
+});
+static JS_KEYWORD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(if|for|while|switch|catch|return|function|new|await|super|this)\b").unwrap()
+});
+static RUST_CONTAINER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(impl|trait|mod)\b").unwrap());
+/// Declarations of the other languages by their usual keywords, C-like function definitions
+/// with a return type and shell functions.
+static GENERIC_ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(((export|public|private|protected|internal|static|final|abstract|sealed|open|override|virtual|inline|async|extern|data|partial|unsafe|local)\s+)*(fn|fun|func|function|def|defp|defmacro|defmodule|class|struct|enum|union|interface|trait|protocol|extension|module|namespace|object|record|impl|typedef|template|sub|proc)\b|[A-Za-z_][\w:<>,*&\[\] ]*[\s*&]~?[A-Za-z_][\w:]*\s*\([^;]*$|[A-Za-z_][\w-]*\s*\(\)\s*\{)").unwrap()
+});
+/// Statements [`GENERIC_ITEM`] mistakes for function definitions.
+static GENERIC_KEYWORD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(if|else|for|foreach|while|do|switch|case|return|throw|catch|try|new|delete|using|goto|sizeof|await|yield|echo|print|puts)\b").unwrap()
+});
+static GENERIC_CONTAINER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^((export|public|private|protected|internal|static|final|abstract|sealed|open|data|partial)\s+)*(class|struct|interface|trait|protocol|extension|namespace|module|object|record|impl|defmodule)\b").unwrap()
+});
+
+/// Whether `line` declares something in a language without its own regexes.
+fn is_generic_item(line: &str) -> bool {
+    GENERIC_ITEM.is_match(line) && !GENERIC_KEYWORD.is_match(line)
+}
+
+/// A cheap regex matching top-level item signatures of `language`, if supported.
+pub fn item_regex(language: &str) -> Option<&'static Regex> {
+    match language {
+        "Rust" => Some(&RUST_ITEM),
+        "Python" => Some(&PYTHON_ITEM),
+        "JavaScript" | "TypeScript" => Some(&JS_ITEM),
+        "Go" => Some(&GO_ITEM),
+        _ => None,
+    }
+}
+
+/// Top-level item signatures of `text` as `(1-based line number, signature)`.
+pub fn top_level_items(language: &str, text: &str) -> Vec<(usize, String)> {
+    let Some(re) = item_regex(language) else {
+        return vec![];
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, ln)| re.is_match(ln))
+        .map(|(i, ln)| {
+            (
+                i + 1,
+                ln.trim_end()
+                    .trim_end_matches('{')
+                    .trim_end_matches(':')
+                    .trim_end()
+                    .to_string(),
+            )
+        })
+        .collect()
+}
+
+/// A symbol of an outline, `depth` is 0 for top-level items and 1 for their members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// 1-based.
+    pub line: usize,
+    pub depth: usize,
+    pub signature: String,
+}
+
+const MAX_SIGNATURE_CHARS: usize = 120;
+
+pub(super) fn signature(line: &str) -> String {
+    let sig = line
+        .trim()
+        .trim_end_matches('{')
+        .trim_end_matches(':')
+        .trim_end();
+    if sig.chars().count() > MAX_SIGNATURE_CHARS {
+        let mut sig = sig
+            .chars()
+            .take(MAX_SIGNATURE_CHARS - 1)
+            .collect::<String>();
+        sig.push('…');
+        sig
+    } else {
+        sig.to_string()
+    }
+}
+
+/// The change of the brace depth over `line`, ignoring braces in strings and `//` comments.
+fn brace_delta(line: &str) -> isize {
+    let mut delta = 0;
+    let mut quote = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '`') => quote = Some(c),
+            (None, '/') if chars.peek() == Some(&'/') => break,
+            (None, '{') => delta += 1,
+            (None, '}') => delta -= 1,
+            _ => {}
+        }
+    }
+    delta
+}
+
+/// Whether members of the item at `line` are outlined as well.
+fn is_container(language: &str, line: &str) -> bool {
+    match language {
+        "Rust" => RUST_CONTAINER.is_match(line) && !line.contains("fn "),
+        "Python" => line.trim_start().starts_with("class "),
+        "JavaScript" | "TypeScript" => line.contains("class ") || line.contains("interface "),
+        "Go" => false,
+        _ => GENERIC_CONTAINER.is_match(line),
+    }
+}
+
+fn is_member(language: &str, line: &str) -> bool {
+    match language {
+        "JavaScript" | "TypeScript" => {
+            JS_MEMBER.is_match(line) && !JS_KEYWORD.is_match(line) || JS_ITEM.is_match(line)
+        }
+        _ => is_item(language, line),
+    }
+}
+
+fn is_item(language: &str, line: &str) -> bool {
+    match item_regex(language) {
+        Some(re) => re.is_match(line),
+        None => is_generic_item(line),
+    }
+}
+
+/// The top-level items of `text` and the members of classes, impls, traits and modules,
+/// found by regexes and the nesting of braces or, for Python, indentation.
+///
+/// Languages without their own regexes get the declarations with the usual keywords like
+/// `class` or `function` and C-like function definitions. Braces in block comments or
+/// multi-line strings can throw the nesting off, the outline is a map for reading the file
+/// rather than an exact parse. With the `tree-sitter` feature, Rust, Python, JavaScript,
+/// TypeScript and Go are parsed instead, see [`super::syntax::outline`].
+pub fn outline(language: &str, text: &str) -> Vec<Symbol> {
+    #[cfg(feature = "tree-sitter")]
+    if let Some(symbols) = super::syntax::outline(language, text) {
+        return symbols;
+    }
+    let mut symbols = vec![];
+    if language == "Python" {
+        // The indentation of the open classes and of their members once known.
+        let mut containers: Vec<(usize, Option<usize>)> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let indent = line.len() - trimmed.len();
+            containers.retain(|(c, _)| *c < indent);
+            let depth = containers.len();
+            // Only the direct members of a class, not the code of its methods.
+            let candidate = match containers.last_mut() {
+                None => indent == 0,
+                Some((_, member)) => *member.get_or_insert(indent) == indent,
+            };
+            if !candidate || !is_item(language, trimmed) {
+                continue;
+            }
+            symbols.push(Symbol {
+                line: i + 1,
+                depth,
+                signature: signature(line),
+            });
+            if is_container(language, line) {
+                containers.push((indent, None));
+            }
+        }
+        return symbols;
+    }
+
+    // The brace depth inside each open container.
+    let mut containers: Vec<isize> = vec![];
+    let mut depth: isize = 0;
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        containers.retain(|c| *c <= depth);
+        let delta = brace_delta(trimmed);
+        let nesting = containers.len();
+        let candidate = if nesting == 0 {
+            depth == 0 && is_item(language, trimmed)
+        } else {
+            containers.last() == Some(&depth) && is_member(language, trimmed)
+        };
+        if candidate {
+            symbols.push(Symbol {
+                line: i + 1,
+                depth: nesting,
+                signature: signature(line),
+            });
+            if delta > 0 && is_container(language, trimmed) {
+                containers.push(depth + 1);
+            }
+        }
+        depth = (depth + delta).max(0);
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(language: &str, text: &str) -> Vec<String> {
+        outline(language, text)
+            .into_iter()
+            .map(|s| format!("{}: {}{}", s.line, "  ".repeat(s.depth), s.signature))
+            .collect()
+    }
+
+    #[test]
+    fn test_generic_outline_java() {
+        let text = r#"package app;
+
+public class Main {
+    private int count;
+
+    public static void main(String[] args) {
+        if (args.length > 0) {
+            System.out.println(args[0]);
+        }
+    }
+
+    private String name() {
+        return "main";
+    }
+}
+"#;
+        assert_eq!(
+            render("Java", text),
+            [
+                "3: public class Main",
+                "6:   public static void main(String[] args)",
+                "12:   private String name()",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generic_outline_c() {
+        let text = "#include <stdio.h>\n\nstruct point {\n    int x;\n};\n\nstatic int add(int a, int b)\n{\n    return a + b;\n}\n\nint main(void) {\n    printf(\"%d\\n\", add(1, 2));\n    return 0;\n}\n";
+        assert_eq!(
+            render("C", text),
+            [
+                "3: struct point",
+                "7: static int add(int a, int b)",
+                "12: int main(void)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::{future::Future, sync::Arc};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, memory::MemoryStore, tool::Tool};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MemoryReadArgs {
+    pub key: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReadTool {
+    pub store: Arc<dyn MemoryStore>,
+}
+
+impl MemoryReadTool {
+    pub fn new(store: Arc<dyn MemoryStore>) -> Self {
+        Self { store }
+    }
+
+    pub async fn memory_read(&self, key: String) -> Result<String, AgentyError> {
+        Ok(match self.store.load(&key).await? {
+            Some(value) => value,
+            None => format!("Nothing is remembered under the key {:?}", &key),
+        })
+    }
+}
+
+impl Tool for MemoryReadTool {
+    type ARGUMENTS = MemoryReadArgs;
+    const NAME: &str = "memory_read";
+    const DESCRIPTION: Option<&str> = Some(
+        "Read what was remembered under `key` with memory_write, possibly in an earlier session.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.memory_read(arguments.key)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MemoryWriteArgs {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryWriteTool {
+    pub store: Arc<dyn MemoryStore>,
+}
+
+impl MemoryWriteTool {
+    pub fn new(store: Arc<dyn MemoryStore>) -> Self {
+        Self { store }
+    }
+
+    pub async fn memory_write(&self, key: String, value: String) -> Result<String, AgentyError> {
+        self.store.save(&key, &value).await?;
+        Ok(format!("Remembered {} chars under the key {:?}", value.chars().count(), &key))
+    }
+}
+
+impl Tool for MemoryWriteTool {
+    type ARGUMENTS = MemoryWriteArgs;
+    const NAME: &str = "memory_write";
+    const DESCRIPTION: Option<&str> = Some(
+        "Remember `value` under `key` so it can be read back with memory_read, even in later sessions. Writing an existing key replaces the old value.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.memory_write(arguments.key, arguments.value)
+    }
+}
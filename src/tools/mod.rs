@@ -1,2 +1,43 @@
+pub mod archive;
+pub mod ask;
+#[cfg(feature = "browser")]
+pub mod browser;
+pub mod cargo;
+pub mod checksum;
+pub mod clock;
+pub mod csv;
+pub mod diff;
+pub mod du;
+pub mod edit;
+pub mod envinfo;
 pub mod file;
+pub mod git;
 pub mod grep;
+pub mod json;
+pub mod journal;
+pub mod lang;
+pub mod memory;
+pub mod netdiag;
+pub mod outline;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+#[cfg(feature = "process")]
+pub mod process;
+pub mod readpage;
+pub mod regex;
+pub mod scratchpad;
+#[cfg(feature = "semantic")]
+pub mod semantic;
+pub mod shell;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod staging;
+pub mod summarize;
+#[cfg(feature = "tree-sitter")]
+pub mod syntax;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod todo;
+pub mod tokens;
+pub mod web;
+pub mod wikipedia;
@@ -0,0 +1,462 @@
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use itertools::Itertools;
+use reqwest::{Url, header::LOCATION, redirect};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::net::{TcpStream, lookup_host};
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::web::{FetchUrlTool, error_chain};
+
+/// For every step, resolving, connecting or waiting for the response, on its own.
+pub const DEFAULT_NETDIAG_TIMEOUT: Duration = Duration::from_secs(5);
+/// Response headers shown at most, some servers send lots of cookies.
+const MAX_HEADERS: usize = 50;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NetDiagOp {
+    Resolve,
+    TcpCheck,
+    Head,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct NetDiagArgs {
+    pub op: NetDiagOp,
+    /// The host name or IP address, required for `resolve` and `tcp_check`.
+    pub host: Option<String>,
+    /// Required for `tcp_check`.
+    pub port: Option<u16>,
+    /// The URL for `head`, `https://{host}:{port}/` if not given.
+    pub url: Option<String>,
+}
+
+/// Whether `ip` is only reachable from inside a network or the machine itself: loopback,
+/// private, shared, link-local and unspecified addresses, like the cloud metadata service.
+pub fn is_internal_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 100.64.0.0/10, shared by carrier-grade NATs.
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| is_internal_ip(IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// `host:port`, IPv6 addresses in brackets.
+fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Resolves host names, checks TCP ports and fetches response headers to troubleshoot
+/// networking issues.
+///
+/// The host lists are the ones of `fetch`, so the tool can not probe hosts that can not be
+/// fetched. Every host is resolved before it is contacted, and refused if any of its
+/// addresses is denied or, unless `allow_internal` is set, internal (see [`is_internal_ip`]),
+/// so the tool can not scan the local network.
+#[derive(Debug, Clone)]
+pub struct NetDiagTool {
+    pub fetch: FetchUrlTool,
+    pub timeout: Duration,
+    /// Allow loopback, private and link-local addresses, off by default.
+    pub allow_internal: bool,
+}
+
+impl Default for NetDiagTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetDiagTool {
+    pub fn new() -> Self {
+        Self::with_fetch(FetchUrlTool::new())
+    }
+
+    /// Share the host lists and the redirect limit of `fetch`.
+    pub fn with_fetch(fetch: FetchUrlTool) -> Self {
+        Self {
+            fetch,
+            timeout: DEFAULT_NETDIAG_TIMEOUT,
+            allow_internal: false,
+        }
+    }
+
+    /// Allow probing internal addresses, for troubleshooting the local network itself.
+    pub fn with_internal_addresses(mut self, allow_internal: bool) -> Self {
+        self.allow_internal = allow_internal;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn check_host(&self, host: &str) -> Result<(), String> {
+        FetchUrlTool::check_host(host, &self.fetch.allowed_hosts, &self.fetch.denied_hosts)
+    }
+
+    /// The addresses of `host` without duplicates, refused if any of them is denied or
+    /// internal so an allowed name pointing to an internal address can not be used to reach
+    /// it.
+    async fn lookup(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+        let addrs = match tokio::time::timeout(self.timeout, lookup_host((host, port))).await {
+            Ok(Ok(addrs)) => addrs.unique().collect::<Vec<_>>(),
+            Ok(Err(e)) => return Err(format!("Fail to resolve {} due to {}", host, e)),
+            Err(_) => {
+                return Err(format!(
+                    "Resolving {} timed out after {}s",
+                    host,
+                    self.timeout.as_secs_f32()
+                ));
+            }
+        };
+        if addrs.is_empty() {
+            return Err(format!("{} has no addresses", host));
+        }
+        for addr in &addrs {
+            let ip = addr.ip().to_string();
+            if FetchUrlTool::check_host(&ip, &None, &self.fetch.denied_hosts).is_err() {
+                return Err(format!("{} resolves to {}, which is not allowed", host, ip));
+            }
+            if !self.allow_internal && is_internal_ip(addr.ip()) {
+                return Err(format!(
+                    "{} resolves to the internal address {}, which is not allowed",
+                    host, ip
+                ));
+            }
+        }
+        Ok(addrs)
+    }
+
+    async fn resolve(&self, host: &str) -> String {
+        let start = Instant::now();
+        match self.lookup(host, 0).await {
+            Ok(addrs) => format!(
+                "{} resolves to {} in {} ms",
+                host,
+                addrs.iter().map(|a| a.ip()).join(", "),
+                start.elapsed().as_millis()
+            ),
+            Err(e) => e,
+        }
+    }
+
+    async fn tcp_check(&self, host: &str, port: u16) -> String {
+        let addrs = match self.lookup(host, port).await {
+            Ok(addrs) => addrs,
+            Err(e) => return e,
+        };
+        let start = Instant::now();
+        match tokio::time::timeout(self.timeout, TcpStream::connect(&addrs[..])).await {
+            Ok(Ok(stream)) => {
+                let peer = stream
+                    .peer_addr()
+                    .map_or_else(|_| "?".to_string(), |a| a.to_string());
+                format!(
+                    "{} is reachable, connected to {} in {} ms",
+                    host_port(host, port),
+                    peer,
+                    start.elapsed().as_millis()
+                )
+            }
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => format!(
+                "{} is not reachable, the connection was refused so nothing listens on the port",
+                host_port(host, port)
+            ),
+            Ok(Err(e)) => format!("{} is not reachable due to {}", host_port(host, port), e),
+            Err(_) => format!(
+                "Connecting to {} timed out after {}s, a firewall may drop the packets",
+                host_port(host, port),
+                self.timeout.as_secs_f32()
+            ),
+        }
+    }
+
+    async fn head(&self, url: Url) -> Result<String, AgentyError> {
+        let start = Instant::now();
+        let mut current = url.clone();
+        let mut redirects = 0;
+        // Redirects are followed by hand, so every hop is resolved and checked like the
+        // first one.
+        let resp = loop {
+            if let Err(e) = FetchUrlTool::check_url(
+                &current,
+                &self.fetch.allowed_hosts,
+                &self.fetch.denied_hosts,
+            ) {
+                return Ok(e);
+            }
+            let host = current
+                .host_str()
+                .unwrap_or_default()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string();
+            let port = current.port_or_known_default().unwrap_or(443);
+            let addrs = match self.lookup(&host, port).await {
+                Ok(addrs) => addrs,
+                Err(e) => return Ok(e),
+            };
+            // Connect to the checked addresses, not to what another lookup may return.
+            let client = reqwest::Client::builder()
+                .timeout(self.timeout)
+                .redirect(redirect::Policy::none())
+                .resolve_to_addrs(&host, &addrs)
+                .build()?;
+            let resp = match client.head(current.clone()).send().await {
+                Ok(resp) => resp,
+                Err(e) if e.is_timeout() => {
+                    return Ok(format!(
+                        "HEAD {} timed out after {}s",
+                        &current,
+                        self.timeout.as_secs_f32()
+                    ));
+                }
+                Err(e) => {
+                    return Ok(format!(
+                        "Fail to reach {} due to {}",
+                        &current,
+                        error_chain(&e)
+                    ));
+                }
+            };
+            let next = resp
+                .status()
+                .is_redirection()
+                .then(|| resp.headers().get(LOCATION))
+                .flatten()
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| current.join(location).ok());
+            match next {
+                Some(_) if redirects >= self.fetch.max_redirects => {
+                    return Ok(format!(
+                        "Fail to reach {} due to more than {} redirects",
+                        &url, self.fetch.max_redirects
+                    ));
+                }
+                Some(next) => {
+                    redirects += 1;
+                    current = next;
+                }
+                None => break resp,
+            }
+        };
+        let mut out = format!(
+            "HTTP {} from {} in {} ms",
+            resp.status(),
+            resp.url(),
+            start.elapsed().as_millis()
+        );
+        if resp.url() != &url {
+            out.push_str(&format!(" (redirected from {})", &url));
+        }
+        for (name, value) in resp.headers().iter().take(MAX_HEADERS) {
+            out.push_str(&format!(
+                "\n{}: {}",
+                name,
+                String::from_utf8_lossy(value.as_bytes())
+            ));
+        }
+        if resp.headers().len() > MAX_HEADERS {
+            out.push_str(&format!(
+                "\n({} more headers not shown)",
+                resp.headers().len() - MAX_HEADERS
+            ));
+        }
+        Ok(out)
+    }
+
+    pub async fn net_diag(&self, arguments: NetDiagArgs) -> Result<String, AgentyError> {
+        // IPv6 addresses are often written in brackets like in URLs.
+        let host = arguments
+            .host
+            .as_deref()
+            .map(|h| h.trim().trim_start_matches('[').trim_end_matches(']'))
+            .filter(|h| !h.is_empty());
+        if arguments.op == NetDiagOp::Head {
+            let url = match (&arguments.url, host) {
+                (Some(url), _) => url.clone(),
+                (None, Some(host)) => {
+                    format!(
+                        "https://{}/",
+                        host_port(host, arguments.port.unwrap_or(443))
+                    )
+                }
+                (None, None) => return Ok("`url` or `host` is required for head".to_string()),
+            };
+            return match Url::parse(&url) {
+                Ok(url) => self.head(url).await,
+                Err(e) => Ok(format!("{} is not a valid URL: {}", &url, e)),
+            };
+        }
+
+        let Some(host) = host else {
+            return Ok("`host` is required for resolve and tcp_check".to_string());
+        };
+        if let Err(e) = self.check_host(host) {
+            return Ok(e);
+        }
+        Ok(match arguments.op {
+            NetDiagOp::Resolve => self.resolve(host).await,
+            NetDiagOp::TcpCheck => match arguments.port {
+                Some(port) => self.tcp_check(host, port).await,
+                None => "`port` is required for tcp_check".to_string(),
+            },
+            NetDiagOp::Head => unreachable!("handled above"),
+        })
+    }
+}
+
+impl Tool for NetDiagTool {
+    type ARGUMENTS = NetDiagArgs;
+    const NAME: &str = "net_diag";
+    const DESCRIPTION: Option<&str> = Some(
+        "Troubleshoot networking: `op` 'resolve' looks up the addresses of `host`, 'tcp_check' tells whether a TCP connection to `host` and `port` can be opened, was refused or timed out, and 'head' sends a HEAD request to `url` (or https://`host`:`port`/) and returns the status and the response headers. Every step gives up after a few seconds. Some hosts and internal addresses like 127.0.0.1 or 10.x.x.x may not be allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.net_diag(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn args(op: NetDiagOp, host: &str, port: Option<u16>, url: Option<String>) -> NetDiagArgs {
+        NetDiagArgs {
+            op,
+            host: Some(host.to_string()),
+            port,
+            url,
+        }
+    }
+
+    /// Answers every connection with `response` and returns the port.
+    async fn serve(response: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_internal_ips() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_internal_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700::1111"] {
+            assert!(!is_internal_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_internal_refused_by_default() {
+        let port = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        let tool = NetDiagTool::new();
+        for (host, port) in [
+            ("127.0.0.1", port),
+            ("localhost", port),
+            ("169.254.169.254", 80),
+        ] {
+            let resp = tool
+                .net_diag(args(NetDiagOp::TcpCheck, host, Some(port), None))
+                .await
+                .unwrap();
+            assert!(resp.contains("internal address"), "{}", resp);
+        }
+        let url = format!("http://127.0.0.1:{}/", port);
+        let resp = tool
+            .net_diag(args(NetDiagOp::Head, "", None, Some(url)))
+            .await
+            .unwrap();
+        assert!(resp.contains("internal address"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_internal_allowed() {
+        let port = serve("HTTP/1.1 204 No Content\r\nX-Probe: yes\r\n\r\n").await;
+        let tool = NetDiagTool::new().with_internal_addresses(true);
+        let resp = tool
+            .net_diag(args(NetDiagOp::TcpCheck, "127.0.0.1", Some(port), None))
+            .await
+            .unwrap();
+        assert!(resp.contains("is reachable"), "{}", resp);
+        let url = format!("http://127.0.0.1:{}/", port);
+        let resp = tool
+            .net_diag(args(NetDiagOp::Head, "", None, Some(url)))
+            .await
+            .unwrap();
+        assert!(resp.starts_with("HTTP 204 No Content"), "{}", resp);
+        assert!(resp.contains("x-probe: yes"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_denied_host_is_refused() {
+        let tool = NetDiagTool::with_fetch(FetchUrlTool {
+            denied_hosts: vec!["127.0.0.1".to_string()],
+            ..FetchUrlTool::new()
+        })
+        .with_internal_addresses(true);
+        let resp = tool
+            .net_diag(args(NetDiagOp::TcpCheck, "localhost", Some(80), None))
+            .await
+            .unwrap();
+        assert!(resp.contains("which is not allowed"), "{}", resp);
+    }
+}
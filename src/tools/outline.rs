@@ -0,0 +1,207 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
+
+use ignore::WalkBuilder;
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{
+    file::{sanitize_join_relative_path, truncate_at_line_boundary},
+    lang::{Symbol, detect_language, item_regex, outline},
+};
+
+pub const DEFAULT_OUTLINE_FILES: usize = 50;
+pub const MAX_OUTLINE_FILES: usize = 500;
+pub const DEFAULT_MAX_OUTLINE_OUTPUT: usize = 32768;
+/// Larger files are most likely generated.
+const MAX_OUTLINE_FILE_BYTES: u64 = 2 * 1024 * 1024;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct OutlineArgs {
+    /// The file to outline, either this or `directory`.
+    pub file_path: Option<PathBuf>,
+    /// Outline all source files under this directory instead.
+    pub directory: Option<PathBuf>,
+    /// Files outlined at most for `directory`, 50 by default.
+    pub max_files: Option<usize>,
+}
+
+/// The language of `path` if it is source code that can be outlined.
+///
+/// Languages without their own regexes are outlined by the generic ones, but data and
+/// markup formats are left out.
+fn outline_language(path: &Path) -> Option<&'static str> {
+    detect_language(path).filter(|l| {
+        item_regex(l).is_some()
+            || !matches!(
+                *l,
+                "Markdown"
+                    | "TOML"
+                    | "JSON"
+                    | "YAML"
+                    | "HTML"
+                    | "CSS"
+                    | "SQL"
+                    | "CSV"
+                    | "TSV"
+                    | "Text"
+            )
+    })
+}
+
+/// `line: signature` per symbol, members indented below their container.
+fn render_symbols(symbols: &[Symbol]) -> String {
+    symbols
+        .iter()
+        .map(|s| format!("{}: {}{}", s.line, "  ".repeat(s.depth), s.signature))
+        .join("\n")
+}
+
+/// Lists the functions, types, impls and constants of source files with their lines.
+#[derive(Debug, Clone)]
+pub struct OutlineTool {
+    pub cwd: PathBuf,
+    /// Bytes of output at most.
+    pub max_output: usize,
+}
+
+impl OutlineTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_OUTLINE_OUTPUT,
+        }
+    }
+
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    fn outline_file(path: &Path, name: &Path) -> String {
+        let Some(language) = outline_language(path) else {
+            return format!(
+                "# {} — not source code, can not be outlined",
+                name.display()
+            );
+        };
+        let text = match std::fs::read(path) {
+            Ok(buf) => String::from_utf8_lossy(&buf).to_string(),
+            Err(e) => return format!("Fail to read {:?} due to {}", name, e),
+        };
+        let symbols = outline(language, &text);
+        if symbols.is_empty() {
+            format!("# {} — {}, no symbols", name.display(), language)
+        } else {
+            format!(
+                "# {} — {}, {} symbols\n{}",
+                name.display(),
+                language,
+                symbols.len(),
+                render_symbols(&symbols)
+            )
+        }
+    }
+
+    fn outline_directory(root: &Path, dir: &Path, max_files: usize) -> Result<String, String> {
+        let mut files = vec![];
+        let mut skipped = 0;
+        for ent in WalkBuilder::new(dir).require_git(false).build() {
+            let ent = ent.map_err(|e| e.to_string())?;
+            let path = ent.path();
+            if !ent.file_type().is_some_and(|t| t.is_file())
+                || outline_language(path).is_none()
+                || ent
+                    .metadata()
+                    .is_ok_and(|m| m.len() > MAX_OUTLINE_FILE_BYTES)
+            {
+                continue;
+            }
+            if files.len() < max_files {
+                files.push(path.to_path_buf());
+            } else {
+                skipped += 1;
+            }
+        }
+        if files.is_empty() {
+            return Err(format!(
+                "No source files under {:?}",
+                dir.strip_prefix(root).unwrap_or(dir)
+            ));
+        }
+        files.sort();
+        let mut resp = files
+            .iter()
+            .map(|path| Self::outline_file(path, path.strip_prefix(root).unwrap_or(path)))
+            .join("\n\n");
+        if skipped > 0 {
+            resp.push_str(&format!(
+                "\n\n({} more files not outlined, raise `max_files` or outline a subdirectory)",
+                skipped
+            ));
+        }
+        Ok(resp)
+    }
+
+    pub async fn outline(&self, arguments: OutlineArgs) -> Result<String, AgentyError> {
+        let (relative, is_dir) = match (arguments.file_path, arguments.directory) {
+            (Some(file_path), None) => (file_path, false),
+            (None, Some(directory)) => (directory, true),
+            _ => return Ok("Exactly one of `file_path` and `directory` is required".to_string()),
+        };
+        let target_path = match sanitize_join_relative_path(&self.cwd, &relative) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        if is_dir && !target_path.is_dir() {
+            return Ok(format!("{:?} is not a directory", &relative));
+        }
+        if !is_dir && !target_path.is_file() {
+            return Ok(format!("{:?} is not a file", &relative));
+        }
+        let root = self.cwd.clone();
+        let max_files = arguments
+            .max_files
+            .unwrap_or(DEFAULT_OUTLINE_FILES)
+            .clamp(1, MAX_OUTLINE_FILES);
+        let resp = tokio::task::spawn_blocking(move || {
+            if is_dir {
+                Self::outline_directory(&root, &target_path, max_files).unwrap_or_else(|e| e)
+            } else {
+                Self::outline_file(&target_path, &relative)
+            }
+        })
+        .await?;
+        let cut = truncate_at_line_boundary(&resp, self.max_output);
+        if cut.len() < resp.len() {
+            Ok(format!(
+                "{}\n(output truncated, {} of {} bytes shown, outline fewer files at once)",
+                cut.trim_end(),
+                cut.len(),
+                resp.len()
+            ))
+        } else {
+            Ok(resp)
+        }
+    }
+}
+
+impl Tool for OutlineTool {
+    type ARGUMENTS = OutlineArgs;
+    const NAME: &str = "outline";
+    const DESCRIPTION: Option<&str> = Some(
+        "Get the outline of a source file instead of reading it: its functions, types, impls, classes and constants as one `line: signature` per symbol, with the members of impls, traits and classes indented. Pass `file_path` for one file or `directory` to outline up to `max_files` files below it. Rust, Python, JavaScript, TypeScript and Go are fully supported, other languages like C, C++, Java or Ruby get a rougher outline of their declarations. All paths should be relative paths and '..' is not allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.outline(arguments)
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::BTreeSet, future::Future, path::PathBuf};
+
+use lopdf::Document;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{sanitize_join_relative_path, truncate_at_line_boundary};
+
+pub const DEFAULT_MAX_PDF_OUTPUT: usize = 32768;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PdfReadArgs {
+    pub file_path: PathBuf,
+    /// The pages to read like `1-3,7`, all by default.
+    pub pages: Option<String>,
+}
+
+/// Parse a page range like `1-3,7,10-` into the sorted page numbers, errors are meant for
+/// the model.
+pub fn parse_page_range(spec: &str, total: u32) -> Result<Vec<u32>, String> {
+    let page = |s: &str| -> Result<u32, String> {
+        let n = s
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("{:?} is not a page number", s.trim()))?;
+        if n == 0 || n > total {
+            return Err(format!(
+                "There is no page {}, the document has {} pages",
+                n, total
+            ));
+        }
+        Ok(n)
+    };
+    let mut pages = BTreeSet::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let from = if from.trim().is_empty() {
+                    1
+                } else {
+                    page(from)?
+                };
+                let to = if to.trim().is_empty() {
+                    total
+                } else {
+                    page(to)?
+                };
+                if from > to {
+                    return Err(format!("The range {:?} is backwards", part));
+                }
+                pages.extend(from..=to);
+            }
+            None => {
+                pages.insert(page(part)?);
+            }
+        }
+    }
+    if pages.is_empty() {
+        return Err(format!("No pages in {:?}, pass them like '1-3,7'", spec));
+    }
+    Ok(pages.into_iter().collect())
+}
+
+/// Extracts the text layer of PDF files, page by page.
+#[derive(Debug, Clone)]
+pub struct PdfReadTool {
+    pub cwd: PathBuf,
+    /// Bytes of output at most.
+    pub max_output: usize,
+}
+
+impl PdfReadTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            max_output: DEFAULT_MAX_PDF_OUTPUT,
+        }
+    }
+
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    fn extract(doc: &Document, total: u32, pages: Option<String>) -> Result<String, String> {
+        let pages = match pages {
+            Some(spec) => parse_page_range(&spec, total)?,
+            None => (1..=total).collect(),
+        };
+        let ids = doc.get_pages();
+        let mut resp = String::new();
+        for n in pages {
+            resp.push_str(&format!("=== page {} ===\n", n));
+            let text = match doc.extract_text(&[n]) {
+                Ok(text) => text,
+                Err(e) => {
+                    resp.push_str(&format!("(fail to extract the text due to {})\n", e));
+                    continue;
+                }
+            };
+            if text.trim().is_empty() {
+                // Scanners produce pages that are a single image without any text.
+                let images = ids
+                    .get(&n)
+                    .and_then(|id| doc.get_page_images(*id).ok())
+                    .is_some_and(|images| !images.is_empty());
+                resp.push_str(if images {
+                    "(no text layer, this page appears to be a scanned image)\n"
+                } else {
+                    "(no text on this page)\n"
+                });
+            } else {
+                resp.push_str(text.trim_end());
+                resp.push('\n');
+            }
+        }
+        Ok(resp)
+    }
+
+    pub async fn pdf_read(
+        &self,
+        file_path: PathBuf,
+        pages: Option<String>,
+    ) -> Result<String, AgentyError> {
+        let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        let max_output = self.max_output;
+        Ok(tokio::task::spawn_blocking(move || {
+            let doc = match Document::load(&target_path) {
+                Ok(doc) if !doc.is_encrypted() => doc,
+                Ok(_)
+                | Err(
+                    lopdf::Error::InvalidPassword
+                    | lopdf::Error::Decryption(_)
+                    | lopdf::Error::UnsupportedSecurityHandler(_),
+                ) => {
+                    return format!(
+                        "{:?} is encrypted and can not be read without its password",
+                        &file_path
+                    );
+                }
+                Err(e) => return format!("Fail to read {:?} as PDF due to {}", &file_path, e),
+            };
+            let total = doc.get_pages().len() as u32;
+            let body = match Self::extract(&doc, total, pages) {
+                Ok(body) => body,
+                Err(e) => return e,
+            };
+            let header = format!("# {} — PDF, {} pages\n", file_path.display(), total);
+            let cut = truncate_at_line_boundary(&body, max_output);
+            if cut.len() < body.len() {
+                format!(
+                    "{}{}\n(output truncated, {} of {} bytes shown, read fewer `pages` at once)",
+                    header,
+                    cut.trim_end(),
+                    cut.len(),
+                    body.len()
+                )
+            } else {
+                format!("{}{}", header, body.trim_end())
+            }
+        })
+        .await?)
+    }
+}
+
+impl Tool for PdfReadTool {
+    type ARGUMENTS = PdfReadArgs;
+    const NAME: &str = "pdf_read";
+    const DESCRIPTION: Option<&str> = Some(
+        "Read the text of the PDF file at `file_path`, page by page. Pass `pages` like '1-3,7' or '10-' to only read those pages, the header tells the total number of pages. Pages that are scanned images have no text to read. The output is limited, so read long documents a few pages at a time.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.pdf_read(arguments.file_path, arguments.pages)
+    }
+}
@@ -0,0 +1,396 @@
+use std::{fmt::Debug, future::Future, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sysinfo::{
+    Pid, Process, ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, Signal, System, UpdateKind,
+};
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{
+    ask::{UserCallback, ask_approval},
+    clock::human_duration,
+    file::human_size,
+    file::table_row,
+    shell::SpawnedProcesses,
+};
+
+pub const DEFAULT_MAX_PROCESSES: usize = 50;
+/// How long CPU usage is measured over, at least [`sysinfo::MINIMUM_CPU_UPDATE_INTERVAL`].
+const CPU_SAMPLE: Duration = Duration::from_millis(250);
+/// How long a process gets to exit after SIGTERM before it is killed with SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(3);
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessOp {
+    List,
+    Info,
+    Kill,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ProcessArgs {
+    pub op: ProcessOp,
+    /// Only list processes whose name or command line contains this, for `list`.
+    pub name_filter: Option<String>,
+    /// The process to show for `info` or to `kill`.
+    pub pid: Option<u32>,
+}
+
+fn refresh(system: &mut System, pids: ProcessesToUpdate<'_>) {
+    system.refresh_processes_specifics(
+        pids,
+        true,
+        ProcessRefreshKind::nothing()
+            .with_cpu()
+            .with_memory()
+            .with_tasks()
+            .with_cmd(UpdateKind::OnlyIfNotSet)
+            .with_cwd(UpdateKind::OnlyIfNotSet),
+    );
+}
+
+/// Measure the CPU usage of `pids` in `system` over [`CPU_SAMPLE`], in percent of one core.
+fn sample(system: &mut System, pids: &[Pid]) {
+    std::thread::sleep(CPU_SAMPLE);
+    refresh(system, ProcessesToUpdate::Some(pids));
+}
+
+fn name(process: &Process) -> String {
+    process.name().to_string_lossy().to_string()
+}
+
+fn command_line(process: &Process) -> String {
+    process
+        .cmd()
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .join(" ")
+}
+
+fn start_time(process: &Process) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(process.start_time() as i64, 0)
+}
+
+/// Whether `pid` was started by [`super::shell::ShellCommandTool`]: it is in the process
+/// group of a command, or, where there are no process groups, descends from one.
+#[cfg_attr(unix, allow(unused_variables))]
+fn is_spawned(system: &System, spawned: &SpawnedProcesses, pid: Pid) -> bool {
+    #[cfg(unix)]
+    {
+        // sysinfo has no process groups, and they survive the command exiting unlike parents.
+        let pgid = unsafe { libc::getpgid(pid.as_u32() as libc::pid_t) };
+        pgid > 0 && spawned.contains(pgid as u32)
+    }
+    #[cfg(not(unix))]
+    {
+        std::iter::successors(system.process(pid), |p| system.process(p.parent()?))
+            .any(|p| spawned.contains(p.pid().as_u32()))
+    }
+}
+
+/// Shows the processes of the machine, and kills the ones started with the shell tool once
+/// the user approves.
+#[derive(Clone)]
+pub struct ProcessTool {
+    /// The process groups of [`super::shell::ShellCommandTool`], only these can be killed.
+    pub spawned: SpawnedProcesses,
+    /// Asked before every kill, killing is refused without it.
+    pub approval: Option<UserCallback>,
+    /// Rows of `list` at most.
+    pub max_processes: usize,
+}
+
+impl Debug for ProcessTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProcessTool")
+            .field("spawned", &self.spawned)
+            .field("max_processes", &self.max_processes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProcessTool {
+    pub fn new(spawned: SpawnedProcesses) -> Self {
+        Self {
+            spawned,
+            approval: None,
+            max_processes: DEFAULT_MAX_PROCESSES,
+        }
+    }
+
+    /// Ask the user through `approval` before every kill, see [`ask_approval`].
+    pub fn with_approval(
+        mut self,
+        approval: impl Fn(String) -> BoxFuture<'static, Result<String, AgentyError>>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        self.approval = Some(Arc::new(approval));
+        self
+    }
+
+    pub fn with_max_processes(mut self, max_processes: usize) -> Self {
+        self.max_processes = max_processes;
+        self
+    }
+
+    fn list(spawned: &SpawnedProcesses, filter: Option<String>, max_processes: usize) -> String {
+        let filter = filter.map(|f| f.to_lowercase());
+        let mut system = System::new();
+        refresh(&mut system, ProcessesToUpdate::All);
+        let pids = system
+            .processes()
+            .values()
+            .filter(|process| process.thread_kind().is_none())
+            .filter(|process| match &filter {
+                Some(filter) => {
+                    name(process).to_lowercase().contains(filter)
+                        || command_line(process).to_lowercase().contains(filter)
+                }
+                None => true,
+            })
+            .map(|process| process.pid())
+            .sorted()
+            .collect_vec();
+        if pids.is_empty() {
+            return match &filter {
+                Some(filter) => format!("No process matches {:?}", filter),
+                None => "No processes found".to_string(),
+            };
+        }
+        let mut table = prettytable::Table::new();
+        table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+        table.set_titles(prettytable::row![
+            "pid", "name", "cpu%", "rss", "started", "spawned", "command"
+        ]);
+        let shown = &pids[..pids.len().min(max_processes)];
+        sample(&mut system, shown);
+        for process in shown.iter().filter_map(|pid| system.process(*pid)) {
+            let started = start_time(process)
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            table.add_row(table_row([
+                process.pid().to_string().as_str(),
+                &name(process),
+                &format!("{:.1}", process.cpu_usage()),
+                &human_size(process.memory()),
+                &started,
+                if is_spawned(&system, spawned, process.pid()) {
+                    "yes"
+                } else {
+                    ""
+                },
+                &command_line(process),
+            ]));
+        }
+        let mut resp = format!("Start times are in UTC\n{}", table);
+        if pids.len() > shown.len() {
+            resp.push_str(&format!(
+                "({} more processes not shown, narrow down with `name_filter`)",
+                pids.len() - shown.len()
+            ));
+        }
+        resp
+    }
+
+    fn info(spawned: &SpawnedProcesses, pid: u32) -> String {
+        let pid = Pid::from_u32(pid);
+        let mut system = System::new();
+        refresh(&mut system, ProcessesToUpdate::All);
+        sample(&mut system, &[pid]);
+        let Some(process) = system.process(pid) else {
+            return format!("No process with pid {}", pid);
+        };
+        let mut resp = format!(
+            "pid: {}\nname: {}\nstate: {}\nparent: {}\ncommand: {}",
+            pid,
+            name(process),
+            process.status(),
+            process
+                .parent()
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            command_line(process)
+        );
+        if let Some(cwd) = process.cwd() {
+            resp.push_str(&format!("\ncwd: {}", cwd.display()));
+        }
+        resp.push_str(&format!(
+            "\ncpu: {:.1}%\nrss: {}\nvirtual: {}",
+            process.cpu_usage(),
+            human_size(process.memory()),
+            human_size(process.virtual_memory())
+        ));
+        if let Some(tasks) = process.tasks() {
+            resp.push_str(&format!("\nthreads: {}", tasks.len()));
+        }
+        if let Some(started) = start_time(process) {
+            resp.push_str(&format!(
+                "\nstarted: {} (running for {})",
+                started.format("%Y-%m-%d %H:%M:%S UTC"),
+                human_duration(process.run_time())
+            ));
+        }
+        resp.push_str(&format!(
+            "\nstarted by run_command: {}",
+            if is_spawned(&system, spawned, pid) {
+                "yes"
+            } else {
+                "no"
+            }
+        ));
+        resp
+    }
+
+    async fn kill(&self, pid: u32) -> Result<String, AgentyError> {
+        let pid = Pid::from_u32(pid);
+        let mut system = System::new();
+        refresh(&mut system, ProcessesToUpdate::All);
+        let Some(process) = system.process(pid) else {
+            return Ok(format!("No process with pid {}", pid));
+        };
+        let (name, started) = (name(process), process.start_time());
+        if !is_spawned(&system, &self.spawned, pid) {
+            return Ok(format!(
+                "Process {} ({}) was not started by run_command, only those can be killed",
+                pid, name
+            ));
+        }
+        let Some(approval) = &self.approval else {
+            return Ok("Killing processes is disabled, the host set up no approvals".to_string());
+        };
+        let action = format!(
+            "Kill process {} ({}): {}?",
+            pid,
+            name,
+            command_line(process)
+        );
+        if !ask_approval(approval, &action).await? {
+            return Ok(format!("The user declined to kill process {}", pid));
+        }
+
+        // The pid may have been reused while waiting for the approval.
+        let alive = |system: &mut System| {
+            refresh(system, ProcessesToUpdate::Some(&[pid]));
+            system
+                .process(pid)
+                .is_some_and(|p| p.start_time() == started && p.status() != ProcessStatus::Zombie)
+        };
+        if !alive(&mut system) {
+            return Ok(format!("Process {} has exited already", pid));
+        }
+        if let Some(process) = system.process(pid)
+            && process.kill_with(Signal::Term).is_none()
+        {
+            // No SIGTERM on Windows, killing is all there is.
+            process.kill();
+        }
+        let deadline = tokio::time::Instant::now() + KILL_GRACE;
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if !alive(&mut system) {
+                return Ok(format!("Process {} ({}) terminated", pid, name));
+            }
+        }
+        if let Some(process) = system.process(pid) {
+            process.kill();
+        }
+        Ok(format!(
+            "Process {} ({}) did not exit within {}s of SIGTERM and was killed with SIGKILL",
+            pid,
+            name,
+            KILL_GRACE.as_secs()
+        ))
+    }
+
+    pub async fn process(&self, arguments: ProcessArgs) -> Result<String, AgentyError> {
+        let spawned = self.spawned.clone();
+        match (arguments.op, arguments.pid) {
+            (ProcessOp::List, _) => {
+                let max_processes = self.max_processes;
+                Ok(tokio::task::spawn_blocking(move || {
+                    Self::list(&spawned, arguments.name_filter, max_processes)
+                })
+                .await?)
+            }
+            (ProcessOp::Info, Some(pid)) => {
+                Ok(tokio::task::spawn_blocking(move || Self::info(&spawned, pid)).await?)
+            }
+            (ProcessOp::Kill, Some(pid)) => self.kill(pid).await,
+            (_, None) => Ok("`pid` is required for `info` and `kill`".to_string()),
+        }
+    }
+}
+
+impl Tool for ProcessTool {
+    type ARGUMENTS = ProcessArgs;
+    const NAME: &str = "process";
+    const DESCRIPTION: Option<&str> = Some(
+        "Check on the processes of the machine, e.g. whether a server you started is still running. `list` shows the pid, name, CPU usage, memory, start time and command line of the processes whose name or command line contains `name_filter`, marking the ones started with run_command as spawned. `info` shows the details of the process `pid`. `kill` terminates the process `pid` after the user approved, only processes started with run_command can be killed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.process(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::tools::shell::ShellCommandTool;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_kill_spawned_process() {
+        let shell = ShellCommandTool::without_approval(std::env::temp_dir());
+        let out = shell
+            .run_command("sleep 33 > /dev/null 2>&1 & echo $!".to_string(), None)
+            .await
+            .unwrap();
+        let pid = out.lines().nth(2).unwrap().parse::<u32>().unwrap();
+        let tool = ProcessTool::new(shell.spawned.clone())
+            .with_approval(|_| async { Ok("yes".to_string()) }.boxed());
+
+        let args = |op, pid| ProcessArgs {
+            op,
+            name_filter: Some("sleep 33".to_string()),
+            pid,
+        };
+        let list = tool.process(args(ProcessOp::List, None)).await.unwrap();
+        let row = list.lines().find(|l| l.contains(&pid.to_string())).unwrap();
+        assert!(row.contains("yes"), "{}", list);
+        let info = tool
+            .process(args(ProcessOp::Info, Some(pid)))
+            .await
+            .unwrap();
+        assert!(info.contains("started by run_command: yes"), "{}", info);
+
+        let declined = ProcessTool::new(shell.spawned.clone())
+            .with_approval(|_| async { Ok("no".to_string()) }.boxed());
+        let resp = declined.process(args(ProcessOp::Kill, Some(pid))).await;
+        assert_eq!(
+            resp.unwrap(),
+            format!("The user declined to kill process {}", pid)
+        );
+        let resp = tool
+            .process(args(ProcessOp::Kill, Some(pid)))
+            .await
+            .unwrap();
+        assert_eq!(resp, format!("Process {} (sleep) terminated", pid));
+
+        let resp = tool.process(args(ProcessOp::Kill, Some(1))).await.unwrap();
+        assert!(resp.ends_with("only those can be killed"), "{}", resp);
+    }
+}
@@ -0,0 +1,639 @@
+use std::{collections::HashMap, future::Future, sync::LazyLock};
+
+use itertools::Itertools;
+use regex::Regex;
+use reqwest::{Url, header::CONTENT_TYPE};
+use schemars::JsonSchema;
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{
+    file::{human_size, truncate_at_line_boundary},
+    web::{FetchUrlTool, error_chain},
+};
+
+pub const DEFAULT_MAX_PAGE_OUTPUT: usize = 32768;
+/// Less text is more likely a teaser or a list of links than an article.
+const MIN_ARTICLE_CHARS: usize = 250;
+/// Shorter paragraphs are mostly captions and bylines, they don't point to the article.
+const MIN_PARAGRAPH_CHARS: usize = 25;
+/// Never part of the article, dropped wherever they are.
+const SKIPPED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "nav", "footer", "aside", "form", "iframe", "svg",
+    "canvas", "button", "select", "textarea", "input", "dialog",
+];
+/// Elements rendered as blocks of their own, everything else is part of a paragraph.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "blockquote",
+    "center",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+/// Class names and ids of boilerplate like share buttons and comment sections.
+static UNLIKELY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)comment|share|social|related|sidebar|advert|promo|newsletter|cookie|breadcrumb|popup|subscribe|sponsor|disqus").unwrap()
+});
+static POSITIVE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)article|body|content|entry|main|page|post|text|blog|story").unwrap()
+});
+static NEGATIVE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)comment|footer|footnote|masthead|meta|nav|promo|related|share|sidebar|sponsor|social|widget|menu|banner|ad-").unwrap()
+});
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ReadWebPageArgs {
+    pub url: String,
+}
+
+/// The text of `el` with runs of whitespace collapsed.
+fn normalized_text(el: ElementRef) -> String {
+    el.text()
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn class_and_id(el: ElementRef) -> String {
+    format!(
+        "{} {}",
+        el.value().attr("class").unwrap_or_default(),
+        el.value().id().unwrap_or_default()
+    )
+}
+
+/// Whether `el` is navigation, a script, hidden or looks like a share or comment section.
+fn is_boilerplate(el: ElementRef) -> bool {
+    let element = el.value();
+    if SKIPPED_TAGS.contains(&element.name())
+        || element.attr("hidden").is_some()
+        || element.attr("aria-hidden") == Some("true")
+    {
+        return true;
+    }
+    if matches!(element.name(), "html" | "body" | "a" | "article" | "main") {
+        return false;
+    }
+    let names = class_and_id(el);
+    UNLIKELY.is_match(&names) && !POSITIVE.is_match(&names)
+}
+
+/// Where the score of a candidate starts, from its tag and its class names.
+fn initial_score(el: ElementRef) -> f64 {
+    let tag = match el.value().name() {
+        "article" | "main" => 10.0,
+        "div" | "section" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    let names = class_and_id(el);
+    let mut class = 0.0;
+    if POSITIVE.is_match(&names) {
+        class += 25.0;
+    }
+    if NEGATIVE.is_match(&names) {
+        class -= 25.0;
+    }
+    tag + class
+}
+
+/// The share of the text of `el` that is link text, high for menus and link lists.
+fn link_density(el: ElementRef) -> f64 {
+    let total = normalized_text(el).chars().count();
+    if total == 0 {
+        return 1.0;
+    }
+    let links = Selector::parse("a").expect("valid selector");
+    let linked: usize = el
+        .select(&links)
+        .map(|a| normalized_text(a).chars().count())
+        .sum();
+    linked as f64 / total as f64
+}
+
+/// The elements holding the article, like readability: every paragraph adds to the score of
+/// its parent and less to its grandparents, and the best container wins after discounting
+/// its links. Its siblings are taken along if they score well or read like paragraphs, as
+/// articles are often split into several blocks. `None` if there is not enough text.
+fn main_content(doc: &Html) -> Option<Vec<ElementRef<'_>>> {
+    let paragraphs = Selector::parse("p, pre, blockquote").expect("valid selector");
+    let mut candidates = HashMap::new();
+    for p in doc.select(&paragraphs) {
+        if p.ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(|a| is_boilerplate(a) || a.value().name() == "header")
+        {
+            continue;
+        }
+        let text = normalized_text(p);
+        let chars = text.chars().count();
+        if chars < MIN_PARAGRAPH_CHARS {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + (chars / 100).min(3) as f64;
+        for (level, ancestor) in p
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .take(3)
+            .enumerate()
+        {
+            let divider = [1.0, 2.0, 6.0][level];
+            candidates
+                .entry(ancestor.id())
+                .or_insert_with(|| (ancestor, initial_score(ancestor)))
+                .1 += score / divider;
+        }
+    }
+    let scores = candidates
+        .into_values()
+        .map(|(el, score)| (el.id(), (el, score * (1.0 - link_density(el)))))
+        .collect::<HashMap<_, _>>();
+    let (best, best_score) = scores
+        .values()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .copied()?;
+    let threshold = (best_score * 0.2).max(10.0);
+    let content = match best.parent().and_then(ElementRef::wrap) {
+        Some(parent) => parent
+            .child_elements()
+            .filter(|sibling| {
+                if *sibling == best {
+                    return true;
+                }
+                if is_boilerplate(*sibling) {
+                    return false;
+                }
+                if scores.get(&sibling.id()).is_some_and(|s| s.1 >= threshold) {
+                    return true;
+                }
+                sibling.value().name() == "p"
+                    && normalized_text(*sibling).chars().count() > 80
+                    && link_density(*sibling) < 0.25
+            })
+            .collect(),
+        None => vec![best],
+    };
+    let chars: usize = content
+        .iter()
+        .map(|el| normalized_text(*el).chars().count())
+        .sum();
+    (chars >= MIN_ARTICLE_CHARS).then_some(content)
+}
+
+/// Appends `text` with runs of whitespace collapsed to one space, as browsers show it.
+fn push_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            out.push(c);
+        } else if !out.is_empty() && !out.ends_with([' ', '\n']) {
+            out.push(' ');
+        }
+    }
+}
+
+/// Ends the paragraph collected in `inline`.
+fn flush_paragraph(inline: &mut String, blocks: &mut Vec<String>) {
+    let paragraph = inline
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .join("\n");
+    if !paragraph.is_empty() {
+        blocks.push(paragraph);
+    }
+    inline.clear();
+}
+
+fn code_block(el: ElementRef) -> String {
+    let language = std::iter::once(el)
+        .chain(el.child_elements())
+        .filter_map(|e| e.value().attr("class"))
+        .flat_map(str::split_whitespace)
+        .find_map(|c| {
+            c.strip_prefix("language-")
+                .or_else(|| c.strip_prefix("lang-"))
+        })
+        .unwrap_or_default();
+    let code = el.text().collect::<String>();
+    let code = code.trim_start_matches('\n').trim_end();
+    let fence = if code.contains("```") { "~~~~" } else { "```" };
+    format!("{}{}\n{}\n{}", fence, language, code, fence)
+}
+
+/// Renders elements as markdown.
+///
+/// The DOM is at hand anyway, and unlike the streaming conversion of `fetch_url` this keeps
+/// the spaces around links and inline code, the indentation of code blocks and nested lists,
+/// and decodes entities.
+struct MarkdownWriter<'a> {
+    /// Links and images are made absolute against it.
+    base: &'a Url,
+    /// Drop `header` elements as well, they are the site header outside an article.
+    skip_header: bool,
+}
+
+impl MarkdownWriter<'_> {
+    fn skipped(&self, el: ElementRef) -> bool {
+        is_boilerplate(el) || (self.skip_header && el.value().name() == "header")
+    }
+
+    /// The blocks like paragraphs, headings and lists of the children of `el`.
+    fn blocks(&self, el: ElementRef, blocks: &mut Vec<String>) {
+        let mut inline = String::new();
+        for child in el.children() {
+            match child.value() {
+                Node::Text(text) => push_text(&mut inline, text),
+                Node::Element(element) => {
+                    let child = ElementRef::wrap(child).expect("an element node");
+                    if self.skipped(child) {
+                        continue;
+                    }
+                    if BLOCK_TAGS.contains(&element.name()) {
+                        flush_paragraph(&mut inline, blocks);
+                        self.block(child, blocks);
+                    } else {
+                        self.inline(child, &mut inline);
+                    }
+                }
+                _ => {}
+            }
+        }
+        flush_paragraph(&mut inline, blocks);
+    }
+
+    fn block(&self, el: ElementRef, blocks: &mut Vec<String>) {
+        let name = el.value().name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let text = self.inline_text(el);
+                if !text.is_empty() {
+                    let level = name[1..].parse().unwrap_or(1);
+                    blocks.push(format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "pre" => blocks.push(code_block(el)),
+            "ul" | "ol" => {
+                if let Some(list) = self.list(el, name == "ol") {
+                    blocks.push(list);
+                }
+            }
+            "blockquote" => {
+                let mut inner = vec![];
+                self.blocks(el, &mut inner);
+                if !inner.is_empty() {
+                    blocks.push(
+                        inner
+                            .join("\n\n")
+                            .lines()
+                            .map(|l| format!("> {}", l).trim_end().to_string())
+                            .join("\n"),
+                    );
+                }
+            }
+            "table" => {
+                if let Some(table) = self.table(el) {
+                    blocks.push(table);
+                }
+            }
+            "hr" => blocks.push("---".to_string()),
+            _ => self.blocks(el, blocks),
+        }
+    }
+
+    fn list(&self, el: ElementRef, ordered: bool) -> Option<String> {
+        let start = el
+            .value()
+            .attr("start")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1);
+        let mut items = vec![];
+        for li in el.child_elements() {
+            if li.value().name() != "li" || self.skipped(li) {
+                continue;
+            }
+            let mut inner = vec![];
+            self.blocks(li, &mut inner);
+            if inner.is_empty() {
+                continue;
+            }
+            let marker = if ordered {
+                format!("{}. ", start + items.len())
+            } else {
+                "- ".to_string()
+            };
+            // Continuation lines and nested lists are indented below the text of the item.
+            let indent = " ".repeat(marker.len());
+            items.push(
+                inner
+                    .join("\n")
+                    .lines()
+                    .enumerate()
+                    .map(|(i, l)| match i {
+                        0 => format!("{}{}", marker, l),
+                        _ if l.is_empty() => String::new(),
+                        _ => format!("{}{}", indent, l),
+                    })
+                    .join("\n"),
+            );
+        }
+        (!items.is_empty()).then(|| items.join("\n"))
+    }
+
+    fn table(&self, el: ElementRef) -> Option<String> {
+        let tr = Selector::parse("tr").expect("valid selector");
+        let rows = el
+            .select(&tr)
+            .map(|row| {
+                row.child_elements()
+                    .filter(|c| matches!(c.value().name(), "td" | "th"))
+                    .map(|c| self.inline_text(c).replace('\n', " ").replace('|', "\\|"))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<_>>();
+        let columns = rows.iter().map(Vec::len).max()?;
+        let line = |row: &[String]| {
+            let cells = (0..columns).map(|i| row.get(i).map_or("", String::as_str));
+            format!("| {} |", cells.collect::<Vec<_>>().join(" | "))
+        };
+        let mut lines = vec![line(&rows[0]), line(&vec!["---".to_string(); columns])];
+        lines.extend(rows[1..].iter().map(|row| line(row)));
+        Some(lines.join("\n"))
+    }
+
+    fn inline(&self, el: ElementRef, out: &mut String) {
+        let element = el.value();
+        match element.name() {
+            "br" => out.push('\n'),
+            "img" => {
+                if let Some(src) = element.attr("src").and_then(|s| self.base.join(s).ok()) {
+                    out.push_str(&format!(
+                        "![{}]({})",
+                        element.attr("alt").unwrap_or_default(),
+                        src
+                    ));
+                }
+            }
+            "code" | "kbd" | "samp" => {
+                let code = normalized_text(el);
+                if !code.is_empty() {
+                    out.push_str(&format!("`{}`", code));
+                }
+            }
+            "a" => {
+                let text = self.inline_text(el);
+                let href = element
+                    .attr("href")
+                    .filter(|h| !h.starts_with("javascript:"))
+                    .and_then(|h| self.base.join(h).ok());
+                match href {
+                    Some(href) if !text.is_empty() => {
+                        out.push_str(&format!("[{}]({})", text, href))
+                    }
+                    _ => out.push_str(&text),
+                }
+            }
+            "em" | "i" | "strong" | "b" => {
+                let text = self.inline_text(el);
+                if !text.is_empty() {
+                    let mark = if matches!(element.name(), "em" | "i") {
+                        "*"
+                    } else {
+                        "**"
+                    };
+                    out.push_str(&format!("{}{}{}", mark, text, mark));
+                }
+            }
+            _ => self.inline_children(el, out),
+        }
+    }
+
+    fn inline_children(&self, el: ElementRef, out: &mut String) {
+        for child in el.children() {
+            match child.value() {
+                Node::Text(text) => push_text(out, text),
+                Node::Element(_) => {
+                    let child = ElementRef::wrap(child).expect("an element node");
+                    if !self.skipped(child) {
+                        self.inline(child, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The content of `el` as inline markdown.
+    fn inline_text(&self, el: ElementRef) -> String {
+        let mut text = String::new();
+        self.inline_children(el, &mut text);
+        text.trim().to_string()
+    }
+}
+
+fn page_title(doc: &Html) -> Option<String> {
+    let title = Selector::parse("title").expect("valid selector");
+    let og_title = Selector::parse(r#"meta[property="og:title"]"#).expect("valid selector");
+    let h1 = Selector::parse("h1").expect("valid selector");
+    doc.select(&title)
+        .map(normalized_text)
+        .chain(
+            doc.select(&og_title)
+                .filter_map(|m| m.value().attr("content"))
+                .map(|t| t.split_whitespace().collect::<Vec<_>>().join(" ")),
+        )
+        .chain(doc.select(&h1).map(normalized_text))
+        .find(|t| !t.is_empty())
+}
+
+struct ExtractedPage {
+    title: Option<String>,
+    markdown: String,
+    /// Whether the main content was found, the whole page was converted otherwise.
+    extracted: bool,
+}
+
+fn extract_page(html: &str, url: &Url) -> ExtractedPage {
+    let doc = Html::parse_document(html);
+    let content = main_content(&doc);
+    let extracted = content.is_some();
+    let writer = MarkdownWriter {
+        base: url,
+        skip_header: !extracted,
+    };
+    let body = Selector::parse("body").expect("valid selector");
+    let roots =
+        content.unwrap_or_else(|| vec![doc.select(&body).next().unwrap_or(doc.root_element())]);
+    let mut blocks = vec![];
+    for root in roots {
+        writer.block(root, &mut blocks);
+    }
+    ExtractedPage {
+        title: page_title(&doc),
+        markdown: blocks.join("\n\n"),
+        extracted,
+    }
+}
+
+/// Fetches a web page and returns its main content as markdown, without the navigation,
+/// sidebars and footers that make up most of the raw page.
+///
+/// The host lists, the size limit and the timeout are the ones of `fetch`, so both tools
+/// can be configured once.
+#[derive(Debug, Clone)]
+pub struct ReadWebPageTool {
+    pub fetch: FetchUrlTool,
+    /// The markdown is cut after this many bytes.
+    pub max_output: usize,
+}
+
+impl Default for ReadWebPageTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReadWebPageTool {
+    pub fn new() -> Self {
+        Self::with_fetch(FetchUrlTool::new())
+    }
+
+    /// Share the host lists, the size limit and the timeout of `fetch`.
+    pub fn with_fetch(fetch: FetchUrlTool) -> Self {
+        Self {
+            fetch,
+            max_output: DEFAULT_MAX_PAGE_OUTPUT,
+        }
+    }
+
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    pub async fn read_web_page(&self, arguments: ReadWebPageArgs) -> Result<String, AgentyError> {
+        let url = match Url::parse(&arguments.url) {
+            Ok(url) => url,
+            Err(e) => return Ok(format!("{} is not a valid URL: {}", &arguments.url, e)),
+        };
+        if let Err(e) =
+            FetchUrlTool::check_url(&url, &self.fetch.allowed_hosts, &self.fetch.denied_hosts)
+        {
+            return Ok(e);
+        }
+        let mut resp = match self.fetch.client()?.get(url.clone()).send().await {
+            Ok(resp) => resp,
+            Err(e) => return Ok(format!("Fail to fetch {} due to {}", &url, error_chain(&e))),
+        };
+
+        let status = resp.status();
+        let final_url = resp.url().clone();
+        let mime = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if !matches!(mime.as_str(), "" | "text/html" | "application/xhtml+xml") {
+            return Ok(format!(
+                "{} is no web page but {}, read it with fetch_url instead",
+                &final_url, mime
+            ));
+        }
+        let (body, truncated) = match self.fetch.read_body(&mut resp).await {
+            Ok(body) => body,
+            Err(e) => {
+                return Ok(format!(
+                    "Fail to read the response of {} due to {}",
+                    &url,
+                    error_chain(&e)
+                ));
+            }
+        };
+
+        let html = String::from_utf8_lossy(&body).to_string();
+        let page_url = final_url.clone();
+        let page = tokio::task::spawn_blocking(move || extract_page(&html, &page_url)).await?;
+
+        let mut resp = format!(
+            "# {}\n",
+            page.title.as_deref().unwrap_or(final_url.as_str())
+        );
+        if !status.is_success() {
+            resp.push_str(&format!("HTTP {} from ", status));
+        }
+        resp.push_str(final_url.as_str());
+        if final_url != url {
+            resp.push_str(&format!(" (redirected from {})", &url));
+        }
+        resp.push_str(&format!(
+            "\n{} of markdown from {}{} of HTML",
+            human_size(page.markdown.len() as u64),
+            if truncated { "the first " } else { "" },
+            human_size(body.len() as u64)
+        ));
+        if !page.extracted {
+            resp.push_str(
+                "\nWarning: no main content found, this is the whole page without navigation and scripts",
+            );
+        }
+        let cut = truncate_at_line_boundary(&page.markdown, self.max_output);
+        resp.push_str(&format!("\n\n{}", cut.trim_end()));
+        if cut.len() < page.markdown.len() {
+            resp.push_str(&format!(
+                "\n(output truncated, {} of {} bytes shown)",
+                cut.len(),
+                page.markdown.len()
+            ));
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for ReadWebPageTool {
+    type ARGUMENTS = ReadWebPageArgs;
+    const NAME: &str = "read_web_page";
+    const DESCRIPTION: Option<&str> = Some(
+        "Read the article or main content of the web page at `url` as markdown with its headings, lists, code blocks and links, leaving out navigation, sidebars, footers and comments. The title and the size are shown first. When no main content is found the whole page is returned with a warning. Prefer this over fetch_url to read pages, use fetch_url for APIs and other content. Long pages are truncated. Some hosts may not be allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.read_web_page(arguments)
+    }
+}
@@ -0,0 +1,250 @@
+use std::future::Future;
+
+use grep::regex::RegexMatcherBuilder;
+use itertools::Itertools;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::ParserBuilder;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+pub const MAX_TEST_STRINGS: usize = 50;
+/// Characters of test strings and captures shown, the spans still cover everything.
+const MAX_SHOWN_CHARS: usize = 200;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RegexArgs {
+    pub pattern: String,
+    /// Strings to match the pattern against.
+    #[serde(default)]
+    pub test_strings: Vec<String>,
+    /// Any of `i` case-insensitive, `m` `^` and `$` match at lines, `s` `.` matches a
+    /// newline, `x` ignore whitespace and `U` swap greediness.
+    pub flags: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct RegexFlags {
+    case_insensitive: bool,
+    multi_line: bool,
+    dot_matches_new_line: bool,
+    ignore_whitespace: bool,
+    swap_greed: bool,
+}
+
+impl RegexFlags {
+    fn parse(flags: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for flag in flags.chars() {
+            match flag {
+                'i' => parsed.case_insensitive = true,
+                'm' => parsed.multi_line = true,
+                's' => parsed.dot_matches_new_line = true,
+                'x' => parsed.ignore_whitespace = true,
+                'U' => parsed.swap_greed = true,
+                _ => {
+                    return Err(format!(
+                        "Unknown flag {:?}, supported are i, m, s, x and U",
+                        flag
+                    ));
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn grep_matcher(&self) -> RegexMatcherBuilder {
+        let mut builder = RegexMatcherBuilder::new();
+        builder
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .ignore_whitespace(self.ignore_whitespace)
+            .swap_greed(self.swap_greed);
+        builder
+    }
+
+    fn regex(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .ignore_whitespace(self.ignore_whitespace)
+            .swap_greed(self.swap_greed)
+            .build()
+    }
+
+    /// Line and column, both 1-based, of the syntax error in `pattern` if there is one.
+    fn error_position(&self, pattern: &str) -> Option<(usize, usize)> {
+        let err = ParserBuilder::new()
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .ignore_whitespace(self.ignore_whitespace)
+            .swap_greed(self.swap_greed)
+            .build()
+            .parse(pattern)
+            .err()?;
+        let start = match &err {
+            regex_syntax::Error::Parse(e) => e.span().start,
+            regex_syntax::Error::Translate(e) => e.span().start,
+            _ => return None,
+        };
+        Some((start.line, start.column))
+    }
+}
+
+/// `text` quoted, cut to [`MAX_SHOWN_CHARS`] characters.
+fn shown(text: &str) -> String {
+    match text.char_indices().nth(MAX_SHOWN_CHARS) {
+        Some((end, _)) => format!("{:?}...", &text[..end]),
+        None => format!("{:?}", text),
+    }
+}
+
+/// Compiles regexes like `grep_files` does and shows what they match, so the model can check
+/// a pattern before searching with it.
+#[derive(Debug, Clone)]
+pub struct RegexTool {
+    pub max_test_strings: usize,
+}
+
+impl Default for RegexTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegexTool {
+    pub fn new() -> Self {
+        Self {
+            max_test_strings: MAX_TEST_STRINGS,
+        }
+    }
+
+    pub fn with_max_test_strings(mut self, max_test_strings: usize) -> Self {
+        self.max_test_strings = max_test_strings;
+        self
+    }
+
+    /// How `regex` matches `text`, the spans are byte offsets.
+    fn describe_match(regex: &Regex, text: &str) -> String {
+        let Some(caps) = regex.captures(text) else {
+            return format!("{} does not match", shown(text));
+        };
+        let count = regex.find_iter(text).count();
+        let whole = caps.get(0).expect("group 0 always participates");
+        let mut resp = format!(
+            "{} matches {} time{}, first at {}..{}: {}",
+            shown(text),
+            count,
+            if count == 1 { "" } else { "s" },
+            whole.start(),
+            whole.end(),
+            shown(whole.as_str())
+        );
+        for (i, name) in regex.capture_names().enumerate().skip(1) {
+            let group = match name {
+                Some(name) => format!("{} ({})", i, name),
+                None => i.to_string(),
+            };
+            match caps.get(i) {
+                Some(m) => resp.push_str(&format!(
+                    "\n  group {}: {}..{}: {}",
+                    group,
+                    m.start(),
+                    m.end(),
+                    shown(m.as_str())
+                )),
+                None => resp.push_str(&format!("\n  group {}: did not participate", group)),
+            }
+        }
+        resp
+    }
+
+    pub async fn test_regex(&self, arguments: RegexArgs) -> Result<String, AgentyError> {
+        let flags = match RegexFlags::parse(arguments.flags.as_deref().unwrap_or_default()) {
+            Ok(flags) => flags,
+            Err(e) => return Ok(e),
+        };
+        let pattern = &arguments.pattern;
+        if arguments.test_strings.len() > self.max_test_strings {
+            return Ok(format!(
+                "At most {} test strings are allowed",
+                self.max_test_strings
+            ));
+        }
+
+        // The same builder as `grep_files`, so what compiles here compiles there.
+        if let Err(e) = flags.grep_matcher().build(pattern) {
+            // grep wraps the pattern in a group, which would shift the positions it shows.
+            let e = flags
+                .regex(pattern)
+                .err()
+                .map_or_else(|| e.to_string(), |e| e.to_string());
+            return Ok(match flags.error_position(pattern) {
+                Some((line, column)) if pattern.contains('\n') => format!(
+                    "The pattern does not compile, error at line {} column {}:\n{}",
+                    line, column, e
+                ),
+                Some((_, column)) => format!(
+                    "The pattern does not compile, error at column {}:\n{}",
+                    column, e
+                ),
+                None => format!("The pattern does not compile: {}", e),
+            });
+        }
+        let regex = flags.regex(pattern)?;
+        let groups = regex.captures_len() - 1;
+        let mut resp = if groups == 0 {
+            "The pattern compiles, no capture groups".to_string()
+        } else {
+            format!(
+                "The pattern compiles, {} capture group{}: {}",
+                groups,
+                if groups == 1 { "" } else { "s" },
+                regex
+                    .capture_names()
+                    .enumerate()
+                    .skip(1)
+                    .map(|(i, name)| match name {
+                        Some(name) => format!("{} ({})", i, name),
+                        None => i.to_string(),
+                    })
+                    .join(", ")
+            )
+        };
+        // `grep_files` searches line by line unless `multiline` is set.
+        if flags
+            .grep_matcher()
+            .line_terminator(Some(b'\n'))
+            .build(pattern)
+            .is_err()
+        {
+            resp.push_str(
+                "\nIt can match a newline, search with `multiline` set to true in grep_files",
+            );
+        }
+        for text in &arguments.test_strings {
+            resp.push('\n');
+            resp.push_str(&Self::describe_match(&regex, text));
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for RegexTool {
+    type ARGUMENTS = RegexArgs;
+    const NAME: &str = "test_regex";
+    const DESCRIPTION: Option<&str> = Some(
+        "Check a regex before searching with it: compiles `pattern` with the same engine as grep_files, tells where the error is if it does not compile, and for each of `test_strings` whether it matches, how often, and the span and text of the match and of every capture group of the first match. Spans are byte offsets. `flags` may contain i (case-insensitive), m (^ and $ match at line breaks), s (. matches newlines), x (ignore whitespace and allow # comments) and U (swap greediness).",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.test_regex(arguments)
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{atomic_write, human_size};
+
+pub const DEFAULT_MAX_NOTE_BYTES: usize = 16 * 1024;
+pub const DEFAULT_MAX_SCRATCHPAD_BYTES: usize = 256 * 1024;
+
+/// Notes the model keeps during a run, shared between the [`ScratchpadTool`] and the host.
+///
+/// Clones share the notes, so the host can seed facts before the run and read the notes
+/// afterwards.
+#[derive(Debug, Clone)]
+pub struct Scratchpad {
+    notes: Arc<RwLock<HashMap<String, String>>>,
+    /// The notes are written to this JSON file after every change, if set.
+    pub path: Option<PathBuf>,
+    /// Bytes of a single note at most.
+    pub max_note_bytes: usize,
+    /// Bytes of all keys and notes together at most.
+    pub max_total_bytes: usize,
+}
+
+impl Default for Scratchpad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scratchpad {
+    pub fn new() -> Self {
+        Self {
+            notes: Arc::new(RwLock::new(HashMap::new())),
+            path: None,
+            max_note_bytes: DEFAULT_MAX_NOTE_BYTES,
+            max_total_bytes: DEFAULT_MAX_SCRATCHPAD_BYTES,
+        }
+    }
+
+    /// Keep the notes in the JSON file at `path`, loading the notes it holds already.
+    pub async fn persisted(path: PathBuf) -> Result<Self, AgentyError> {
+        let notes = match tokio::fs::read(&path).await {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            notes: Arc::new(RwLock::new(notes)),
+            path: Some(path),
+            ..Self::new()
+        })
+    }
+
+    pub fn with_limits(mut self, max_note_bytes: usize, max_total_bytes: usize) -> Self {
+        self.max_note_bytes = max_note_bytes;
+        self.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    fn total_bytes(notes: &HashMap<String, String>) -> usize {
+        notes.iter().map(|(k, v)| k.len() + v.len()).sum()
+    }
+
+    /// Store `value` under `key`, errors are meant for the model.
+    pub fn set(&self, key: &str, value: String) -> Result<(), String> {
+        let mut notes = self.notes.write().expect("poisoned");
+        self.insert(&mut notes, key, value)
+    }
+
+    /// Append `value` to the note under `key`, creating it if missing.
+    pub fn append(&self, key: &str, value: &str) -> Result<(), String> {
+        // Under one guard, or concurrent appends could lose each other's writes.
+        let mut notes = self.notes.write().expect("poisoned");
+        let mut note = notes.get(key).cloned().unwrap_or_default();
+        note.push_str(value);
+        self.insert(&mut notes, key, note)
+    }
+
+    /// Insert `value` into the locked `notes` if it fits the limits.
+    fn insert(
+        &self,
+        notes: &mut HashMap<String, String>,
+        key: &str,
+        value: String,
+    ) -> Result<(), String> {
+        if value.len() > self.max_note_bytes {
+            return Err(format!(
+                "The note is {}, a single note can hold at most {}",
+                human_size(value.len() as u64),
+                human_size(self.max_note_bytes as u64)
+            ));
+        }
+        let freed = notes.get(key).map_or(0, |old| key.len() + old.len());
+        let total = Self::total_bytes(notes) - freed + key.len() + value.len();
+        if total > self.max_total_bytes {
+            return Err(format!(
+                "The scratchpad would hold {} with this note, more than its limit of {}. Delete notes that are not needed anymore first",
+                human_size(total as u64),
+                human_size(self.max_total_bytes as u64)
+            ));
+        }
+        notes.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.notes.read().expect("poisoned").get(key).cloned()
+    }
+
+    pub fn delete(&self, key: &str) -> Option<String> {
+        self.notes.write().expect("poisoned").remove(key)
+    }
+
+    /// The keys with the sizes of their notes, sorted by key.
+    pub fn list(&self) -> Vec<(String, usize)> {
+        self.notes
+            .read()
+            .expect("poisoned")
+            .iter()
+            .map(|(k, v)| (k.clone(), v.len()))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// All notes, e.g. to show them after the run.
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        self.notes.read().expect("poisoned").clone()
+    }
+
+    /// Write the notes to `path`, if set.
+    pub async fn save(&self) -> Result<(), AgentyError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = serde_json::to_vec_pretty(&self.snapshot())?;
+        atomic_write(path, content).await?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScratchpadOp {
+    Set,
+    Get,
+    Append,
+    List,
+    Delete,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ScratchpadArgs {
+    pub op: ScratchpadOp,
+    /// Required by all operations but `list`.
+    pub key: Option<String>,
+    /// The text to `set` or `append`.
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScratchpadTool {
+    pub scratchpad: Scratchpad,
+}
+
+impl ScratchpadTool {
+    pub fn new(scratchpad: Scratchpad) -> Self {
+        Self { scratchpad }
+    }
+
+    pub async fn scratchpad(&self, arguments: ScratchpadArgs) -> Result<String, AgentyError> {
+        let pad = &self.scratchpad;
+        if arguments.op == ScratchpadOp::List {
+            let notes = pad.list();
+            if notes.is_empty() {
+                return Ok("The scratchpad is empty".to_string());
+            }
+            return Ok(notes
+                .into_iter()
+                .map(|(key, size)| format!("{:?}: {}", key, human_size(size as u64)))
+                .collect::<Vec<_>>()
+                .join("\n"));
+        }
+        let Some(key) = arguments.key else {
+            return Ok("`key` is required for this operation".to_string());
+        };
+        let changed = match (arguments.op, arguments.value) {
+            (ScratchpadOp::Get, _) => {
+                return Ok(pad
+                    .get(&key)
+                    .unwrap_or_else(|| format!("No note under the key {:?}", &key)));
+            }
+            (ScratchpadOp::Delete, _) => match pad.delete(&key) {
+                Some(_) => format!("Deleted the note {:?}", &key),
+                None => return Ok(format!("No note under the key {:?}", &key)),
+            },
+            (ScratchpadOp::Set | ScratchpadOp::Append, None) => {
+                return Ok("`value` is required to set or append".to_string());
+            }
+            (ScratchpadOp::Set, Some(value)) => match pad.set(&key, value) {
+                Ok(()) => format!("Saved the note {:?}", &key),
+                Err(e) => return Ok(e),
+            },
+            (ScratchpadOp::Append, Some(value)) => match pad.append(&key, &value) {
+                Ok(()) => format!("Appended to the note {:?}", &key),
+                Err(e) => return Ok(e),
+            },
+            (ScratchpadOp::List, _) => unreachable!("listed above"),
+        };
+        pad.save().await?;
+        Ok(changed)
+    }
+}
+
+impl Tool for ScratchpadTool {
+    type ARGUMENTS = ScratchpadArgs;
+    const NAME: &str = "scratchpad";
+    const DESCRIPTION: Option<&str> = Some(
+        "Keep notes during the task that stay available even when earlier messages are forgotten. `op` is 'set' to store `value` under `key`, 'append' to add `value` to the end of the note under `key`, 'get' to read a note, 'delete' to remove it and 'list' to list all keys with the sizes of their notes. The size of notes is limited.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.scratchpad(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_appends_are_kept() {
+        let pad = Scratchpad::new();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        pad.append("log", "x").unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(pad.get("log").unwrap().len(), 800);
+    }
+}
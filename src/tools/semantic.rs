@@ -0,0 +1,421 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use ignore::WalkBuilder;
+use itertools::Itertools;
+use openai_models::llm::OpenAISetup;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{atomic_write, looks_textual};
+
+/// The index file under `cwd`, hidden so walks and searches skip it.
+pub const SEMANTIC_INDEX_FILE: &str = ".agenty_semantic_index.json";
+pub const DEFAULT_CHUNK_LINES: usize = 40;
+/// Longer chunks are cut, embedding models take a few thousand tokens at most.
+pub const MAX_CHUNK_CHARS: usize = 6000;
+pub const DEFAULT_MAX_INDEX_FILE_BYTES: u64 = 1024 * 1024;
+pub const DEFAULT_TOP_K: usize = 5;
+pub const MAX_TOP_K: usize = 20;
+const EMBEDDING_BATCH: usize = 64;
+
+/// Talks to an OpenAI compatible `/embeddings` endpoint.
+#[derive(Debug, Clone)]
+pub struct EmbeddingClient {
+    /// The API base like `https://api.openai.com/v1`.
+    pub url: String,
+    pub key: Option<String>,
+    pub model: String,
+    pub timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+impl EmbeddingClient {
+    pub fn new(url: String, key: Option<String>, model: String) -> Self {
+        Self {
+            url,
+            key,
+            model,
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Use the endpoint and key of the chat model.
+    pub fn from_setup(setup: &OpenAISetup, model: String) -> Self {
+        Self::new(setup.openai_url.clone(), setup.openai_key.clone(), model)
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The embeddings of `inputs`, in the same order.
+    pub async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, AgentyError> {
+        let client = reqwest::Client::builder().timeout(self.timeout).build()?;
+        let mut vectors = Vec::with_capacity(inputs.len());
+        for batch in inputs.chunks(EMBEDDING_BATCH) {
+            let body = serde_json::json!({ "model": &self.model, "input": batch });
+            let mut req = client
+                .post(format!("{}/embeddings", self.url.trim_end_matches('/')))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?);
+            if let Some(key) = &self.key {
+                req = req.bearer_auth(key);
+            }
+            let resp = req.send().await?;
+            let status = resp.status();
+            let body = resp.bytes().await?;
+            if !status.is_success() {
+                return Err(AgentyError::Unexpected(format!(
+                    "embedding request failed with {}: {}",
+                    status,
+                    String::from_utf8_lossy(&body)
+                )));
+            }
+            let mut data = serde_json::from_slice::<EmbeddingResponse>(&body)?.data;
+            if data.len() != batch.len() {
+                return Err(AgentyError::Unexpected(format!(
+                    "{} embeddings for {} inputs",
+                    data.len(),
+                    batch.len()
+                )));
+            }
+            data.sort_by_key(|d| d.index);
+            vectors.extend(data.into_iter().map(|d| d.embedding));
+        }
+        Ok(vectors)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedChunk {
+    /// 1-based, inclusive.
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub mtime: SystemTime,
+    pub chunks: Vec<IndexedChunk>,
+}
+
+/// The embedded chunks of all text files of a workspace, by relative path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SemanticIndex {
+    pub model: String,
+    pub files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+/// What [`SemanticSearchTool::build_index`] did.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct IndexStats {
+    pub embedded_files: usize,
+    pub unchanged_files: usize,
+    pub removed_files: usize,
+    pub chunks: usize,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SemanticSearchArgs {
+    /// What to look for, in natural language.
+    pub query: String,
+    /// Chunks returned, 5 by default.
+    pub top_k: Option<usize>,
+}
+
+/// Split `text` into chunks of `chunk_lines` lines at most, with their 1-based line ranges.
+pub fn chunk_lines(text: &str, chunk_lines: usize) -> Vec<(usize, usize, String)> {
+    let mut chunks = vec![];
+    for (i, lines) in text
+        .lines()
+        .chunks(chunk_lines.max(1))
+        .into_iter()
+        .enumerate()
+    {
+        let lines = lines.collect_vec();
+        let start = i * chunk_lines.max(1) + 1;
+        let mut chunk = lines.join("\n");
+        if chunk.trim().is_empty() {
+            continue;
+        }
+        if let Some((cut, _)) = chunk.char_indices().nth(MAX_CHUNK_CHARS) {
+            chunk.truncate(cut);
+        }
+        chunks.push((start, start + lines.len() - 1, chunk));
+    }
+    chunks
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Finds code and text by meaning rather than by words, over an index of embeddings.
+///
+/// The host builds and refreshes the index with [`SemanticSearchTool::build_index`], the
+/// tool refuses to search while the index is missing or older than the files.
+#[derive(Debug, Clone)]
+pub struct SemanticSearchTool {
+    pub cwd: PathBuf,
+    pub client: EmbeddingClient,
+    pub index_path: PathBuf,
+    pub chunk_lines: usize,
+    /// Larger files are not indexed.
+    pub max_file_bytes: u64,
+}
+
+impl SemanticSearchTool {
+    pub fn new(cwd: PathBuf, client: EmbeddingClient) -> Self {
+        let index_path = cwd.join(SEMANTIC_INDEX_FILE);
+        Self {
+            cwd,
+            client,
+            index_path,
+            chunk_lines: DEFAULT_CHUNK_LINES,
+            max_file_bytes: DEFAULT_MAX_INDEX_FILE_BYTES,
+        }
+    }
+
+    pub fn with_index_path(mut self, index_path: PathBuf) -> Self {
+        self.index_path = index_path;
+        self
+    }
+
+    pub fn with_chunk_lines(mut self, chunk_lines: usize) -> Self {
+        self.chunk_lines = chunk_lines;
+        self
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    /// Modification times of the files to index by relative path, honoring `.gitignore`.
+    async fn walk(&self) -> Result<BTreeMap<PathBuf, SystemTime>, AgentyError> {
+        let root = self.cwd.clone();
+        let index_path = self.index_path.clone();
+        let max_file_bytes = self.max_file_bytes;
+        tokio::task::spawn_blocking(move || {
+            let mut files = BTreeMap::new();
+            for ent in WalkBuilder::new(&root).require_git(false).build() {
+                let ent = ent?;
+                if !ent.file_type().is_some_and(|t| t.is_file()) || ent.path() == index_path {
+                    continue;
+                }
+                let meta = ent.metadata()?;
+                if meta.len() == 0 || meta.len() > max_file_bytes {
+                    continue;
+                }
+                files.insert(
+                    ent.path()
+                        .strip_prefix(&root)
+                        .expect("walked outside root?!")
+                        .to_path_buf(),
+                    meta.modified()?,
+                );
+            }
+            Ok(files)
+        })
+        .await?
+    }
+
+    pub async fn load_index(&self) -> Result<Option<SemanticIndex>, AgentyError> {
+        match tokio::fs::read(&self.index_path).await {
+            Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Embed the files changed since the last run and drop the deleted ones, everything is
+    /// embedded again if the model changed. Meant to be called by the host, not the model.
+    pub async fn build_index(&self) -> Result<IndexStats, AgentyError> {
+        let mut index = match self.load_index().await? {
+            Some(index) if index.model == self.client.model => index,
+            _ => SemanticIndex {
+                model: self.client.model.clone(),
+                files: BTreeMap::new(),
+            },
+        };
+        let files = self.walk().await?;
+        let mut stats = IndexStats::default();
+        let before = index.files.len();
+        index.files.retain(|path, _| files.contains_key(path));
+        stats.removed_files = before - index.files.len();
+
+        for (path, mtime) in files {
+            if index.files.get(&path).is_some_and(|f| f.mtime == mtime) {
+                stats.unchanged_files += 1;
+                continue;
+            }
+            let buf = tokio::fs::read(self.cwd.join(&path)).await?;
+            if !looks_textual(&buf) {
+                // Kept without chunks so new binary files are told apart from new text files.
+                let chunks = vec![];
+                index.files.insert(path, IndexedFile { mtime, chunks });
+                continue;
+            }
+            let chunks = chunk_lines(&String::from_utf8_lossy(&buf), self.chunk_lines);
+            let texts = chunks
+                .iter()
+                .map(|(_, _, text)| format!("{}\n{}", path.display(), text))
+                .collect_vec();
+            let vectors = self.client.embed(&texts).await?;
+            debug!("Embedded {} chunks of {}", chunks.len(), path.display());
+            let chunks = chunks
+                .into_iter()
+                .zip(vectors)
+                .map(|((start_line, end_line, text), vector)| IndexedChunk {
+                    start_line,
+                    end_line,
+                    text,
+                    vector,
+                })
+                .collect();
+            index.files.insert(path, IndexedFile { mtime, chunks });
+            stats.embedded_files += 1;
+        }
+        stats.chunks = index.files.values().map(|f| f.chunks.len()).sum();
+        atomic_write(&self.index_path, serde_json::to_vec(&index)?).await?;
+        info!(
+            "Semantic index of {} files and {} chunks, {} files embedded",
+            index.files.len(),
+            stats.chunks,
+            stats.embedded_files
+        );
+        Ok(stats)
+    }
+
+    /// Why the index can not be used, `None` if it is up to date.
+    fn stale_reason(
+        &self,
+        index: &SemanticIndex,
+        files: &BTreeMap<PathBuf, SystemTime>,
+    ) -> Option<String> {
+        if index.model != self.client.model {
+            return Some(format!(
+                "it was built with the model {} instead of {}",
+                index.model, self.client.model
+            ));
+        }
+        let changed = files
+            .iter()
+            .filter(|(path, mtime)| index.files.get(*path).is_some_and(|f| f.mtime != **mtime))
+            .count();
+        let removed = index
+            .files
+            .keys()
+            .filter(|path| !files.contains_key(*path))
+            .count();
+        let added = files
+            .keys()
+            .filter(|path| !index.files.contains_key(*path))
+            .count();
+        if changed + removed + added == 0 {
+            return None;
+        }
+        Some(format!(
+            "{} files changed, {} added and {} removed since it was built",
+            changed, added, removed
+        ))
+    }
+
+    fn display_index_path(&self) -> String {
+        self.index_path
+            .strip_prefix(&self.cwd)
+            .unwrap_or(&self.index_path)
+            .display()
+            .to_string()
+    }
+
+    pub async fn semantic_search(
+        &self,
+        query: String,
+        top_k: Option<usize>,
+    ) -> Result<String, AgentyError> {
+        let Some(index) = self.load_index().await? else {
+            return Ok(format!(
+                "The semantic index {} does not exist, so semantic search is unavailable. Use grep instead and tell the user that the host has to build the index with `SemanticSearchTool::build_index`.",
+                self.display_index_path()
+            ));
+        };
+        let files = self.walk().await?;
+        if let Some(reason) = self.stale_reason(&index, &files) {
+            return Ok(format!(
+                "The semantic index {} is stale: {}. Results would be wrong, so use grep instead and tell the user that the host has to refresh the index with `SemanticSearchTool::build_index`.",
+                self.display_index_path(),
+                reason
+            ));
+        }
+        if query.trim().is_empty() {
+            return Ok("The query is empty".to_string());
+        }
+
+        let top_k = top_k.unwrap_or(DEFAULT_TOP_K).clamp(1, MAX_TOP_K);
+        let query_vector = self.client.embed(&[query]).await?.pop().unwrap_or_default();
+        let hits = index
+            .files
+            .iter()
+            .flat_map(|(path, file)| file.chunks.iter().map(move |chunk| (path, chunk)))
+            .map(|(path, chunk)| (cosine_similarity(&query_vector, &chunk.vector), path, chunk))
+            .sorted_by(|a, b| b.0.total_cmp(&a.0))
+            .take(top_k)
+            .collect_vec();
+        if hits.is_empty() {
+            return Ok("The semantic index is empty".to_string());
+        }
+        Ok(hits
+            .into_iter()
+            .map(|(score, path, chunk)| {
+                format!(
+                    "{}:{}-{} (similarity {:.3})\n{}",
+                    path.display(),
+                    chunk.start_line,
+                    chunk.end_line,
+                    score,
+                    chunk.text
+                )
+            })
+            .join("\n\n"))
+    }
+}
+
+impl Tool for SemanticSearchTool {
+    type ARGUMENTS = SemanticSearchArgs;
+    const NAME: &str = "semantic_search";
+    const DESCRIPTION: Option<&str> = Some(
+        "Search the workspace by meaning instead of exact words, e.g. 'where are the retries of failed requests handled'. Returns the `top_k` most similar chunks of text files with their paths, line ranges and similarity scores, higher is more similar. Use grep for exact names and strings.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.semantic_search(arguments.query, arguments.top_k)
+    }
+}
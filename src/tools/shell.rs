@@ -0,0 +1,363 @@
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    future::Future,
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, Command},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{
+    ask::{UserCallback, ask_approval},
+    file::{human_size, truncate_at_line_boundary},
+};
+
+pub const DEFAULT_SHELL_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_STREAM_BYTES: usize = 8192;
+
+/// Characters that let a command line run more programs than its first word.
+const SHELL_OPERATORS: &[&str] = &[";", "&", "|", "`", "$(", ">", "<", "\n"];
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ShellCommandArgs {
+    pub command: String,
+    /// Kill the command after this many seconds, can not exceed the limit of the host.
+    pub timeout_secs: Option<u64>,
+}
+
+/// What was read from a stream, only the first `max` bytes are kept.
+struct Captured {
+    head: Vec<u8>,
+    total: usize,
+}
+
+fn capture(
+    stream: Option<impl AsyncRead + Unpin + Send + 'static>,
+    max: usize,
+) -> JoinHandle<Captured> {
+    tokio::spawn(async move {
+        let mut captured = Captured {
+            head: vec![],
+            total: 0,
+        };
+        let Some(mut stream) = stream else {
+            return captured;
+        };
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = stream.read(&mut buf).await {
+            if n == 0 {
+                break;
+            }
+            captured.total += n;
+            // Keep going past `max` so the command does not block on a full pipe.
+            let keep = n.min(max.saturating_sub(captured.head.len()));
+            captured.head.extend_from_slice(&buf[..keep]);
+        }
+        captured
+    })
+}
+
+fn render_stream(name: &str, captured: &Captured) -> String {
+    let text = String::from_utf8_lossy(&captured.head);
+    if captured.total > captured.head.len() {
+        format!(
+            "{} ({}, truncated):\n{}",
+            name,
+            human_size(captured.total as u64),
+            // The kept bytes may end in the middle of a character or line.
+            truncate_at_line_boundary(&text, captured.head.len())
+        )
+    } else {
+        format!("{} ({}):\n{}", name, human_size(captured.total as u64), text)
+    }
+}
+
+/// Kill the command along with everything it started.
+///
+/// `pid` must be taken at spawn, `child.id()` is gone once the command exited even if what
+/// it started in the background still runs.
+pub(crate) async fn kill(pid: Option<u32>, child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        // The command leads its own process group, see `run_command`.
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+    }
+    let _ = child.kill().await;
+}
+
+/// The process groups of the commands run by [`ShellCommandTool`], shared by its clones.
+///
+/// Every command leads its own group, so anything it started in the background, like a
+/// server, stays recognizable after the command returned.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnedProcesses(Arc<Mutex<HashSet<u32>>>);
+
+impl SpawnedProcesses {
+    pub fn insert(&self, pgid: u32) {
+        self.0.lock().unwrap().insert(pgid);
+    }
+
+    pub fn contains(&self, pgid: u32) -> bool {
+        self.0.lock().unwrap().contains(&pgid)
+    }
+}
+
+#[derive(Clone)]
+pub struct ShellCommandTool {
+    pub cwd: PathBuf,
+    /// Programs the first word of a command may name, anything is allowed if `None`.
+    ///
+    /// With an allowlist, commands chaining programs with `;`, `|`, `&&` and the like are
+    /// refused since only the first one could be checked.
+    pub allowlist: Option<Vec<String>>,
+    /// The timeout if the model does not ask for a shorter one.
+    pub timeout: Duration,
+    /// Bytes returned at most of each of stdout and stderr.
+    pub max_stream_bytes: usize,
+    /// Pass a clone to the process tool to let it kill what the commands started.
+    pub spawned: SpawnedProcesses,
+    /// Asked before every command, commands run unasked if `None`.
+    pub approval: Option<UserCallback>,
+}
+
+impl Debug for ShellCommandTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShellCommandTool")
+            .field("cwd", &self.cwd)
+            .field("allowlist", &self.allowlist)
+            .field("timeout", &self.timeout)
+            .field("max_stream_bytes", &self.max_stream_bytes)
+            .field("spawned", &self.spawned)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ShellCommandTool {
+    /// Run commands in `cwd` once the user approved them through `approval`, see
+    /// [`ask_approval`].
+    pub fn new(
+        cwd: PathBuf,
+        approval: impl Fn(String) -> BoxFuture<'static, Result<String, AgentyError>>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        Self {
+            approval: Some(Arc::new(approval)),
+            ..Self::without_approval(cwd)
+        }
+    }
+
+    /// Run commands in `cwd` without asking anyone, only for sandboxes where whatever the
+    /// model runs is fine. Consider an allowlist at least.
+    pub fn without_approval(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            allowlist: None,
+            timeout: DEFAULT_SHELL_TIMEOUT,
+            max_stream_bytes: DEFAULT_MAX_STREAM_BYTES,
+            spawned: SpawnedProcesses::default(),
+            approval: None,
+        }
+    }
+
+    pub fn with_allowlist(mut self, allowlist: Vec<String>) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn check_allowlist(&self, command: &str) -> Result<(), String> {
+        let Some(allowlist) = &self.allowlist else {
+            return Ok(());
+        };
+        if let Some(op) = SHELL_OPERATORS.iter().find(|op| command.contains(*op)) {
+            return Err(format!(
+                "{:?} is not allowed in commands, run a single program per call",
+                op
+            ));
+        }
+        let program = command.split_whitespace().next().unwrap_or_default();
+        if !allowlist.iter().any(|p| p == program) {
+            return Err(format!(
+                "{:?} is not allowed, the allowed programs are: {}",
+                program,
+                allowlist.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn run_command(
+        &self,
+        command: String,
+        timeout_secs: Option<u64>,
+    ) -> Result<String, AgentyError> {
+        if let Err(e) = self.check_allowlist(&command) {
+            return Ok(e);
+        }
+        let action = format!("Run `{}` in {}?", command, self.cwd.display());
+        if let Some(approval) = &self.approval
+            && !ask_approval(approval, &action).await?
+        {
+            return Ok(format!("The user declined to run {:?}", &command));
+        }
+        let timeout = timeout_secs
+            .map(Duration::from_secs)
+            .map_or(self.timeout, |t| t.min(self.timeout));
+
+        #[cfg(windows)]
+        let mut cmd = {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C").arg(&command);
+            cmd
+        };
+        #[cfg(not(windows))]
+        let mut cmd = {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(&command);
+            cmd
+        };
+        cmd.current_dir(&self.cwd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // Lead a new process group so a timeout kills whatever the command spawned as well.
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => return Ok(format!("Fail to run {:?} due to {}", &command, e)),
+        };
+        let pid = child.id();
+        #[cfg(unix)]
+        if let Some(pid) = pid {
+            self.spawned.insert(pid);
+        }
+        let mut stdout = capture(child.stdout.take(), self.max_stream_bytes);
+        let mut stderr = capture(child.stderr.take(), self.max_stream_bytes);
+
+        // Background processes keep the pipes open after the shell exits, so wait for the
+        // streams to close as well.
+        let deadline = Instant::now() + timeout;
+        let finished = tokio::time::timeout_at(deadline, async {
+            let status = child.wait().await;
+            let out = (&mut stdout).await;
+            let err = (&mut stderr).await;
+            (status, out, err)
+        })
+        .await;
+        let (status, out, err) = match finished {
+            Ok((status, out, err)) => (Some(status?), out?, err?),
+            Err(_) => {
+                kill(pid, &mut child).await;
+                let grace = Duration::from_secs(1);
+                let out = tokio::time::timeout(grace, stdout).await;
+                let err = tokio::time::timeout(grace, stderr).await;
+                let empty = || Captured {
+                    head: vec![],
+                    total: 0,
+                };
+                (
+                    None,
+                    out.ok().and_then(|r| r.ok()).unwrap_or_else(empty),
+                    err.ok().and_then(|r| r.ok()).unwrap_or_else(empty),
+                )
+            }
+        };
+
+        let outcome = match status.map(|s| s.code()) {
+            None => format!("killed after the timeout of {}s", timeout.as_secs_f32()),
+            Some(Some(code)) => format!("exit code: {}", code),
+            Some(None) => "terminated by a signal".to_string(),
+        };
+        Ok(format!(
+            "{}\n{}\n{}",
+            outcome,
+            render_stream("stdout", &out),
+            render_stream("stderr", &err)
+        ))
+    }
+}
+
+impl Tool for ShellCommandTool {
+    type ARGUMENTS = ShellCommandArgs;
+    const NAME: &str = "run_command";
+    const DESCRIPTION: Option<&str> = Some(
+        "Run a shell command in the working directory and return its exit code, stdout and stderr. Long outputs are truncated, so prefer commands with focused output. The command is killed with everything it started after `timeout_secs` seconds or the limit of the host, whichever is shorter. Commands are not interactive, stdin is empty.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.run_command(arguments.command, arguments.timeout_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn replying(reply: &'static str) -> ShellCommandTool {
+        ShellCommandTool::new(std::env::temp_dir(), move |_| {
+            async move { Ok(reply.to_string()) }.boxed()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_commands_need_approval() {
+        let out = replying("no")
+            .run_command("echo ran".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(out, "The user declined to run \"echo ran\"");
+
+        let out = replying("yes")
+            .run_command("echo ran".to_string(), None)
+            .await
+            .unwrap();
+        assert!(out.starts_with("exit code: 0\nstdout"), "{}", out);
+        assert!(out.contains("ran"), "{}", out);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_timeout_kills_background_processes() {
+        // The shell exits at once, the background sleep keeps the pipes open.
+        let tool = ShellCommandTool::without_approval(std::env::temp_dir());
+        let out = tool
+            .run_command("sleep 30 & echo $!".to_string(), Some(1))
+            .await
+            .unwrap();
+        assert!(out.starts_with("killed after the timeout of 1s"), "{}", out);
+        let pid = out.lines().nth(2).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let state = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .map(|stat| stat.rsplit(") ").next().unwrap().starts_with('Z'));
+        assert!(state.unwrap_or(true), "{} still runs", pid);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{future::Future, path::PathBuf};
+
+use itertools::Itertools;
+use rusqlite::{Connection, OpenFlags, types::ValueRef};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+pub const DEFAULT_SQL_ROW_LIMIT: usize = 50;
+/// Longer values are cut in the result table.
+const MAX_CELL_CHARS: usize = 200;
+/// The `sql` listing the tables and their columns instead of running a query.
+pub const SCHEMA_COMMAND: &str = "\\schema";
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SqliteQueryArgs {
+    pub sql: String,
+}
+
+fn render_value(value: ValueRef<'_>) -> String {
+    let mut value = match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).replace(['\r', '\n'], " "),
+        ValueRef::Blob(b) => format!("<blob of {} bytes>", b.len()),
+    };
+    if value.chars().count() > MAX_CELL_CHARS {
+        value = value.chars().take(MAX_CELL_CHARS - 1).collect();
+        value.push('…');
+    }
+    value
+}
+
+/// Queries a SQLite database, a new connection is opened for every call.
+#[derive(Debug, Clone)]
+pub struct SqliteQueryTool {
+    pub db_path: PathBuf,
+    /// Refuse statements that would write to the database, true by default.
+    pub read_only: bool,
+    /// Rows returned at most per query.
+    pub row_limit: usize,
+}
+
+impl SqliteQueryTool {
+    pub fn new(db_path: PathBuf) -> Self {
+        Self {
+            db_path,
+            read_only: true,
+            row_limit: DEFAULT_SQL_ROW_LIMIT,
+        }
+    }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn with_row_limit(mut self, row_limit: usize) -> Self {
+        self.row_limit = row_limit;
+        self
+    }
+
+    fn open(&self) -> rusqlite::Result<Connection> {
+        if self.read_only {
+            // Also opened read-only so nothing slips past the statement check.
+            Connection::open_with_flags(
+                &self.db_path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+        } else {
+            Connection::open(&self.db_path)
+        }
+    }
+
+    fn schema(conn: &Connection) -> rusqlite::Result<String> {
+        let mut stmt = conn.prepare(
+            "SELECT name, type FROM sqlite_master WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )?;
+        let tables = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        if tables.is_empty() {
+            return Ok("The database has no tables".to_string());
+        }
+        let mut lns = vec![];
+        for (table, kind) in tables {
+            let mut info =
+                conn.prepare("SELECT name, type, \"notnull\", pk FROM pragma_table_info(?1)")?;
+            let columns = info
+                .query_map([&table], |row| {
+                    let mut column =
+                        format!("{} {}", row.get::<_, String>(0)?, row.get::<_, String>(1)?);
+                    if row.get::<_, i64>(3)? > 0 {
+                        column.push_str(" PRIMARY KEY");
+                    }
+                    if row.get::<_, bool>(2)? {
+                        column.push_str(" NOT NULL");
+                    }
+                    Ok(column.trim().to_string())
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            lns.push(format!("{} {}({})", kind, table, columns.join(", ")));
+        }
+        Ok(lns.join("\n"))
+    }
+
+    fn query(&self, conn: &Connection, sql: &str) -> rusqlite::Result<String> {
+        let mut stmt = conn.prepare(sql)?;
+        // Asks SQLite itself, so writes hidden in CTEs or pragmas are caught as well.
+        if self.read_only && !stmt.readonly() {
+            return Ok(
+                "Only statements reading the database are allowed, the database is read-only"
+                    .to_string(),
+            );
+        }
+        if stmt.column_count() == 0 {
+            let changed = stmt.execute([])?;
+            return Ok(format!("Done, {} rows changed", changed));
+        }
+
+        let names = stmt
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect_vec();
+        let mut table = prettytable::Table::new();
+        table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+        table.set_titles(prettytable::Row::new(
+            names.iter().map(|n| prettytable::Cell::new(n)).collect(),
+        ));
+        let mut rows = stmt.query([])?;
+        let mut shown = 0;
+        let mut more = 0;
+        while let Some(row) = rows.next()? {
+            if shown >= self.row_limit {
+                more += 1;
+                continue;
+            }
+            let mut cells = vec![];
+            for i in 0..names.len() {
+                cells.push(prettytable::Cell::new(&render_value(row.get_ref(i)?)));
+            }
+            table.add_row(prettytable::Row::new(cells));
+            shown += 1;
+        }
+        if shown == 0 {
+            return Ok(format!("No rows, the columns are: {}", names.join(", ")));
+        }
+        let mut resp = table.to_string();
+        if more > 0 {
+            resp.push_str(&format!("{} more rows", more));
+        }
+        Ok(resp)
+    }
+
+    pub async fn sqlite_query(&self, sql: String) -> Result<String, AgentyError> {
+        let this = self.clone();
+        Ok(tokio::task::spawn_blocking(move || {
+            let resp = this.open().and_then(|conn| {
+                if sql.trim() == SCHEMA_COMMAND {
+                    Self::schema(&conn)
+                } else {
+                    this.query(&conn, &sql)
+                }
+            });
+            // The model can usually fix its query given the message of SQLite.
+            resp.unwrap_or_else(|e| format!("SQL error: {}", e))
+        })
+        .await?)
+    }
+}
+
+impl Tool for SqliteQueryTool {
+    type ARGUMENTS = SqliteQueryArgs;
+    const NAME: &str = "sqlite_query";
+    const DESCRIPTION: Option<&str> = Some(
+        "Run a single SQL statement against the SQLite database and get the result as a table. Pass '\\schema' as `sql` to list the tables with their columns first. Only a limited number of rows is returned, so aggregate or filter in SQL where possible. The database may be read-only, then only statements reading it are allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.sqlite_query(arguments.sql)
+    }
+}
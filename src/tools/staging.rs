@@ -0,0 +1,310 @@
+use std::{
+    future::Future,
+    path::{Component, Path, PathBuf},
+};
+
+use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::journal::FsJournal;
+
+/// Directory under `cwd` holding one subdirectory per stage.
+pub const STAGING_DIR: &str = ".agenty_staging";
+
+/// Open a new stage under `cwd`, returning its id.
+pub fn create_stage(cwd: &Path) -> std::io::Result<String> {
+    let root = cwd.join(STAGING_DIR);
+    std::fs::create_dir_all(&root)?;
+    let dir = tempfile::Builder::new()
+        .prefix("stage-")
+        .rand_bytes(8)
+        .tempdir_in(&root)?
+        .keep();
+    Ok(dir
+        .file_name()
+        .expect("tempdir without a name?!")
+        .to_string_lossy()
+        .to_string())
+}
+
+/// The directory of the existing stage `stage_id`, errors are meant for the model.
+pub fn stage_dir(cwd: &Path, stage_id: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(stage_id).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(format!("{:?} is not a valid stage id", stage_id));
+    }
+    let dir = cwd.join(STAGING_DIR).join(stage_id);
+    if !dir.is_dir() {
+        return Err(format!(
+            "No stage {:?}, it may be committed or rolled back already",
+            stage_id
+        ));
+    }
+    Ok(dir)
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct StagingArgs {
+    pub stage_id: String,
+}
+
+/// Moves the files of a stage written by [`super::file::WriteFileTool::staged`] to their
+/// destinations.
+#[derive(Debug, Clone)]
+pub struct CommitStagingTool {
+    pub cwd: PathBuf,
+    pub journal: Option<FsJournal>,
+}
+
+impl CommitStagingTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self { cwd, journal: None }
+    }
+
+    pub fn with_journal(mut self, journal: FsJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub async fn commit_staging(&self, stage_id: String) -> Result<String, AgentyError> {
+        let dir = match stage_dir(&self.cwd, &stage_id) {
+            Ok(dir) => dir,
+            Err(e) => return Ok(e),
+        };
+        let walk_dir = dir.clone();
+        let staged = tokio::task::spawn_blocking(move || {
+            WalkDir::new(&walk_dir)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path())
+                .collect_vec()
+        })
+        .await?;
+
+        // Without a journal of the host, the originals are kept in one of our own until all
+        // files are moved, so a failure can put them back.
+        let (journal, _journal_dir) = match &self.journal {
+            Some(journal) => (journal.clone(), None),
+            None => {
+                let journal_dir = tempfile::Builder::new()
+                    .prefix("journal-")
+                    .tempdir_in(self.cwd.join(STAGING_DIR))?;
+                let journal = FsJournal::new(journal_dir.path().to_path_buf()).await?;
+                (journal, Some(journal_dir))
+            }
+        };
+
+        // Renames within the same filesystem are atomic, so every file is either entirely
+        // old or entirely new, and should one fail, the files moved already are put back.
+        let mut moved = vec![];
+        let mut committed = vec![];
+        for path in staged {
+            let rel = path
+                .strip_prefix(&dir)
+                .expect("staged file outside of its stage?!")
+                .to_path_buf();
+            let target_path = self.cwd.join(&rel);
+            if let Err(e) = Self::move_staged(&journal, &path, &target_path, &mut moved).await {
+                Self::move_back(&journal, moved).await?;
+                return Ok(format!(
+                    "Fail to commit {:?} of stage {} due to {}, no file was changed and the stage is kept",
+                    &rel, &stage_id, e
+                ));
+            }
+            committed.push(rel);
+        }
+        tokio::fs::remove_dir_all(&dir).await?;
+
+        Ok(format!(
+            "Committed {} files of stage {}: {}",
+            committed.len(),
+            &stage_id,
+            committed.iter().map(|p| format!("{:?}", p)).join(", ")
+        ))
+    }
+
+    /// Move the staged file `path` to `target_path`, adding it to `moved` as soon as the
+    /// journal has recorded the original.
+    async fn move_staged(
+        journal: &FsJournal,
+        path: &Path,
+        target_path: &Path,
+        moved: &mut Vec<(PathBuf, PathBuf, u64)>,
+    ) -> Result<(), AgentyError> {
+        if let Some(parent) = target_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let id = journal.record(target_path).await?;
+        moved.push((path.to_path_buf(), target_path.to_path_buf(), id));
+        tokio::fs::rename(path, target_path).await?;
+        Ok(())
+    }
+
+    /// Return the files in `moved` to the stage and restore their originals, newest first.
+    async fn move_back(
+        journal: &FsJournal,
+        moved: Vec<(PathBuf, PathBuf, u64)>,
+    ) -> Result<(), AgentyError> {
+        for (path, target_path, id) in moved.into_iter().rev() {
+            if !tokio::fs::try_exists(&path).await? {
+                tokio::fs::rename(&target_path, &path).await?;
+            }
+            journal.undo(id).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Tool for CommitStagingTool {
+    type ARGUMENTS = StagingArgs;
+    const NAME: &str = "commit_staging";
+    const DESCRIPTION: Option<&str> = Some(
+        "Write all files staged under `stage_id` by write_file to their destinations. Either all files are written or, should one fail, none and the stage is kept. The stage is closed afterwards and the next write_file opens a new one.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.commit_staging(arguments.stage_id)
+    }
+}
+
+/// Discards a stage written by [`super::file::WriteFileTool::staged`].
+#[derive(Debug, Clone)]
+pub struct RollbackStagingTool {
+    pub cwd: PathBuf,
+}
+
+impl RollbackStagingTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self { cwd }
+    }
+
+    pub async fn rollback_staging(&self, stage_id: String) -> Result<String, AgentyError> {
+        let dir = match stage_dir(&self.cwd, &stage_id) {
+            Ok(dir) => dir,
+            Err(e) => return Ok(e),
+        };
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(format!(
+            "Rolled back stage {}, no staged file was written",
+            &stage_id
+        ))
+    }
+}
+
+impl Tool for RollbackStagingTool {
+    type ARGUMENTS = StagingArgs;
+    const NAME: &str = "rollback_staging";
+    const DESCRIPTION: Option<&str> = Some(
+        "Discard all files staged under `stage_id` by write_file, leaving the original files untouched. The next write_file opens a new stage.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.rollback_staging(arguments.stage_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        file::{FindFileFormat, FindFileTool},
+        grep::GrepTool,
+    };
+
+    /// A tree with `a.txt` and a directory `b`, and a stage replacing `a.txt` and trying to
+    /// replace `b` with a file, which fails.
+    fn failing_stage() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("a.txt"), "old a\n").unwrap();
+        std::fs::create_dir(root.join("b")).unwrap();
+        std::fs::write(root.join("b/inner.txt"), "inner\n").unwrap();
+        let stage_id = create_stage(root).unwrap();
+        let stage = stage_dir(root, &stage_id).unwrap();
+        std::fs::write(stage.join("a.txt"), "new a\n").unwrap();
+        std::fs::write(stage.join("b"), "new b\n").unwrap();
+        (dir, stage_id)
+    }
+
+    fn assert_rolled_back(root: &Path, stage_id: &str, resp: &str) {
+        assert!(resp.starts_with("Fail to commit \"b\""), "{}", resp);
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&root.join("a.txt")), "old a\n");
+        assert_eq!(read(&root.join("b/inner.txt")), "inner\n");
+        let stage = stage_dir(root, stage_id).unwrap();
+        assert_eq!(read(&stage.join("a.txt")), "new a\n");
+        assert_eq!(read(&stage.join("b")), "new b\n");
+        let stages = std::fs::read_dir(root.join(STAGING_DIR)).unwrap().count();
+        assert_eq!(stages, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_is_rolled_back() {
+        let (dir, stage_id) = failing_stage();
+        let resp = CommitStagingTool::new(dir.path().to_path_buf())
+            .commit_staging(stage_id.clone())
+            .await
+            .unwrap();
+        assert_rolled_back(dir.path(), &stage_id, &resp);
+    }
+
+    #[tokio::test]
+    async fn test_failed_commit_is_rolled_back_with_journal() {
+        let (dir, stage_id) = failing_stage();
+        let journal_dir = tempfile::tempdir().unwrap();
+        let journal = FsJournal::new(journal_dir.path().to_path_buf())
+            .await
+            .unwrap();
+        let resp = CommitStagingTool::new(dir.path().to_path_buf())
+            .with_journal(journal.clone())
+            .commit_staging(stage_id.clone())
+            .await
+            .unwrap();
+        assert_rolled_back(dir.path(), &stage_id, &resp);
+        assert!(journal.changes().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_staged_files_are_not_searched() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("a.txt"), "needle\n").unwrap();
+        let stage_id = create_stage(root).unwrap();
+        let stage = stage_dir(root, &stage_id).unwrap();
+        std::fs::write(stage.join("a.txt"), "needle\n").unwrap();
+
+        let args = serde_json::json!({ "path": ".", "pattern": "needle", "unique_files": true });
+        let resp = GrepTool::new(root.to_path_buf())
+            .grep(serde_json::from_value(args).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp, "a.txt");
+        let resp = FindFileTool::find_file(
+            root.to_path_buf(),
+            PathBuf::from("."),
+            vec!["*.txt".to_string()],
+            true,
+            false,
+            FindFileFormat::Json,
+            true,
+        )
+        .unwrap();
+        assert!(!resp.contains(STAGING_DIR), "{}", resp);
+        assert!(resp.contains("a.txt"), "{}", resp);
+    }
+}
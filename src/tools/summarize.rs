@@ -0,0 +1,283 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use openai_models::llm::{LLM, LLMSettings};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    error::AgentyError,
+    tokens::{HeuristicTokenCounter, TokenCounter},
+    tool::Tool,
+};
+
+use super::file::{human_size, sanitize_join_relative_path};
+
+/// Tokens of the child model's window, the input is cut to half of it so the prompt and
+/// the summary fit as well.
+pub const DEFAULT_SUMMARIZE_WINDOW_TOKENS: usize = 32768;
+pub const DEFAULT_MAX_SUMMARIZE_FILE_BYTES: u64 = 8 * 1024 * 1024;
+pub const DEFAULT_SUMMARY_WORDS: usize = 300;
+
+const SUMMARIZE_SYSTEM: &str = "You summarize files for another assistant that can not read them itself. Keep the facts it needs: names, numbers, decisions, errors and where in the file they are. Reply with the summary only.";
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SummarizeFileArgs {
+    pub file_path: PathBuf,
+    /// What the summary should concentrate on.
+    pub focus: Option<String>,
+    /// Words of the summary at most, 300 by default.
+    pub max_words: Option<usize>,
+}
+
+/// What the child model spent on summaries so far.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SummarizeMetrics {
+    pub files: u64,
+    pub llm_calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Summarizes files with a separate, usually cheaper, model so only the summary enters the
+/// context of the agent.
+///
+/// Files too large for the child model's window are summarized in chunks and the chunk
+/// summaries are summarized again. Clones share the model and the metrics.
+#[derive(Debug, Clone)]
+pub struct SummarizeFileTool {
+    pub cwd: PathBuf,
+    pub llm: Arc<tokio::sync::Mutex<LLM>>,
+    pub settings: LLMSettings,
+    pub counter: Arc<dyn TokenCounter>,
+    pub window_tokens: usize,
+    /// Larger files are not summarized.
+    pub max_file_bytes: u64,
+    metrics: Arc<Mutex<SummarizeMetrics>>,
+}
+
+/// Split `text` at line boundaries into chunks of at most `budget` tokens, lines longer than
+/// that are split as well.
+fn split_chunks(text: &str, counter: &dyn TokenCounter, budget: usize) -> Vec<String> {
+    let budget = budget.max(1);
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    let mut chunk_tokens = 0;
+    for line in text.split_inclusive('\n') {
+        let tokens = counter.count(line, None);
+        let pieces = if tokens > budget {
+            // Cut in proportion to the tokens, the counter does not say where they are.
+            let chars = line.chars().collect::<Vec<_>>();
+            let step = (chars.len() * budget / tokens).max(1);
+            chars
+                .chunks(step)
+                .map(|p| p.iter().collect::<String>())
+                .collect()
+        } else {
+            vec![line.to_string()]
+        };
+        for piece in pieces {
+            let tokens = counter.count(&piece, None);
+            if chunk_tokens + tokens > budget && !chunk.is_empty() {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_tokens = 0;
+            }
+            chunk.push_str(&piece);
+            chunk_tokens += tokens;
+        }
+    }
+    if !chunk.trim().is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+impl SummarizeFileTool {
+    pub fn new(cwd: PathBuf, llm: Arc<tokio::sync::Mutex<LLM>>, settings: LLMSettings) -> Self {
+        Self {
+            cwd,
+            llm,
+            settings,
+            counter: Arc::new(HeuristicTokenCounter),
+            window_tokens: DEFAULT_SUMMARIZE_WINDOW_TOKENS,
+            max_file_bytes: DEFAULT_MAX_SUMMARIZE_FILE_BYTES,
+            metrics: Arc::new(Mutex::new(SummarizeMetrics::default())),
+        }
+    }
+
+    pub fn with_counter(mut self, counter: impl TokenCounter + 'static) -> Self {
+        self.counter = Arc::new(counter);
+        self
+    }
+
+    pub fn with_window_tokens(mut self, window_tokens: usize) -> Self {
+        self.window_tokens = window_tokens;
+        self
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    /// The usage of the child model, separate from the tokens of the agent.
+    pub fn metrics(&self) -> SummarizeMetrics {
+        *self.metrics.lock().unwrap()
+    }
+
+    async fn complete(&self, prompt: String) -> Result<String, AgentyError> {
+        let resp = self
+            .llm
+            .lock()
+            .await
+            .prompt_once_with_retry(
+                SUMMARIZE_SYSTEM,
+                &prompt,
+                Some("summarize"),
+                Some(self.settings.clone()),
+            )
+            .await?;
+        {
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.llm_calls += 1;
+            if let Some(usage) = &resp.usage {
+                metrics.prompt_tokens += usage.prompt_tokens as u64;
+                metrics.completion_tokens += usage.completion_tokens as u64;
+            }
+        }
+        resp.choices
+            .into_iter()
+            .next()
+            .and_then(|c| c.message.content)
+            .ok_or_else(|| AgentyError::Unexpected("empty summary".to_string()))
+    }
+
+    pub async fn summarize_file(
+        &self,
+        arguments: SummarizeFileArgs,
+    ) -> Result<String, AgentyError> {
+        let file_path = arguments.file_path;
+        let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        match tokio::fs::metadata(&target_path).await {
+            Ok(meta) if meta.len() > self.max_file_bytes => {
+                return Ok(format!(
+                    "{:?} is {}, more than the {} that can be summarized",
+                    &file_path,
+                    human_size(meta.len()),
+                    human_size(self.max_file_bytes)
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+        }
+        let text = match tokio::fs::read(&target_path).await {
+            Ok(content) => match String::from_utf8(content) {
+                Ok(text) => text,
+                Err(_) => return Ok(format!("{:?} is not a UTF-8 text file", &file_path)),
+            },
+            Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+        };
+        if text.trim().is_empty() {
+            return Ok(format!("{:?} is empty", &file_path));
+        }
+
+        let focus = match &arguments.focus {
+            Some(focus) => format!(" Concentrate on: {}.", focus),
+            None => String::new(),
+        };
+        let max_words = arguments.max_words.unwrap_or(DEFAULT_SUMMARY_WORDS).max(1);
+        let budget = self.window_tokens / 2;
+        let name = file_path.display();
+
+        // Map: summarize every chunk, then summarize the summaries until they fit at once.
+        let chunks = split_chunks(&text, self.counter.as_ref(), budget);
+        let total_chunks = chunks.len();
+        let mut parts = chunks;
+        let mut round = 0;
+        while parts.len() > 1 {
+            round += 1;
+            let count = parts.len();
+            debug!("Summarizing {} in {} chunks, round {}", name, count, round);
+            let mut summaries = vec![];
+            for (i, part) in parts.iter().enumerate() {
+                let what = if round == 1 {
+                    "the file"
+                } else {
+                    "summaries of the file"
+                };
+                summaries.push(
+                    self.complete(format!(
+                        "This is part {} of {} of {} {}.{} Summarize it in at most {} words.\n\n{}",
+                        i + 1,
+                        count,
+                        what,
+                        name,
+                        focus,
+                        max_words,
+                        part
+                    ))
+                    .await?,
+                );
+            }
+            parts = split_chunks(&summaries.join("\n\n"), self.counter.as_ref(), budget);
+            // Summaries longer than their input would never converge, reduce them at once.
+            if parts.len() >= count {
+                parts = vec![summaries.join("\n\n")];
+            }
+        }
+
+        // Reduce: the final summary of the whole file or of the joined summaries.
+        let what = if total_chunks > 1 {
+            "These are summaries of consecutive parts of the file"
+        } else {
+            "This is the file"
+        };
+        let summary = self
+            .complete(format!(
+                "{} {}.{} Summarize it in at most {} words.\n\n{}",
+                what,
+                name,
+                focus,
+                max_words,
+                parts.pop().unwrap_or_default()
+            ))
+            .await?;
+        self.metrics.lock().unwrap().files += 1;
+
+        let chunked = if total_chunks > 1 {
+            format!(", summarized in {} chunks", total_chunks)
+        } else {
+            String::new()
+        };
+        Ok(format!(
+            "# {} — {}, {} lines{}\n{}",
+            name,
+            human_size(text.len() as u64),
+            text.lines().count(),
+            chunked,
+            summary.trim()
+        ))
+    }
+}
+
+impl Tool for SummarizeFileTool {
+    type ARGUMENTS = SummarizeFileArgs;
+    const NAME: &str = "summarize_file";
+    const DESCRIPTION: Option<&str> = Some(
+        "Get a summary of the text file at `file_path` instead of reading it, useful for large files like logs, dumps or documentation. Pass `focus` to say what the summary should concentrate on and `max_words` to limit its length. The header tells the size and the number of lines, read the relevant lines afterwards for details.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.summarize_file(arguments)
+    }
+}
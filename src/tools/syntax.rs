@@ -0,0 +1,278 @@
+use tree_sitter::{Language, Node, Parser};
+
+use super::lang::{Symbol, signature};
+
+/// The grammars for `language`, later ones are only tried if the earlier ones fail to parse
+/// the file without errors.
+fn grammars(language: &str) -> Vec<Language> {
+    match language {
+        "Rust" => vec![tree_sitter_rust::LANGUAGE.into()],
+        "Python" => vec![tree_sitter_python::LANGUAGE.into()],
+        "JavaScript" => vec![tree_sitter_javascript::LANGUAGE.into()],
+        // `.ts` and `.tsx` are not told apart, TSX only rejects the old `<T>value` casts.
+        "TypeScript" => vec![
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            tree_sitter_typescript::LANGUAGE_TSX.into(),
+        ],
+        "Go" => vec![tree_sitter_go::LANGUAGE.into()],
+        _ => vec![],
+    }
+}
+
+/// Whether `node`, a child of the file or of a container body, is listed in the outline.
+fn is_symbol(language: &str, node: &Node, source: &[u8]) -> bool {
+    match language {
+        "Rust" => matches!(
+            node.kind(),
+            "function_item"
+                | "function_signature_item"
+                | "struct_item"
+                | "enum_item"
+                | "union_item"
+                | "trait_item"
+                | "impl_item"
+                | "mod_item"
+                | "type_item"
+                | "associated_type"
+                | "const_item"
+                | "static_item"
+                | "macro_definition"
+        ),
+        "Python" => match node.kind() {
+            "function_definition" | "class_definition" | "decorated_definition" => true,
+            // Only constants like `MAX_SIZE = 10`, as the regexes do.
+            "expression_statement" => node
+                .named_child(0)
+                .filter(|n| n.kind() == "assignment")
+                .and_then(|n| n.child_by_field_name("left"))
+                .filter(|n| n.kind() == "identifier")
+                .and_then(|n| n.utf8_text(source).ok())
+                .is_some_and(|name| {
+                    name.starts_with(|c: char| c.is_ascii_uppercase())
+                        && !name.contains(|c: char| c.is_ascii_lowercase())
+                }),
+            _ => false,
+        },
+        "JavaScript" | "TypeScript" => match node.kind() {
+            "function_declaration"
+            | "generator_function_declaration"
+            | "class_declaration"
+            | "abstract_class_declaration"
+            | "interface_declaration"
+            | "type_alias_declaration"
+            | "enum_declaration"
+            | "lexical_declaration"
+            | "variable_declaration"
+            | "ambient_declaration"
+            | "internal_module"
+            | "method_definition"
+            | "method_signature"
+            | "abstract_method_signature"
+            | "field_definition"
+            | "public_field_definition"
+            | "property_signature" => true,
+            // Not `export { a, b }`, which declares nothing.
+            "export_statement" => {
+                node.child_by_field_name("declaration").is_some()
+                    || node.child_by_field_name("value").is_some()
+            }
+            _ => false,
+        },
+        "Go" => matches!(
+            node.kind(),
+            "function_declaration"
+                | "method_declaration"
+                | "type_declaration"
+                | "const_declaration"
+                | "var_declaration"
+        ),
+        _ => false,
+    }
+}
+
+/// The declaration `node` stands for, looking through decorators and `export`.
+fn definition<'a>(node: Node<'a>) -> Node<'a> {
+    match node.kind() {
+        "decorated_definition" => node.child_by_field_name("definition").unwrap_or(node),
+        "export_statement" => node.child_by_field_name("declaration").unwrap_or(node),
+        _ => node,
+    }
+}
+
+/// Whether the members of `node` are outlined as well.
+fn is_container(node: &Node) -> bool {
+    matches!(
+        node.kind(),
+        "impl_item"
+            | "trait_item"
+            | "mod_item"
+            | "class_definition"
+            | "class_declaration"
+            | "abstract_class_declaration"
+            | "interface_declaration"
+            | "internal_module"
+    )
+}
+
+fn collect(
+    language: &str,
+    parent: Node,
+    depth: usize,
+    source: &[u8],
+    lines: &[&str],
+    symbols: &mut Vec<Symbol>,
+) {
+    let mut cursor = parent.walk();
+    for node in parent.named_children(&mut cursor) {
+        if !is_symbol(language, &node, source) {
+            continue;
+        }
+        let def = definition(node);
+        // The line of the `def`, not of the decorators, but the one of `export`.
+        let row = if node.kind() == "decorated_definition" {
+            def.start_position().row
+        } else {
+            node.start_position().row
+        };
+        symbols.push(Symbol {
+            line: row + 1,
+            depth,
+            signature: signature(lines.get(row).copied().unwrap_or_default()),
+        });
+        if is_container(&def)
+            && let Some(body) = def.child_by_field_name("body")
+        {
+            collect(language, body, depth + 1, source, lines, symbols);
+        }
+    }
+}
+
+/// The outline of `text` from its syntax tree, `None` if there is no grammar for
+/// `language`.
+///
+/// Lists the same kinds of symbols as [`super::lang::outline`] does with regexes, but is
+/// not confused by braces in strings and comments or unusual formatting.
+pub fn outline(language: &str, text: &str) -> Option<Vec<Symbol>> {
+    let mut parser = Parser::new();
+    let mut best = None;
+    for grammar in grammars(language) {
+        parser.set_language(&grammar).ok()?;
+        let Some(tree) = parser.parse(text, None) else {
+            continue;
+        };
+        let clean = !tree.root_node().has_error();
+        if best.is_none() || clean {
+            best = Some(tree);
+        }
+        if clean {
+            break;
+        }
+    }
+    let tree = best?;
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut symbols = vec![];
+    collect(
+        language,
+        tree.root_node(),
+        0,
+        text.as_bytes(),
+        &lines,
+        &mut symbols,
+    );
+    Some(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(language: &str, text: &str) -> Vec<String> {
+        outline(language, text)
+            .unwrap()
+            .into_iter()
+            .map(|s| format!("{}: {}{}", s.line, "  ".repeat(s.depth), s.signature))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust() {
+        let text = r#"const BRACES: &str = "}}}";
+
+/// Docs.
+#[derive(Debug)]
+pub struct Point {
+    x: i32,
+}
+
+impl Point {
+    pub fn new() -> Self {
+        let s = "{";
+        Self { x: 0 }
+    }
+}
+
+mod inner {
+    fn helper() {}
+}
+"#;
+        assert_eq!(
+            render("Rust", text),
+            [
+                "1: const BRACES: &str = \"}}}\";",
+                "5: pub struct Point",
+                "9: impl Point",
+                "10:   pub fn new() -> Self",
+                "16: mod inner",
+                "17:   fn helper() {}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_python() {
+        let text = "MAX_SIZE = 10\nlower = 1\n\n@dataclass\nclass Item:\n    \"\"\"An item.\"\"\"\n    LIMIT = 3\n\n    def price(self):\n        def inner():\n            pass\n        return 1\n";
+        assert_eq!(
+            render("Python", text),
+            [
+                "1: MAX_SIZE = 10",
+                "5: class Item",
+                "7:   LIMIT = 3",
+                "9:   def price(self)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_typescript() {
+        let text = "export interface Shape {\n  area(): number;\n}\n\nexport { a, b };\n\nexport class Square implements Shape {\n  side = 1;\n  area(): number {\n    return this.side ** 2;\n  }\n}\n\nconst render = () => <div>{1}</div>;\n";
+        assert_eq!(
+            render("TypeScript", text),
+            [
+                "1: export interface Shape",
+                "2:   area(): number;",
+                "7: export class Square implements Shape",
+                "8:   side = 1;",
+                "9:   area(): number",
+                "14: const render = () => <div>{1}</div>;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_go() {
+        let text = "package main\n\ntype T struct{}\n\nfunc (t T) Run() {}\n\nfunc main() {\n}\n";
+        assert_eq!(
+            render("Go", text),
+            [
+                "3: type T struct{}",
+                "5: func (t T) Run() {}",
+                "7: func main()"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(outline("Java", "class A {}").is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use std::{future::Future, time::Duration};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{error::AgentyError, tool::Tool};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct EchoArgs {
+    pub message: String,
+}
+
+/// Returns the `message` it is called with, a tool with predictable behavior for tests.
+#[derive(Debug, Clone, Default)]
+pub struct EchoTool;
+
+impl EchoTool {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub async fn echo(&self, message: String) -> Result<String, AgentyError> {
+        Ok(message)
+    }
+}
+
+impl Tool for EchoTool {
+    type ARGUMENTS = EchoArgs;
+    const NAME: &str = "echo";
+    const DESCRIPTION: Option<&str> = Some("Return the given `message` unchanged.");
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.echo(arguments.message)
+    }
+}
+
+/// Like [`EchoTool`] but waits `delay_ms` first, e.g. to test timeouts and concurrency.
+#[derive(Debug, Clone)]
+pub struct DelayTool {
+    pub delay_ms: u64,
+}
+
+impl DelayTool {
+    pub fn new(delay_ms: u64) -> Self {
+        Self { delay_ms }
+    }
+
+    pub async fn delay(&self, message: String) -> Result<String, AgentyError> {
+        tokio::time::sleep(Duration::from_millis(self.delay_ms)).await;
+        Ok(message)
+    }
+}
+
+impl Tool for DelayTool {
+    type ARGUMENTS = EchoArgs;
+    const NAME: &str = "delay";
+    const DESCRIPTION: Option<&str> =
+        Some("Return the given `message` unchanged after a fixed delay.");
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.delay(arguments.message)
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fmt::Write,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AgentyError, tool::Tool};
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoStatus {
+    #[default]
+    Pending,
+    InProgress,
+    Done,
+    Blocked,
+}
+
+impl TodoStatus {
+    fn mark(self) -> &'static str {
+        match self {
+            TodoStatus::Pending => "[ ]",
+            TodoStatus::InProgress => "[>]",
+            TodoStatus::Done => "[x]",
+            TodoStatus::Blocked => "[!]",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TodoItem {
+    /// Stays the same when the plan is reordered.
+    pub id: usize,
+    pub title: String,
+    pub status: TodoStatus,
+    /// Why the item is blocked or what came out of it.
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoOp {
+    Add,
+    Update,
+    Reorder,
+    Show,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct TodoArgs {
+    pub op: TodoOp,
+    /// The items to `add`, appended in this order.
+    pub titles: Option<Vec<String>>,
+    /// The item to `update`.
+    pub id: Option<usize>,
+    /// The new status of the item to `update`.
+    pub status: Option<TodoStatus>,
+    /// A note for the item to `update`, like why it is blocked.
+    pub note: Option<String>,
+    /// The ids in their new order to `reorder`, items left out keep their order at the end.
+    pub order: Option<Vec<usize>>,
+}
+
+/// A plan the model maintains, shared with the host through `items`.
+///
+/// Clones share the items, so the host can seed the plan or show the progress while the
+/// agent runs. See [`crate::agent::Agent::set_todo`] to show the plan to the model on every
+/// request.
+#[derive(Debug, Clone, Default)]
+pub struct TodoTool {
+    pub items: Arc<Mutex<Vec<TodoItem>>>,
+}
+
+impl TodoTool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The plan as shown to the model, empty if there are no items.
+    pub fn render(&self) -> String {
+        let items = self.items.lock().expect("poisoned");
+        if items.is_empty() {
+            return String::new();
+        }
+        let done = items
+            .iter()
+            .filter(|i| i.status == TodoStatus::Done)
+            .count();
+        let mut plan = format!("Plan ({}/{} done):", done, items.len());
+        for item in items.iter() {
+            let _ = write!(plan, "\n{}. {} {}", item.id, item.status.mark(), item.title);
+            if let Some(note) = &item.note {
+                let _ = write!(plan, " ({})", note);
+            }
+        }
+        plan
+    }
+
+    fn add(&self, titles: Vec<String>) -> String {
+        let mut items = self.items.lock().expect("poisoned");
+        let first = items.iter().map(|i| i.id).max().unwrap_or_default() + 1;
+        for (id, title) in (first..).zip(titles) {
+            items.push(TodoItem {
+                id,
+                title,
+                status: TodoStatus::Pending,
+                note: None,
+            });
+        }
+        format!("The plan has {} items now", items.len())
+    }
+
+    fn update(&self, id: usize, status: Option<TodoStatus>, note: Option<String>) -> String {
+        let mut items = self.items.lock().expect("poisoned");
+        let Some(item) = items.iter_mut().find(|i| i.id == id) else {
+            return format!("No item with the id {}", id);
+        };
+        if let Some(status) = status {
+            item.status = status;
+        }
+        if note.is_some() {
+            item.note = note;
+        }
+        format!("Updated item {}", id)
+    }
+
+    fn reorder(&self, order: Vec<usize>) -> String {
+        let mut items = self.items.lock().expect("poisoned");
+        if let Some(id) = order.iter().find(|id| !items.iter().any(|i| i.id == **id)) {
+            return format!("No item with the id {}, the plan is unchanged", id);
+        }
+        // A stable sort keeps the items left out in their order behind the given ones.
+        items.sort_by_key(|i| {
+            order
+                .iter()
+                .position(|id| *id == i.id)
+                .unwrap_or(usize::MAX)
+        });
+        "Reordered the plan".to_string()
+    }
+
+    pub async fn todo(&self, arguments: TodoArgs) -> Result<String, AgentyError> {
+        let resp = match arguments.op {
+            TodoOp::Add => match arguments.titles {
+                Some(titles) if !titles.is_empty() => self.add(titles),
+                _ => return Ok("`titles` is required to add items".to_string()),
+            },
+            TodoOp::Update => match arguments.id {
+                Some(id) => self.update(id, arguments.status, arguments.note),
+                None => return Ok("`id` is required to update an item".to_string()),
+            },
+            TodoOp::Reorder => match arguments.order {
+                Some(order) => self.reorder(order),
+                None => return Ok("`order` is required to reorder the plan".to_string()),
+            },
+            TodoOp::Show => String::new(),
+        };
+        let mut plan = self.render();
+        if plan.is_empty() {
+            plan = "The plan is empty".to_string();
+        }
+        Ok(if resp.is_empty() {
+            plan
+        } else {
+            format!("{}\n{}", resp, plan)
+        })
+    }
+}
+
+impl Tool for TodoTool {
+    type ARGUMENTS = TodoArgs;
+    const NAME: &str = "todo";
+    const DESCRIPTION: Option<&str> = Some(
+        "Maintain a plan of the task as a list of items. `op` is 'add' to append the items in `titles`, 'update' to set the `status` of the item `id` to 'pending', 'in_progress', 'done' or 'blocked' with an optional `note`, 'reorder' to put the items in the order of the ids in `order`, and 'show' to see the plan. Every operation returns the current plan. Keep the plan up to date as you work.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.todo(arguments)
+    }
+}
@@ -0,0 +1,112 @@
+use std::{future::Future, path::PathBuf, sync::Arc};
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    error::AgentyError,
+    tokens::{HeuristicTokenCounter, TokenCounter},
+    tool::Tool,
+};
+
+use super::file::{human_size, sanitize_join_relative_path};
+
+pub const DEFAULT_MAX_COUNT_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CountTokensArgs {
+    /// The text to count, either this or `file_path`.
+    pub text: Option<String>,
+    /// A text file to count, either this or `text`.
+    pub file_path: Option<PathBuf>,
+    /// Count for this model instead of the default one.
+    pub model: Option<String>,
+}
+
+/// Lets the model check its own output against a token budget.
+#[derive(Debug, Clone)]
+pub struct CountTokensTool {
+    pub cwd: PathBuf,
+    pub counter: Arc<dyn TokenCounter>,
+    /// Larger files are not counted.
+    pub max_file_bytes: u64,
+}
+
+impl CountTokensTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self {
+            cwd,
+            counter: Arc::new(HeuristicTokenCounter),
+            max_file_bytes: DEFAULT_MAX_COUNT_FILE_BYTES,
+        }
+    }
+
+    pub fn with_counter(mut self, counter: impl TokenCounter + 'static) -> Self {
+        self.counter = Arc::new(counter);
+        self
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    pub async fn count_tokens(&self, arguments: CountTokensArgs) -> Result<String, AgentyError> {
+        let text = match (arguments.text, arguments.file_path) {
+            (Some(text), None) => text,
+            (None, Some(file_path)) => {
+                let target_path = match sanitize_join_relative_path(&self.cwd, &file_path) {
+                    Ok(p) => p,
+                    Err(e) => return Ok(e),
+                };
+                match tokio::fs::metadata(&target_path).await {
+                    Ok(meta) if meta.len() > self.max_file_bytes => {
+                        return Ok(format!(
+                            "{:?} is {}, more than the {} that can be counted",
+                            &file_path,
+                            human_size(meta.len()),
+                            human_size(self.max_file_bytes)
+                        ));
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+                }
+                match tokio::fs::read(&target_path).await {
+                    Ok(content) => match String::from_utf8(content) {
+                        Ok(text) => text,
+                        Err(_) => return Ok(format!("{:?} is not a UTF-8 text file", &file_path)),
+                    },
+                    Err(e) => return Ok(format!("Fail to read {:?} due to {}", &file_path, e)),
+                }
+            }
+            _ => return Ok("Exactly one of `text` and `file_path` is required".to_string()),
+        };
+        let counter = self.counter.clone();
+        let model = arguments.model;
+        let (tokens, chars) = tokio::task::spawn_blocking(move || {
+            (counter.count(&text, model.as_deref()), text.chars().count())
+        })
+        .await?;
+        Ok(format!(
+            "{} tokens ({}), {} characters",
+            tokens,
+            self.counter.name(),
+            chars
+        ))
+    }
+}
+
+impl Tool for CountTokensTool {
+    type ARGUMENTS = CountTokensArgs;
+    const NAME: &str = "count_tokens";
+    const DESCRIPTION: Option<&str> = Some(
+        "Count the tokens and characters of `text` or of the text file at `file_path`, e.g. to check a result against a token budget before returning it. Pass `model` to count for a specific model.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.count_tokens(arguments)
+    }
+}
@@ -0,0 +1,521 @@
+use std::{collections::HashMap, future::Future, path::PathBuf, time::Duration};
+
+use itertools::Itertools;
+use reqwest::{
+    Method, Url,
+    header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue},
+    redirect,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::file::{
+    human_size, looks_textual, sanitize_join_relative_path, truncate_at_line_boundary,
+};
+
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+pub const DEFAULT_MAX_FETCH_OUTPUT: usize = 16384;
+pub const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 100 * 1024 * 1024;
+pub const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct FetchUrlArgs {
+    pub url: String,
+    /// `GET` by default.
+    pub method: Option<HttpMethod>,
+    pub body: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+/// `e` followed by its sources, reqwest keeps the interesting part like a refused redirect
+/// in the source.
+pub(super) fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut msg = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        msg.push_str(&format!(": {}", e));
+        source = e.source();
+    }
+    msg
+}
+
+/// Whether `host` is one of `hosts` or a subdomain of one.
+fn host_matches(host: &str, hosts: &[String]) -> bool {
+    hosts.iter().any(|h| {
+        host.eq_ignore_ascii_case(h)
+            || host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", h.to_ascii_lowercase()))
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchUrlTool {
+    /// Only these hosts and their subdomains can be fetched, any host if `None`.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// These hosts and their subdomains can never be fetched, also not by a redirect.
+    pub denied_hosts: Vec<String>,
+    /// The download stops after this many bytes.
+    pub max_response_bytes: usize,
+    /// The rendered response is cut after this many bytes.
+    pub max_output: usize,
+    pub timeout: Duration,
+    pub max_redirects: usize,
+}
+
+impl Default for FetchUrlTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FetchUrlTool {
+    pub fn new() -> Self {
+        Self {
+            allowed_hosts: None,
+            denied_hosts: vec![],
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            max_output: DEFAULT_MAX_FETCH_OUTPUT,
+            timeout: DEFAULT_FETCH_TIMEOUT,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+
+    pub fn with_allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = Some(hosts);
+        self
+    }
+
+    pub fn with_denied_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.denied_hosts = hosts;
+        self
+    }
+
+    /// Why `host` can not be reached, if it can not.
+    pub(super) fn check_host(
+        host: &str,
+        allowed: &Option<Vec<String>>,
+        denied: &[String],
+    ) -> Result<(), String> {
+        if host_matches(host, denied) {
+            return Err(format!("{} is not allowed", host));
+        }
+        if let Some(allowed) = allowed
+            && !host_matches(host, allowed)
+        {
+            return Err(format!(
+                "{} is not allowed, the allowed hosts are: {}",
+                host,
+                allowed.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Why `url` can not be fetched, if it can not.
+    pub(super) fn check_url(
+        url: &Url,
+        allowed: &Option<Vec<String>>,
+        denied: &[String],
+    ) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} is not an http or https URL", url));
+        }
+        Self::check_host(url.host_str().unwrap_or_default(), allowed, denied)
+            .map_err(|e| format!("fetching from {}", e))
+    }
+
+    pub(super) fn client(&self) -> Result<reqwest::Client, AgentyError> {
+        let allowed = self.allowed_hosts.clone();
+        let denied = self.denied_hosts.clone();
+        let max_redirects = self.max_redirects;
+        // Redirects are checked against the host lists as well, or any allowed host could
+        // bounce the request anywhere.
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                attempt.error(format!("more than {} redirects", max_redirects))
+            } else if let Err(e) = Self::check_url(attempt.url(), &allowed, &denied) {
+                let target = attempt.url().to_string();
+                attempt.error(format!("redirected to {}: {}", target, e))
+            } else {
+                attempt.follow()
+            }
+        });
+        Ok(reqwest::Client::builder()
+            .timeout(self.timeout)
+            .redirect(policy)
+            .build()?)
+    }
+
+    /// The body of `resp` up to `max_response_bytes`, and whether it was cut there.
+    pub(super) async fn read_body(
+        &self,
+        resp: &mut reqwest::Response,
+    ) -> Result<(Vec<u8>, bool), reqwest::Error> {
+        let mut body = vec![];
+        while let Some(chunk) = resp.chunk().await? {
+            if body.len() + chunk.len() > self.max_response_bytes {
+                let keep = self.max_response_bytes - body.len();
+                body.extend_from_slice(&chunk[..keep]);
+                return Ok((body, true));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok((body, false))
+    }
+
+    /// Render the body for the model according to its content type.
+    fn render_body(content_type: &str, body: &[u8], url: &Url) -> Option<String> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if mime == "text/html" || mime == "application/xhtml+xml" {
+            let html = String::from_utf8_lossy(body);
+            return Some(html2md::rewrite_html_custom_with_url(
+                &html,
+                &None,
+                false,
+                &Some(url.clone()),
+            ));
+        }
+        if mime == "application/json" || mime.ends_with("+json") {
+            // A body cut by the size cap is no valid JSON anymore, show it as it is then.
+            return Some(
+                serde_json::from_slice::<serde_json::Value>(body)
+                    .ok()
+                    .and_then(|v| serde_json::to_string_pretty(&v).ok())
+                    .unwrap_or_else(|| String::from_utf8_lossy(body).to_string()),
+            );
+        }
+        if mime.starts_with("text/")
+            || mime.ends_with("xml")
+            || mime.ends_with("javascript")
+            || (mime.is_empty() && looks_textual(body))
+        {
+            return Some(String::from_utf8_lossy(body).to_string());
+        }
+        None
+    }
+
+    pub async fn fetch_url(&self, arguments: FetchUrlArgs) -> Result<String, AgentyError> {
+        let url = match Url::parse(&arguments.url) {
+            Ok(url) => url,
+            Err(e) => return Ok(format!("{} is not a valid URL: {}", &arguments.url, e)),
+        };
+        if let Err(e) = Self::check_url(&url, &self.allowed_hosts, &self.denied_hosts) {
+            return Ok(e);
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in arguments.headers.unwrap_or_default() {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) else {
+                return Ok(format!("{}: {} is not a valid header", name, value));
+            };
+            headers.insert(name, value);
+        }
+        let method = match arguments.method.unwrap_or_default() {
+            HttpMethod::Get => Method::GET,
+            HttpMethod::Post => Method::POST,
+        };
+
+        let mut req = self.client()?.request(method, url.clone()).headers(headers);
+        if let Some(body) = arguments.body {
+            req = req.body(body);
+        }
+        let mut resp = match req.send().await {
+            Ok(resp) => resp,
+            Err(e) => return Ok(format!("Fail to fetch {} due to {}", &url, error_chain(&e))),
+        };
+
+        let status = resp.status();
+        let final_url = resp.url().clone();
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let (body, truncated) = match self.read_body(&mut resp).await {
+            Ok(body) => body,
+            Err(e) => {
+                return Ok(format!(
+                    "Fail to read the response of {} due to {}",
+                    &url,
+                    error_chain(&e)
+                ));
+            }
+        };
+
+        let mut header = format!("HTTP {} from {}", status, &final_url);
+        if final_url != url {
+            header.push_str(&format!(" (redirected from {})", &url));
+        }
+        let size = if truncated {
+            format!("more than {}", human_size(body.len() as u64))
+        } else {
+            human_size(body.len() as u64)
+        };
+        let Some(text) = Self::render_body(&content_type, &body, &final_url) else {
+            return Ok(format!(
+                "{}\nbinary content of type {}, {}",
+                header,
+                if content_type.is_empty() { "unknown" } else { &content_type },
+                size
+            ));
+        };
+        let mut resp = format!("{}\n{}, {}\n\n{}", header, content_type, size, text);
+        if truncated {
+            resp.push_str("\n(response truncated at the download limit)");
+        }
+        let cut = truncate_at_line_boundary(&resp, self.max_output);
+        if cut.len() < resp.len() {
+            resp = format!(
+                "{}\n(output truncated, {} of {} bytes shown)",
+                cut.trim_end(),
+                cut.len(),
+                resp.len()
+            );
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for FetchUrlTool {
+    type ARGUMENTS = FetchUrlArgs;
+    const NAME: &str = "fetch_url";
+    const DESCRIPTION: Option<&str> = Some(
+        "Fetch `url` over http or https with an optional `method` ('GET' or 'POST'), `body` and `headers`. HTML pages are returned as markdown with absolute links, JSON is pretty-printed, other text as it is and binary content only by its type and size. Long responses are truncated. The final URL is reported when the request was redirected. Some hosts may not be allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.fetch_url(arguments)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DownloadFileArgs {
+    pub url: String,
+    /// Where to save the file, relative to the workspace.
+    pub destination: PathBuf,
+    /// Give up on larger downloads, can only lower the limit of the tool.
+    pub max_bytes: Option<u64>,
+    /// Replace an existing file at `destination`, false by default.
+    pub overwrite: Option<bool>,
+}
+
+/// Saves a URL to a file in the workspace for other tools to work with.
+///
+/// The host lists and the redirect limit are the ones of `fetch`, so both tools can be
+/// configured once. Downloads are never resumed: a failed download leaves nothing behind and
+/// is started over.
+#[derive(Debug, Clone)]
+pub struct DownloadFileTool {
+    pub cwd: PathBuf,
+    pub fetch: FetchUrlTool,
+    /// Downloads larger than this are refused or stopped.
+    pub max_bytes: u64,
+    /// For the whole download, longer than for fetching as files can be large.
+    pub timeout: Duration,
+}
+
+impl DownloadFileTool {
+    pub fn new(cwd: PathBuf) -> Self {
+        Self::with_fetch(cwd, FetchUrlTool::new())
+    }
+
+    /// Share the host lists and the redirect limit of `fetch`.
+    pub fn with_fetch(cwd: PathBuf, fetch: FetchUrlTool) -> Self {
+        Self {
+            cwd,
+            fetch,
+            max_bytes: DEFAULT_MAX_DOWNLOAD_BYTES,
+            timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn download_file(&self, arguments: DownloadFileArgs) -> Result<String, AgentyError> {
+        let url = match Url::parse(&arguments.url) {
+            Ok(url) => url,
+            Err(e) => return Ok(format!("{} is not a valid URL: {}", &arguments.url, e)),
+        };
+        if let Err(e) =
+            FetchUrlTool::check_url(&url, &self.fetch.allowed_hosts, &self.fetch.denied_hosts)
+        {
+            return Ok(e);
+        }
+        let destination = arguments.destination;
+        let target_path = match sanitize_join_relative_path(&self.cwd, &destination) {
+            Ok(p) => p,
+            Err(e) => return Ok(e),
+        };
+        let overwrite = arguments.overwrite.unwrap_or(false);
+        if let Ok(meta) = tokio::fs::metadata(&target_path).await {
+            if meta.is_dir() {
+                return Ok(format!("Path {:?} is a directory", &destination));
+            }
+            if !overwrite {
+                return Ok(format!(
+                    "{:?} exists already, pass `overwrite: true` to replace it",
+                    &destination
+                ));
+            }
+        }
+        let limit = arguments
+            .max_bytes
+            .map_or(self.max_bytes, |max| max.min(self.max_bytes));
+
+        let client = FetchUrlTool {
+            timeout: self.timeout,
+            ..self.fetch.clone()
+        }
+        .client()?;
+        let mut resp = match client.get(url.clone()).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                return Ok(format!(
+                    "Fail to download {} due to {}",
+                    &url,
+                    error_chain(&e)
+                ));
+            }
+        };
+        if !resp.status().is_success() {
+            return Ok(format!("Fail to download {}: HTTP {}", &url, resp.status()));
+        }
+        let expected = resp.content_length();
+        if let Some(expected) = expected
+            && expected > limit
+        {
+            return Ok(format!(
+                "{} is {}, more than the limit of {}",
+                &url,
+                human_size(expected),
+                human_size(limit)
+            ));
+        }
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let parent = target_path
+            .parent()
+            .expect("sanitized path without a parent?!");
+        tokio::fs::create_dir_all(parent).await?;
+        // Written next to the destination and renamed at the end, so a failed download is
+        // removed with the temporary file and never mistaken for a complete one.
+        let tmp = tempfile::Builder::new()
+            .prefix(".download-")
+            .tempfile_in(parent)?;
+        let mut fp = tokio::fs::File::from_std(tmp.reopen()?);
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        loop {
+            match resp.chunk().await {
+                Ok(Some(chunk)) => {
+                    size += chunk.len() as u64;
+                    if size > limit {
+                        return Ok(format!(
+                            "Stopped downloading {} at the limit of {}",
+                            &url,
+                            human_size(limit)
+                        ));
+                    }
+                    hasher.update(&chunk);
+                    fp.write_all(&chunk).await?;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    return Ok(format!(
+                        "Fail to download {} due to {}",
+                        &url,
+                        error_chain(&e)
+                    ));
+                }
+            }
+        }
+        if let Some(expected) = expected
+            && expected != size
+        {
+            return Ok(format!(
+                "The download of {} is incomplete, got {} of {} bytes",
+                &url, size, expected
+            ));
+        }
+        fp.sync_all().await?;
+        drop(fp);
+        let persisted = if overwrite {
+            tmp.persist(&target_path)
+        } else {
+            // Something may have created the file during the download.
+            tmp.persist_noclobber(&target_path)
+        };
+        if let Err(e) = persisted {
+            return Ok(format!(
+                "Fail to save {:?} due to {}",
+                &destination, e.error
+            ));
+        }
+        Ok(format!(
+            "Saved {} to {:?}: {}, {}, sha256 {}",
+            &url,
+            &destination,
+            human_size(size),
+            content_type,
+            hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .join("")
+        ))
+    }
+}
+
+impl Tool for DownloadFileTool {
+    type ARGUMENTS = DownloadFileArgs;
+    const NAME: &str = "download_file";
+    const DESCRIPTION: Option<&str> = Some(
+        "Download `url` and save it to the file `destination` in the workspace, e.g. an archive or a dataset to work on with other tools. The size, content type and sha256 of the saved file are returned. An existing file is only replaced with `overwrite` set to true. Downloads larger than `max_bytes` or the limit of the tool are refused. The path should be always relative path and '.' is allowed while '..' is not allowed. Some hosts may not be allowed.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.download_file(arguments)
+    }
+}
@@ -0,0 +1,500 @@
+use std::{future::Future, time::Duration};
+
+use itertools::Itertools;
+use reqwest::{StatusCode, Url};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{error::AgentyError, tool::Tool};
+
+use super::{file::truncate_at_line_boundary, web::error_chain};
+
+/// `{language}` is replaced by the language of the query.
+pub const DEFAULT_WIKIPEDIA_URL: &str = "https://{language}.wikipedia.org";
+pub const DEFAULT_WIKIPEDIA_TIMEOUT: Duration = Duration::from_secs(20);
+pub const DEFAULT_MAX_ARTICLE_BYTES: usize = 32768;
+/// Pages of a disambiguation page offered at most.
+const MAX_DISAMBIGUATION_OPTIONS: usize = 30;
+/// Search results besides the best match mentioned at the end.
+const MAX_OTHER_MATCHES: usize = 4;
+/// Wikipedia asks clients to identify themselves and blocks generic user agents.
+const USER_AGENT: &str = concat!(
+    "agenty/",
+    env!("CARGO_PKG_VERSION"),
+    " (https://github.com/wtdcode/agenty)"
+);
+
+#[derive(Deserialize, JsonSchema)]
+pub struct WikipediaArgs {
+    /// What to look up, a title or a few keywords.
+    pub query: String,
+    /// The language edition like `en`, `de` or `zh`, `en` by default.
+    pub language: Option<String>,
+    /// Return the whole article as plain text instead of its summary.
+    pub full_article: Option<bool>,
+}
+
+/// The parts of a page summary of the REST API the tool shows.
+#[derive(Deserialize)]
+struct PageSummary {
+    #[serde(rename = "type", default)]
+    kind: String,
+    title: String,
+    #[serde(default)]
+    extract: String,
+    content_urls: Option<ContentUrls>,
+}
+
+#[derive(Deserialize)]
+struct ContentUrls {
+    desktop: PageUrls,
+}
+
+#[derive(Deserialize)]
+struct PageUrls {
+    page: String,
+}
+
+/// Looks up Wikipedia articles, for general knowledge without a search engine.
+#[derive(Debug, Clone)]
+pub struct WikipediaTool {
+    /// The site to query, see [`DEFAULT_WIKIPEDIA_URL`]. Point it to a mirror or a mock.
+    pub base_url: String,
+    pub default_language: String,
+    pub timeout: Duration,
+    /// Full articles are cut after this many bytes.
+    pub max_article_bytes: usize,
+}
+
+impl Default for WikipediaTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WikipediaTool {
+    pub fn new() -> Self {
+        Self {
+            base_url: DEFAULT_WIKIPEDIA_URL.to_string(),
+            default_language: "en".to_string(),
+            timeout: DEFAULT_WIKIPEDIA_TIMEOUT,
+            max_article_bytes: DEFAULT_MAX_ARTICLE_BYTES,
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn with_default_language(mut self, language: String) -> Self {
+        self.default_language = language;
+        self
+    }
+
+    pub fn with_max_article_bytes(mut self, max_article_bytes: usize) -> Self {
+        self.max_article_bytes = max_article_bytes;
+        self
+    }
+
+    /// GET `url` and parse the JSON answer, `None` for a 404. Errors are meant for the model.
+    async fn get_json(client: &reqwest::Client, url: Url) -> Result<Option<Value>, String> {
+        let resp = client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("Fail to reach Wikipedia due to {}", error_chain(&e)))?;
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(format!("Wikipedia answered with HTTP {}", status));
+        }
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|e| format!("Fail to read the answer of Wikipedia due to {}", e))?;
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|e| format!("Wikipedia answered with invalid JSON: {}", e))
+    }
+
+    /// A query of the MediaWiki action API.
+    async fn action(
+        client: &reqwest::Client,
+        base: &str,
+        params: &[(&str, &str)],
+    ) -> Result<Value, String> {
+        let url = Url::parse_with_params(
+            &format!("{}/w/api.php", base),
+            [
+                ("action", "query"),
+                ("format", "json"),
+                ("formatversion", "2"),
+            ]
+            .iter()
+            .chain(params),
+        )
+        .map_err(|e| format!("Invalid Wikipedia URL {}: {}", base, e))?;
+        Ok(Self::get_json(client, url).await?.unwrap_or_default())
+    }
+
+    /// The titles of the pages best matching `query` and the suggested spelling if any.
+    async fn search(
+        client: &reqwest::Client,
+        base: &str,
+        query: &str,
+    ) -> Result<(Vec<String>, Option<String>), String> {
+        let value = Self::action(
+            client,
+            base,
+            &[("list", "search"), ("srsearch", query), ("srlimit", "5")],
+        )
+        .await?;
+        let titles = value["query"]["search"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r["title"].as_str().map(str::to_string))
+            .collect();
+        let suggestion = value["query"]["searchinfo"]["suggestion"]
+            .as_str()
+            .map(str::to_string);
+        Ok((titles, suggestion))
+    }
+
+    async fn summary(
+        client: &reqwest::Client,
+        base: &str,
+        title: &str,
+    ) -> Result<Option<PageSummary>, String> {
+        let mut url = Url::parse(&format!("{}/api/rest_v1/page/summary", base))
+            .map_err(|e| format!("Invalid Wikipedia URL {}: {}", base, e))?;
+        url.path_segments_mut()
+            .map_err(|_| format!("Invalid Wikipedia URL {}", base))?
+            .push(&title.replace(' ', "_"));
+        match Self::get_json(client, url).await? {
+            Some(value) => serde_json::from_value(value)
+                .map(Some)
+                .map_err(|e| format!("Unexpected page summary from Wikipedia: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    /// The articles a disambiguation page links to.
+    async fn disambiguation_options(
+        client: &reqwest::Client,
+        base: &str,
+        title: &str,
+    ) -> Result<Vec<String>, String> {
+        let value = Self::action(
+            client,
+            base,
+            &[
+                ("prop", "links"),
+                ("titles", title),
+                ("plnamespace", "0"),
+                ("pllimit", "max"),
+            ],
+        )
+        .await?;
+        Ok(value["query"]["pages"][0]["links"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|l| l["title"].as_str().map(str::to_string))
+            .collect())
+    }
+
+    /// The plain text of the whole article, `None` if there is no such page.
+    async fn article(
+        client: &reqwest::Client,
+        base: &str,
+        title: &str,
+    ) -> Result<Option<String>, String> {
+        let value = Self::action(
+            client,
+            base,
+            &[
+                ("prop", "extracts"),
+                ("explaintext", "1"),
+                ("redirects", "1"),
+                ("titles", title),
+            ],
+        )
+        .await?;
+        Ok(value["query"]["pages"][0]["extract"]
+            .as_str()
+            .map(str::to_string))
+    }
+
+    pub async fn wikipedia(&self, arguments: WikipediaArgs) -> Result<String, AgentyError> {
+        let language = arguments
+            .language
+            .unwrap_or_else(|| self.default_language.clone())
+            .to_ascii_lowercase();
+        // The language ends up in the host name.
+        if language.is_empty()
+            || language.len() > 12
+            || !language.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+        {
+            return Ok(format!("{:?} is not a Wikipedia language code", language));
+        }
+        let query = arguments.query.trim();
+        if query.is_empty() {
+            return Ok("The query is empty".to_string());
+        }
+        let base = self.base_url.replace("{language}", &language);
+        let base = base.trim_end_matches('/');
+        let client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .user_agent(USER_AGENT)
+            .build()?;
+
+        let (titles, suggestion) = match Self::search(&client, base, query).await {
+            Ok(v) => v,
+            Err(e) => return Ok(e),
+        };
+        let Some(best) = titles.first() else {
+            let mut resp = format!("No Wikipedia article matches {:?}", query);
+            if let Some(suggestion) = suggestion {
+                resp.push_str(&format!(", did you mean {:?}?", suggestion));
+            }
+            return Ok(resp);
+        };
+        // The summary follows redirects, so its title may differ from the search result.
+        let summary = match Self::summary(&client, base, best).await {
+            Ok(Some(summary)) => summary,
+            Ok(None) => return Ok(format!("The article {:?} does not exist", best)),
+            Err(e) => return Ok(e),
+        };
+        let mut resp = format!("# {}", summary.title);
+        if &summary.title != best {
+            resp.push_str(&format!(" (redirected from {})", best));
+        }
+        if let Some(urls) = &summary.content_urls {
+            resp.push_str(&format!("\n{}", urls.desktop.page));
+        }
+
+        if summary.kind == "disambiguation" {
+            let options = match Self::disambiguation_options(&client, base, &summary.title).await {
+                Ok(options) => options,
+                Err(e) => return Ok(e),
+            };
+            resp.push_str(&format!(
+                "\n\n{:?} is ambiguous, call again with the title of one of these articles as the query:\n{}",
+                query,
+                options
+                    .iter()
+                    .take(MAX_DISAMBIGUATION_OPTIONS)
+                    .map(|o| format!("- {}", o))
+                    .join("\n")
+            ));
+            if options.len() > MAX_DISAMBIGUATION_OPTIONS {
+                resp.push_str(&format!(
+                    "\n({} more not shown)",
+                    options.len() - MAX_DISAMBIGUATION_OPTIONS
+                ));
+            }
+            return Ok(resp);
+        }
+
+        if arguments.full_article == Some(true) {
+            let text = match Self::article(&client, base, &summary.title).await {
+                Ok(Some(text)) => text,
+                Ok(None) => {
+                    return Ok(format!("The article {:?} does not exist", summary.title));
+                }
+                Err(e) => return Ok(e),
+            };
+            let cut = truncate_at_line_boundary(&text, self.max_article_bytes);
+            resp.push_str(&format!("\n\n{}", cut.trim_end()));
+            if cut.len() < text.len() {
+                resp.push_str(&format!(
+                    "\n(article truncated, {} of {} bytes shown)",
+                    cut.len(),
+                    text.len()
+                ));
+            }
+        } else {
+            resp.push_str(&format!("\n\n{}", summary.extract.trim()));
+        }
+        let others = titles
+            .iter()
+            .skip(1)
+            .filter(|t| **t != summary.title)
+            .take(MAX_OTHER_MATCHES)
+            .join(", ");
+        if !others.is_empty() {
+            resp.push_str(&format!("\n\nOther matches: {}", others));
+        }
+        Ok(resp)
+    }
+}
+
+impl Tool for WikipediaTool {
+    type ARGUMENTS = WikipediaArgs;
+    const NAME: &str = "wikipedia";
+    const DESCRIPTION: Option<&str> = Some(
+        "Look up `query` on Wikipedia and get the summary of the best matching article with its title and URL, or the whole article as plain text with `full_article` set to true, which is cut when long. When the query is ambiguous the candidate articles are listed instead, call again with one of their titles. Set `language` like 'de' or 'ja' to use another language edition.",
+    );
+
+    fn invoke(
+        &self,
+        arguments: Self::ARGUMENTS,
+    ) -> impl Future<Output = Result<String, AgentyError>> + Send {
+        self.wikipedia(arguments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_util::{Request, Response, serve};
+
+    fn ok(value: Value) -> Response {
+        (200, "application/json", value.to_string())
+    }
+
+    fn summary(kind: &str, title: &str, extract: &str) -> Response {
+        let page = format!("https://en.wikipedia.org/wiki/{}", title.replace(' ', "_"));
+        ok(json!({
+            "type": kind,
+            "title": title,
+            "extract": extract,
+            "content_urls": {"desktop": {"page": page}},
+        }))
+    }
+
+    /// A small Wikipedia with a redirect, a disambiguation page and a search result whose
+    /// article is gone.
+    fn wiki(request: Request) -> Response {
+        assert_eq!(
+            (request.method.as_str(), request.body.as_str()),
+            ("GET", "")
+        );
+        let url = Url::parse(&format!("http://wiki{}", request.path)).unwrap();
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.to_string())
+                .unwrap_or_default()
+        };
+        match url.path() {
+            "/w/api.php" if param("list") == "search" => {
+                let titles: &[&str] = match param("srsearch").as_str() {
+                    "rust language" => &["Rust (programming language)", "Rust", "Cargo (software)"],
+                    "tokio" => &["Tokio (software)"],
+                    "mercury" => &["Mercury"],
+                    "gone" => &["Gone article"],
+                    _ => &[],
+                };
+                let search = titles.iter().map(|t| json!({"title": t})).collect_vec();
+                ok(json!({"query": {"search": search, "searchinfo": {"suggestion": "rust"}}}))
+            }
+            "/w/api.php" if param("prop") == "links" => {
+                assert_eq!(param("titles"), "Mercury");
+                ok(json!({"query": {"pages": [{"links": [
+                    {"title": "Mercury (planet)"},
+                    {"title": "Mercury (element)"},
+                ]}]}}))
+            }
+            "/w/api.php" if param("prop") == "extracts" => {
+                let extract = format!("{} line one\nline two\nline three\n", param("titles"));
+                ok(json!({"query": {"pages": [{"extract": extract}]}}))
+            }
+            "/api/rest_v1/page/summary/Rust_(programming_language)" => summary(
+                "standard",
+                "Rust (programming language)",
+                "Rust is a programming language. ",
+            ),
+            "/api/rest_v1/page/summary/Tokio_(software)" => {
+                summary("standard", "Tokio (runtime)", "Tokio is a runtime.")
+            }
+            "/api/rest_v1/page/summary/Mercury" => {
+                summary("disambiguation", "Mercury", "Mercury may refer to:")
+            }
+            _ => (404, "application/json", "{}".to_string()),
+        }
+    }
+
+    async fn lookup(query: &str, full_article: bool) -> String {
+        let url = serve(wiki).await;
+        WikipediaTool::new()
+            .with_base_url(url)
+            .with_max_article_bytes(40)
+            .wikipedia(WikipediaArgs {
+                query: query.to_string(),
+                language: None,
+                full_article: Some(full_article),
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_summary() {
+        assert_eq!(
+            lookup("rust language", false).await,
+            "# Rust (programming language)\n\
+             https://en.wikipedia.org/wiki/Rust_(programming_language)\n\n\
+             Rust is a programming language.\n\n\
+             Other matches: Rust, Cargo (software)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_article_is_cut() {
+        assert_eq!(
+            lookup("rust language", true).await,
+            "# Rust (programming language)\n\
+             https://en.wikipedia.org/wiki/Rust_(programming_language)\n\n\
+             Rust (programming language) line one\n\
+             (article truncated, 37 of 57 bytes shown)\n\n\
+             Other matches: Rust, Cargo (software)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let resp = lookup("tokio", false).await;
+        assert!(
+            resp.starts_with("# Tokio (runtime) (redirected from Tokio (software))\n"),
+            "{}",
+            resp
+        );
+        assert!(resp.ends_with("\n\nTokio is a runtime."), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_disambiguation() {
+        let resp = lookup("mercury", false).await;
+        assert!(
+            resp.ends_with(
+                "\n\n\"mercury\" is ambiguous, call again with the title of one of these \
+                 articles as the query:\n\
+                 - Mercury (planet)\n\
+                 - Mercury (element)"
+            ),
+            "{}",
+            resp
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_article() {
+        assert_eq!(
+            lookup("gone", false).await,
+            "The article \"Gone article\" does not exist"
+        );
+        assert_eq!(
+            lookup("rustt", false).await,
+            "No Wikipedia article matches \"rustt\", did you mean \"rust\"?"
+        );
+    }
+}